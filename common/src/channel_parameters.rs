@@ -3,15 +3,112 @@ use config::Value;
 use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, time::Duration};
 
+/// Serialized as `{"latency_us": <u64>, "loss": <f64>}`, plus `jitter_us`
+/// and `reorder` when not zero and `bandwidth_bps` when set. Deserialization also accepts `latency_ms` in
+/// place of `latency_us`, but not both.
+///
+/// The latency is also serialized as the `{"secs", "nanos"}` pair under
+/// `latency` that clients built before the explicit units still read. It is
+/// only used when deserializing without `latency_ms` or `latency_us`.
 #[derive(Debug, Copy, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(try_from = "WireParameters", into = "WireParameters")]
 pub struct ChannelParameters {
     pub latency: Duration,
     pub loss: f64,
+    /// Latency of each frame is drawn uniformly within this much of `latency`
+    pub jitter: Duration,
+    /// Probability of a frame overtaking the one queued before it
+    pub reorder: f64,
+    /// Throughput cap in bits per second, frames beyond it are delayed
+    pub bandwidth_bps: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WireParameters {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    latency: Option<Duration>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    latency_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    latency_us: Option<u64>,
+    loss: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    jitter_us: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reorder: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    bandwidth_bps: Option<u64>,
+}
+
+impl TryFrom<WireParameters> for ChannelParameters {
+    type Error = String;
+
+    fn try_from(wire: WireParameters) -> Result<Self, Self::Error> {
+        let latency = match (wire.latency_ms, wire.latency_us) {
+            (Some(_), Some(_)) => {
+                return Err("latency_ms and latency_us are mutually exclusive".to_string())
+            }
+            (Some(ms), None) => Duration::from_millis(ms),
+            (None, Some(us)) => Duration::from_micros(us),
+            (None, None) => wire
+                .latency
+                .ok_or_else(|| "missing latency_ms or latency_us".to_string())?,
+        };
+
+        Self {
+            latency,
+            loss: wire.loss,
+            jitter: Duration::from_micros(wire.jitter_us.unwrap_or(0)),
+            reorder: wire.reorder.unwrap_or(0.0),
+            bandwidth_bps: wire.bandwidth_bps,
+        }
+        .checked()
+    }
+}
+
+impl ChannelParameters {
+    /// These parameters, or why a channel cannot apply them
+    fn checked(self) -> Result<Self, String> {
+        if !(0.0..=1.0).contains(&self.loss) {
+            return Err(format!("loss must be within [0, 1], got {}", self.loss));
+        }
+
+        if !(0.0..=1.0).contains(&self.reorder) {
+            return Err(format!(
+                "reorder must be within [0, 1], got {}",
+                self.reorder
+            ));
+        }
+
+        if self.bandwidth_bps == Some(0) {
+            return Err("bandwidth_bps must be above 0".to_string());
+        }
+
+        Ok(self)
+    }
+}
+
+impl From<ChannelParameters> for WireParameters {
+    fn from(params: ChannelParameters) -> Self {
+        Self {
+            latency: Some(params.latency),
+            latency_ms: None,
+            latency_us: Some(u64::try_from(params.latency.as_micros()).unwrap_or(u64::MAX)),
+            loss: params.loss,
+            jitter_us: (!params.jitter.is_zero())
+                .then(|| u64::try_from(params.jitter.as_micros()).unwrap_or(u64::MAX)),
+            reorder: (params.reorder != 0.0).then_some(params.reorder),
+            bandwidth_bps: params.bandwidth_bps,
+        }
+    }
 }
 
 #[cfg(not(target_family = "wasm"))]
-impl From<HashMap<String, Value>> for ChannelParameters {
-    fn from(param: HashMap<String, Value>) -> Self {
+impl TryFrom<HashMap<String, Value>> for ChannelParameters {
+    type Error = String;
+
+    fn try_from(param: HashMap<String, Value>) -> Result<Self, Self::Error> {
         let latency = match param.get("latency") {
             Some(val) => val.clone().into_uint().unwrap_or(0),
             None => 0,
@@ -20,10 +117,129 @@ impl From<HashMap<String, Value>> for ChannelParameters {
             Some(val) => val.clone().into_float().unwrap_or(0.0),
             None => 0.0,
         };
+        let jitter = match param.get("jitter") {
+            Some(val) => val.clone().into_uint().unwrap_or(0),
+            None => 0,
+        };
+        let reorder = match param.get("reorder") {
+            Some(val) => val.clone().into_float().unwrap_or(0.0),
+            None => 0.0,
+        };
+        let bandwidth_bps = param
+            .get("bandwidth_bps")
+            .and_then(|val| val.clone().into_uint().ok());
 
         Self {
             latency: Duration::from_millis(latency),
             loss,
+            jitter: Duration::from_millis(jitter),
+            reorder,
+            bandwidth_bps,
+        }
+        .checked()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ChannelParameters;
+    use std::time::Duration;
+
+    #[test]
+    fn deserializes_latency_in_milliseconds() {
+        let params: ChannelParameters =
+            serde_json::from_str(r#"{"latency_ms": 12, "loss": 0.1}"#).expect("valid parameters");
+        assert_eq!(params.latency, Duration::from_millis(12));
+        assert_eq!(params.loss, 0.1);
+    }
+
+    #[test]
+    fn deserializes_latency_in_microseconds() {
+        let params: ChannelParameters =
+            serde_json::from_str(r#"{"latency_us": 1500, "loss": 0.0}"#).expect("valid parameters");
+        assert_eq!(params.latency, Duration::from_micros(1500));
+    }
+
+    #[test]
+    fn deserializes_latency_read_by_older_clients() {
+        let params: ChannelParameters =
+            serde_json::from_str(r#"{"latency": {"secs": 1, "nanos": 500}, "loss": 0.0}"#)
+                .expect("valid parameters");
+        assert_eq!(params.latency, Duration::new(1, 500));
+
+        // Explicit units win over it
+        let params: ChannelParameters = serde_json::from_str(
+            r#"{"latency": {"secs": 1, "nanos": 0}, "latency_ms": 5, "loss": 0.0}"#,
+        )
+        .expect("valid parameters");
+        assert_eq!(params.latency, Duration::from_millis(5));
+    }
+
+    #[test]
+    fn serializes_to_canonical_microseconds() {
+        let params = ChannelParameters {
+            latency: Duration::from_millis(3),
+            loss: 0.5,
+            jitter: Duration::ZERO,
+            reorder: 0.0,
+            bandwidth_bps: None,
+        };
+        let json = serde_json::to_value(params).expect("serializable");
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "latency": {"secs": 0, "nanos": 3_000_000},
+                "latency_us": 3000,
+                "loss": 0.5
+            })
+        );
+        assert_eq!(
+            serde_json::from_value::<ChannelParameters>(json).expect("round trip"),
+            params
+        );
+    }
+
+    #[test]
+    fn jitter_and_reorder_round_trip_when_set() {
+        let params: ChannelParameters = serde_json::from_str(
+            r#"{"latency_ms": 10, "loss": 0.0, "jitter_us": 2000, "reorder": 0.1}"#,
+        )
+        .expect("valid parameters");
+        assert_eq!(params.jitter, Duration::from_millis(2));
+        assert_eq!(params.reorder, 0.1);
+        let json = serde_json::to_value(params).expect("serializable");
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "latency": {"secs": 0, "nanos": 10_000_000},
+                "latency_us": 10000,
+                "loss": 0.0,
+                "jitter_us": 2000,
+                "reorder": 0.1
+            })
+        );
+
+        let params: ChannelParameters =
+            serde_json::from_str(r#"{"latency_ms": 10, "loss": 0.0}"#).expect("valid parameters");
+        assert_eq!(params.jitter, Duration::ZERO);
+        assert_eq!(params.reorder, 0.0);
+    }
+
+    #[test]
+    fn rejects_conflicting_or_invalid_fields() {
+        for input in [
+            r#"{"latency_ms": 1, "latency_us": 1000, "loss": 0.0}"#,
+            r#"{"loss": 0.0}"#,
+            r#"{"latency_ms": 1, "loss": 1.5}"#,
+            r#"{"latency_ms": 1, "loss": -0.1}"#,
+            r#"{"latency": 1, "loss": 0.0}"#,
+            r#"{"latency_ms": 1, "loss": 0.0, "reorder": 2.0}"#,
+            r#"{"latency_ms": 1, "loss": 0.0, "bandwidth_bps": 0}"#,
+        ] {
+            assert!(
+                serde_json::from_str::<ChannelParameters>(input).is_err(),
+                "accepted {input}"
+            );
         }
     }
 }
@@ -1,5 +1,5 @@
 use crate::network_interface::NetworkInterface;
-use crate::stats::Stats;
+use crate::stats::{FrameSizeHistogram, Stats};
 use anyhow::{Context, Result};
 use futures::ready;
 use libc::{sockaddr, sockaddr_ll, AF_PACKET};
@@ -10,6 +10,8 @@ use std::io::{self, ErrorKind, IoSlice, Read, Write};
 use std::os::fd::IntoRawFd;
 use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
 use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
 #[cfg(feature = "stats")]
 use std::sync::RwLock;
 use std::task::{self, Poll};
@@ -21,8 +23,17 @@ const ETH_P_ALL: u16 = 0x0003;
 pub struct Device {
     mac_address: MacAddress,
     fd: AsyncFd<DeviceIo>,
+    /// Socket priority of frames sent without their own, held while a frame
+    /// is sent so a per-frame priority cannot leak into frames sent
+    /// concurrently.
+    marking: Mutex<u32>,
+    /// Drop received frames not addressed to this device
+    destination_filter: AtomicBool,
+    filtered: AtomicU64,
     #[cfg(feature = "stats")]
     stats: RwLock<Stats>,
+    #[cfg(feature = "stats")]
+    frame_sizes: RwLock<FrameSizeHistogram>,
 }
 
 impl AsyncRead for Device {
@@ -182,6 +193,63 @@ impl DeviceIo {
         }
         Ok(n as _)
     }
+
+    pub fn set_priority(&self, priority: u32) -> io::Result<()> {
+        let value = libc::c_int::try_from(priority)
+            .map_err(|_| io::Error::from(ErrorKind::InvalidInput))?;
+        let ret = unsafe {
+            libc::setsockopt(
+                self.0,
+                libc::SOL_SOCKET,
+                libc::SO_PRIORITY,
+                std::ptr::addr_of!(value).cast(),
+                std::mem::size_of::<libc::c_int>() as _,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    pub fn priority(&self) -> io::Result<u32> {
+        let mut value: libc::c_int = 0;
+        let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+        let ret = unsafe {
+            libc::getsockopt(
+                self.0,
+                libc::SOL_SOCKET,
+                libc::SO_PRIORITY,
+                std::ptr::addr_of_mut!(value).cast(),
+                &mut len,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        u32::try_from(value).map_err(|_| io::Error::from(ErrorKind::InvalidData))
+    }
+
+    /// Sends with `priority` applied to this frame only, going back to the
+    /// `default` socket priority afterwards. A frame that was sent is not
+    /// reported as failed when going back fails, so it is not sent again.
+    pub fn sendv_with_priority(
+        &self,
+        bufs: &[IoSlice<'_>],
+        priority: u32,
+        default: u32,
+    ) -> io::Result<usize> {
+        if priority == default {
+            return self.sendv(bufs);
+        }
+
+        self.set_priority(priority)?;
+        let res = self.sendv(bufs);
+        let _ = self
+            .set_priority(default)
+            .inspect_err(|e| tracing::warn!(?e, default, "error restoring socket priority"));
+        res
+    }
 }
 
 impl Drop for DeviceIo {
@@ -190,6 +258,15 @@ impl Drop for DeviceIo {
     }
 }
 
+/// Whether `frame` is addressed to `own` or to a broadcast or multicast
+/// group. Frames too short to carry a destination are not.
+fn accepts_destination(own: MacAddress, frame: &[u8]) -> bool {
+    let Some(destination) = frame.get(0..6) else {
+        return false;
+    };
+    destination == own.bytes() || destination[0] & 0x1 != 0
+}
+
 impl NetworkInterface for Device {
     fn mac_address(&self) -> MacAddress {
         self.mac_address
@@ -227,8 +304,13 @@ impl Device {
         Ok(Self {
             mac_address,
             fd: AsyncFd::new(unsafe { DeviceIo::from_raw_fd(raw_fd) })?,
+            marking: Mutex::new(0),
+            destination_filter: AtomicBool::new(false),
+            filtered: AtomicU64::new(0),
             #[cfg(feature = "stats")]
             stats: Stats::default().into(),
+            #[cfg(feature = "stats")]
+            frame_sizes: FrameSizeHistogram::default().into(),
         })
     }
 
@@ -236,12 +318,20 @@ impl Device {
         loop {
             let mut guard = self.fd.readable().await?;
             match guard.try_io(|inner| inner.get_ref().recv(buf)) {
+                Ok(Ok(size))
+                    if self.destination_filter.load(Ordering::Relaxed)
+                        && !accepts_destination(self.mac_address, &buf[..size]) =>
+                {
+                    self.filtered.fetch_add(1, Ordering::Relaxed);
+                    continue;
+                }
                 Ok(res) => {
                     #[cfg(feature = "stats")]
                     if let Ok(size) = res {
                         let mut stats = self.stats.write().unwrap();
                         stats.received_packets += 1;
                         stats.received_bytes += size as u128;
+                        self.frame_sizes.write().unwrap().record(size);
                     }
                     return res;
                 }
@@ -253,13 +343,17 @@ impl Device {
     pub async fn send(&self, buf: &[u8]) -> io::Result<usize> {
         loop {
             let mut guard = self.fd.writable().await?;
-            match guard.try_io(|inner| inner.get_ref().send(buf)) {
+            match guard.try_io(|inner| {
+                let _marking = self.marking.lock().unwrap();
+                inner.get_ref().send(buf)
+            }) {
                 Ok(res) => {
                     #[cfg(feature = "stats")]
                     if let Ok(size) = res {
                         let mut stats = self.stats.write().unwrap();
                         stats.transmitted_packets += 1;
                         stats.transmitted_bytes += size as u128;
+                        self.frame_sizes.write().unwrap().record(size);
                     }
                     return res;
                 }
@@ -292,13 +386,17 @@ impl Device {
     pub async fn send_vectored(&self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
         loop {
             let mut guard = self.fd.writable().await?;
-            match guard.try_io(|inner| inner.get_ref().sendv(bufs)) {
+            match guard.try_io(|inner| {
+                let _marking = self.marking.lock().unwrap();
+                inner.get_ref().sendv(bufs)
+            }) {
                 Ok(res) => {
                     #[cfg(feature = "stats")]
                     if let Ok(size) = res {
                         let mut stats = self.stats.write().unwrap();
                         stats.transmitted_packets += 1;
                         stats.transmitted_bytes += size as u128;
+                        self.frame_sizes.write().unwrap().record(size);
                     }
                     return res;
                 }
@@ -307,8 +405,133 @@ impl Device {
         }
     }
 
+    /// Sends a frame with its own socket priority (SO_PRIORITY), so it can
+    /// be queued ahead of frames sent at the socket default.
+    pub async fn send_vectored_with_priority(
+        &self,
+        bufs: &[IoSlice<'_>],
+        priority: u32,
+    ) -> io::Result<usize> {
+        loop {
+            let mut guard = self.fd.writable().await?;
+            match guard.try_io(|inner| {
+                let default = self.marking.lock().unwrap();
+                inner
+                    .get_ref()
+                    .sendv_with_priority(bufs, priority, *default)
+            }) {
+                Ok(res) => {
+                    #[cfg(feature = "stats")]
+                    if let Ok(size) = res {
+                        let mut stats = self.stats.write().unwrap();
+                        stats.transmitted_packets += 1;
+                        stats.transmitted_bytes += size as u128;
+                        self.frame_sizes.write().unwrap().record(size);
+                    }
+                    return res;
+                }
+                Err(_) => continue,
+            }
+        }
+    }
+
+    /// Only hand over received frames addressed to this device or to a
+    /// group address, as a shared medium delivers every frame to everyone.
+    pub fn set_destination_filter(&self, enabled: bool) {
+        self.destination_filter.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Frames dropped by the destination filter
+    pub fn filtered_frames(&self) -> u64 {
+        self.filtered.load(Ordering::Relaxed)
+    }
+
+    /// Sets the socket priority used by frames sent without their own.
+    pub fn set_priority(&self, priority: u32) -> io::Result<()> {
+        let mut default = self.marking.lock().unwrap();
+        self.fd.get_ref().set_priority(priority)?;
+        *default = priority;
+        Ok(())
+    }
+
+    pub fn priority(&self) -> io::Result<u32> {
+        self.fd.get_ref().priority()
+    }
+
     #[cfg(feature = "stats")]
     pub fn stats(&self) -> Stats {
         *self.stats.read().unwrap()
     }
+
+    /// Sizes of the frames received and transmitted on this device
+    #[cfg(feature = "stats")]
+    pub fn frame_sizes(&self) -> FrameSizeHistogram {
+        self.frame_sizes.read().unwrap().clone()
+    }
+
+    /// Replaces the frame size buckets, discarding the counts so far
+    #[cfg(feature = "stats")]
+    pub fn set_frame_size_buckets(&self, bounds: Vec<usize>) {
+        *self.frame_sizes.write().unwrap() = FrameSizeHistogram::new(bounds);
+    }
+
+    #[cfg(feature = "stats")]
+    pub fn record_tun_read_error(&self) {
+        self.stats.write().unwrap().tun_read_errors += 1;
+    }
+
+    #[cfg(feature = "stats")]
+    pub fn record_tun_write_error(&self) {
+        self.stats.write().unwrap().tun_write_errors += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{accepts_destination, Device};
+    use mac_address::MacAddress;
+    use std::io::IoSlice;
+
+    // Opening a packet socket needs CAP_NET_RAW, skip when it is missing.
+    fn loopback() -> Option<Device> {
+        Device::new("lo").ok()
+    }
+
+    #[tokio::test]
+    async fn socket_priority_reads_back() {
+        let Some(device) = loopback() else {
+            return;
+        };
+
+        device.set_priority(2).expect("set priority");
+        assert_eq!(device.priority().expect("get priority"), 2);
+
+        let frame = [0u8; 60];
+        device
+            .send_vectored_with_priority(&[IoSlice::new(&frame)], 6)
+            .await
+            .expect("sent frame");
+        assert_eq!(device.priority().expect("get priority"), 2);
+
+        device
+            .send_vectored_with_priority(&[IoSlice::new(&frame)], 2)
+            .await
+            .expect("sent frame at the default priority");
+        assert_eq!(device.priority().expect("get priority"), 2);
+    }
+
+    #[test]
+    fn frames_for_other_macs_are_filtered() {
+        let own = MacAddress::new([2; 6]);
+        let frame = |to: [u8; 6]| [to, [9; 6]].concat();
+
+        assert!(accepts_destination(own, &frame([2; 6])));
+        assert!(accepts_destination(own, &frame([0xff; 6])));
+        assert!(accepts_destination(
+            own,
+            &frame([0x01, 0x00, 0x5e, 0, 0, 1])
+        ));
+        assert!(!accepts_destination(own, &frame([4; 6])));
+        assert!(!accepts_destination(own, &[2; 5]));
+    }
 }
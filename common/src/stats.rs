@@ -6,4 +6,71 @@ pub struct Stats {
     pub received_bytes: u128,
     pub transmitted_packets: u128,
     pub transmitted_bytes: u128,
+    #[serde(default)]
+    pub tun_read_errors: u128,
+    #[serde(default)]
+    pub tun_write_errors: u128,
+}
+
+/// Upper bounds, in bytes, of the frame size buckets used unless configured
+pub const DEFAULT_FRAME_SIZE_BUCKETS: [usize; 6] = [64, 128, 256, 512, 1024, 1518];
+
+/// Number of frames seen per size bucket. Bucket `i` counts frames of at most
+/// `bounds[i]` bytes not counted by a previous bucket; the last count holds
+/// frames larger than every bound.
+#[derive(Serialize, Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct FrameSizeHistogram {
+    bounds: Vec<usize>,
+    counts: Vec<u128>,
+}
+
+impl FrameSizeHistogram {
+    pub fn new(mut bounds: Vec<usize>) -> Self {
+        bounds.sort_unstable();
+        bounds.dedup();
+        let counts = vec![0; bounds.len() + 1];
+        Self { bounds, counts }
+    }
+
+    pub fn record(&mut self, size: usize) {
+        let bucket = self.bounds.partition_point(|bound| *bound < size);
+        self.counts[bucket] += 1;
+    }
+
+    pub fn bounds(&self) -> &[usize] {
+        &self.bounds
+    }
+
+    pub fn counts(&self) -> &[u128] {
+        &self.counts
+    }
+}
+
+impl Default for FrameSizeHistogram {
+    fn default() -> Self {
+        Self::new(DEFAULT_FRAME_SIZE_BUCKETS.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FrameSizeHistogram;
+
+    #[test]
+    fn frames_are_counted_in_their_bucket() {
+        let mut histogram = FrameSizeHistogram::new(vec![1500, 64, 512, 64]);
+        assert_eq!(histogram.bounds(), [64, 512, 1500]);
+
+        for size in [0, 64, 65, 512, 600, 1500, 1501, 9000] {
+            histogram.record(size);
+        }
+        assert_eq!(histogram.counts(), [2, 2, 2, 2]);
+    }
+
+    #[test]
+    fn histogram_without_bounds_has_a_single_bucket() {
+        let mut histogram = FrameSizeHistogram::new(vec![]);
+        histogram.record(42);
+        assert_eq!(histogram.counts(), [1]);
+    }
 }
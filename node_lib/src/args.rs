@@ -1,4 +1,13 @@
+use crate::{
+    control::{
+        fragment::FRAGMENT_HEADER_LEN,
+        node::{DATA_OVERHEAD, ETHERNET_HEADER_LEN},
+    },
+    error::ConfigError,
+    position::Position,
+};
 use clap::{Parser, ValueEnum};
+use mac_address::MacAddress;
 use std::net::Ipv4Addr;
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
@@ -7,6 +16,23 @@ pub enum NodeType {
     Obu,
 }
 
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
+pub enum RouteMetric {
+    Latency,
+    Geographic,
+    /// Latency penalized by its standard deviation
+    LowJitter,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
+pub enum UpstreamMode {
+    /// Every frame goes through the selected upstream
+    Single,
+    /// Flows are spread across every upstream as few hops away from an RSU
+    /// as the selected one
+    Ecmp,
+}
+
 #[derive(clap::Args, Clone, Debug)]
 #[group(required = true, multiple = false)]
 pub struct NodeParameters {
@@ -21,6 +47,154 @@ pub struct NodeParameters {
     /// Hello periodicity
     #[arg(short, long)]
     pub hello_periodicity: Option<u32>,
+
+    /// RSU only: milliseconds to wait before sending the first heartbeat
+    #[arg(long)]
+    pub startup_delay: Option<u32>,
+
+    /// RSU only: adapt the hello periodicity to routing churn, never below this many milliseconds
+    #[arg(long)]
+    pub adaptive_hello_min: Option<u32>,
+
+    /// RSU only: with `adaptive_hello_min`, never adapt the hello periodicity above this many milliseconds
+    #[arg(long)]
+    pub adaptive_hello_max: Option<u32>,
+
+    /// Probability of dropping a frame received on the wire (0.0-1.0)
+    #[arg(long, default_value_t = 0.0)]
+    pub ingress_loss: f64,
+
+    /// Seed for the ingress loss generator
+    #[arg(long)]
+    pub ingress_loss_seed: Option<u64>,
+
+    /// Geographic position advertised in heartbeats (lat,lon)
+    #[arg(long)]
+    pub position: Option<Position>,
+
+    /// Metric used to select routes
+    #[arg(long, value_enum, default_value_t = RouteMetric::Latency)]
+    pub route_metric: RouteMetric,
+
+    /// RSU only: metric used to select routes for management traffic, `route_metric` if unset
+    #[arg(long, value_enum)]
+    pub management_route_metric: Option<RouteMetric>,
+
+    /// Tag packets from the TAP with a CS6 or CS7 DSCP as management traffic
+    #[arg(long, default_value_t = false)]
+    pub tag_management_traffic: bool,
+
+    /// OBU only: how frames sent upstream are spread across next hops
+    #[arg(long, value_enum, default_value_t = UpstreamMode::Single)]
+    pub upstream_mode: UpstreamMode,
+
+    /// Log upstream changes, at most once per this many milliseconds
+    #[arg(long)]
+    pub upstream_change_log_interval: Option<u32>,
+
+    /// Use this node's MAC as the source of forwarded frames
+    #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
+    pub rewrite_source_mac: bool,
+
+    /// Drop forwarded frames larger than the MTU learned for their path
+    #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
+    pub clamp_path_mtu: bool,
+
+    /// MACs never selected as upstream or next hop (comma separated)
+    #[arg(long, value_delimiter = ',')]
+    pub upstream_blacklist: Vec<MacAddress>,
+
+    /// OBU only: drop heartbeats from every RSU but this one
+    #[arg(long)]
+    pub only_rsu: Option<MacAddress>,
+
+    /// Socket priority (SO_PRIORITY) for control frames such as heartbeats
+    #[arg(long)]
+    pub control_priority: Option<u32>,
+
+    /// Priority of data frames entering from the TAP, higher is forwarded first
+    #[arg(long, default_value_t = 0)]
+    pub data_priority: u8,
+
+    /// Drop heartbeat replies already forwarded upstream within this many milliseconds
+    #[arg(long)]
+    pub reply_dedup_window: Option<u32>,
+
+    /// OBU only: reject heartbeats generated more than this many milliseconds ago, or stamped as far ahead
+    #[arg(long)]
+    pub heartbeat_max_age: Option<u32>,
+
+    /// OBU only: send an empty upstream frame after this many milliseconds without TAP traffic
+    #[arg(long)]
+    pub keepalive_interval: Option<u32>,
+
+    /// OBU only: ignore new heartbeats from a source sooner than this many milliseconds after the last
+    #[arg(long)]
+    pub min_heartbeat_interval: Option<u32>,
+
+    /// OBU only: reply at most once per this many milliseconds to heartbeats of RSUs further than the nearest one
+    #[arg(long)]
+    pub reply_suppression_interval: Option<u32>,
+
+    /// OBU only: extra hops charged to a next hop each time it stopped being the upstream
+    #[arg(long, default_value_t = 0)]
+    pub flap_penalty: u32,
+
+    /// Milliseconds over which a flap penalty decays to zero
+    #[arg(long, default_value_t = 30000)]
+    pub flap_decay: u32,
+
+    /// RSU only: clients remembered behind their node, the least recently used is forgotten first
+    #[arg(long, default_value_t = 4096)]
+    pub client_cache_capacity: u32,
+
+    /// RSU only: milliseconds after which a client not seen again is forgotten
+    #[arg(long)]
+    pub client_ttl: Option<u32>,
+
+    /// OBU only: keep the upstream until a candidate is more than this many hops closer to an RSU
+    #[arg(long)]
+    pub handover_margin: Option<u32>,
+
+    /// OBU only: heartbeats in a row a candidate must beat the upstream by the handover margin
+    #[arg(long, default_value_t = 1)]
+    pub handover_observations: u32,
+
+    /// OBU only: receive and learn routes without transmitting anything
+    #[arg(long, default_value_t = false)]
+    pub passive: bool,
+
+    /// Let a passive OBU answer heartbeats so it can be discovered
+    #[arg(long, default_value_t = false)]
+    pub passive_replies: bool,
+
+    /// RSU only: drop upstream frames sent from a group address or to the null address
+    #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
+    pub drop_malformed_frames: bool,
+
+    /// Drop data frames carrying at most an ethernet header instead of forwarding them
+    #[arg(long, default_value_t = true, action = clap::ArgAction::Set)]
+    pub drop_header_only_frames: bool,
+
+    /// Split data frames larger than this many bytes into fragments, reassembled at their destination
+    #[arg(long)]
+    pub fragment_size: Option<u32>,
+
+    /// Milliseconds after which a frame still missing fragments is discarded
+    #[arg(long, default_value_t = 5000)]
+    pub fragment_timeout: u32,
+
+    /// Compress data frames with LZ4 before sending them, leaving small or incompressible ones as they are
+    #[arg(long, default_value_t = false, action = clap::ArgAction::Set)]
+    pub compress: bool,
+
+    /// Forget routes learned from heartbeats older than this many milliseconds
+    #[arg(long)]
+    pub route_ttl: Option<u32>,
+
+    /// Frames waiting in the wire receive queue above which a warning is logged
+    #[arg(long)]
+    pub receive_queue_high_watermark: Option<u32>,
 }
 
 #[derive(Parser, Debug, Clone)]
@@ -42,7 +216,225 @@ pub struct Args {
     #[arg(short, long, default_value_t = 1459)]
     pub mtu: i32,
 
+    /// MTU of the interface bound to, advertised in heartbeats so data
+    /// messages are held to the smallest one on their path
+    #[arg(long, default_value_t = 1500)]
+    pub wire_mtu: u16,
+
+    /// TAP read buffer size, defaults to the MTU plus the Ethernet header
+    #[arg(long)]
+    pub tap_buffer_size: Option<usize>,
+
+    /// Retries for sends failing with a transient error
+    #[arg(long, default_value_t = 3)]
+    pub send_retries: u32,
+
+    /// Drop frames received for other MACs before processing them
+    #[arg(long, default_value_t = false)]
+    pub filter_destination: bool,
+
+    /// File with routes to use until heartbeats confirm them
+    #[arg(long)]
+    pub warm_routes: Option<String>,
+
+    /// Write every frame sent or received on the wire to this pcap file
+    #[arg(long)]
+    pub capture_path: Option<String>,
+
     /// Node Parameters
     #[command(flatten)]
     pub node_params: NodeParameters,
 }
+
+impl Args {
+    /// Checks every invariant creating a node relies on, without side
+    /// effects, reporting all the problems found rather than the first
+    pub fn validate(&self) -> Result<(), Vec<ConfigError>> {
+        let params = &self.node_params;
+        let mut errors = Vec::new();
+        if self.bind.is_empty() {
+            errors.push(ConfigError::EmptyBind);
+        }
+
+        match usize::try_from(self.mtu) {
+            Ok(mtu) if mtu > 0 && u16::try_from(mtu).is_ok() => {
+                let frame = mtu + ETHERNET_HEADER_LEN;
+                if let Some(size) = self.tap_buffer_size.filter(|size| *size < frame) {
+                    errors.push(ConfigError::TapBufferTooSmall { size, frame });
+                }
+                let wire_frame = usize::from(self.wire_mtu) + ETHERNET_HEADER_LEN;
+                if params.fragment_size.is_none() && frame + DATA_OVERHEAD > wire_frame {
+                    errors.push(ConfigError::FrameAboveWireMtu {
+                        frame,
+                        wire_mtu: self.wire_mtu,
+                    });
+                }
+            }
+            _ => errors.push(ConfigError::InvalidMtu(self.mtu)),
+        }
+
+        // Fragments are carried in data messages, which must fit the wire
+        let max = (usize::from(self.wire_mtu) + ETHERNET_HEADER_LEN).saturating_sub(DATA_OVERHEAD);
+        if let Some(size) = params.fragment_size.filter(|size| *size as usize > max) {
+            errors.push(ConfigError::FragmentSizeAboveMtu { size, max });
+        }
+
+        if let Some(size) = params
+            .fragment_size
+            .filter(|size| *size as usize <= FRAGMENT_HEADER_LEN)
+        {
+            errors.push(ConfigError::FragmentSizeTooSmall(size));
+        }
+
+        if params.hello_history == 0 {
+            errors.push(ConfigError::NoHelloHistory);
+        }
+
+        if params.node_type == NodeType::Rsu && params.hello_periodicity.is_none() {
+            errors.push(ConfigError::MissingHelloPeriodicity);
+        }
+
+        if let (Some(min), Some(max)) = (params.adaptive_hello_min, params.adaptive_hello_max) {
+            if min > max {
+                errors.push(ConfigError::AdaptiveHelloRange { min, max });
+            }
+        }
+
+        if !(0.0..=1.0).contains(&params.ingress_loss) {
+            errors.push(ConfigError::InvalidIngressLoss(params.ingress_loss));
+        }
+
+        if params.handover_observations == 0 {
+            errors.push(ConfigError::NoHandoverObservations);
+        }
+
+        if params.client_cache_capacity == 0 {
+            errors.push(ConfigError::NoClientCacheCapacity);
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NodeType;
+    use crate::{error::ConfigError, test_helpers};
+
+    #[test]
+    fn valid_configs_pass() {
+        for node_type in [NodeType::Rsu, NodeType::Obu] {
+            let mut args = test_helpers::args(node_type);
+            args.bind = "eth0".to_string();
+            args.node_params.hello_periodicity = Some(5000);
+            assert_eq!(args.validate(), Ok(()));
+        }
+    }
+
+    #[test]
+    fn rsu_without_hello_periodicity_is_rejected() {
+        let mut args = test_helpers::args(NodeType::Rsu);
+        args.bind = "eth0".to_string();
+
+        assert_eq!(
+            args.validate(),
+            Err(vec![ConfigError::MissingHelloPeriodicity])
+        );
+    }
+
+    #[test]
+    fn every_problem_is_reported() {
+        let mut args = test_helpers::args(NodeType::Obu);
+        args.mtu = 0;
+        args.node_params.adaptive_hello_min = Some(200);
+        args.node_params.adaptive_hello_max = Some(100);
+
+        assert_eq!(
+            args.validate(),
+            Err(vec![
+                ConfigError::EmptyBind,
+                ConfigError::InvalidMtu(0),
+                ConfigError::AdaptiveHelloRange { min: 200, max: 100 },
+            ])
+        );
+
+        args.bind = "eth0".to_string();
+        args.mtu = 1459;
+        args.tap_buffer_size = Some(1459);
+        args.node_params.adaptive_hello_max = None;
+        args.node_params.hello_history = 0;
+        assert_eq!(
+            args.validate(),
+            Err(vec![
+                ConfigError::TapBufferTooSmall {
+                    size: 1459,
+                    frame: 1473
+                },
+                ConfigError::NoHelloHistory,
+            ])
+        );
+    }
+
+    #[test]
+    fn invariants_of_later_options_are_checked() {
+        let mut args = test_helpers::args(NodeType::Obu);
+        args.bind = "eth0".to_string();
+        args.node_params.handover_observations = 0;
+        args.node_params.client_cache_capacity = 0;
+        args.node_params.fragment_size = Some(18);
+        assert_eq!(
+            args.validate(),
+            Err(vec![
+                ConfigError::FragmentSizeTooSmall(18),
+                ConfigError::NoHandoverObservations,
+                ConfigError::NoClientCacheCapacity,
+            ])
+        );
+
+        args.node_params.handover_observations = 1;
+        args.node_params.client_cache_capacity = 1;
+        args.node_params.fragment_size = Some(1481);
+        assert_eq!(
+            args.validate(),
+            Err(vec![ConfigError::FragmentSizeAboveMtu {
+                size: 1481,
+                max: 1480
+            }])
+        );
+
+        args.node_params.fragment_size = Some(1480);
+        assert_eq!(args.validate(), Ok(()));
+
+        args.node_params.fragment_size = Some(19);
+        assert_eq!(args.validate(), Ok(()));
+    }
+
+    #[test]
+    fn frames_too_large_for_the_wire_need_fragmentation() {
+        let mut args = test_helpers::args(NodeType::Obu);
+        args.bind = "eth0".to_string();
+        args.mtu = 9000;
+        args.tap_buffer_size = Some(9014);
+        assert_eq!(
+            args.validate(),
+            Err(vec![ConfigError::FrameAboveWireMtu {
+                frame: 9014,
+                wire_mtu: 1500
+            }])
+        );
+
+        // 34 bytes of data headers must fit alongside a full frame
+        args.mtu = 1466;
+        assert_eq!(args.validate(), Ok(()));
+        args.mtu = 1467;
+        assert!(args.validate().is_err());
+
+        args.mtu = 9000;
+        args.node_params.fragment_size = Some(1400);
+        assert_eq!(args.validate(), Ok(()));
+    }
+}
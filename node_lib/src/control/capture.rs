@@ -0,0 +1,118 @@
+use anyhow::Result;
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    sync::mpsc::{self, Receiver, Sender},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Link type of frames carrying an Ethernet header
+const LINKTYPE_ETHERNET: u32 = 1;
+
+/// Longest frame recorded, longer ones are truncated
+const SNAPLEN: u32 = 65535;
+
+/// Writes frames sent and received on the wire to a pcap file, so they can
+/// be inspected with Wireshark. Records are written by a thread of its own
+/// so the tasks handling traffic never wait on the file.
+#[derive(Debug)]
+pub struct Capture {
+    records: Sender<Vec<u8>>,
+}
+
+impl Capture {
+    pub fn create(path: &str) -> Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+        let header = [
+            &0xa1b2_c3d4_u32.to_le_bytes()[..],
+            &2u16.to_le_bytes(),
+            &4u16.to_le_bytes(),
+            &0i32.to_le_bytes(),
+            &0u32.to_le_bytes(),
+            &SNAPLEN.to_le_bytes(),
+            &LINKTYPE_ETHERNET.to_le_bytes(),
+        ]
+        .concat();
+        file.write_all(&header)?;
+        file.flush()?;
+        let (records, rx) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("capture".to_string())
+            .spawn(move || write_records(file, rx))?;
+        tracing::info!(path, "capturing wire traffic");
+        Ok(Self { records })
+    }
+
+    /// Records a frame sent or received now
+    pub fn record(&self, frame: &[u8]) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        self.record_at(frame, now);
+    }
+
+    fn record_at(&self, frame: &[u8], timestamp: Duration) {
+        let len = u32::try_from(frame.len()).unwrap_or(u32::MAX);
+        let captured = &frame[..frame.len().min(SNAPLEN as usize)];
+        let record = [
+            &u32::try_from(timestamp.as_secs())
+                .unwrap_or(u32::MAX)
+                .to_le_bytes()[..],
+            &timestamp.subsec_micros().to_le_bytes(),
+            &(captured.len() as u32).to_le_bytes(),
+            &len.to_le_bytes(),
+            captured,
+        ]
+        .concat();
+        let _ = self.records.send(record);
+    }
+}
+
+/// Writes records until every sender is gone, flushing whenever none are
+/// waiting so the file stays current without a syscall per frame.
+fn write_records(mut file: BufWriter<File>, records: Receiver<Vec<u8>>) {
+    while let Ok(record) = records.recv() {
+        let written = std::iter::once(record)
+            .chain(records.try_iter())
+            .try_for_each(|record| file.write_all(&record))
+            .and_then(|()| file.flush());
+        if let Err(e) = written {
+            tracing::warn!(?e, "error writing capture");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Capture;
+    use crate::test_helpers;
+    use std::time::Duration;
+
+    #[test]
+    fn frames_are_written_as_pcap_records() {
+        let path = std::env::temp_dir().join(format!("capture_{}.pcap", std::process::id()));
+        let path = path.to_string_lossy().to_string();
+        let capture = Capture::create(&path).expect("created capture");
+        let frame = [&[255; 6][..], &[2; 6], &[0x30, 0x30], &[7; 20]].concat();
+        capture.record_at(&frame, Duration::new(1_700_000_000, 250_000_000));
+        capture.record(&frame[..14]);
+
+        let records = test_helpers::capture_records(&path, 2);
+        let pcap = std::fs::read(&path).expect("read capture");
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(pcap.len(), 24 + (16 + 34) + (16 + 14));
+        assert_eq!(pcap[0..4], 0xa1b2_c3d4_u32.to_le_bytes());
+        assert_eq!(pcap[20..24], 1u32.to_le_bytes());
+
+        let [first, second] = &records[..] else {
+            panic!("{} records captured", records.len());
+        };
+        assert_eq!(first[0..4], 1_700_000_000u32.to_le_bytes());
+        assert_eq!(first[4..8], 250_000u32.to_le_bytes());
+        assert_eq!(first[8..12], 34u32.to_le_bytes());
+        assert_eq!(first[12..16], 34u32.to_le_bytes());
+        assert_eq!(first[16..], frame[..]);
+        assert_eq!(second[8..12], 14u32.to_le_bytes());
+        assert_eq!(second[16..], frame[..14]);
+    }
+}
@@ -1,18 +1,183 @@
-use std::{collections::HashMap, sync::RwLock};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        RwLock,
+    },
+    time::{Duration, Instant},
+};
 
 use mac_address::MacAddress;
 
-#[derive(Default)]
+#[derive(Debug)]
+struct Entry {
+    node: MacAddress,
+    stored_at: Instant,
+    /// Tick of the last store or lookup of the client
+    used: AtomicU64,
+}
+
+/// Node each client was last seen behind. Holds at most `capacity` clients,
+/// evicting the least recently used one to make room for a new client.
+/// Clients not seen again within the TTL are forgotten, so one that roamed
+/// away is not routed to its old node forever.
+///
+/// Lookups only take a read lock and mark the client used in place; the
+/// least recently used client is searched for when a new one does not fit.
 pub struct ClientCache {
-    cache: RwLock<HashMap<MacAddress, MacAddress>>,
+    capacity: usize,
+    ttl: Option<Duration>,
+    clock: AtomicU64,
+    cache: RwLock<HashMap<MacAddress, Entry>>,
 }
 
 impl ClientCache {
+    pub fn new(capacity: usize, ttl: Option<Duration>) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            ttl,
+            clock: AtomicU64::new(0),
+            cache: RwLock::default(),
+        }
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn expired(&self, entry: &Entry, now: Instant) -> bool {
+        self.ttl
+            .is_some_and(|ttl| now.saturating_duration_since(entry.stored_at) > ttl)
+    }
+
     pub fn store_mac(&self, client: MacAddress, node: MacAddress) {
-        self.cache.write().unwrap().insert(client, node);
+        self.store_mac_at(client, node, Instant::now());
+    }
+
+    fn store_mac_at(&self, client: MacAddress, node: MacAddress, now: Instant) {
+        let entry = Entry {
+            node,
+            stored_at: now,
+            used: AtomicU64::new(self.tick()),
+        };
+        let mut cache = self.cache.write().unwrap();
+        if !cache.contains_key(&client) && cache.len() >= self.capacity {
+            let evicted = cache
+                .iter()
+                .min_by_key(|(_, entry)| {
+                    (
+                        !self.expired(entry, now),
+                        entry.used.load(Ordering::Relaxed),
+                    )
+                })
+                .map(|(client, _)| *client);
+            if let Some(evicted) = evicted {
+                cache.remove(&evicted);
+                tracing::trace!(%evicted, "client evicted from cache");
+            }
+        }
+        cache.insert(client, entry);
     }
 
     pub fn get(&self, client: MacAddress) -> Option<MacAddress> {
-        self.cache.read().unwrap().get(&client).copied()
+        self.get_at(client, Instant::now())
+    }
+
+    fn get_at(&self, client: MacAddress, now: Instant) -> Option<MacAddress> {
+        {
+            let cache = self.cache.read().unwrap();
+            let entry = cache.get(&client)?;
+            if !self.expired(entry, now) {
+                entry.used.store(self.tick(), Ordering::Relaxed);
+                return Some(entry.node);
+            }
+        }
+
+        let mut cache = self.cache.write().unwrap();
+        if let Some(entry) = cache.get(&client).filter(|entry| self.expired(entry, now)) {
+            tracing::trace!(%client, node = %entry.node, "cached client expired");
+            cache.remove(&client);
+        }
+        None
+    }
+
+    pub fn len(&self) -> usize {
+        self.cache.read().unwrap().len()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ClientCache;
+    use mac_address::MacAddress;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn least_recently_used_client_is_evicted() {
+        let node = MacAddress::new([9; 6]);
+        let client = |id: u8| MacAddress::new([id; 6]);
+        let cache = ClientCache::new(3, None);
+        for id in 1..=3 {
+            cache.store_mac(client(id), node);
+        }
+
+        assert_eq!(cache.get(client(1)), Some(node));
+        cache.store_mac(client(2), node);
+        cache.store_mac(client(4), node);
+
+        assert_eq!(cache.len(), cache.capacity());
+        assert_eq!(cache.get(client(3)), None);
+        for id in [1, 2, 4] {
+            assert_eq!(cache.get(client(id)), Some(node));
+        }
+
+        cache.store_mac(client(5), node);
+        assert_eq!(cache.get(client(1)), None);
+        assert_eq!(cache.len(), 3);
+    }
+
+    #[test]
+    fn clients_expire_after_their_ttl() {
+        let (client, node) = (MacAddress::new([1; 6]), MacAddress::new([9; 6]));
+        let cache = ClientCache::new(16, Some(Duration::from_secs(30)));
+        let start = Instant::now();
+        cache.store_mac_at(client, node, start);
+
+        assert_eq!(
+            cache.get_at(client, start + Duration::from_secs(30)),
+            Some(node)
+        );
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.get_at(client, start + Duration::from_secs(31)), None);
+        assert_eq!(cache.len(), 0);
+
+        cache.store_mac_at(client, node, start + Duration::from_secs(40));
+        assert_eq!(
+            cache.get_at(client, start + Duration::from_secs(60)),
+            Some(node)
+        );
+    }
+
+    #[test]
+    fn expired_clients_are_evicted_first() {
+        let node = MacAddress::new([9; 6]);
+        let client = |id: u8| MacAddress::new([id; 6]);
+        let cache = ClientCache::new(2, Some(Duration::from_secs(30)));
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        cache.store_mac_at(client(1), node, at(0));
+        cache.store_mac_at(client(2), node, at(20));
+        assert_eq!(cache.get_at(client(1), at(25)), Some(node));
+
+        // The first client was used last but has expired
+        cache.store_mac_at(client(3), node, at(35));
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get_at(client(2), at(35)), Some(node));
+        assert_eq!(cache.get_at(client(3), at(35)), Some(node));
     }
 }
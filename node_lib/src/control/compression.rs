@@ -0,0 +1,106 @@
+use anyhow::{bail, Result};
+
+/// Frames shorter than this are sent as they are, too small to gain from
+/// compression
+const MIN_COMPRESSED_LEN: usize = 128;
+
+/// Largest frame decompressed, so a bogus length cannot reserve arbitrary
+/// memory
+const MAX_FRAME_LEN: usize = 1 << 16;
+
+/// Destination and source addresses, left uncompressed so relays hash the
+/// flow of a compressed frame as they would the frame itself
+const ADDRESSES_LEN: usize = 12;
+
+/// Compresses data frames with LZ4 before they are sent, when enabled
+#[derive(Debug)]
+pub struct Compressor {
+    enabled: bool,
+}
+
+impl Compressor {
+    pub fn new(enabled: bool) -> Self {
+        Self { enabled }
+    }
+
+    /// `frame` past its addresses compressed and prefixed with its length,
+    /// unless compression is disabled, the frame is small or compressing it
+    /// would not shrink it
+    pub fn compress(&self, frame: &[u8]) -> Option<Vec<u8>> {
+        if !self.enabled || frame.len() < MIN_COMPRESSED_LEN {
+            return None;
+        }
+
+        let (addresses, rest) = frame.split_at(ADDRESSES_LEN);
+        let len = u32::try_from(rest.len()).ok()?;
+        let compressed = [addresses, &len.to_be_bytes()[..], &lz4_flex::compress(rest)].concat();
+        (compressed.len() < frame.len()).then_some(compressed)
+    }
+}
+
+/// Frame carried compressed in `data`
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>> {
+    let (Some(addresses), Some(len), Some(compressed)) = (
+        data.get(..ADDRESSES_LEN),
+        data.get(ADDRESSES_LEN..ADDRESSES_LEN + 4),
+        data.get(ADDRESSES_LEN + 4..),
+    ) else {
+        bail!("compressed frame without a length");
+    };
+    let len = usize::try_from(u32::from_be_bytes(len.try_into()?))?;
+    if len > MAX_FRAME_LEN {
+        bail!("compressed frame of {len} bytes too large");
+    }
+
+    Ok([addresses, &lz4_flex::decompress(compressed, len)?].concat())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decompress, Compressor};
+    use crate::{
+        control::node,
+        messages::{
+            data::{Data, ToUpstream},
+            message::Message,
+            packet_type::PacketType,
+        },
+    };
+    use rand::{rngs::StdRng, RngCore, SeedableRng};
+
+    fn wire_len(data: &[u8], compressed: bool) -> usize {
+        let msg = Message::new(
+            [1; 6].into(),
+            [2; 6].into(),
+            PacketType::Data(Data::Upstream(
+                ToUpstream::new([1; 6].into(), data).with_compressed(compressed),
+            )),
+        );
+        Vec::<Vec<u8>>::from(&msg).concat().len()
+    }
+
+    #[test]
+    fn compressible_frames_shrink_on_the_wire() {
+        let frame: Vec<u8> = (0..2048).map(|x| (x % 16) as u8).collect();
+        let compressed = Compressor::new(true)
+            .compress(&frame)
+            .expect("frame compressed");
+
+        assert!(wire_len(&compressed, true) < wire_len(&frame, false) / 4);
+        assert_eq!(decompress(&compressed).expect("decompressed"), frame);
+        assert_eq!(node::flow_hash(&compressed), node::flow_hash(&frame));
+    }
+
+    #[test]
+    fn incompressible_and_small_frames_are_sent_as_they_are() {
+        let mut frame = vec![0u8; 2048];
+        StdRng::seed_from_u64(7).fill_bytes(&mut frame);
+        let compressor = Compressor::new(true);
+
+        assert!(compressor.compress(&frame).is_none());
+        assert!(compressor.compress(&[0; 64]).is_none());
+        assert!(Compressor::new(false).compress(&[0; 2048]).is_none());
+        assert!(decompress(&[0xff; 8]).is_err());
+        assert!(decompress(&[0xff; 20]).is_err());
+    }
+}
@@ -0,0 +1,106 @@
+use crate::control::node::ReplyType;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Frames a node moved through its wire device and its TAP, as exposed in
+/// metrics
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct NodeCounters {
+    pub wire_received: u64,
+    pub wire_sent: u64,
+    pub tap_received: u64,
+    pub tap_sent: u64,
+}
+
+/// Counts frames a node receives and sends on the wire and on the TAP.
+#[derive(Debug, Default)]
+pub struct PacketCounters {
+    wire_received: AtomicU64,
+    wire_sent: AtomicU64,
+    tap_received: AtomicU64,
+    tap_sent: AtomicU64,
+}
+
+impl PacketCounters {
+    pub fn wire_received(&self) {
+        self.wire_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn tap_received(&self) {
+        self.tap_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts every frame about to be sent in reply
+    pub fn record_replies(&self, replies: &[ReplyType]) {
+        let wire = replies
+            .iter()
+            .filter(|reply| matches!(reply, ReplyType::Wire(_)))
+            .count() as u64;
+        self.wire_sent.fetch_add(wire, Ordering::Relaxed);
+        self.tap_sent
+            .fetch_add(replies.len() as u64 - wire, Ordering::Relaxed);
+    }
+
+    pub fn counts(&self) -> NodeCounters {
+        NodeCounters {
+            wire_received: self.wire_received.load(Ordering::Relaxed),
+            wire_sent: self.wire_sent.load(Ordering::Relaxed),
+            tap_received: self.tap_received.load(Ordering::Relaxed),
+            tap_sent: self.tap_sent.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NodeCounters, PacketCounters};
+    use crate::{
+        args::NodeType,
+        control::{node::ReplyType, obu::Routing},
+        messages::{
+            control::{heartbeat::Heartbeat, Control},
+            message::Message,
+            packet_type::PacketType,
+        },
+        test_helpers,
+    };
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn frames_in_and_out_are_counted() {
+        let counters = PacketCounters::default();
+        let mut routing =
+            Routing::new(&test_helpers::args(NodeType::Obu), &Instant::now()).expect("routing");
+        let msg = Message::new(
+            [1; 6].into(),
+            [255; 6].into(),
+            PacketType::Control(Control::Heartbeat(Heartbeat::new(
+                Duration::default(),
+                0,
+                [1; 6].into(),
+            ))),
+        );
+
+        counters.wire_received();
+        let replies = routing
+            .handle_heartbeat(&msg, [2; 6].into())
+            .expect("handled heartbeat")
+            .expect("heartbeat is forwarded and answered");
+        counters.record_replies(&replies);
+        let wire_sent = replies.len() as u64;
+        assert!(wire_sent > 0);
+
+        counters.tap_received();
+        counters.record_replies(&[ReplyType::Tap(vec![vec![0; 64]])]);
+
+        assert_eq!(
+            counters.counts(),
+            NodeCounters {
+                wire_received: 1,
+                wire_sent,
+                tap_received: 1,
+                tap_sent: 1,
+            }
+        );
+    }
+}
@@ -0,0 +1,115 @@
+use mac_address::MacAddress;
+use std::{
+    collections::VecDeque,
+    fmt::{Display, Formatter, Result},
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Recent drops kept per node, older ones are forgotten
+const DROP_LOG_CAPACITY: usize = 128;
+
+/// Why a node dropped a data frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropReason {
+    /// No route towards the destination, or no upstream
+    NoRoute,
+    /// Larger than the MTU learned for its path
+    PathMtu,
+    /// Ethernet addresses rejected as malformed
+    Malformed,
+    /// Nothing past the ethernet header
+    HeaderOnly,
+}
+
+impl Display for DropReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        f.write_str(match self {
+            DropReason::NoRoute => "no_route",
+            DropReason::PathMtu => "path_mtu",
+            DropReason::Malformed => "malformed",
+            DropReason::HeaderOnly => "header_only",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DropRecord {
+    pub reason: DropReason,
+    pub source: Option<MacAddress>,
+    pub destination: Option<MacAddress>,
+    /// Wall clock time of the drop, since the Unix epoch
+    pub at: Duration,
+}
+
+/// Bounded log of the data frames a node dropped most recently, for
+/// debugging after the fact.
+#[derive(Debug, Default)]
+pub struct DropLog {
+    records: Mutex<VecDeque<DropRecord>>,
+}
+
+impl DropLog {
+    pub fn record(
+        &self,
+        reason: DropReason,
+        source: Option<MacAddress>,
+        destination: Option<MacAddress>,
+    ) {
+        tracing::trace!(%reason, ?source, ?destination, "data frame dropped");
+        let mut records = self.records.lock().unwrap();
+        if records.len() == DROP_LOG_CAPACITY {
+            records.pop_front();
+        }
+        records.push_back(DropRecord {
+            reason,
+            source,
+            destination,
+            at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default(),
+        });
+    }
+
+    /// Up to `limit` drops, most recent first
+    pub fn recent(&self, limit: usize) -> Vec<DropRecord> {
+        self.records
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .take(limit)
+            .copied()
+            .collect()
+    }
+}
+
+/// MAC address held in the first six bytes of `bytes`, if any
+pub fn mac_of(bytes: &[u8]) -> Option<MacAddress> {
+    let mac: [u8; 6] = bytes.get(0..6)?.try_into().ok()?;
+    Some(mac.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DropLog, DropReason, DROP_LOG_CAPACITY};
+    use mac_address::MacAddress;
+
+    #[test]
+    fn recent_drops_are_bounded_and_newest_first() {
+        let drops = DropLog::default();
+        for byte in 0..=u8::try_from(DROP_LOG_CAPACITY).unwrap() {
+            drops.record(DropReason::PathMtu, Some(MacAddress::new([byte; 6])), None);
+        }
+
+        let recent = drops.recent(usize::MAX);
+        assert_eq!(recent.len(), DROP_LOG_CAPACITY);
+        assert_eq!(recent[0].source, Some(MacAddress::new([128; 6])));
+        assert_eq!(
+            recent[DROP_LOG_CAPACITY - 1].source,
+            Some(MacAddress::new([1; 6]))
+        );
+        assert_eq!(drops.recent(2).len(), 2);
+        assert_eq!(DropReason::PathMtu.to_string(), "path_mtu");
+    }
+}
@@ -0,0 +1,397 @@
+use mac_address::MacAddress;
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Fragmenting node, then frame id, offset of the fragment into the frame
+/// and frame length, all big endian u32
+pub const FRAGMENT_HEADER_LEN: usize = 18;
+
+/// Largest frame reassembled, so a bogus length cannot reserve arbitrary
+/// memory
+const MAX_FRAME_LEN: usize = 1 << 16;
+
+/// Most frames reassembled at once, and most bytes they may take together.
+/// The oldest frame is discarded to make room for a new one.
+const MAX_PENDING_FRAMES: usize = 64;
+const MAX_PENDING_BYTES: usize = 1 << 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FragmentHeader {
+    node: MacAddress,
+    id: u32,
+    offset: u32,
+    total: u32,
+}
+
+impl FragmentHeader {
+    fn to_bytes(self) -> [u8; FRAGMENT_HEADER_LEN] {
+        let mut bytes = [0; FRAGMENT_HEADER_LEN];
+        bytes[0..6].copy_from_slice(&self.node.bytes());
+        bytes[6..10].copy_from_slice(&self.id.to_be_bytes());
+        bytes[10..14].copy_from_slice(&self.offset.to_be_bytes());
+        bytes[14..18].copy_from_slice(&self.total.to_be_bytes());
+        bytes
+    }
+
+    fn parse(fragment: &[u8]) -> Option<(Self, &[u8])> {
+        let (Some(node), Some(id), Some(offset), Some(total), Some(data)) = (
+            fragment.get(0..6),
+            fragment.get(6..10),
+            fragment.get(10..14),
+            fragment.get(14..18),
+            fragment.get(FRAGMENT_HEADER_LEN..),
+        ) else {
+            return None;
+        };
+        let node: [u8; 6] = node.try_into().ok()?;
+        let header = Self {
+            node: node.into(),
+            id: u32::from_be_bytes(id.try_into().ok()?),
+            offset: u32::from_be_bytes(offset.try_into().ok()?),
+            total: u32::from_be_bytes(total.try_into().ok()?),
+        };
+        Some((header, data))
+    }
+}
+
+/// Fragments of frame `id` of `node` carrying `frame`, each at most `size`
+/// bytes with its header
+pub fn split(frame: &[u8], node: MacAddress, id: u32, size: usize) -> Vec<Vec<u8>> {
+    let chunk = size.saturating_sub(FRAGMENT_HEADER_LEN).max(1);
+    let total = u32::try_from(frame.len()).unwrap_or(u32::MAX);
+    frame
+        .chunks(chunk)
+        .enumerate()
+        .map(|(index, data)| {
+            let header = FragmentHeader {
+                node,
+                id,
+                offset: u32::try_from(index * chunk).unwrap_or(u32::MAX),
+                total,
+            };
+            [&header.to_bytes()[..], data].concat()
+        })
+        .collect()
+}
+
+/// Splits data frames larger than the fragment size before they are sent
+#[derive(Debug)]
+pub struct Fragmenter {
+    node: MacAddress,
+    size: Option<usize>,
+    next_id: AtomicU32,
+}
+
+impl Fragmenter {
+    /// Fragmenter of the frames sent by `node`, which names them so frames
+    /// of different nodes are reassembled apart
+    pub fn new(node: MacAddress, size: Option<u32>) -> Self {
+        Self {
+            node,
+            size: size.and_then(|size| usize::try_from(size).ok()),
+            next_id: AtomicU32::new(0),
+        }
+    }
+
+    /// Payloads carrying `frame`, each paired with whether it is a fragment.
+    /// Fragments are held to `budget` too when it is below the fragment
+    /// size. Frames that fit, or every frame when fragmentation is disabled,
+    /// are carried whole.
+    pub fn payloads<'a>(
+        &self,
+        frame: &'a [u8],
+        budget: Option<usize>,
+    ) -> Vec<(bool, Cow<'a, [u8]>)> {
+        let Some(size) = self
+            .size
+            .map(|size| budget.map_or(size, |budget| size.min(budget)))
+            .filter(|size| frame.len() > *size)
+        else {
+            return vec![(false, Cow::Borrowed(frame))];
+        };
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        split(frame, self.node, id, size)
+            .into_iter()
+            .map(|fragment| (true, Cow::Owned(fragment)))
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+struct Partial {
+    frame: Vec<u8>,
+    /// Ranges of the frame received, by start
+    ranges: BTreeMap<usize, usize>,
+    received: usize,
+    started: Instant,
+}
+
+impl Partial {
+    /// Marks `offset..end` received, unless it overlaps a range already
+    /// received so a frame only completes once every byte was carried
+    fn cover(&mut self, offset: usize, end: usize) -> bool {
+        let overlaps = self
+            .ranges
+            .range(..end)
+            .next_back()
+            .is_some_and(|(_, covered)| *covered > offset);
+        if overlaps {
+            return false;
+        }
+        self.ranges.insert(offset, end);
+        self.received += end - offset;
+        true
+    }
+}
+
+/// Reassembles fragmented frames per fragmenting node. Frames still missing
+/// fragments after the timeout are discarded.
+#[derive(Debug)]
+pub struct Reassembly {
+    timeout: Duration,
+    pending: Mutex<HashMap<(MacAddress, u32), Partial>>,
+}
+
+impl Reassembly {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            pending: Mutex::default(),
+        }
+    }
+
+    /// Adds a fragment, returning its frame once every fragment of it
+    /// arrived. Malformed fragments, and fragments overlapping ones already
+    /// received, are ignored.
+    pub fn push(&self, fragment: &[u8]) -> Option<Vec<u8>> {
+        self.push_at(fragment, Instant::now())
+    }
+
+    fn push_at(&self, fragment: &[u8], now: Instant) -> Option<Vec<u8>> {
+        let mut pending = self.pending.lock().unwrap();
+        let before = pending.len();
+        pending.retain(|_, partial| now.duration_since(partial.started) < self.timeout);
+        let expired = before - pending.len();
+        if expired > 0 {
+            tracing::debug!(expired, "incomplete fragmented frames discarded");
+        }
+
+        let (header, data) = FragmentHeader::parse(fragment)?;
+        let total = usize::try_from(header.total).ok()?;
+        let offset = usize::try_from(header.offset).ok()?;
+        let end = offset.checked_add(data.len())?;
+        if total == 0 || total > MAX_FRAME_LEN || data.is_empty() || end > total {
+            tracing::debug!(?header, "malformed fragment ignored");
+            return None;
+        }
+
+        let key = (header.node, header.id);
+        if !pending.contains_key(&key) {
+            make_room(&mut pending, total);
+        }
+        let partial = pending.entry(key).or_insert_with(|| Partial {
+            frame: vec![0; total],
+            ranges: BTreeMap::default(),
+            received: 0,
+            started: now,
+        });
+        if partial.frame.len() != total {
+            tracing::debug!(?header, "fragment length mismatch ignored");
+            return None;
+        }
+
+        if !partial.cover(offset, end) {
+            tracing::trace!(?header, "overlapping fragment ignored");
+            return None;
+        }
+        partial.frame[offset..end].copy_from_slice(data);
+
+        if partial.received < total {
+            return None;
+        }
+
+        pending.remove(&key).map(|partial| partial.frame)
+    }
+}
+
+/// Discards the oldest frames being reassembled until one more of `total`
+/// bytes fits within the limits
+fn make_room(pending: &mut HashMap<(MacAddress, u32), Partial>, total: usize) {
+    let mut bytes: usize = pending.values().map(|partial| partial.frame.len()).sum();
+    while !pending.is_empty()
+        && (pending.len() >= MAX_PENDING_FRAMES || bytes + total > MAX_PENDING_BYTES)
+    {
+        let Some(oldest) = pending
+            .iter()
+            .min_by_key(|(_, partial)| partial.started)
+            .map(|(key, _)| *key)
+        else {
+            break;
+        };
+        if let Some(partial) = pending.remove(&oldest) {
+            bytes -= partial.frame.len();
+            tracing::debug!(node = %oldest.0, id = oldest.1, "fragmented frame discarded for room");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        split, FragmentHeader, Fragmenter, Reassembly, FRAGMENT_HEADER_LEN, MAX_FRAME_LEN,
+        MAX_PENDING_BYTES, MAX_PENDING_FRAMES,
+    };
+    use mac_address::MacAddress;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn oversized_frames_survive_fragmentation() {
+        let frame: Vec<u8> = (0..4000).map(|x| (x % 251) as u8).collect();
+        let fragmenter = Fragmenter::new([1; 6].into(), Some(1500));
+        let payloads = fragmenter.payloads(&frame, None);
+        assert_eq!(payloads.len(), 3);
+        assert!(payloads
+            .iter()
+            .all(|(fragment, payload)| *fragment && payload.len() <= 1500));
+
+        let reassembly = Reassembly::new(Duration::from_secs(5));
+        assert!(reassembly.push(&payloads[2].1).is_none());
+        assert!(reassembly.push(&payloads[0].1).is_none());
+        assert!(reassembly.push(&payloads[0].1).is_none());
+        assert_eq!(reassembly.push(&payloads[1].1), Some(frame));
+    }
+
+    #[test]
+    fn frames_of_different_nodes_sharing_an_id_are_reassembled_apart() {
+        let (first, second) = ([1u8; 100], [2u8; 100]);
+        let size = FRAGMENT_HEADER_LEN + 50;
+        let first = split(&first, MacAddress::new([1; 6]), 0, size);
+        let second = split(&second, MacAddress::new([2; 6]), 0, size);
+        let reassembly = Reassembly::new(Duration::from_secs(5));
+
+        assert!(reassembly.push(&first[0]).is_none());
+        assert!(reassembly.push(&second[1]).is_none());
+        assert_eq!(reassembly.push(&first[1]), Some(vec![1; 100]));
+        assert_eq!(reassembly.push(&second[0]), Some(vec![2; 100]));
+    }
+
+    #[test]
+    fn frames_that_fit_are_sent_whole() {
+        let frame = [7u8; 1500];
+        let payloads = Fragmenter::new([1; 6].into(), Some(1500)).payloads(&frame, None);
+        assert_eq!(payloads.len(), 1);
+        assert!(!payloads[0].0);
+        assert_eq!(&payloads[0].1[..], &frame[..]);
+
+        // Without fragmentation the budget is left for the path MTU check
+        let payloads = Fragmenter::new([1; 6].into(), None).payloads(&[7u8; 4000], Some(1480));
+        assert_eq!(payloads.len(), 1);
+        assert!(!payloads[0].0);
+    }
+
+    #[test]
+    fn fragments_are_held_to_a_budget_below_the_fragment_size() {
+        let frame = [7u8; 4000];
+        let payloads = Fragmenter::new([1; 6].into(), Some(8000)).payloads(&frame, Some(1480));
+        assert_eq!(payloads.len(), 3);
+        assert!(payloads
+            .iter()
+            .all(|(fragment, payload)| *fragment && payload.len() <= 1480));
+
+        let payloads = Fragmenter::new([1; 6].into(), Some(1000)).payloads(&frame, Some(1480));
+        assert!(payloads.iter().all(|(_, payload)| payload.len() <= 1000));
+    }
+
+    #[test]
+    fn incomplete_frames_are_discarded_after_the_timeout() {
+        let frame = [3u8; 100];
+        let fragments = split(&frame, MacAddress::new([1; 6]), 0, FRAGMENT_HEADER_LEN + 50);
+        let reassembly = Reassembly::new(Duration::from_millis(100));
+        let start = Instant::now();
+
+        assert!(reassembly.push_at(&fragments[0], start).is_none());
+        assert!(reassembly
+            .push_at(&fragments[1], start + Duration::from_millis(100))
+            .is_none());
+        assert_eq!(reassembly.pending.lock().unwrap().len(), 1);
+
+        assert!(reassembly.push_at(&[0; 4], start).is_none());
+    }
+
+    /// Fragment of `frame` carrying `len` bytes from `offset`
+    fn fragment(frame: &[u8], offset: usize, len: usize) -> Vec<u8> {
+        let header = FragmentHeader {
+            node: [1; 6].into(),
+            id: 0,
+            offset: offset as u32,
+            total: frame.len() as u32,
+        };
+        [&header.to_bytes()[..], &frame[offset..offset + len]].concat()
+    }
+
+    #[test]
+    fn overlapping_fragments_do_not_complete_a_frame() {
+        let frame: Vec<u8> = (0..100).collect();
+        let reassembly = Reassembly::new(Duration::from_secs(5));
+
+        // Received lengths add up to the frame but 75..100 was never sent
+        assert!(reassembly.push(&fragment(&frame, 0, 50)).is_none());
+        assert!(reassembly.push(&fragment(&frame, 25, 50)).is_none());
+        assert!(reassembly.push(&fragment(&frame, 0, 0)).is_none());
+        assert_eq!(reassembly.push(&fragment(&frame, 50, 50)), Some(frame));
+    }
+
+    #[test]
+    fn frames_being_reassembled_are_capped() {
+        let reassembly = Reassembly::new(Duration::from_secs(5));
+        let start = Instant::now();
+        for id in 0..(MAX_PENDING_FRAMES as u32 + 8) {
+            let fragments = split(
+                &[id as u8; 100],
+                MacAddress::new([1; 6]),
+                id,
+                FRAGMENT_HEADER_LEN + 50,
+            );
+            assert!(reassembly
+                .push_at(&fragments[0], start + Duration::from_millis(id.into()))
+                .is_none());
+        }
+        assert_eq!(reassembly.pending.lock().unwrap().len(), MAX_PENDING_FRAMES);
+
+        // The oldest frames were discarded, the newest still complete
+        let last = MAX_PENDING_FRAMES as u32 + 7;
+        let fragments = split(
+            &[last as u8; 100],
+            MacAddress::new([1; 6]),
+            last,
+            FRAGMENT_HEADER_LEN + 50,
+        );
+        assert_eq!(
+            reassembly.push_at(&fragments[1], start),
+            Some(vec![last as u8; 100])
+        );
+        let fragments = split(
+            &[0; 100],
+            MacAddress::new([1; 6]),
+            0,
+            FRAGMENT_HEADER_LEN + 50,
+        );
+        assert!(reassembly.push_at(&fragments[1], start).is_none());
+
+        for id in 1000..1020 {
+            let fragments = split(&vec![1; MAX_FRAME_LEN], MacAddress::new([2; 6]), id, 1500);
+            assert!(reassembly.push_at(&fragments[0], start).is_none());
+        }
+        let pending = reassembly.pending.lock().unwrap();
+        let bytes: usize = pending.values().map(|partial| partial.frame.len()).sum();
+        assert!(bytes <= MAX_PENDING_BYTES);
+    }
+}
@@ -0,0 +1,96 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Mutex,
+};
+
+/// Random drop of frames received by a node, independent of the channel loss
+/// applied by the simulator.
+pub struct IngressLoss {
+    loss: f64,
+    rng: Mutex<StdRng>,
+    dropped: AtomicU64,
+}
+
+impl IngressLoss {
+    pub fn new(loss: f64, seed: Option<u64>) -> Self {
+        let rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+
+        Self {
+            loss,
+            rng: rng.into(),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    pub fn should_drop(&self) -> bool {
+        if self.loss <= 0.0 {
+            return false;
+        }
+
+        let drop = self.rng.lock().unwrap().gen::<f64>() < self.loss;
+        if drop {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        drop
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IngressLoss;
+    use crate::messages::{
+        control::{heartbeat::Heartbeat, Control},
+        message::Message,
+        packet_type::PacketType,
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn full_ingress_loss_drops_everything() {
+        let loss = IngressLoss::new(1.0, Some(0));
+        let msg = Message::new(
+            [1; 6].into(),
+            [255; 6].into(),
+            PacketType::Control(Control::Heartbeat(Heartbeat::new(
+                Duration::default(),
+                0,
+                [1; 6].into(),
+            ))),
+        );
+        let pkt: Vec<Vec<u8>> = (&msg).into();
+        let pkt: Vec<u8> = pkt.iter().flat_map(|x| x.iter()).cloned().collect();
+
+        let processed = (0..100)
+            .filter(|_| !loss.should_drop())
+            .filter_map(|_| Message::try_from(&pkt[..]).ok())
+            .count();
+
+        assert_eq!(processed, 0);
+        assert_eq!(loss.dropped(), 100);
+    }
+
+    #[test]
+    fn ingress_loss_is_seed_deterministic() {
+        let first = IngressLoss::new(0.5, Some(42));
+        let second = IngressLoss::new(0.5, Some(42));
+
+        let first: Vec<bool> = (0..100).map(|_| first.should_drop()).collect();
+        let second: Vec<bool> = (0..100).map(|_| second.should_drop()).collect();
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn no_ingress_loss_drops_nothing() {
+        let loss = IngressLoss::new(0.0, None);
+        assert!((0..100).all(|_| !loss.should_drop()));
+        assert_eq!(loss.dropped(), 0);
+    }
+}
@@ -0,0 +1,69 @@
+use std::sync::{Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+fn recover<G>(clear_poison: impl FnOnce(), poisoned: PoisonError<G>) -> G {
+    tracing::warn!("recovering lock poisoned by a panicked task");
+    clear_poison();
+    poisoned.into_inner()
+}
+
+/// Read-locks `lock`, recovering it if a task panicked while holding it so
+/// one failed handler does not take down every later access.
+pub fn read<T: ?Sized>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read()
+        .unwrap_or_else(|e| recover(|| lock.clear_poison(), e))
+}
+
+/// Write-locks `lock`, recovering it like [`read`].
+pub fn write<T: ?Sized>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write()
+        .unwrap_or_else(|e| recover(|| lock.clear_poison(), e))
+}
+
+/// Locks `mutex`, recovering it like [`read`].
+pub fn lock<T: ?Sized>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|e| recover(|| mutex.clear_poison(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{lock, read, write};
+    use std::sync::{Arc, Mutex, RwLock};
+
+    #[tokio::test]
+    async fn poisoned_lock_is_recovered() {
+        let lock = Arc::new(RwLock::new(1));
+        let lockc = lock.clone();
+        let handle = tokio::task::spawn_blocking(move || {
+            let mut guard = lockc.write().unwrap();
+            *guard = 2;
+            panic!("handler failed");
+        });
+        assert!(handle.await.is_err());
+        assert!(lock.is_poisoned());
+
+        assert_eq!(*read(&lock), 2);
+        *write(&lock) = 3;
+        assert_eq!(*read(&lock), 3);
+        assert!(!lock.is_poisoned());
+    }
+
+    #[tokio::test]
+    async fn poisoned_mutex_is_recovered() {
+        let mutex = Arc::new(Mutex::new(1));
+        let mutexc = mutex.clone();
+        let handle = tokio::task::spawn_blocking(move || {
+            let mut guard = mutexc.lock().unwrap();
+            *guard = 2;
+            panic!("handler failed");
+        });
+        assert!(handle.await.is_err());
+        assert!(mutex.is_poisoned());
+
+        assert_eq!(*lock(&mutex), 2);
+        *lock(&mutex) = 3;
+        assert_eq!(*lock(&mutex), 3);
+        assert!(!mutex.is_poisoned());
+    }
+}
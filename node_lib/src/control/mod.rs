@@ -1,5 +1,18 @@
+mod capture;
 mod client_cache;
+mod compression;
+pub mod counters;
+pub mod drops;
+pub(crate) mod fragment;
+mod ingress;
+mod lock;
 pub mod node;
 pub mod obu;
+mod periodicity;
+pub mod receive_queue;
 mod route;
 pub mod rsu;
+mod sequence;
+pub mod snapshot;
+mod tasks;
+pub mod traffic;
@@ -1,11 +1,26 @@
-use crate::messages::message::Message;
+use crate::{
+    control::{capture::Capture, receive_queue::ReceiveQueue, tasks::Tasks},
+    messages::message::Message,
+    Args,
+};
 use anyhow::{bail, Result};
 use common::device::Device;
 use futures::{future::join_all, Future};
 use itertools::Itertools;
-use std::{io::IoSlice, sync::Arc};
+use mac_address::MacAddress;
+use std::{
+    cmp::Reverse,
+    collections::{hash_map::DefaultHasher, BinaryHeap},
+    hash::{Hash, Hasher},
+    io::{self, ErrorKind, IoSlice},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::mpsc;
 use tokio_tun::Tun;
-use uninit::uninit_array;
 
 #[derive(Debug)]
 pub enum ReplyType {
@@ -44,10 +59,164 @@ pub fn get_msgs(response: &Result<Option<Vec<ReplyType>>>) -> Result<Option<Vec<
     }
 }
 
+/// Source MAC of a frame forwarded by this node, either our own or the one
+/// of the node it was received from.
+pub fn forwarding_source(rewrite: bool, own: MacAddress, received_from: MacAddress) -> MacAddress {
+    if rewrite {
+        own
+    } else {
+        received_from
+    }
+}
+
+/// Destination and source MACs of an ethernet frame read from the TAP.
+pub fn ethernet_addresses(data: &[u8]) -> Result<(MacAddress, MacAddress)> {
+    let (Some(to), Some(from)) = (data.get(0..6), data.get(6..12)) else {
+        bail!("frame too short for ethernet header");
+    };
+
+    let to: [u8; 6] = to.try_into()?;
+    let from: [u8; 6] = from.try_into()?;
+    Ok((to.into(), from.into()))
+}
+
+/// Hash of the ethernet addresses of a frame, identifying its flow. Frames
+/// too short to carry both addresses hash whatever they hold.
+pub fn flow_hash(data: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    data.get(..12).unwrap_or(data).hash(&mut hasher);
+    hasher.finish()
+}
+
+fn is_group_address(mac: MacAddress) -> bool {
+    mac.bytes()[0] & 0x1 != 0
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameDestination {
+    /// Broadcast or multicast, fanned out to every node
+    Broadcast,
+    /// Routed to the single node behind this address
+    Unicast(MacAddress),
+}
+
+/// Classifies an ethernet frame by its destination. With `strict`, frames
+/// sent from a group address or to the null address are rejected as
+/// malformed.
+pub fn frame_destination(data: &[u8], strict: bool) -> Result<FrameDestination> {
+    let (to, from) = ethernet_addresses(data)?;
+    if strict && is_group_address(from) {
+        bail!("frame sent from group address {from}");
+    }
+
+    if strict && to == MacAddress::new([0; 6]) {
+        bail!("frame sent to the null address");
+    }
+
+    Ok(if is_group_address(to) {
+        FrameDestination::Broadcast
+    } else {
+        FrameDestination::Unicast(to)
+    })
+}
+
+/// What a data message carries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Payload {
+    /// Nothing at all, sent upstream to keep routes alive
+    Keepalive,
+    /// At most an ethernet header, nothing a host could make use of
+    HeaderOnly,
+    Frame,
+}
+
+pub fn payload(data: &[u8]) -> Payload {
+    match data.len() {
+        0 => Payload::Keepalive,
+        len if len <= ETHERNET_HEADER_LEN => Payload::HeaderOnly,
+        _ => Payload::Frame,
+    }
+}
+
+/// Drops header-only data frames before they are forwarded or delivered,
+/// when enabled, counting them.
+#[derive(Debug)]
+pub struct HeaderOnlyFilter {
+    enabled: bool,
+    dropped: AtomicU64,
+}
+
+impl HeaderOnlyFilter {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    pub fn should_drop(&self, data: &[u8]) -> bool {
+        if !self.enabled || payload(data) != Payload::HeaderOnly {
+            return false;
+        }
+
+        let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+        tracing::debug!(size = data.len(), dropped, "header-only frame dropped");
+        true
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+const SEND_RETRY_BACKOFF: Duration = Duration::from_micros(500);
+
+fn is_transient(e: &io::Error) -> bool {
+    matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::Interrupted)
+        || e.raw_os_error() == Some(libc::ENOBUFS)
+}
+
+pub async fn send_with_retry<Fut>(retries: u32, mut send: impl FnMut() -> Fut) -> io::Result<usize>
+where
+    Fut: Future<Output = io::Result<usize>>,
+{
+    let mut attempt = 0;
+    loop {
+        match send().await {
+            Err(e) if is_transient(&e) && attempt < retries => {
+                attempt += 1;
+                tracing::debug!(?e, attempt, "transient send error, retrying");
+                let _ = tokio_timerfd::sleep(SEND_RETRY_BACKOFF * attempt).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+fn is_control_reply(reply: &[Vec<u8>]) -> bool {
+    let head: Vec<u8> = reply.iter().flatten().take(15).copied().collect();
+    Message::is_control_frame(&head)
+}
+
+/// Sends a wire frame, with `priority` as its socket priority if given.
+pub async fn send_wire(
+    dev: &Device,
+    bufs: &[IoSlice<'_>],
+    priority: Option<u32>,
+) -> io::Result<usize> {
+    match priority {
+        Some(priority) => dev.send_vectored_with_priority(bufs, priority).await,
+        None => dev.send_vectored(bufs).await,
+    }
+}
+
 pub async fn handle_messages(
     messages: Vec<ReplyType>,
     tun: &Arc<Tun>,
     dev: &Arc<Device>,
+    retries: u32,
+    control_priority: Option<u32>,
+    capture: Option<&Capture>,
 ) -> Result<()> {
     let future_vec = messages
         .iter()
@@ -55,15 +224,23 @@ pub async fn handle_messages(
             match reply {
                 ReplyType::Tap(buf) => {
                     let vec: Vec<IoSlice> = buf.iter().map(|x| IoSlice::new(x)).collect();
-                    let _ = tun
-                        .send_vectored(&vec)
+                    let _ = send_with_retry(retries, || tun.send_vectored(&vec))
                         .await
-                        .inspect_err(|e| tracing::error!(?e, "error sending to tap"));
+                        .inspect_err(|e| {
+                            tracing::error!(?e, "error sending to tap");
+                            #[cfg(feature = "stats")]
+                            dev.record_tun_write_error();
+                        });
                 }
                 ReplyType::Wire(reply) => {
+                    if let Some(capture) = capture {
+                        capture.record(&reply.concat());
+                    }
+
                     let vec: Vec<IoSlice> = reply.iter().map(|x| IoSlice::new(x)).collect();
-                    let _ = dev
-                        .send_vectored(&vec)
+                    let vec = &vec;
+                    let priority = control_priority.filter(|_| is_control_reply(reply));
+                    let _ = send_with_retry(retries, || send_wire(dev, vec, priority))
                         .await
                         .inspect_err(|e| tracing::error!(?e, "error sending to dev"));
                 }
@@ -75,31 +252,632 @@ pub async fn handle_messages(
     Ok(())
 }
 
-fn buffer() -> [u8; 1500] {
-    let buf = uninit_array![u8; 1500];
-    unsafe { std::mem::transmute::<_, [u8; 1500]>(buf) }
+/// Length of the ethernet header leading every frame carried in data messages
+pub const ETHERNET_HEADER_LEN: usize = 14;
+
+pub fn tap_buffer_size(args: &Args) -> Result<usize> {
+    let frame_size = usize::try_from(args.mtu)? + ETHERNET_HEADER_LEN;
+    let size = args.tap_buffer_size.unwrap_or(frame_size);
+    if size < frame_size {
+        bail!(
+            "tap buffer of {} bytes cannot hold a {} byte frame (mtu {})",
+            size,
+            frame_size,
+            args.mtu
+        );
+    }
+    Ok(size)
+}
+
+/// Most bytes a data message adds around the frame it carries: the outer
+/// ethernet header, the packet and data types and the downstream header
+pub const DATA_OVERHEAD: usize = 34;
+
+/// MTU this node advertises in heartbeats, the one of the wire it sends
+/// data messages on.
+pub fn advertised_mtu(args: &Args) -> u16 {
+    args.wire_mtu
+}
+
+/// Largest payload a data message carries within the MTU learned for its
+/// path, or None when clamping is disabled or no MTU was learned.
+pub fn payload_budget(clamp: bool, path_mtu: Option<u16>) -> Option<usize> {
+    let mtu = path_mtu.filter(|_| clamp)?;
+    Some((usize::from(mtu) + ETHERNET_HEADER_LEN).saturating_sub(DATA_OVERHEAD))
+}
+
+/// Checks if a wire message, counting its ethernet and data headers, fits
+/// the wire MTU learned for its path. Messages always fit when clamping is
+/// disabled or no MTU was learned for the path.
+pub fn fits_path_mtu(clamp: bool, wire: &[Vec<u8>], path_mtu: Option<u16>) -> bool {
+    let Some(mtu) = path_mtu.filter(|_| clamp) else {
+        return true;
+    };
+
+    let size: usize = wire.iter().map(Vec::len).sum();
+    let fits = size <= usize::from(mtu) + ETHERNET_HEADER_LEN;
+    if !fits {
+        tracing::debug!(size, mtu, "message exceeds path mtu, dropping");
+    }
+    fits
+}
+
+/// Wire messages carrying a frame as replies, or None when any of them
+/// exceeds the MTU learned for its path.
+pub fn within_path_mtu(
+    clamp: bool,
+    wire: Vec<Vec<Vec<u8>>>,
+    path_mtu: Option<u16>,
+) -> Option<Vec<ReplyType>> {
+    wire.iter()
+        .all(|message| fits_path_mtu(clamp, message, path_mtu))
+        .then(|| wire.into_iter().map(ReplyType::Wire).collect())
+}
+
+/// Size of the buffers frames are read from the wire into, large enough for
+/// a whole frame of the wire MTU
+pub fn wire_frame_size(args: &Args) -> usize {
+    usize::from(args.wire_mtu) + ETHERNET_HEADER_LEN
+}
+
+const RECEIVE_QUEUE_CAPACITY: usize = 1024;
+
+/// Frame read from the wire, control frames ahead of any data, then ordered
+/// by data priority and by arrival, earliest first
+struct QueuedFrame {
+    control: bool,
+    priority: u8,
+    arrival: Reverse<u64>,
+    frame: (Vec<u8>, usize),
+}
+
+impl PartialEq for QueuedFrame {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for QueuedFrame {}
+
+impl PartialOrd for QueuedFrame {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for QueuedFrame {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+impl QueuedFrame {
+    fn key(&self) -> (bool, u8, Reverse<u64>) {
+        (self.control, self.priority, self.arrival)
+    }
+}
+
+/// Frames read from the wire waiting to be processed. When frames queue up,
+/// control frames are taken first, then higher priority data frames, in
+/// arrival order among frames of the same priority.
+pub struct WireFrames {
+    rx: mpsc::Receiver<(Vec<u8>, usize)>,
+    pending: BinaryHeap<QueuedFrame>,
+    arrivals: u64,
+}
+
+impl WireFrames {
+    fn new(rx: mpsc::Receiver<(Vec<u8>, usize)>) -> Self {
+        Self {
+            rx,
+            pending: BinaryHeap::new(),
+            arrivals: 0,
+        }
+    }
+
+    fn queue(&mut self, frame: (Vec<u8>, usize)) {
+        let (buf, n) = &frame;
+        self.arrivals += 1;
+        self.pending.push(QueuedFrame {
+            control: Message::is_control_frame(&buf[..*n]),
+            priority: Message::data_priority(&buf[..*n]).unwrap_or(0),
+            arrival: Reverse(self.arrivals),
+            frame,
+        });
+    }
+
+    /// Highest priority frame waiting, waiting for one if there is none
+    async fn next(&mut self) -> Option<(Vec<u8>, usize)> {
+        if self.pending.is_empty() {
+            let frame = self.rx.recv().await?;
+            self.queue(frame);
+        }
+
+        while self.pending.len() < RECEIVE_QUEUE_CAPACITY {
+            let Ok(frame) = self.rx.try_recv() else {
+                break;
+            };
+            self.queue(frame);
+        }
+        self.pending.pop().map(|queued| queued.frame)
+    }
+}
+
+/// Reads frames from `dev` in the background so the ones arriving faster
+/// than they are processed queue up, tracked by `queue`.
+pub fn receive_frames(
+    tasks: &Tasks,
+    dev: Arc<Device>,
+    frame_size: usize,
+    queue: Arc<ReceiveQueue>,
+    capture: Option<Arc<Capture>>,
+) -> WireFrames {
+    let (tx, rx) = mpsc::channel(RECEIVE_QUEUE_CAPACITY);
+    tasks.spawn(async move {
+        loop {
+            let mut buf = vec![0u8; frame_size];
+            let n = match dev.recv(&mut buf).await {
+                Ok(n) => n,
+                Err(e) => {
+                    tracing::trace!(?e, "error reading from wire");
+                    continue;
+                }
+            };
+
+            if let Some(capture) = &capture {
+                capture.record(&buf[..n]);
+            }
+
+            queue.push(Instant::now());
+            if tx.send((buf, n)).await.is_err() {
+                break;
+            }
+        }
+    });
+    WireFrames::new(rx)
 }
 
 pub async fn wire_traffic<Fut>(
-    dev: &Arc<Device>,
-    callable: impl FnOnce([u8; 1500], usize) -> Fut,
+    frames: &mut WireFrames,
+    queue: &ReceiveQueue,
+    callable: impl FnOnce(Vec<u8>, usize) -> Fut,
 ) -> Result<Option<Vec<ReplyType>>>
 where
     Fut: Future<Output = Result<Option<Vec<ReplyType>>>>,
 {
-    let mut buf = buffer();
-    let n = dev.recv(&mut buf).await?;
+    let Some((buf, n)) = frames.next().await else {
+        bail!("wire receiver stopped");
+    };
+    queue.pop();
     callable(buf, n).await
 }
 
+#[cfg_attr(not(feature = "stats"), allow(unused_variables))]
+pub async fn tap_recv(tun: &Tun, dev: &Device, buf: &mut [u8]) -> io::Result<usize> {
+    tun.recv(buf).await.inspect_err(|e| {
+        tracing::error!(?e, "error reading from tap");
+        #[cfg(feature = "stats")]
+        dev.record_tun_read_error();
+    })
+}
+
 pub async fn tap_traffic<Fut>(
-    dev: &Arc<Tun>,
-    callable: impl FnOnce([u8; 1500], usize) -> Fut,
+    tun: &Arc<Tun>,
+    dev: &Arc<Device>,
+    buffer_size: usize,
+    callable: impl FnOnce(Vec<u8>, usize) -> Fut,
 ) -> Result<Option<Vec<ReplyType>>>
 where
     Fut: Future<Output = Result<Option<Vec<ReplyType>>>>,
 {
-    let mut buf = buffer();
-    let n = dev.recv(&mut buf).await?;
+    let mut buf = vec![0u8; buffer_size];
+    let n = tap_recv(tun, dev, &mut buf).await?;
     callable(buf, n).await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ethernet_addresses, forwarding_source, frame_destination, is_control_reply, payload,
+        payload_budget, send_with_retry, tap_buffer_size, tap_recv, wire_frame_size, wire_traffic,
+        FrameDestination, HeaderOnlyFilter, Payload, WireFrames, DATA_OVERHEAD,
+        ETHERNET_HEADER_LEN, RECEIVE_QUEUE_CAPACITY,
+    };
+    use crate::{
+        args::NodeType,
+        control::receive_queue::ReceiveQueue,
+        messages::{
+            control::{heartbeat::Heartbeat, Control},
+            data::{Data, ToDownstream, ToUpstream},
+            message::Message,
+            packet_type::PacketType,
+        },
+        test_helpers, Args,
+    };
+    use std::{
+        io::{self, ErrorKind},
+        sync::atomic::{AtomicU32, Ordering},
+        time::{Duration, Instant},
+    };
+    use tokio::sync::mpsc;
+
+    fn args(mtu: i32, tap_buffer_size: Option<usize>) -> Args {
+        let mut args = test_helpers::args(NodeType::Obu);
+        args.mtu = mtu;
+        args.tap_buffer_size = tap_buffer_size;
+        args
+    }
+
+    #[test]
+    fn default_tap_buffer_holds_a_full_frame() {
+        let size = tap_buffer_size(&args(9000, None)).expect("valid size");
+        assert_eq!(size, 9014);
+    }
+
+    #[test]
+    fn tap_buffer_smaller_than_mtu_is_rejected() {
+        assert!(tap_buffer_size(&args(1500, Some(1500))).is_err());
+        assert!(tap_buffer_size(&args(1500, Some(1514))).is_ok());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs CAP_NET_ADMIN to create TAPs"]
+    async fn full_frames_are_read_whole_from_the_tap_and_fit_the_wire() {
+        let interfaces = test_helpers::node_interfaces();
+        let args = test_helpers::args(NodeType::Obu);
+        let size = tap_buffer_size(&args).expect("valid size");
+        let from = [2, 0, 0, 0, 0, 7];
+        let frame: Vec<u8> = [[0xff; 6], from]
+            .concat()
+            .into_iter()
+            .chain((0..size - 12).map(|x| (x % 251) as u8))
+            .collect();
+        interfaces.device.send(&frame).await.expect("sent frame");
+
+        let tap = interfaces.wire;
+        let read = tokio::time::timeout(Duration::from_secs(1), async {
+            let mut buf = vec![0u8; size];
+            // Skip what the kernel sends through the TAP on its own
+            loop {
+                let n = tap_recv(&tap, &interfaces.device, &mut buf)
+                    .await
+                    .expect("read frame");
+                if buf.get(6..12) == Some(&from[..]) {
+                    return buf[..n].to_vec();
+                }
+            }
+        })
+        .await
+        .expect("frame read back");
+        assert_eq!(read, frame);
+
+        let wire: Vec<Vec<u8>> = (&Message::new(
+            [1; 6].into(),
+            [3; 6].into(),
+            PacketType::Data(Data::Downstream(ToDownstream::new(
+                &from,
+                [1; 6].into(),
+                &read,
+            ))),
+        ))
+            .into();
+        assert!(wire.concat().len() <= usize::from(args.wire_mtu) + ETHERNET_HEADER_LEN);
+    }
+
+    #[test]
+    fn data_overhead_covers_the_headers_of_either_direction() {
+        let frame = [0x42; 100];
+        let upstream: Vec<Vec<u8>> = (&Message::new(
+            [1; 6].into(),
+            [2; 6].into(),
+            PacketType::Data(Data::Upstream(ToUpstream::new([1; 6].into(), &frame))),
+        ))
+            .into();
+        let downstream: Vec<Vec<u8>> = (&Message::new(
+            [2; 6].into(),
+            [1; 6].into(),
+            PacketType::Data(Data::Downstream(ToDownstream::new(
+                &[3; 6],
+                [1; 6].into(),
+                &frame,
+            ))),
+        ))
+            .into();
+
+        assert!(upstream.concat().len() - frame.len() <= DATA_OVERHEAD);
+        assert_eq!(downstream.concat().len() - frame.len(), DATA_OVERHEAD);
+    }
+
+    #[test]
+    fn payload_budget_leaves_room_for_the_data_headers() {
+        assert_eq!(payload_budget(true, Some(1500)), Some(1480));
+        assert_eq!(payload_budget(false, Some(1500)), None);
+        assert_eq!(payload_budget(true, None), None);
+    }
+
+    #[tokio::test]
+    async fn transient_send_errors_are_retried() {
+        let attempts = AtomicU32::new(0);
+        let result = send_with_retry(3, || async {
+            match attempts.fetch_add(1, Ordering::SeqCst) {
+                0 | 1 => Err(io::Error::from(ErrorKind::WouldBlock)),
+                _ => Ok(42),
+            }
+        })
+        .await;
+
+        assert_eq!(result.expect("eventually sent"), 42);
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn fatal_send_errors_are_not_retried() {
+        let attempts = AtomicU32::new(0);
+        let result = send_with_retry(3, || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err::<usize, _>(io::Error::from(ErrorKind::BrokenPipe))
+        })
+        .await;
+
+        assert_eq!(result.expect_err("fails").kind(), ErrorKind::BrokenPipe);
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn send_retries_are_bounded() {
+        let attempts = AtomicU32::new(0);
+        let result = send_with_retry(2, || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err::<usize, _>(io::Error::from(ErrorKind::WouldBlock))
+        })
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn forwarded_frame_source_follows_rewrite_option() {
+        let own = [1; 6].into();
+        let received_from = [2; 6].into();
+        let payload = [0u8; 14];
+
+        for (rewrite, expected) in [(true, [1u8; 6]), (false, [2u8; 6])] {
+            let msg = Message::new(
+                forwarding_source(rewrite, own, received_from),
+                [3; 6].into(),
+                PacketType::Data(Data::Upstream(ToUpstream::new([4; 6].into(), &payload))),
+            );
+            let wire: Vec<Vec<u8>> = (&msg).into();
+            let wire: Vec<u8> = wire.iter().flat_map(|x| x.iter()).cloned().collect();
+            assert_eq!(wire[6..12], expected);
+        }
+    }
+
+    #[test]
+    fn short_tap_frames_have_no_ethernet_addresses() {
+        let frame = [[1u8; 6], [2u8; 6]].concat();
+        let (to, from) = ethernet_addresses(&frame).expect("full header");
+        assert_eq!(to, [1; 6].into());
+        assert_eq!(from, [2; 6].into());
+        assert!(ethernet_addresses(&frame[..11]).is_err());
+
+        let frames = test_helpers::fuzzed_frames(0x7a9, 5000);
+        test_helpers::assert_no_panics(&frames, |frame| {
+            let _ = ethernet_addresses(frame);
+        });
+    }
+
+    #[test]
+    fn header_only_frames_follow_the_configured_disposition() {
+        let frames: Vec<Vec<u8>> = [&[][..], &[7u8; 14][..], &[7u8; 60][..]]
+            .iter()
+            .map(|data| {
+                let msg = Message::new(
+                    [1; 6].into(),
+                    [2; 6].into(),
+                    PacketType::Data(Data::Upstream(ToUpstream::new([1; 6].into(), data))),
+                );
+                let wire: Vec<Vec<u8>> = (&msg).into();
+                wire.concat()
+            })
+            .collect();
+        let upstream_data = |frame: &[u8]| {
+            let msg = Message::try_from(frame).expect("is message");
+            let PacketType::Data(Data::Upstream(up)) = msg.get_packet_type() else {
+                panic!("not an upstream frame");
+            };
+            up.data().to_vec()
+        };
+
+        let kinds: Vec<_> = frames.iter().map(|f| payload(&upstream_data(f))).collect();
+        assert_eq!(
+            kinds,
+            [Payload::Keepalive, Payload::HeaderOnly, Payload::Frame]
+        );
+
+        for (enabled, dropped) in [(true, [false, true, false]), (false, [false; 3])] {
+            let filter = HeaderOnlyFilter::new(enabled);
+            let result: Vec<_> = frames
+                .iter()
+                .map(|f| filter.should_drop(&upstream_data(f)))
+                .collect();
+            assert_eq!(result, dropped);
+            assert_eq!(filter.dropped(), u64::from(enabled));
+        }
+    }
+
+    #[test]
+    fn frames_are_classified_by_destination() {
+        let frame = |to: [u8; 6], from: [u8; 6]| [to, from].concat();
+        let unicast = [2; 6];
+
+        for (to, expected) in [
+            ([0xff; 6], FrameDestination::Broadcast),
+            (
+                [0x01, 0x00, 0x5e, 0x00, 0x00, 0x01],
+                FrameDestination::Broadcast,
+            ),
+            (
+                [0x33, 0x33, 0x00, 0x00, 0x00, 0x01],
+                FrameDestination::Broadcast,
+            ),
+            (unicast, FrameDestination::Unicast(unicast.into())),
+        ] {
+            let destination = frame_destination(&frame(to, [4; 6]), true).expect("well formed");
+            assert_eq!(destination, expected);
+        }
+
+        let from_group = frame(unicast, [0xff; 6]);
+        let to_null = frame([0; 6], [4; 6]);
+        for malformed in [&from_group, &to_null] {
+            assert!(frame_destination(malformed, true).is_err());
+            assert!(frame_destination(malformed, false).is_ok());
+        }
+        assert!(frame_destination(&from_group[..11], false).is_err());
+    }
+
+    #[test]
+    fn only_control_replies_are_marked() {
+        let heartbeat = Message::new(
+            [1; 6].into(),
+            [255; 6].into(),
+            PacketType::Control(Control::Heartbeat(Heartbeat::new(
+                Duration::default(),
+                0,
+                [1; 6].into(),
+            ))),
+        );
+        let data = Message::new(
+            [1; 6].into(),
+            [2; 6].into(),
+            PacketType::Data(Data::Upstream(ToUpstream::new([1; 6].into(), &[0; 14]))),
+        );
+
+        let heartbeat: Vec<Vec<u8>> = (&heartbeat).into();
+        let data: Vec<Vec<u8>> = (&data).into();
+        assert!(is_control_reply(&heartbeat));
+        assert!(!is_control_reply(&data));
+        assert!(!is_control_reply(&[vec![0; 6]]));
+    }
+
+    #[tokio::test]
+    async fn queued_frames_are_processed_highest_priority_first() {
+        let (tx, rx) = mpsc::channel(RECEIVE_QUEUE_CAPACITY);
+        let mut frames = WireFrames::new(rx);
+        let queue = ReceiveQueue::new(None);
+        for (priority, payload) in [(0, 1), (0, 2), (7, 3), (0, 4)] {
+            let payload = [payload; 14];
+            let msg = Message::new(
+                [2; 6].into(),
+                [3; 6].into(),
+                PacketType::Data(Data::Downstream(
+                    ToDownstream::new(&[1; 6], [4; 6].into(), &payload).with_priority(priority),
+                )),
+            );
+            let wire: Vec<Vec<u8>> = (&msg).into();
+            let wire = wire.concat();
+            queue.push(Instant::now());
+            tx.send((wire.clone(), wire.len())).await.expect("queued");
+        }
+
+        let mut order = Vec::new();
+        for _ in 0..4 {
+            let _ = wire_traffic(&mut frames, &queue, |pkt, size| {
+                let msg = Message::try_from(&pkt[..size]).expect("valid frame");
+                let PacketType::Data(Data::Downstream(down)) = msg.get_packet_type() else {
+                    panic!("not a downstream frame");
+                };
+                order.push((down.priority(), down.data()[0]));
+                async { Ok(None) }
+            })
+            .await;
+        }
+
+        assert_eq!(order, vec![(7, 3), (0, 1), (0, 2), (0, 4)]);
+        assert_eq!(queue.depth().depth, 0);
+    }
+
+    #[tokio::test]
+    async fn queued_control_frames_go_ahead_of_any_data() {
+        let (tx, rx) = mpsc::channel(RECEIVE_QUEUE_CAPACITY);
+        let mut frames = WireFrames::new(rx);
+        let queue = ReceiveQueue::new(None);
+        let data = Message::new(
+            [2; 6].into(),
+            [3; 6].into(),
+            PacketType::Data(Data::Downstream(
+                ToDownstream::new(&[1; 6], [4; 6].into(), &[0; 14]).with_priority(7),
+            )),
+        );
+        let heartbeat = Message::new(
+            [1; 6].into(),
+            [255; 6].into(),
+            PacketType::Control(Control::Heartbeat(Heartbeat::new(
+                Duration::default(),
+                0,
+                [1; 6].into(),
+            ))),
+        );
+        for msg in [&data, &heartbeat] {
+            let wire: Vec<Vec<u8>> = msg.into();
+            let wire = wire.concat();
+            queue.push(Instant::now());
+            tx.send((wire.clone(), wire.len())).await.expect("queued");
+        }
+
+        let mut control = Vec::new();
+        for _ in 0..2 {
+            let _ = wire_traffic(&mut frames, &queue, |pkt, size| {
+                control.push(Message::is_control_frame(&pkt[..size]));
+                async { Ok(None) }
+            })
+            .await;
+        }
+
+        assert_eq!(control, vec![true, false]);
+    }
+
+    #[tokio::test]
+    async fn full_tap_frames_survive_the_wire_buffers() {
+        let mut args = test_helpers::args(NodeType::Obu);
+        args.bind = "eth0".to_string();
+        // Largest TAP MTU whose frames cross the wire whole
+        args.mtu = i32::from(args.wire_mtu) - DATA_OVERHEAD as i32;
+        assert_eq!(args.validate(), Ok(()));
+
+        let size = tap_buffer_size(&args).expect("valid size");
+        let frame: Vec<u8> = (0..size).map(|x| (x % 251) as u8).collect();
+        let msg = Message::new(
+            [2; 6].into(),
+            [3; 6].into(),
+            PacketType::Data(Data::Downstream(ToDownstream::new(
+                &[1; 6],
+                [3; 6].into(),
+                &frame,
+            ))),
+        );
+        let wire: Vec<Vec<u8>> = (&msg).into();
+        let wire = wire.concat();
+        let mut buf = vec![0u8; wire_frame_size(&args)];
+        buf[..wire.len()].copy_from_slice(&wire);
+
+        let (tx, rx) = mpsc::channel(1);
+        let mut frames = WireFrames::new(rx);
+        let queue = ReceiveQueue::new(None);
+        queue.push(Instant::now());
+        tx.send((buf, wire.len())).await.expect("queued");
+        let mut received = None;
+        let _ = wire_traffic(&mut frames, &queue, |pkt, size| {
+            let msg = Message::try_from(&pkt[..size]).expect("valid frame");
+            let PacketType::Data(Data::Downstream(down)) = msg.get_packet_type() else {
+                panic!("not a downstream frame");
+            };
+            received = Some(down.data().to_vec());
+            async { Ok(None) }
+        })
+        .await;
+
+        assert_eq!(received, Some(frame));
+    }
+}
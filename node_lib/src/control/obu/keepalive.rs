@@ -0,0 +1,82 @@
+use crate::control::lock;
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Tracks TAP activity so an idle OBU sends a keepalive upstream once per
+/// `interval` without traffic, keeping upstream state warm.
+#[derive(Debug)]
+pub struct Keepalive {
+    interval: Duration,
+    last_activity: Mutex<Instant>,
+    sent: AtomicU64,
+}
+
+impl Keepalive {
+    pub fn new(interval: Duration, now: Instant) -> Self {
+        Self {
+            interval,
+            last_activity: Mutex::new(now),
+            sent: AtomicU64::new(0),
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Records real upstream traffic, postponing the next keepalive
+    pub fn touch(&self, now: Instant) {
+        let mut last = lock::lock(&self.last_activity);
+        *last = (*last).max(now);
+    }
+
+    /// Whether a keepalive is due at `now`. A due keepalive counts as
+    /// activity and is recorded as sent.
+    pub fn due(&self, now: Instant) -> bool {
+        let mut last = lock::lock(&self.last_activity);
+        if now.saturating_duration_since(*last) < self.interval {
+            return false;
+        }
+
+        *last = now;
+        self.sent.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+
+    pub fn sent(&self) -> u64 {
+        self.sent.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Keepalive;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn keepalives_are_sent_only_while_tap_is_idle() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let keepalive = Keepalive::new(Duration::from_millis(100), start);
+
+        assert!(!keepalive.due(at(50)));
+        assert!(keepalive.due(at(100)));
+        assert!(!keepalive.due(at(150)));
+        assert!(keepalive.due(at(200)));
+        assert_eq!(keepalive.sent(), 2);
+
+        for ms in (220..=600).step_by(40) {
+            keepalive.touch(at(ms));
+            assert!(!keepalive.due(at(ms + 20)));
+        }
+        assert_eq!(keepalive.sent(), 2);
+
+        assert!(keepalive.due(at(700)));
+        assert_eq!(keepalive.sent(), 3);
+    }
+}
@@ -1,25 +1,48 @@
+mod keepalive;
+mod passive;
 mod routing;
 mod session;
 
 use super::node::ReplyType;
 use crate::{
-    control::{node, obu::session::Session},
+    args::NodeParameters,
+    control::{
+        capture::Capture,
+        compression::{self, Compressor},
+        counters::{NodeCounters, PacketCounters},
+        drops::{self, DropLog, DropReason, DropRecord},
+        fragment::{Fragmenter, Reassembly},
+        ingress::IngressLoss,
+        lock,
+        node::{self, HeaderOnlyFilter},
+        obu::{keepalive::Keepalive, passive::Passive, session::Session},
+        receive_queue::{QueueDepth, ReceiveQueue},
+        route::Route,
+        sequence::{SequenceGaps, SequenceNumbers},
+        snapshot::RouteEntry,
+        tasks::Tasks,
+        traffic::{self, ClassCounters, ClassCounts},
+    },
     messages::{
         control::Control,
         data::{Data, ToUpstream},
         message::Message,
         packet_type::PacketType,
     },
+    position::Position,
     Args,
 };
 use anyhow::{anyhow, Result};
 use common::{device::Device, network_interface::NetworkInterface};
 use mac_address::MacAddress;
-use routing::Routing;
+pub use passive::ObservedFrames;
+pub(crate) use routing::Routing;
+pub use routing::UpstreamChange;
 use std::{
     sync::{Arc, RwLock},
-    time::Instant,
+    time::{Duration, Instant},
 };
+use tokio::sync::broadcast;
 use tokio_tun::Tun;
 
 pub struct Obu {
@@ -28,6 +51,21 @@ pub struct Obu {
     tun: Arc<Tun>,
     device: Arc<Device>,
     session: Arc<Session>,
+    ingress: Arc<IngressLoss>,
+    receive_queue: Arc<ReceiveQueue>,
+    sequence: Arc<SequenceNumbers>,
+    gaps: Arc<SequenceGaps>,
+    classes: Arc<ClassCounters>,
+    counters: Arc<PacketCounters>,
+    header_only: HeaderOnlyFilter,
+    drops: Arc<DropLog>,
+    fragmenter: Arc<Fragmenter>,
+    compressor: Arc<Compressor>,
+    reassembly: Reassembly,
+    capture: Option<Arc<Capture>>,
+    tasks: Arc<Tasks>,
+    passive: Option<Arc<Passive>>,
+    keepalive: Option<Arc<Keepalive>>,
 }
 
 impl Obu {
@@ -35,6 +73,42 @@ impl Obu {
         let boot = Instant::now();
         let obu = Arc::new(Self {
             routing: Arc::new(RwLock::new(Routing::new(&args, &boot)?)),
+            ingress: IngressLoss::new(
+                args.node_params.ingress_loss,
+                args.node_params.ingress_loss_seed,
+            )
+            .into(),
+            receive_queue: ReceiveQueue::new(args.node_params.receive_queue_high_watermark).into(),
+            sequence: SequenceNumbers::default().into(),
+            gaps: SequenceGaps::default().into(),
+            classes: ClassCounters::default().into(),
+            counters: PacketCounters::default().into(),
+            header_only: HeaderOnlyFilter::new(args.node_params.drop_header_only_frames),
+            drops: DropLog::default().into(),
+            fragmenter: Fragmenter::new(device.mac_address(), args.node_params.fragment_size)
+                .into(),
+            compressor: Compressor::new(args.node_params.compress).into(),
+            reassembly: Reassembly::new(Duration::from_millis(
+                args.node_params.fragment_timeout.into(),
+            )),
+            capture: args
+                .capture_path
+                .as_deref()
+                .map(Capture::create)
+                .transpose()?
+                .map(Arc::new),
+            tasks: Tasks::default().into(),
+            passive: args
+                .node_params
+                .passive
+                .then(|| Passive::new(args.node_params.passive_replies).into()),
+            keepalive: args
+                .node_params
+                .keepalive_interval
+                .filter(|_| !args.node_params.passive)
+                .map(|interval| {
+                    Keepalive::new(Duration::from_millis(interval.into()), boot).into()
+                }),
             args,
             tun: tun.clone(),
             device,
@@ -43,29 +117,162 @@ impl Obu {
 
         tracing::info!(?obu.args, "Setup Obu");
         obu.session_task()?;
+        obu.keepalive_task()?;
+        obu.route_expiry_task()?;
         Obu::wire_traffic_task(obu.clone())?;
         Ok(obu)
     }
 
+    pub fn upstream_path(&self) -> Vec<MacAddress> {
+        lock::read(&self.routing).upstream_path()
+    }
+
+    /// Notifies every change of the upstream next hop, as an alternative to
+    /// polling [`Obu::upstream_path`]
+    pub fn subscribe_upstream_changes(&self) -> broadcast::Receiver<UpstreamChange> {
+        lock::read(&self.routing).subscribe_upstream_changes()
+    }
+
+    /// Position `mac` advertised in its latest heartbeat, if any
+    pub fn position_of(&self, mac: MacAddress) -> Option<Position> {
+        lock::read(&self.routing).position_of(mac)
+    }
+
+    /// Most recent changes of the upstream next hop, oldest first
+    pub fn upstream_changes(&self) -> Vec<UpstreamChange> {
+        lock::read(&self.routing).upstream_changes()
+    }
+
+    pub fn routing_snapshot(&self) -> Vec<RouteEntry> {
+        lock::read(&self.routing).routing_snapshot()
+    }
+
+    /// Frames received from the wire and waiting to be processed
+    pub fn receive_queue(&self) -> QueueDepth {
+        self.receive_queue.depth()
+    }
+
+    /// Data frames addressed to this node missing from their flow sequence
+    pub fn lost_frames(&self) -> u64 {
+        self.gaps.lost_frames()
+    }
+
+    /// Data frames handled per traffic class
+    pub fn traffic_classes(&self) -> ClassCounts {
+        self.classes.counts()
+    }
+
+    /// Frames received and sent on the wire and on the TAP
+    pub fn counters(&self) -> NodeCounters {
+        self.counters.counts()
+    }
+
+    /// Data frames dropped for carrying no payload past the ethernet header
+    pub fn header_only_frames(&self) -> u64 {
+        self.header_only.dropped()
+    }
+
+    /// Up to `limit` data frames dropped most recently, newest first
+    pub fn recent_drops(&self, limit: usize) -> Vec<DropRecord> {
+        self.drops.recent(limit)
+    }
+
+    /// Frames received so far, when running in passive mode
+    pub fn observed_frames(&self) -> Option<ObservedFrames> {
+        self.passive.as_ref().map(|passive| passive.observed())
+    }
+
+    /// Heartbeats ignored for arriving faster than the minimum interval
+    pub fn throttled_heartbeats(&self) -> u64 {
+        lock::read(&self.routing).throttled_heartbeats()
+    }
+
+    /// Share of heartbeats missed from every source heard from
+    pub fn heartbeat_loss(&self) -> Vec<(MacAddress, f64)> {
+        lock::read(&self.routing).heartbeat_loss()
+    }
+
+    /// Heartbeat replies left out for RSUs further than the nearest one
+    pub fn suppressed_replies(&self) -> u64 {
+        lock::read(&self.routing).suppressed_replies()
+    }
+
+    /// Keepalives sent upstream while the TAP was idle
+    pub fn keepalives_sent(&self) -> u64 {
+        self.keepalive
+            .as_ref()
+            .map_or(0, |keepalive| keepalive.sent())
+    }
+
+    pub fn update_routing_params(&self, params: NodeParameters) -> Result<()> {
+        lock::write(&self.routing).update_params(params)
+    }
+
+    pub fn set_upstream_blacklist(&self, blacklist: Vec<MacAddress>) {
+        lock::write(&self.routing).set_upstream_blacklist(blacklist)
+    }
+
+    /// Stops every task of the node, returning once they finished
+    pub async fn shutdown(&self) {
+        self.tasks.shutdown().await;
+        tracing::info!("Obu shut down");
+    }
+
     fn wire_traffic_task(obu: Arc<Self>) -> Result<()> {
         let device = obu.device.clone();
         let tun = obu.tun.clone();
-        tokio::task::spawn(async move {
+        let retries = obu.args.send_retries;
+        let control_priority = obu.args.node_params.control_priority;
+        let queue = obu.receive_queue.clone();
+        let counters = obu.counters.clone();
+        let capture = obu.capture.clone();
+        let tasks = obu.tasks.clone();
+        let mut frames = node::receive_frames(
+            &tasks,
+            device.clone(),
+            node::wire_frame_size(&obu.args),
+            queue.clone(),
+            capture.clone(),
+        );
+        tasks.spawn(async move {
             loop {
                 let obu = obu.clone();
-                let messages = node::wire_traffic(&device, |pkt, size| {
+                let messages = node::wire_traffic(&mut frames, &queue, |pkt, size| {
                     async move {
+                        if obu.ingress.should_drop() {
+                            tracing::trace!(dropped = obu.ingress.dropped(), "ingress loss");
+                            return Ok(None);
+                        }
+
+                        obu.counters.wire_received();
                         let Ok(msg) = Message::try_from(&pkt[..size]) else {
                             return Ok(None);
                         };
 
-                        let response = obu.handle_msg(&msg).await;
+                        if is_isolated_from(obu.args.node_params.only_rsu, &msg) {
+                            return Ok(None);
+                        }
+
+                        let mut response = obu.handle_msg(&msg).await;
+                        if let Some(passive) = &obu.passive {
+                            passive.record(&msg);
+                            response = response.map(|r| passive.filter(r));
+                        }
                         tracing::trace!(incoming = ?msg, outgoing = ?node::get_msgs(&response), "transaction");
                         response
                     }
                 }).await;
                 if let Ok(Some(messages)) = messages {
-                    let _ = node::handle_messages(messages, &tun, &device).await;
+                    counters.record_replies(&messages);
+                    let _ = node::handle_messages(
+                        messages,
+                        &tun,
+                        &device,
+                        retries,
+                        control_priority,
+                        capture.as_deref(),
+                    )
+                    .await;
                 }
             }
         });
@@ -73,40 +280,162 @@ impl Obu {
     }
 
     fn session_task(&self) -> Result<()> {
+        let buffer_size = node::tap_buffer_size(&self.args)?;
         let routing = self.routing.clone();
         let session = self.session.clone();
         let device = self.device.clone();
         let tun = self.tun.clone();
-        tokio::task::spawn(async move {
+        let retries = self.args.send_retries;
+        let control_priority = self.args.node_params.control_priority;
+        let clamp = self.args.node_params.clamp_path_mtu;
+        let passive = self.args.node_params.passive;
+        let data_priority = self.args.node_params.data_priority;
+        let keepalive = self.keepalive.clone();
+        let sequence = self.sequence.clone();
+        let classes = self.classes.clone();
+        let counters = self.counters.clone();
+        let capture = self.capture.clone();
+        let drops = self.drops.clone();
+        let fragmenter = self.fragmenter.clone();
+        let compressor = self.compressor.clone();
+        let tag_management = self.args.node_params.tag_management_traffic;
+        self.tasks.spawn(async move {
             loop {
                 let devicec = device.clone();
                 let routing = routing.clone();
+                let keepalive = keepalive.clone();
+                let sequence = sequence.clone();
+                let classes = classes.clone();
+                let tap_counters = counters.clone();
+                let drops = drops.clone();
+                let fragmenter = fragmenter.clone();
+                let compressor = compressor.clone();
                 let messages = session
-                    .process(|x, size| async move {
+                    .process(buffer_size, &device, |x, size| async move {
+                        tap_counters.tap_received();
+                        if passive {
+                            return Ok(None);
+                        }
+
                         let y: &[u8] = &x[..size];
-                        let Some(upstream) = routing.read().unwrap().get_route_to(None) else {
+                        let routing = lock::read(&routing);
+                        let source = Some(devicec.mac_address());
+                        let Some(upstream) = routing.get_upstream_route(node::flow_hash(y)) else {
+                            drops.record(DropReason::NoRoute, source, drops::mac_of(y));
                             return Ok(None);
                         };
 
-                        let outgoing = vec![ReplyType::Wire(
-                            (&Message::new(
-                                devicec.mac_address(),
-                                upstream.mac,
-                                PacketType::Data(Data::Upstream(ToUpstream::new(
+                        let class = traffic::classify(y, tag_management);
+                        let sequence = sequence.next(devicec.mac_address(), y);
+                        let compressed = compressor.compress(y);
+                        let path_mtu = routing.path_mtu(None);
+                        let wire = fragmenter
+                            .payloads(
+                                compressed.as_deref().unwrap_or(y),
+                                node::payload_budget(clamp, path_mtu),
+                            )
+                            .iter()
+                            .map(|(fragment, payload)| {
+                                (&Message::new(
                                     devicec.mac_address(),
-                                    y,
-                                ))),
-                            ))
-                                .into(),
-                        )];
+                                    upstream.mac,
+                                    PacketType::Data(Data::Upstream(
+                                        ToUpstream::new(devicec.mac_address(), payload)
+                                            .with_priority(data_priority)
+                                            .with_class(class)
+                                            .with_sequence(sequence)
+                                            .with_fragment(*fragment)
+                                            .with_compressed(compressed.is_some()),
+                                    )),
+                                ))
+                                    .into()
+                            })
+                            .collect();
+                        let Some(outgoing) = node::within_path_mtu(clamp, wire, path_mtu) else {
+                            drops.record(DropReason::PathMtu, source, drops::mac_of(y));
+                            return Ok(None);
+                        };
+
+                        if let Some(keepalive) = keepalive {
+                            keepalive.touch(Instant::now());
+                        }
+
+                        classes.record(class);
                         tracing::trace!(?outgoing, "outgoing from tap");
                         Ok(Some(outgoing))
                     })
                     .await;
 
                 if let Ok(Some(messages)) = messages {
-                    let _ = node::handle_messages(messages, &tun, &device).await;
+                    counters.record_replies(&messages);
+                    let _ = node::handle_messages(
+                        messages,
+                        &tun,
+                        &device,
+                        retries,
+                        control_priority,
+                        capture.as_deref(),
+                    )
+                    .await;
+                }
+            }
+        });
+        Ok(())
+    }
+
+    fn keepalive_task(&self) -> Result<()> {
+        let Some(keepalive) = self.keepalive.clone() else {
+            return Ok(());
+        };
+        let capture = self.capture.clone();
+        let routing = self.routing.clone();
+        let device = self.device.clone();
+        let tun = self.tun.clone();
+        let retries = self.args.send_retries;
+        let control_priority = self.args.node_params.control_priority;
+        self.tasks.spawn(async move {
+            loop {
+                let _ = tokio_timerfd::sleep(keepalive.interval()).await;
+                let upstream = lock::read(&routing).get_route_to(None);
+                let Some(upstream) = upstream else {
+                    continue;
+                };
+
+                if !keepalive.due(Instant::now()) {
+                    continue;
                 }
+
+                let msg = Message::new(
+                    device.mac_address(),
+                    upstream.mac,
+                    PacketType::Data(Data::Upstream(ToUpstream::new(device.mac_address(), &[]))),
+                );
+                tracing::trace!(?msg, "keepalive");
+                let messages = vec![ReplyType::Wire((&msg).into())];
+                let _ = node::handle_messages(
+                    messages,
+                    &tun,
+                    &device,
+                    retries,
+                    control_priority,
+                    capture.as_deref(),
+                )
+                .await;
+            }
+        });
+        Ok(())
+    }
+
+    fn route_expiry_task(&self) -> Result<()> {
+        let Some(ttl) = self.args.node_params.route_ttl else {
+            return Ok(());
+        };
+        let period = (Duration::from_millis(ttl.into()) / 2).max(Duration::from_millis(1));
+        let routing = self.routing.clone();
+        self.tasks.spawn(async move {
+            loop {
+                let _ = tokio_timerfd::sleep(period).await;
+                lock::write(&routing).expire_routes();
             }
         });
         Ok(())
@@ -114,22 +443,66 @@ impl Obu {
 
     async fn handle_msg(&self, msg: &Message<'_>) -> Result<Option<Vec<ReplyType>>> {
         match msg.get_packet_type() {
+            PacketType::Data(Data::Upstream(buf))
+                if !buf.is_fragment()
+                    && !buf.is_compressed()
+                    && self.header_only.should_drop(buf.data()) =>
+            {
+                self.drops
+                    .record(DropReason::HeaderOnly, drops::mac_of(buf.source()), None);
+                Ok(None)
+            }
+            PacketType::Data(Data::Downstream(buf))
+                if !buf.is_fragment()
+                    && !buf.is_compressed()
+                    && self.header_only.should_drop(buf.data()) =>
+            {
+                self.drops.record(
+                    DropReason::HeaderOnly,
+                    drops::mac_of(buf.source()),
+                    drops::mac_of(buf.destination()),
+                );
+                Ok(None)
+            }
             PacketType::Data(Data::Upstream(buf)) => {
-                let routing = self.routing.read().unwrap();
-                let Some(upstream) = routing.get_route_to(None) else {
+                self.classes.record(buf.class());
+                let source = drops::mac_of(buf.source());
+                let routing = lock::read(&self.routing);
+                let flow = if buf.is_fragment() {
+                    node::flow_hash(buf.source())
+                } else {
+                    node::flow_hash(buf.data())
+                };
+                let Some(upstream) = routing.get_upstream_route(flow) else {
+                    self.drops
+                        .record(DropReason::NoRoute, source, drops::mac_of(buf.data()));
                     return Ok(None);
                 };
 
-                Ok(Some(vec![ReplyType::Wire(
-                    (&Message::new(
+                let wire: Vec<Vec<u8>> = (&Message::new(
+                    node::forwarding_source(
+                        self.args.node_params.rewrite_source_mac,
                         self.device.mac_address(),
-                        upstream.mac,
-                        PacketType::Data(Data::Upstream(buf.clone())),
-                    ))
-                        .into(),
-                )]))
+                        msg.from()?,
+                    ),
+                    upstream.mac,
+                    PacketType::Data(Data::Upstream(buf.clone())),
+                ))
+                    .into();
+                if !node::fits_path_mtu(
+                    self.args.node_params.clamp_path_mtu,
+                    &wire,
+                    routing.path_mtu(None),
+                ) {
+                    self.drops
+                        .record(DropReason::PathMtu, source, drops::mac_of(buf.data()));
+                    return Ok(None);
+                }
+
+                Ok(Some(vec![ReplyType::Wire(wire)]))
             }
             PacketType::Data(Data::Downstream(buf)) => {
+                self.classes.record(buf.class());
                 let destination: [u8; 6] = buf
                     .destination()
                     .get(0..6)
@@ -137,36 +510,342 @@ impl Obu {
                     .try_into()?;
                 let destination: MacAddress = destination.into();
                 if destination == self.device.mac_address() {
-                    return Ok(Some(vec![ReplyType::Tap(vec![buf.data().to_vec()])]));
+                    let source: [u8; 6] = buf
+                        .source()
+                        .get(0..6)
+                        .ok_or_else(|| anyhow!("error"))?
+                        .try_into()?;
+                    let frame = if buf.is_fragment() {
+                        let Some(frame) = self.reassembly.push(buf.data()) else {
+                            return Ok(None);
+                        };
+                        frame
+                    } else {
+                        buf.data().to_vec()
+                    };
+                    let frame = if buf.is_compressed() {
+                        match compression::decompress(&frame) {
+                            Ok(frame) => frame,
+                            Err(e) => {
+                                tracing::debug!(?e, "undecodable compressed frame dropped");
+                                self.drops.record(
+                                    DropReason::Malformed,
+                                    Some(source.into()),
+                                    Some(destination),
+                                );
+                                return Ok(None);
+                            }
+                        }
+                    } else {
+                        frame
+                    };
+                    self.gaps.record(source.into(), &frame, buf.sequence());
+                    let from = msg.from()?;
+                    let unconfirmed = lock::read(&self.routing).unconfirmed_upstream(from);
+                    if let Some(rsu) = unconfirmed {
+                        lock::write(&self.routing).confirm_upstream(rsu, from);
+                    }
+                    return Ok(Some(vec![ReplyType::Tap(vec![frame])]));
                 }
 
                 let target = destination;
-                let routing = self.routing.read().unwrap();
+                let source = drops::mac_of(buf.source());
+                let routing = lock::read(&self.routing);
                 Ok(Some({
-                    let Some(next_hop) = routing.get_route_to(Some(target)) else {
+                    let Some(next_hop) = next_hop_or_drop(&routing, &self.drops, source, target)
+                    else {
                         return Ok(None);
                     };
 
-                    vec![ReplyType::Wire(
-                        (&Message::new(
+                    let wire: Vec<Vec<u8>> = (&Message::new(
+                        node::forwarding_source(
+                            self.args.node_params.rewrite_source_mac,
                             self.device.mac_address(),
-                            next_hop.mac,
-                            PacketType::Data(Data::Downstream(buf.clone())),
-                        ))
-                            .into(),
-                    )]
+                            msg.from()?,
+                        ),
+                        next_hop.mac,
+                        PacketType::Data(Data::Downstream(buf.clone())),
+                    ))
+                        .into();
+                    if !node::fits_path_mtu(
+                        self.args.node_params.clamp_path_mtu,
+                        &wire,
+                        routing.path_mtu(Some(target)),
+                    ) {
+                        self.drops.record(DropReason::PathMtu, source, Some(target));
+                        return Ok(None);
+                    }
+
+                    vec![ReplyType::Wire(wire)]
                 }))
             }
-            PacketType::Control(Control::Heartbeat(_)) => self
-                .routing
-                .write()
-                .unwrap()
-                .handle_heartbeat(msg, self.device.mac_address()),
-            PacketType::Control(Control::HeartbeatReply(_)) => self
-                .routing
-                .write()
-                .unwrap()
-                .handle_heartbeat_reply(msg, self.device.mac_address()),
+            PacketType::Control(Control::Heartbeat(_)) => {
+                lock::write(&self.routing).handle_heartbeat(msg, self.device.mac_address())
+            }
+            PacketType::Control(Control::HeartbeatReply(_)) => {
+                lock::write(&self.routing).handle_heartbeat_reply(msg, self.device.mac_address())
+            }
         }
     }
 }
+
+/// Route towards `destination` for a frame forwarded downstream, recording a
+/// drop when there is none.
+fn next_hop_or_drop(
+    routing: &Routing,
+    drops: &DropLog,
+    source: Option<MacAddress>,
+    destination: MacAddress,
+) -> Option<Route> {
+    let route = routing.get_route_to(Some(destination));
+    if route.is_none() {
+        drops.record(DropReason::NoRoute, source, Some(destination));
+    }
+    route
+}
+
+/// Whether `msg` is a heartbeat from an RSU other than `only_rsu`, which an
+/// isolated OBU drops before it reaches routing.
+fn is_isolated_from(only_rsu: Option<MacAddress>, msg: &Message) -> bool {
+    let (Some(only_rsu), PacketType::Control(Control::Heartbeat(hb))) =
+        (only_rsu, msg.get_packet_type())
+    else {
+        return false;
+    };
+
+    let isolated = hb.source() != only_rsu;
+    if isolated {
+        tracing::trace!(source = %hb.source(), "heartbeat from ignored rsu");
+    }
+    isolated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_isolated_from, next_hop_or_drop, Obu};
+    use crate::{
+        args::NodeType,
+        control::{
+            drops::{DropLog, DropReason},
+            node::ReplyType,
+            obu::Routing,
+            route::RouteStatus,
+            rsu::Routing as RsuRouting,
+        },
+        messages::{
+            control::{heartbeat::Heartbeat, Control},
+            data::{Data, ToDownstream, ToUpstream},
+            message::Message,
+            packet_type::PacketType,
+        },
+        test_helpers,
+    };
+    use common::network_interface::NetworkInterface;
+    use mac_address::MacAddress;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn isolated_obu_only_routes_to_configured_rsu() {
+        let (rsu1, rsu2, obu) = (
+            MacAddress::new([1; 6]),
+            MacAddress::new([3; 6]),
+            MacAddress::new([2; 6]),
+        );
+        let mut args = test_helpers::args(NodeType::Obu);
+        args.node_params.only_rsu = Some(rsu2);
+        let mut routing = Routing::new(&args, &Instant::now()).expect("routing");
+        let rsu_args = test_helpers::args(NodeType::Rsu);
+        let mut rsus = [
+            (rsu1, RsuRouting::new(&rsu_args).expect("routing")),
+            (rsu2, RsuRouting::new(&rsu_args).expect("routing")),
+        ];
+
+        for (mac, rsu) in rsus.iter_mut() {
+            let hello: Vec<Vec<u8>> = (&rsu.send_heartbeat(*mac)).into();
+            let hello = hello.concat();
+            let msg = Message::try_from(&hello[..]).expect("valid frame");
+            if is_isolated_from(args.node_params.only_rsu, &msg) {
+                continue;
+            }
+            routing
+                .handle_heartbeat(&msg, obu)
+                .expect("handled heartbeat");
+        }
+
+        assert_eq!(
+            routing.get_route_to(None).map(|route| route.mac),
+            Some(rsu2)
+        );
+        assert!(routing.get_route_to(Some(rsu1)).is_none());
+        assert!(routing.get_route_to(Some(rsu2)).is_some());
+    }
+
+    #[test]
+    fn frames_without_route_are_logged_as_dropped() {
+        let routing =
+            Routing::new(&test_helpers::args(NodeType::Obu), &Instant::now()).expect("routing");
+        let drops = DropLog::default();
+        let (source, destination) = (MacAddress::new([1; 6]), MacAddress::new([4; 6]));
+
+        assert!(next_hop_or_drop(&routing, &drops, Some(source), destination).is_none());
+
+        let recent = drops.recent(10);
+        assert_eq!(recent.len(), 1);
+        assert_eq!(recent[0].reason, DropReason::NoRoute);
+        assert_eq!(recent[0].source, Some(source));
+        assert_eq!(recent[0].destination, Some(destination));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs CAP_NET_ADMIN to create TAPs"]
+    async fn relayed_frames_must_fit_the_path_mtu_with_their_headers() {
+        let interfaces = test_helpers::node_interfaces();
+        let obu = Obu::new(
+            test_helpers::args(NodeType::Obu),
+            interfaces.tun.clone(),
+            interfaces.device.clone(),
+        )
+        .expect("obu");
+        let (rsu, child) = (MacAddress::new([1; 6]), MacAddress::new([7; 6]));
+        let heartbeat = Message::new(
+            rsu,
+            [255; 6].into(),
+            PacketType::Control(Control::Heartbeat(
+                Heartbeat::new(Duration::default(), 0, rsu).with_mtu(1500),
+            )),
+        );
+        obu.handle_msg(&heartbeat).await.expect("handled heartbeat");
+
+        let relay = |len: usize| {
+            let frame = vec![0x42; len];
+            let msg: Vec<Vec<u8>> = (&Message::new(
+                child,
+                interfaces.device.mac_address(),
+                PacketType::Data(Data::Upstream(ToUpstream::new(child, &frame))),
+            ))
+                .into();
+            msg.concat()
+        };
+
+        // A full frame of the 1459 byte TAP MTU fits the 1500 byte wire
+        for len in [1473, 1486] {
+            let msg = relay(len);
+            let msg = Message::try_from(&msg[..]).expect("valid frame");
+            let relayed = obu
+                .handle_msg(&msg)
+                .await
+                .expect("handled frame")
+                .expect("relayed upstream");
+            let [ReplyType::Wire(wire)] = &relayed[..] else {
+                panic!("not relayed as a single wire message");
+            };
+            // 28 bytes of ethernet and data headers wrap the frame
+            assert_eq!(wire.concat().len(), len + 28);
+        }
+
+        let oversized = relay(1487);
+        let msg = Message::try_from(&oversized[..]).expect("valid frame");
+        assert!(obu.handle_msg(&msg).await.expect("handled frame").is_none());
+        let drops = obu.recent_drops(1);
+        assert_eq!(drops[0].reason, DropReason::PathMtu);
+        assert_eq!(drops[0].source, Some(child));
+
+        obu.shutdown().await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs CAP_NET_ADMIN to create TAPs"]
+    async fn wire_traffic_is_captured_as_it_is_received_and_sent() {
+        let interfaces = test_helpers::node_interfaces();
+        let path = std::env::temp_dir().join(format!("obu_capture_{}.pcap", std::process::id()));
+        let path = path.to_string_lossy().to_string();
+        let mut args = test_helpers::args(NodeType::Obu);
+        args.capture_path = Some(path.clone());
+        let obu = Obu::new(args, interfaces.tun.clone(), interfaces.device.clone()).expect("obu");
+        let rsu = MacAddress::new([1; 6]);
+        let heartbeat: Vec<Vec<u8>> = (&Message::new(
+            rsu,
+            [255; 6].into(),
+            PacketType::Control(Control::Heartbeat(Heartbeat::new(
+                Duration::default(),
+                0,
+                rsu,
+            ))),
+        ))
+            .into();
+        let heartbeat = heartbeat.concat();
+        interfaces
+            .wire
+            .send(&heartbeat)
+            .await
+            .expect("sent heartbeat");
+
+        // The heartbeat as received, then the reply and the relayed copy
+        let records = {
+            let path = path.clone();
+            tokio::task::spawn_blocking(move || test_helpers::capture_records(&path, 3))
+                .await
+                .expect("read capture")
+        };
+        obu.shutdown().await;
+        let _ = std::fs::remove_file(&path);
+        // Leave out data the kernel sends through the TAPs on its own
+        let frames: Vec<_> = records
+            .iter()
+            .map(|record| &record[16..])
+            .filter(|frame| Message::is_control_frame(frame))
+            .collect();
+        assert_eq!(frames.first(), Some(&&heartbeat[..]));
+        let own = interfaces.device.mac_address().bytes();
+        let sent = frames.iter().filter(|frame| frame[6..12] == own).count();
+        assert_eq!(sent, 2, "{} frames captured", frames.len());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs CAP_NET_ADMIN to create TAPs"]
+    async fn upstream_route_is_confirmed_once_the_rsu_routes_to_this_node() {
+        let interfaces = test_helpers::node_interfaces();
+        let obu = Obu::new(
+            test_helpers::args(NodeType::Obu),
+            interfaces.tun.clone(),
+            interfaces.device.clone(),
+        )
+        .expect("obu");
+        let rsu = MacAddress::new([1; 6]);
+        let own = interfaces.device.mac_address();
+        let heartbeat = Message::new(
+            rsu,
+            [255; 6].into(),
+            PacketType::Control(Control::Heartbeat(Heartbeat::new(
+                Duration::default(),
+                0,
+                rsu,
+            ))),
+        );
+        obu.handle_msg(&heartbeat).await.expect("handled heartbeat");
+        let status = || {
+            obu.routing_snapshot()
+                .into_iter()
+                .find(|entry| entry.destination == rsu)
+                .map(|entry| entry.status)
+        };
+        assert_eq!(status(), Some(RouteStatus::Tentative));
+
+        let origin = rsu.bytes();
+        let frame = [[2; 6], [4; 6]].concat().repeat(4);
+        let msg = Message::new(
+            rsu,
+            own,
+            PacketType::Data(Data::Downstream(ToDownstream::new(&origin, own, &frame))),
+        );
+        let delivered = obu
+            .handle_msg(&msg)
+            .await
+            .expect("handled frame")
+            .expect("delivered");
+        assert!(matches!(&delivered[..], [ReplyType::Tap(_)]));
+        assert_eq!(status(), Some(RouteStatus::Confirmed));
+
+        obu.shutdown().await;
+    }
+}
@@ -0,0 +1,167 @@
+use crate::{
+    control::node::ReplyType,
+    messages::{control::Control, data::Data, message::Message, packet_type::PacketType},
+};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Frames received by a passive node, by kind
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ObservedFrames {
+    pub heartbeats: u64,
+    pub heartbeat_replies: u64,
+    pub data: u64,
+}
+
+/// Listen-only mode: frames are counted and still feed the routing table,
+/// but nothing is put on the wire except, optionally, heartbeat replies so
+/// the node remains discoverable.
+#[derive(Debug, Default)]
+pub struct Passive {
+    replies: bool,
+    heartbeats: AtomicU64,
+    heartbeat_replies: AtomicU64,
+    data: AtomicU64,
+}
+
+impl Passive {
+    pub fn new(replies: bool) -> Self {
+        Self {
+            replies,
+            ..Default::default()
+        }
+    }
+
+    pub fn record(&self, msg: &Message) {
+        let counter = match msg.get_packet_type() {
+            PacketType::Control(Control::Heartbeat(_)) => &self.heartbeats,
+            PacketType::Control(Control::HeartbeatReply(_)) => &self.heartbeat_replies,
+            PacketType::Data(Data::Upstream(_) | Data::Downstream(_)) => &self.data,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Drops every wire reply the node is not allowed to send
+    pub fn filter(&self, replies: Option<Vec<ReplyType>>) -> Option<Vec<ReplyType>> {
+        let replies: Vec<_> = replies?
+            .into_iter()
+            .filter(|reply| match reply {
+                ReplyType::Tap(_) => true,
+                ReplyType::Wire(pkt) => self.replies && Self::is_heartbeat_reply(pkt),
+            })
+            .collect();
+        (!replies.is_empty()).then_some(replies)
+    }
+
+    fn is_heartbeat_reply(pkt: &[Vec<u8>]) -> bool {
+        let frame = pkt.concat();
+        Message::try_from(&frame[..]).is_ok_and(|msg| {
+            matches!(
+                msg.get_packet_type(),
+                PacketType::Control(Control::HeartbeatReply(_))
+            )
+        })
+    }
+
+    pub fn observed(&self) -> ObservedFrames {
+        ObservedFrames {
+            heartbeats: self.heartbeats.load(Ordering::Relaxed),
+            heartbeat_replies: self.heartbeat_replies.load(Ordering::Relaxed),
+            data: self.data.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ObservedFrames, Passive};
+    use crate::{
+        args::NodeType,
+        control::{node::ReplyType, obu::Routing},
+        messages::{
+            control::{heartbeat::Heartbeat, Control},
+            data::{Data, ToDownstream},
+            message::Message,
+            packet_type::PacketType,
+        },
+        test_helpers,
+    };
+    use mac_address::MacAddress;
+    use std::time::{Duration, Instant};
+
+    fn heartbeat() -> Message<'static> {
+        Message::new(
+            [1; 6].into(),
+            [255; 6].into(),
+            PacketType::Control(Control::Heartbeat(Heartbeat::new(
+                Duration::from_millis(1),
+                0,
+                [1; 6].into(),
+            ))),
+        )
+    }
+
+    fn wire_count(replies: &Option<Vec<ReplyType>>) -> usize {
+        replies
+            .iter()
+            .flatten()
+            .filter(|r| matches!(r, ReplyType::Wire(_)))
+            .count()
+    }
+
+    #[test]
+    fn passive_node_records_frames_but_emits_nothing() {
+        let mut routing =
+            Routing::new(&test_helpers::args(NodeType::Obu), &Instant::now()).expect("routing");
+        let passive = Passive::new(false);
+        let obu = MacAddress::new([2; 6]);
+
+        let hb = heartbeat();
+        passive.record(&hb);
+        let replies = routing.handle_heartbeat(&hb, obu).expect("handled");
+        assert!(wire_count(&replies) > 0);
+        assert_eq!(wire_count(&passive.filter(replies)), 0);
+        assert!(routing.get_route_to(None).is_some());
+
+        let data = Message::new(
+            [1; 6].into(),
+            obu,
+            PacketType::Data(Data::Downstream(ToDownstream::new(
+                &[1; 6],
+                [3; 6].into(),
+                &[0xaa; 32],
+            ))),
+        );
+        passive.record(&data);
+
+        assert_eq!(
+            passive.observed(),
+            ObservedFrames {
+                heartbeats: 1,
+                heartbeat_replies: 0,
+                data: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn passive_node_may_answer_heartbeats() {
+        let mut routing =
+            Routing::new(&test_helpers::args(NodeType::Obu), &Instant::now()).expect("routing");
+        let passive = Passive::new(true);
+
+        let replies = routing
+            .handle_heartbeat(&heartbeat(), [2; 6].into())
+            .expect("handled");
+        let replies = passive.filter(replies).expect("heartbeat reply kept");
+        assert_eq!(replies.len(), 1);
+        let ReplyType::Wire(pkt) = &replies[0] else {
+            panic!("not a wire reply");
+        };
+        let frame = pkt.concat();
+        let msg = Message::try_from(&frame[..]).expect("valid frame");
+        assert!(matches!(
+            msg.get_packet_type(),
+            PacketType::Control(Control::HeartbeatReply(_))
+        ));
+    }
+}
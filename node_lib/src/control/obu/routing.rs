@@ -1,20 +1,32 @@
 use crate::{
-    control::route::Route,
+    args::{NodeParameters, RouteMetric, UpstreamMode},
+    control::{
+        lock, node,
+        route::{load_warm_routes, resize_history, LatencySamples, Route, RouteStatus},
+        snapshot::RouteEntry,
+    },
     messages::{
         control::{heartbeat::HeartbeatReply, Control},
         message::Message,
         packet_type::PacketType,
     },
+    position::Position,
     Args, ReplyType,
 };
 use anyhow::{bail, Result};
 use indexmap::IndexMap;
+use itertools::Itertools;
 use mac_address::MacAddress;
 use std::{
-    collections::{hash_map::Entry, HashMap},
+    collections::{
+        hash_map::{DefaultHasher, Entry},
+        HashMap, HashSet, VecDeque,
+    },
+    hash::{Hash, Hasher},
     sync::{Arc, Mutex},
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
+use tokio::sync::broadcast;
 use tracing::Level;
 
 #[derive(Debug)]
@@ -24,6 +36,23 @@ struct Target {
     latency: Option<Duration>,
 }
 
+const UPSTREAM_CHANGE_HISTORY: usize = 64;
+
+/// How far the mean delay of a next hop behind the first copies of the
+/// heartbeats may trail the fastest next hop's for both to be equal-cost
+const ECMP_LATENCY_TOLERANCE: Duration = Duration::from_millis(5);
+
+#[derive(Debug, Clone)]
+pub struct UpstreamChange {
+    pub old: Option<MacAddress>,
+    pub new: MacAddress,
+    /// Hops through the old upstream, if it was still a candidate
+    pub old_hops: Option<u32>,
+    pub new_hops: u32,
+    pub reason: &'static str,
+    pub at: Duration,
+}
+
 #[derive(Debug)]
 pub struct Routing {
     args: Args,
@@ -36,12 +65,32 @@ pub struct Routing {
                 Duration,
                 MacAddress,
                 u32,
-                IndexMap<Duration, MacAddress>,
+                HashMap<MacAddress, (Duration, u32)>,
                 HashMap<MacAddress, Vec<Target>>,
             ),
         >,
     >,
     cached_upstream: Arc<Mutex<Option<MacAddress>>>,
+    /// Candidate beating the cached upstream by more than the handover
+    /// margin, with how many heartbeats in a row it did so
+    challenger: Mutex<Option<(MacAddress, u32)>>,
+    positions: HashMap<MacAddress, Position>,
+    mtu: u16,
+    path_mtus: HashMap<MacAddress, u16>,
+    /// Hops and relays of the latest heartbeat per source and next hop
+    upstream_relays: HashMap<(MacAddress, MacAddress), (u32, Vec<MacAddress>)>,
+    warm: HashMap<MacAddress, Route>,
+    /// Destinations and the next hops they were confirmed reachable through
+    confirmed: HashSet<(MacAddress, MacAddress)>,
+    forwarded_replies: HashMap<(MacAddress, u32, MacAddress), Duration>,
+    last_heartbeat: HashMap<MacAddress, Duration>,
+    throttled_heartbeats: u64,
+    heartbeat_hops: HashMap<MacAddress, (u32, Duration)>,
+    last_reply: HashMap<MacAddress, Duration>,
+    suppressed_replies: u64,
+    upstream_changes: Mutex<VecDeque<UpstreamChange>>,
+    change_notifier: broadcast::Sender<UpstreamChange>,
+    last_change_log: Mutex<Option<Instant>>,
 }
 
 impl Routing {
@@ -49,14 +98,67 @@ impl Routing {
         if args.node_params.hello_history == 0 {
             bail!("we need to be able to store at least 1 hello");
         }
+        let warm = match args.warm_routes {
+            Some(ref path) => load_warm_routes(path)?,
+            None => HashMap::default(),
+        };
         Ok(Self {
             args: args.clone(),
             boot: *boot,
             routes: HashMap::default(),
             cached_upstream: Arc::new(Mutex::new(None)),
+            challenger: Mutex::new(None),
+            positions: HashMap::default(),
+            mtu: node::advertised_mtu(args),
+            path_mtus: HashMap::default(),
+            upstream_relays: HashMap::default(),
+            warm,
+            confirmed: HashSet::default(),
+            forwarded_replies: HashMap::default(),
+            last_heartbeat: HashMap::default(),
+            throttled_heartbeats: 0,
+            heartbeat_hops: HashMap::default(),
+            last_reply: HashMap::default(),
+            suppressed_replies: 0,
+            upstream_changes: Mutex::new(VecDeque::with_capacity(UPSTREAM_CHANGE_HISTORY)),
+            change_notifier: broadcast::channel(UPSTREAM_CHANGE_HISTORY).0,
+            last_change_log: Mutex::new(None),
         })
     }
 
+    /// Applies a new parameter set. History buffers are resized keeping the
+    /// most recent samples.
+    pub fn update_params(&mut self, params: NodeParameters) -> Result<()> {
+        if params.hello_history == 0 {
+            bail!("we need to be able to store at least 1 hello");
+        }
+
+        let capacity = usize::try_from(params.hello_history)?;
+        self.routes
+            .values_mut()
+            .for_each(|history| resize_history(history, capacity));
+        self.args.node_params = params;
+        self.drop_blacklisted_upstream();
+        Ok(())
+    }
+
+    /// Replaces the set of MACs never selected as upstream or next hop.
+    pub fn set_upstream_blacklist(&mut self, blacklist: Vec<MacAddress>) {
+        self.args.node_params.upstream_blacklist = blacklist;
+        self.drop_blacklisted_upstream();
+    }
+
+    fn drop_blacklisted_upstream(&self) {
+        let mut cached = lock::lock(&self.cached_upstream);
+        if cached.is_some_and(|mac| self.args.node_params.upstream_blacklist.contains(&mac)) {
+            *cached = None;
+        }
+    }
+
+    fn is_blacklisted(&self, mac: &MacAddress) -> bool {
+        self.args.node_params.upstream_blacklist.contains(mac)
+    }
+
     pub fn handle_heartbeat(
         &mut self,
         pkt: &Message,
@@ -66,6 +168,19 @@ impl Routing {
             bail!("this is supposed to be a HeartBeat");
         };
 
+        if !self.is_fresh(message.timestamp()) {
+            tracing::debug!(
+                source = %message.source(),
+                id = message.id(),
+                "stale heartbeat rejected",
+            );
+            return Ok(None);
+        }
+
+        if self.is_throttled(message.source(), message.id()) {
+            return Ok(None);
+        }
+
         let old_route = self.get_route_to(Some(message.source()));
         let old_route_from = self.get_route_to(Some(pkt.from()?));
         let entry = self
@@ -83,7 +198,12 @@ impl Routing {
             entry.swap_remove_index(0);
         }
 
-        if let Some((_, _, _hops, _, _)) = entry.get(&message.id()) {
+        let duration = Instant::now().duration_since(self.boot);
+        if let Some((_, _, _hops, copies, _)) = entry.get_mut(&message.id()) {
+            // Later copies only tell how far their next hop trails
+            copies
+                .entry(pkt.from()?)
+                .or_insert((duration, message.hops()));
             return Ok(None);
             // So this makes us prioritize hops instead of latency
             // TODO: Is that preferable
@@ -92,14 +212,34 @@ impl Routing {
             // }
         }
 
-        let duration = Instant::now().duration_since(self.boot);
+        if let Some(position) = message.position() {
+            self.positions.insert(message.source(), position);
+        }
+
+        let path_mtu = message.mtu().map_or(self.mtu, |mtu| mtu.min(self.mtu));
+        self.path_mtus.insert(message.source(), path_mtu);
+        self.upstream_relays.insert(
+            (message.source(), pkt.from()?),
+            (message.hops(), message.relays()),
+        );
+
+        for confirmed in [message.source(), pkt.from()?] {
+            if let Some(warm) = self.warm.remove(&confirmed) {
+                tracing::debug!(
+                    to = %confirmed,
+                    was_through = %warm,
+                    "warm route replaced on heartbeat",
+                );
+            }
+        }
+
         entry.insert(
             message.id(),
             (
                 duration,
                 pkt.from()?,
                 message.hops(),
-                IndexMap::new(),
+                HashMap::from([(pkt.from()?, (duration, message.hops()))]),
                 HashMap::default(),
             ),
         );
@@ -125,11 +265,13 @@ impl Routing {
                 duration,
                 pkt.from()?,
                 1,
-                IndexMap::new(),
+                HashMap::from([(pkt.from()?, (duration, 1))]),
                 HashMap::default(),
             ),
         );
 
+        self.observe_challenger(message.source());
+
         match (old_route, self.get_route_to(Some(message.source()))) {
             (None, Some(new_route)) => {
                 tracing::event!(
@@ -182,26 +324,65 @@ impl Routing {
             }
         }
 
-        Ok(Some(vec![
-            ReplyType::Wire(
-                (&Message::new(
-                    mac,
-                    [255; 6].into(),
-                    PacketType::Control(Control::Heartbeat(message.clone())),
-                ))
-                    .into(),
-            ),
-            ReplyType::Wire(
+        let mut replies = vec![ReplyType::Wire(
+            (&Message::new(
+                mac,
+                [255; 6].into(),
+                PacketType::Control(Control::Heartbeat(
+                    message.clone().with_mtu(path_mtu).with_relay(mac),
+                )),
+            ))
+                .into(),
+        )];
+        if self.should_reply(message.source(), message.hops()) {
+            replies.push(ReplyType::Wire(
                 (&Message::new(
                     mac,
                     pkt.from()?,
-                    PacketType::Control(Control::HeartbeatReply(HeartbeatReply::from_sender(
-                        message, mac,
-                    ))),
+                    PacketType::Control(Control::HeartbeatReply(
+                        HeartbeatReply::from_sender(message, mac).with_mtu(self.mtu),
+                    )),
                 ))
                     .into(),
-            ),
-        ]))
+            ));
+        }
+        Ok(Some(replies))
+    }
+
+    /// Whether to reply to a heartbeat from `source` that travelled `hops`.
+    /// With reply suppression, RSUs further away than the nearest one heard
+    /// within the interval are not upstream candidates and only get a reply
+    /// once per interval, enough to keep their route to this node alive.
+    fn should_reply(&mut self, source: MacAddress, hops: u32) -> bool {
+        let Some(interval) = self.args.node_params.reply_suppression_interval else {
+            return true;
+        };
+
+        let now = Instant::now().duration_since(self.boot);
+        let interval = Duration::from_millis(interval.into());
+        self.heartbeat_hops.insert(source, (hops, now));
+        self.heartbeat_hops
+            .retain(|_, (_, at)| now.saturating_sub(*at) < interval);
+        let nearest = self.heartbeat_hops.values().map(|(hops, _)| *hops).min();
+        if nearest.is_none_or(|nearest| hops <= nearest) {
+            return true;
+        }
+
+        if let Some(last) = self.last_reply.get(&source) {
+            if now.saturating_sub(*last) < interval {
+                self.suppressed_replies += 1;
+                tracing::trace!(%source, hops, "heartbeat reply suppressed");
+                return false;
+            }
+        }
+
+        self.last_reply.insert(source, now);
+        true
+    }
+
+    /// Heartbeat replies left out for RSUs further than the nearest one
+    pub fn suppressed_replies(&self) -> u64 {
+        self.suppressed_replies
     }
 
     pub fn handle_heartbeat_reply(
@@ -269,15 +450,27 @@ impl Routing {
             }
         };
 
+        let next_upstream = *next_upstream;
         let sender = message.sender();
-        let reply = Ok(Some(vec![ReplyType::Wire(
-            (&Message::new(
-                mac,
-                *next_upstream,
-                PacketType::Control(Control::HeartbeatReply(message.clone())),
-            ))
-                .into(),
-        )]));
+        self.confirmed.insert((sender, pkt.from()?));
+        self.confirmed.insert((pkt.from()?, pkt.from()?));
+        let path_mtu = message.mtu().map_or(self.mtu, |mtu| mtu.min(self.mtu));
+        self.path_mtus.insert(sender, path_mtu);
+        let reply = if self.already_forwarded((message.source(), message.id(), sender), seen_at) {
+            tracing::trace!(%sender, id = message.id(), "duplicate heartbeat reply not forwarded");
+            Ok(None)
+        } else {
+            Ok(Some(vec![ReplyType::Wire(
+                (&Message::new(
+                    mac,
+                    next_upstream,
+                    PacketType::Control(Control::HeartbeatReply(
+                        message.clone().with_mtu(path_mtu),
+                    )),
+                ))
+                    .into(),
+            )]))
+        };
 
         match (old_route, self.get_route_to(Some(sender))) {
             (None, Some(new_route)) => {
@@ -334,26 +527,695 @@ impl Routing {
         reply
     }
 
-    pub fn get_route_to(&self, mac: Option<MacAddress>) -> Option<Route> {
-        let Some(mac) = mac else {
-            return self.cached_upstream.lock().unwrap().map(|mac| Route {
-                hops: 1,
-                mac,
-                latency: None,
-            });
+    /// Forgets heartbeats, and the routes learned through them, received
+    /// longer than the route TTL ago, along with what was kept about the
+    /// nodes no route leads to anymore. The cached upstream is dropped once no
+    /// remaining route goes through it.
+    pub fn expire_routes(&mut self) {
+        self.expire_routes_at(Instant::now().duration_since(self.boot));
+    }
+
+    fn expire_routes_at(&mut self, now: Duration) {
+        let Some(ttl) = self.args.node_params.route_ttl else {
+            return;
         };
-        let mut upstream_routes: Vec<_> = self
+
+        let ttl = Duration::from_millis(ttl.into());
+        self.routes.retain(|source, seqs| {
+            seqs.retain(|_, (seen_at, _, _, _, _)| now.saturating_sub(*seen_at) <= ttl);
+            if seqs.is_empty() {
+                tracing::debug!(%source, "route expired");
+            }
+            !seqs.is_empty()
+        });
+        self.positions
+            .retain(|mac, _| self.routes.contains_key(mac));
+        let path_mtus = std::mem::take(&mut self.path_mtus);
+        self.path_mtus = path_mtus
+            .into_iter()
+            .filter(|(mac, _)| self.knows(*mac))
+            .collect();
+        self.upstream_relays
+            .retain(|(source, _), _| self.routes.contains_key(source));
+        self.last_heartbeat
+            .retain(|source, _| self.routes.contains_key(source));
+        self.last_reply
+            .retain(|source, _| self.routes.contains_key(source));
+        self.heartbeat_hops
+            .retain(|source, _| self.routes.contains_key(source));
+        self.forwarded_replies
+            .retain(|(source, _, _), _| self.routes.contains_key(source));
+        let decay = Duration::from_millis(self.args.node_params.flap_decay.into());
+        lock::lock(&self.upstream_changes).retain(|change| {
+            now.saturating_sub(change.at) < decay
+                || self.routes_through(change.new)
+                || change.old.is_some_and(|old| self.routes_through(old))
+        });
+        let confirmed = std::mem::take(&mut self.confirmed);
+        self.confirmed = confirmed
+            .into_iter()
+            .filter(|(_, next_hop)| self.routes_through(*next_hop))
+            .collect();
+
+        let mut cached = lock::lock(&self.cached_upstream);
+        let expired = cached.is_some_and(|upstream| !self.routes_through(upstream));
+        if expired {
+            tracing::debug!(upstream = ?*cached, "cached upstream expired");
+            *cached = None;
+        }
+    }
+
+    /// Whether a route heard at `heard_at` is still within the route TTL
+    fn is_live(&self, heard_at: Duration, now: Duration) -> bool {
+        self.args
+            .node_params
+            .route_ttl
+            .is_none_or(|ttl| now.saturating_sub(heard_at) <= Duration::from_millis(ttl.into()))
+    }
+
+    /// Whether any route learned goes through `next_hop`
+    fn routes_through(&self, next_hop: MacAddress) -> bool {
+        self.routes
+            .values()
+            .flat_map(|seqs| seqs.values())
+            .any(|(_, via, _, _, _)| *via == next_hop)
+    }
+
+    /// Whether `mac` sent a heartbeat, relayed one or is reached downstream
+    /// through any route learned
+    fn knows(&self, mac: MacAddress) -> bool {
+        self.routes.contains_key(&mac)
+            || self
+                .routes
+                .values()
+                .flat_map(|seqs| seqs.values())
+                .any(|(_, via, _, _, downstream)| *via == mac || downstream.contains_key(&mac))
+    }
+
+    /// Heartbeats must carry a timestamp within the configured maximum age
+    /// once one is set. Timestamps ahead of the local clock are tolerated by
+    /// as much, for clock skew, so a heartbeat stamped far in the future
+    /// cannot stay fresh forever.
+    fn is_fresh(&self, timestamp: Option<Duration>) -> bool {
+        let Some(max_age) = self.args.node_params.heartbeat_max_age else {
+            return true;
+        };
+        let Some(timestamp) = timestamp else {
+            return false;
+        };
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let max_age = Duration::from_millis(max_age.into());
+        now.saturating_sub(timestamp) <= max_age && timestamp <= now + max_age
+    }
+
+    /// Whether a new heartbeat from `source` arrived sooner than the minimum
+    /// interval after the last one processed. Copies of an already processed
+    /// heartbeat are left to the duplicate check.
+    fn is_throttled(&mut self, source: MacAddress, id: u32) -> bool {
+        let Some(interval) = self.args.node_params.min_heartbeat_interval else {
+            return false;
+        };
+
+        if self
+            .routes
+            .get(&source)
+            .is_some_and(|seqs| seqs.contains_key(&id))
+        {
+            return false;
+        }
+
+        let now = Instant::now().duration_since(self.boot);
+        let interval = Duration::from_millis(interval.into());
+        if let Some(last) = self.last_heartbeat.get(&source) {
+            if now.saturating_sub(*last) < interval {
+                self.throttled_heartbeats += 1;
+                tracing::trace!(%source, id, "heartbeat throttled");
+                return true;
+            }
+        }
+
+        self.last_heartbeat.insert(source, now);
+        false
+    }
+
+    /// Heartbeats ignored for arriving faster than the minimum interval
+    pub fn throttled_heartbeats(&self) -> u64 {
+        self.throttled_heartbeats
+    }
+
+    /// Share of the heartbeat ids from `source` missing among the last
+    /// `hello_history` ids up to the newest one received
+    pub fn heartbeat_loss_ratio(&self, source: MacAddress) -> Option<f64> {
+        let seqs = self.routes.get(&source)?;
+        let newest = *seqs.keys().max()?;
+        let window = self.args.node_params.hello_history.saturating_sub(1);
+        let start = (*seqs.keys().min()?).max(newest.saturating_sub(window));
+        let received = seqs
+            .keys()
+            .filter(|id| (start..=newest).contains(id))
+            .count();
+        Some(1.0 - received as f64 / f64::from(newest - start + 1))
+    }
+
+    /// Heartbeat loss ratio towards every heartbeat source
+    pub fn heartbeat_loss(&self) -> Vec<(MacAddress, f64)> {
+        self.routes
+            .keys()
+            .filter_map(|source| Some((*source, self.heartbeat_loss_ratio(*source)?)))
+            .collect()
+    }
+
+    /// Whether the reply identified by `(source, id, sender)` was already
+    /// forwarded within the dedup window, recording it otherwise. The same
+    /// reply arrives once per downstream path the heartbeat took.
+    fn already_forwarded(&mut self, key: (MacAddress, u32, MacAddress), now: Duration) -> bool {
+        let Some(window) = self.args.node_params.reply_dedup_window else {
+            return false;
+        };
+
+        let window = Duration::from_millis(window.into());
+        self.forwarded_replies
+            .retain(|_, at| now.saturating_sub(*at) < window);
+        if self.forwarded_replies.contains_key(&key) {
+            return true;
+        }
+
+        self.forwarded_replies.insert(key, now);
+        false
+    }
+
+    /// Next hop, and hops through it, out of `candidates` sorted best first.
+    /// With a handover margin the cached upstream is kept until a candidate
+    /// beats it by more than the margin for enough heartbeats in a row, and
+    /// routes not going through it leave it alone while it is still in use.
+    fn select_upstream(
+        &self,
+        cached: &mut Option<MacAddress>,
+        candidates: &[(&u32, &MacAddress, &MacAddress, &u32)],
+        now: Duration,
+    ) -> Option<(MacAddress, u32)> {
+        let (_, rsu, best, best_hops) = candidates.first()?;
+        let (best, best_hops) = (**best, **best_hops);
+        let skipped: HashSet<MacAddress> = self
+            .routes
+            .get(*rsu)
+            .into_iter()
+            .flat_map(|seqs| seqs.values())
+            .filter(|(heard_at, mac, _, _, _)| {
+                self.is_live(*heard_at, now) && self.is_blacklisted(mac)
+            })
+            .map(|(_, mac, _, _, _)| *mac)
+            .collect();
+        if !skipped.is_empty() {
+            tracing::debug!(?skipped, "skipping blacklisted upstream candidates");
+        }
+        let (Some(margin), Some(current)) = (self.args.node_params.handover_margin, *cached) else {
+            let reason = match *cached {
+                None => "no cached upstream",
+                Some(current) if hops_through(candidates, current).is_none() => {
+                    "cached upstream not a candidate"
+                }
+                Some(_) => "better candidate",
+            };
+            self.set_cached_upstream(cached, candidates, best, best_hops, reason);
+            return Some((best, best_hops));
+        };
+
+        let Some(current_hops) = hops_through(candidates, current) else {
+            if !self.routes_through(current) {
+                self.set_cached_upstream(
+                    cached,
+                    candidates,
+                    best,
+                    best_hops,
+                    "cached upstream not a candidate",
+                );
+            }
+            return Some((best, best_hops));
+        };
+
+        if self.outperforming(candidates, current, margin, now) != Some(best) {
+            return Some((current, current_hops));
+        }
+
+        let mut challenger = lock::lock(&self.challenger);
+        let streak = match *challenger {
+            Some((mac, streak)) if mac == best => streak,
+            _ => 0,
+        };
+        if streak < self.args.node_params.handover_observations {
+            return Some((current, current_hops));
+        }
+
+        tracing::debug!(%current, %best, streak, "handover margin exceeded");
+        *challenger = None;
+        std::mem::drop(challenger);
+        self.set_cached_upstream(
+            cached,
+            candidates,
+            best,
+            best_hops,
+            "handover margin exceeded",
+        );
+        Some((best, best_hops))
+    }
+
+    /// Best of `candidates` when it beats `current` by more than `margin`
+    fn outperforming(
+        &self,
+        candidates: &[(&u32, &MacAddress, &MacAddress, &u32)],
+        current: MacAddress,
+        margin: u32,
+        now: Duration,
+    ) -> Option<MacAddress> {
+        let (_, _, best, best_hops) = candidates.first()?;
+        let current_hops = hops_through(candidates, current)?;
+        let cost = |next_hop, hops: u32| hops.saturating_add(self.flap_penalty(next_hop, now));
+        (**best != current
+            && cost(&current, current_hops) > cost(best, **best_hops).saturating_add(margin))
+        .then_some(**best)
+    }
+
+    /// Counts the heartbeats in a row in which a route to `rsu` beat the
+    /// cached upstream by more than the handover margin. Heartbeats from
+    /// RSUs the cached upstream does not lead to leave the count alone.
+    fn observe_challenger(&mut self, rsu: MacAddress) {
+        let (Some(margin), Some(current)) = (
+            self.args.node_params.handover_margin,
+            *lock::lock(&self.cached_upstream),
+        ) else {
+            return;
+        };
+
+        let now = Instant::now().duration_since(self.boot);
+        let candidates = self.upstream_candidates(rsu, now);
+        if hops_through(&candidates, current).is_none() {
+            return;
+        }
+        let best = self.outperforming(&candidates, current, margin, now);
+        let mut challenger = lock::lock(&self.challenger);
+        *challenger = best.map(|best| match *challenger {
+            Some((mac, streak)) if mac == best => (best, streak + 1),
+            _ => (best, 1),
+        });
+    }
+
+    fn set_cached_upstream(
+        &self,
+        cached: &mut Option<MacAddress>,
+        candidates: &[(&u32, &MacAddress, &MacAddress, &u32)],
+        new: MacAddress,
+        new_hops: u32,
+        reason: &'static str,
+    ) {
+        let old = cached.replace(new);
+        if old == Some(new) {
+            return;
+        }
+
+        let change = UpstreamChange {
+            old,
+            new,
+            old_hops: old.and_then(|old| hops_through(candidates, old)),
+            new_hops,
+            reason,
+            at: Instant::now().duration_since(self.boot),
+        };
+
+        if let Some(interval) = self.args.node_params.upstream_change_log_interval {
+            let now = Instant::now();
+            let mut last = lock::lock(&self.last_change_log);
+            if last.is_none_or(|last| {
+                now.duration_since(last) >= Duration::from_millis(interval.into())
+            }) {
+                *last = Some(now);
+                tracing::info!(
+                    old = ?change.old,
+                    new = %change.new,
+                    old_hops = ?change.old_hops,
+                    new_hops = change.new_hops,
+                    reason = change.reason,
+                    "upstream changed",
+                );
+            }
+        }
+
+        // Nobody listening is not an error
+        let _ = self.change_notifier.send(change.clone());
+        let mut changes = lock::lock(&self.upstream_changes);
+        if changes.len() == UPSTREAM_CHANGE_HISTORY {
+            changes.pop_front();
+        }
+        changes.push_back(change);
+    }
+
+    /// Extra hops charged to `next_hop` for recently losing the upstream
+    /// role. Each such change costs `flap_penalty` hops, decaying linearly to
+    /// zero over `flap_decay`.
+    fn flap_penalty(&self, next_hop: &MacAddress, now: Duration) -> u32 {
+        let penalty = u128::from(self.args.node_params.flap_penalty);
+        let decay = u128::from(self.args.node_params.flap_decay);
+        if penalty == 0 || decay == 0 {
+            return 0;
+        }
+
+        let total: u128 = lock::lock(&self.upstream_changes)
+            .iter()
+            .filter(|change| change.old.as_ref() == Some(next_hop))
+            .map(|change| {
+                let age = now.saturating_sub(change.at).as_millis();
+                penalty * decay.saturating_sub(age) / decay
+            })
+            .sum();
+        u32::try_from(total).unwrap_or(u32::MAX)
+    }
+
+    /// Receives every upstream change from now on. A subscriber lagging more
+    /// than the change history behind misses the oldest changes.
+    pub fn subscribe_upstream_changes(&self) -> broadcast::Receiver<UpstreamChange> {
+        self.change_notifier.subscribe()
+    }
+
+    /// Most recent upstream changes, oldest first
+    pub fn upstream_changes(&self) -> Vec<UpstreamChange> {
+        lock::lock(&self.upstream_changes).iter().cloned().collect()
+    }
+
+    /// Hops from this node to the RSU terminating the upstream, walking the
+    /// relays that forwarded its heartbeats back from the next hop. When a
+    /// relay is missing from the chain only the known prefix is listed.
+    pub fn upstream_path(&self) -> Vec<MacAddress> {
+        let Some((next_hop, rsu)) = self.upstream() else {
+            return Vec::default();
+        };
+        if next_hop == rsu {
+            return vec![rsu];
+        }
+
+        let mut path = vec![next_hop];
+        let Some((hops, relays)) = self.upstream_relays.get(&(rsu, next_hop)) else {
+            return path;
+        };
+        let mut walk = relays.iter().rev();
+        if walk.next() != Some(&next_hop) {
+            return path;
+        }
+        path.extend(walk);
+        if usize::try_from(*hops).is_ok_and(|hops| hops == relays.len() + 1) {
+            path.push(rsu);
+        }
+        path
+    }
+
+    /// Next hop of the upstream and the RSU terminating it
+    fn upstream(&self) -> Option<(MacAddress, MacAddress)> {
+        let next_hop = self.get_route_to(None)?.mac;
+        Some((next_hop, self.upstream_rsu(next_hop).unwrap_or(next_hop)))
+    }
+
+    /// Heartbeat source other than `next_hop` itself that live routes
+    /// through `next_hop` reach in the fewest hops
+    fn upstream_rsu(&self, next_hop: MacAddress) -> Option<MacAddress> {
+        let now = Instant::now().duration_since(self.boot);
+        self.routes
+            .iter()
+            .filter(|(source, _)| **source != next_hop)
+            .filter_map(|(source, seqs)| {
+                seqs.values()
+                    .filter(|(heard_at, mac, _, _, _)| {
+                        *mac == next_hop && self.is_live(*heard_at, now)
+                    })
+                    .map(|(_, _, hops, _, _)| (*hops, *source))
+                    .min()
+            })
+            .min_by_key(|(hops, source)| (*hops, source.bytes()))
+            .map(|(_, rsu)| rsu)
+    }
+
+    /// Fewest hops in which live routes through `next_hop` reach `rsu`
+    fn hops_through(&self, rsu: MacAddress, next_hop: MacAddress, now: Duration) -> Option<u32> {
+        self.routes
+            .get(&rsu)?
+            .values()
+            .filter(|(heard_at, mac, _, _, _)| *mac == next_hop && self.is_live(*heard_at, now))
+            .map(|(_, _, hops, _, _)| *hops)
+            .min()
+    }
+
+    /// Smallest MTU advertised on the path to `mac`, or to the upstream RSU
+    /// when `mac` is `None`.
+    pub fn path_mtu(&self, mac: Option<MacAddress>) -> Option<u16> {
+        let mac = match mac {
+            Some(mac) => mac,
+            None => self.upstream()?.1,
+        };
+        self.path_mtus.get(&mac).copied()
+    }
+
+    /// Current route to every known RSU, relay and downstream node, with
+    /// its latency and age
+    pub fn routing_snapshot(&self) -> Vec<RouteEntry> {
+        let now = Instant::now().duration_since(self.boot);
+        self.current_routes()
+            .into_iter()
+            .map(|(destination, route)| RouteEntry {
+                destination,
+                next_hop: route.mac,
+                hops: route.hops,
+                status: route.status,
+                latency: route.latency,
+                age: self
+                    .last_heard(destination)
+                    .map(|seen_at| now.saturating_sub(seen_at)),
+            })
+            .collect()
+    }
+
+    fn current_routes(&self) -> Vec<(MacAddress, Route)> {
+        self.routes
+            .iter()
+            .flat_map(|(source, seqs)| {
+                std::iter::once(*source).chain(
+                    seqs.values()
+                        .flat_map(|(_, _, _, _, downstream)| downstream.keys().copied()),
+                )
+            })
+            .unique()
+            .filter_map(|destination| Some((destination, self.get_route_to(Some(destination))?)))
+            .collect()
+    }
+
+    /// When the newest heartbeat from `destination`, or answered by it, was
+    /// received
+    fn last_heard(&self, destination: MacAddress) -> Option<Duration> {
+        self.routes
+            .iter()
+            .flat_map(|(source, seqs)| {
+                seqs.values()
+                    .filter(move |(_, _, _, _, downstream)| {
+                        *source == destination || downstream.contains_key(&destination)
+                    })
+                    .map(|(seen_at, _, _, _, _)| *seen_at)
+            })
+            .max()
+    }
+
+    /// Routes learned from heartbeats are confirmed once a heartbeat reply
+    /// from `destination` arrived through `next_hop`, or for the upstream
+    /// RSU once it routed a frame to this node through `next_hop`.
+    fn route_status(&self, destination: MacAddress, next_hop: MacAddress) -> RouteStatus {
+        if self.confirmed.contains(&(destination, next_hop)) {
+            RouteStatus::Confirmed
+        } else {
+            RouteStatus::Tentative
+        }
+    }
+
+    /// RSU terminating the upstream through `next_hop`, while its route is
+    /// not confirmed yet
+    pub fn unconfirmed_upstream(&self, next_hop: MacAddress) -> Option<MacAddress> {
+        let (upstream, rsu) = self.upstream()?;
+        (upstream == next_hop && !self.confirmed.contains(&(rsu, next_hop))).then_some(rsu)
+    }
+
+    /// Confirms the route to `rsu` through `next_hop` after the RSU routed a
+    /// frame to this node through it, which it only does once a heartbeat
+    /// reply of this node reached it.
+    pub fn confirm_upstream(&mut self, rsu: MacAddress, next_hop: MacAddress) {
+        tracing::debug!(%rsu, %next_hop, "upstream route confirmed");
+        self.confirmed.insert((rsu, next_hop));
+    }
+
+    pub fn position_of(&self, mac: MacAddress) -> Option<Position> {
+        self.positions.get(&mac).copied()
+    }
+
+    fn geographic_upstream(&self) -> Option<Route> {
+        if self.args.node_params.route_metric != RouteMetric::Geographic {
+            return None;
+        }
+
+        let own = self.args.node_params.position?;
+        self.positions
+            .iter()
+            .map(|(mac, position)| (own.distance_to(position), mac))
+            .sorted_by(|(da, ma), (db, mb)| {
+                da.total_cmp(db).then_with(|| ma.bytes().cmp(&mb.bytes()))
+            })
+            .find_map(|(_, mac)| self.get_route_to(Some(*mac)))
+    }
+
+    /// Upstream route for the flow hashed to `flow`. In ECMP mode flows are
+    /// spread across every next hop as few hops away from an RSU as the
+    /// selected upstream, each flow pinned to one of them to keep its frames
+    /// in order. Flows pick the next hop by rendezvous hashing, so losing one
+    /// only moves the flows that went through it.
+    pub fn get_upstream_route(&self, flow: u64) -> Option<Route> {
+        let upstream = self.get_route_to(None)?;
+        if self.args.node_params.upstream_mode == UpstreamMode::Single {
+            return Some(upstream);
+        }
+
+        let rsu = self.upstream_rsu(upstream.mac).unwrap_or(upstream.mac);
+        let candidates = self.equal_cost_upstreams(upstream.mac, rsu);
+        if candidates.len() < 2 {
+            return Some(upstream);
+        }
+
+        candidates.into_iter().max_by_key(|route| {
+            let mut hasher = DefaultHasher::new();
+            (flow, route.mac.bytes()).hash(&mut hasher);
+            hasher.finish()
+        })
+    }
+
+    /// Routes to `rsu` through every next hop reaching it in as few hops as
+    /// `upstream` does, when that is the fewest, each with its own hops and
+    /// status. Only copies of live heartbeats from `rsu` count, and a next
+    /// hop only shows up when its copies trail the first ones by no more than
+    /// [`ECMP_LATENCY_TOLERANCE`] over the fastest next hop.
+    fn equal_cost_upstreams(&self, upstream: MacAddress, rsu: MacAddress) -> Vec<Route> {
+        let Some(seqs) = self.routes.get(&rsu) else {
+            return Vec::default();
+        };
+        let now = Instant::now().duration_since(self.boot);
+        let copies: Vec<_> = seqs
+            .values()
+            .filter(|(heard_at, _, _, _, _)| self.is_live(*heard_at, now))
+            .flat_map(|(heard_at, _, _, copies, _)| {
+                copies.iter().map(|(next_hop, (arrived_at, hops))| {
+                    (*next_hop, *hops, arrived_at.saturating_sub(*heard_at))
+                })
+            })
+            .filter(|(next_hop, _, _)| !self.is_blacklisted(next_hop))
+            .collect();
+        let Some(shortest) = copies.iter().map(|(_, hops, _)| *hops).min() else {
+            return Vec::default();
+        };
+
+        let delays = copies
+            .into_iter()
+            .filter(|(_, hops, _)| *hops == shortest)
+            .fold(
+                HashMap::default(),
+                |mut hm: HashMap<MacAddress, LatencySamples>, (next_hop, _, delay)| {
+                    hm.entry(next_hop)
+                        .or_default()
+                        .push(delay.as_micros() as f64);
+                    hm
+                },
+            );
+        let fastest = delays
+            .values()
+            .map(LatencySamples::mean)
+            .min_by(f64::total_cmp)
+            .unwrap_or_default();
+        let tolerance = ECMP_LATENCY_TOLERANCE.as_micros() as f64;
+        let equal_cost: Vec<_> = delays
+            .into_iter()
+            .filter(|(_, samples)| samples.mean() <= fastest + tolerance)
+            .map(|(next_hop, _)| next_hop)
+            .sorted_by_key(|next_hop| next_hop.bytes())
+            .collect();
+        if !equal_cost.contains(&upstream) {
+            return Vec::default();
+        }
+
+        equal_cost
+            .into_iter()
+            .map(|next_hop| Route {
+                hops: shortest,
+                mac: next_hop,
+                latency: None,
+                status: self.route_status(rsu, next_hop),
+            })
+            .collect()
+    }
+
+    /// Routes to `mac` as (sequence, destination, next hop, hops), cheapest
+    /// first counting the flap penalty of each next hop
+    fn upstream_candidates(
+        &self,
+        mac: MacAddress,
+        now: Duration,
+    ) -> Vec<(&u32, &MacAddress, &MacAddress, &u32)> {
+        let mut candidates: Vec<_> = self
             .routes
             .iter()
             .flat_map(|(rsu_mac, seqs)| {
                 seqs.iter()
+                    .filter(move |(_, (heard_at, _, _, _, _))| self.is_live(*heard_at, now))
                     .map(move |(seq, (_, mac, hops, _, _))| (seq, rsu_mac, mac, hops))
             })
             .filter(|(_, rsu_mac, _, _)| rsu_mac == &&mac)
+            .filter(|(_, _, next_hop, _)| !self.is_blacklisted(next_hop))
             .collect();
-        upstream_routes.sort_by(|(_, _, _, hops), (_, _, _, bhops)| hops.cmp(bhops));
+        candidates.sort_by_cached_key(|(_, _, next_hop, hops)| {
+            hops.saturating_add(self.flap_penalty(next_hop, now))
+        });
+        candidates
+    }
+
+    pub fn get_route_to(&self, mac: Option<MacAddress>) -> Option<Route> {
+        let Some(mac) = mac else {
+            if let Some(route) = self.geographic_upstream() {
+                return Some(route);
+            }
+
+            // Expiry only runs every so often, leaving stale routes behind,
+            // so the cached upstream needs a live route to its RSU
+            let now = Instant::now().duration_since(self.boot);
+            let cached = lock::lock(&self.cached_upstream).and_then(|mac| {
+                let rsu = self.upstream_rsu(mac).unwrap_or(mac);
+                Some(Route {
+                    hops: self.hops_through(rsu, mac, now)?,
+                    mac,
+                    latency: None,
+                    status: self.route_status(rsu, mac),
+                })
+            });
+            return cached.or_else(|| {
+                self.warm
+                    .values()
+                    .filter(|route| !self.is_blacklisted(&route.mac))
+                    .min_by_key(|route| route.hops)
+                    .cloned()
+            });
+        };
+
+        if !self.routes.contains_key(&mac) {
+            if let Some(route) = self.warm.get(&mac) {
+                return (!self.is_blacklisted(&route.mac)).then(|| route.clone());
+            }
+        }
+
+        let now = Instant::now().duration_since(self.boot);
+        let upstream_routes = self.upstream_candidates(mac, now);
 
-        let mut cached = self.cached_upstream.lock().unwrap();
+        let mut cached = lock::lock(&self.cached_upstream);
         if let Some(cached_upstream) = cached.as_ref() {
             if let Some((_, _, upstream_route, hops)) = upstream_routes
                 .iter()
@@ -363,16 +1225,19 @@ impl Routing {
                     hops: **hops,
                     mac: **upstream_route,
                     latency: None,
+                    status: self.route_status(mac, **upstream_route),
                 });
             }
         }
 
-        if let Some((_, _, upstream_route, hops)) = upstream_routes.first() {
-            *cached = Some(**upstream_route);
+        if let Some((upstream_route, hops)) =
+            self.select_upstream(&mut cached, &upstream_routes, now)
+        {
             return Some(Route {
-                hops: **hops,
-                mac: **upstream_route,
+                hops,
+                mac: upstream_route,
                 latency: None,
+                status: self.route_status(mac, upstream_route),
             });
         }
         std::mem::drop(cached);
@@ -381,9 +1246,11 @@ impl Routing {
             .routes
             .iter()
             .flat_map(|(rsus, im)| {
-                im.iter().map(move |(seq, (dur, mac, hops, _, rout))| {
-                    (seq, (dur, mac, hops, rout, rsus))
-                })
+                im.iter()
+                    .filter(move |(_, (dur, _, _, _, _))| self.is_live(*dur, now))
+                    .map(move |(seq, (dur, mac, hops, _, rout))| {
+                        (seq, (dur, mac, hops, rout, rsus))
+                    })
             })
             .collect();
 
@@ -396,6 +1263,7 @@ impl Routing {
             })
             .filter(|(_, smac, _)| &&mac == smac)
             .flat_map(|(seq, mac, route)| route.iter().map(move |r| (seq, mac, r)))
+            .filter(|(_, _, route)| !self.is_blacklisted(&route.mac))
             .fold(
                 IndexMap::default(),
                 |mut hm: IndexMap<u32, (usize, u32, Vec<_>, Vec<_>)>, (seq, _, route)| {
@@ -417,45 +1285,1117 @@ impl Routing {
 
         let (min_hops, _) = route_options.first()?;
 
-        let route_options: IndexMap<_, _> = route_options
+        let metric = self.args.node_params.route_metric;
+        route_options
             .iter()
             .filter(|(h, _)| h == &min_hops)
             .flat_map(|(hops, (_count, _min_seq, next, latency))| {
                 latency.iter().zip(next).fold(
                     HashMap::default(),
-                    |mut hm: HashMap<MacAddress, (f32, f32, f32, f32, u32)>, (val, mac)| {
-                        let entry = hm
-                            .entry(*mac)
-                            .or_insert((f32::MAX, 0.0, f32::MIN, 0.0, *hops));
+                    |mut hm: HashMap<MacAddress, (LatencySamples, u32)>, (val, mac)| {
+                        let entry = hm.entry(*mac).or_insert((LatencySamples::default(), *hops));
                         if let Some(val) = *val {
-                            let val = val as f32;
-
-                            if entry.0 > val {
-                                entry.0 = val;
-                            }
-
-                            if entry.2 < val {
-                                entry.2 = val;
-                            }
-
-                            entry.1 += val;
-                            entry.3 += 1.0;
+                            entry.0.push(val as f64);
                         }
                         hm
                     },
                 )
             })
-            .map(|(mac, (min, sum, _, n, hops))| {
-                (((min + (sum / n)) / 2.0) as usize, (mac, hops, sum / n))
+            .min_by(|(ma, (a, _)), (mb, (b, _))| {
+                a.score(metric)
+                    .total_cmp(&b.score(metric))
+                    .then_with(|| ma.bytes().cmp(&mb.bytes()))
+            })
+            .map(|(mac, (samples, hops))| Route {
+                hops,
+                mac,
+                latency: Some(Duration::from_micros(samples.mean() as u64)),
+                status: RouteStatus::Confirmed,
+            })
+    }
+}
+
+/// Fewest hops through `next_hop` among `candidates`
+fn hops_through(
+    candidates: &[(&u32, &MacAddress, &MacAddress, &u32)],
+    next_hop: MacAddress,
+) -> Option<u32> {
+    candidates
+        .iter()
+        .filter(|(_, _, mac, _)| **mac == next_hop)
+        .map(|(_, _, _, hops)| **hops)
+        .min()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Routing, ECMP_LATENCY_TOLERANCE};
+    use crate::{
+        args::{NodeType, RouteMetric, UpstreamMode},
+        control::{lock, node, route::RouteStatus},
+        messages::{
+            control::{
+                heartbeat::{Heartbeat, HeartbeatReply},
+                Control,
+            },
+            message::Message,
+            packet_type::PacketType,
+        },
+        position::Position,
+        test_helpers, Args, ReplyType,
+    };
+    use mac_address::MacAddress;
+    use std::{
+        collections::HashSet,
+        time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    };
+
+    fn obu_args() -> Args {
+        test_helpers::args(NodeType::Obu)
+    }
+
+    fn heartbeat_from(source: [u8; 6], position: Position) -> Message<'static> {
+        Message::new(
+            source.into(),
+            [255; 6].into(),
+            PacketType::Control(Control::Heartbeat(
+                Heartbeat::new(Duration::default(), 0, source.into()).with_position(position),
+            )),
+        )
+    }
+
+    #[test]
+    fn ecmp_spreads_flows_across_equal_cost_upstreams() {
+        let rsu: MacAddress = [1; 6].into();
+        let mut args = obu_args();
+        args.node_params.upstream_mode = UpstreamMode::Ecmp;
+        args.node_params.route_ttl = Some(5000);
+        let boot = Instant::now() - Duration::from_secs(10);
+        let mut routing = Routing::new(&args, &boot).expect("routing");
+        // Another neighbour relays a farther RSU
+        for (id, neighbour, source, hops) in [
+            (0, [5; 6], [6; 6].into(), 3),
+            (0, [3; 6], rsu, 1),
+            (1, [4; 6], rsu, 1),
+        ] {
+            let frame = relayed_heartbeat(source, neighbour, id, hops);
+            let msg = Message::try_from(&frame[..]).expect("is message");
+            routing
+                .handle_heartbeat(&msg, [2; 6].into())
+                .expect("handled heartbeat");
+        }
+
+        let frame = |destination: u8| [[destination; 6], [2; 6]].concat();
+        let next_hops: HashSet<_> = (0..32)
+            .filter_map(|destination| {
+                routing.get_upstream_route(node::flow_hash(&frame(destination)))
             })
+            .map(|route| route.mac)
             .collect();
+        assert_eq!(next_hops, HashSet::from([[3; 6].into(), [4; 6].into()]));
 
-        route_options
-            .first()
-            .map(|(_, (mac, hops, latency))| Route {
-                hops: *hops,
-                mac: *mac,
-                latency: Some(Duration::from_micros(*latency as u64)),
+        // Each next hop comes with its own route
+        routing.confirmed.insert((rsu, [3; 6].into()));
+        for destination in 0..32 {
+            let route = routing
+                .get_upstream_route(node::flow_hash(&frame(destination)))
+                .expect("has upstream");
+            let confirmed = route.mac == [3; 6].into();
+            assert_eq!(route.status == RouteStatus::Confirmed, confirmed);
+        }
+
+        let upstream = |routing: &Routing, flow| routing.get_upstream_route(flow).map(|r| r.mac);
+        let flow = node::flow_hash(&frame(7));
+        let pinned = upstream(&routing, flow);
+        assert!(pinned.is_some());
+        assert!((0..8).all(|_| upstream(&routing, flow) == pinned));
+
+        // A next hop last heard past the route TTL is left out
+        let stale = routing
+            .routes
+            .get_mut(&rsu)
+            .and_then(|seqs| seqs.get_mut(&0));
+        stale.expect("heard through [3; 6]").0 = Duration::ZERO;
+        assert!((0..32).all(|destination| {
+            upstream(&routing, node::flow_hash(&frame(destination))) == Some([4; 6].into())
+        }));
+
+        args.node_params.upstream_mode = UpstreamMode::Single;
+        routing.update_params(args.node_params).expect("updated");
+        let single = routing.get_route_to(None).map(|route| route.mac);
+        assert!((0..32)
+            .all(|destination| upstream(&routing, node::flow_hash(&frame(destination))) == single));
+    }
+
+    #[test]
+    fn ecmp_only_moves_the_flows_of_a_lost_next_hop() {
+        let rsu: MacAddress = [1; 6].into();
+        let mut args = obu_args();
+        args.node_params.upstream_mode = UpstreamMode::Ecmp;
+        args.node_params.hello_history = 8;
+        args.node_params.route_ttl = Some(5000);
+        let boot = Instant::now() - Duration::from_secs(10);
+        let mut routing = Routing::new(&args, &boot).expect("routing");
+        for (id, neighbour) in [(0, [3; 6]), (1, [4; 6]), (2, [5; 6])] {
+            let frame = relayed_heartbeat(rsu, neighbour, id, 1);
+            let msg = Message::try_from(&frame[..]).expect("is message");
+            routing
+                .handle_heartbeat(&msg, [2; 6].into())
+                .expect("handled heartbeat");
+        }
+
+        let upstream =
+            |routing: &Routing, flow| routing.get_upstream_route(flow).expect("has upstream").mac;
+        let before: Vec<_> = (0..64).map(|flow| upstream(&routing, flow)).collect();
+        assert_eq!(before.iter().collect::<HashSet<_>>().len(), 3);
+
+        // [4; 6] is no longer heard from
+        let lost = MacAddress::new([4; 6]);
+        let stale = routing
+            .routes
+            .get_mut(&rsu)
+            .and_then(|seqs| seqs.get_mut(&1));
+        stale.expect("heard through [4; 6]").0 = Duration::ZERO;
+        for (flow, was) in (0..64).zip(before) {
+            let now = upstream(&routing, flow);
+            assert_ne!(now, lost);
+            if was != lost {
+                assert_eq!(now, was);
+            }
+        }
+    }
+
+    #[test]
+    fn ecmp_leaves_out_next_hops_trailing_past_the_tolerance() {
+        let rsu: MacAddress = [1; 6].into();
+        let (fast, slow) = (MacAddress::new([3; 6]), MacAddress::new([4; 6]));
+        let mut args = obu_args();
+        args.node_params.upstream_mode = UpstreamMode::Ecmp;
+        args.node_params.hello_history = 8;
+        let mut routing = Routing::new(&args, &Instant::now()).expect("routing");
+        // Every heartbeat arrives through both, first through `fast`
+        for id in 0..4 {
+            for neighbour in [fast, slow] {
+                let frame = relayed_heartbeat(rsu, neighbour.bytes(), id, 1);
+                let msg = Message::try_from(&frame[..]).expect("is message");
+                routing
+                    .handle_heartbeat(&msg, [2; 6].into())
+                    .expect("handled heartbeat");
+            }
+        }
+
+        let next_hops = |routing: &Routing| -> HashSet<_> {
+            (0..64)
+                .filter_map(|flow| routing.get_upstream_route(flow))
+                .map(|route| route.mac)
+                .collect()
+        };
+        assert_eq!(next_hops(&routing), HashSet::from([fast, slow]));
+
+        for (_, _, _, copies, _) in routing.routes.get_mut(&rsu).expect("heard").values_mut() {
+            copies.get_mut(&slow).expect("copy through slow").0 += ECMP_LATENCY_TOLERANCE * 2;
+        }
+        assert_eq!(next_hops(&routing), HashSet::from([fast]));
+    }
+
+    #[test]
+    fn heartbeat_position_is_stored_against_route() {
+        let mut routing = Routing::new(&obu_args(), &Instant::now()).expect("routing");
+        let position = Position {
+            lat: 38.7223,
+            lon: -9.1393,
+        };
+        let msg = Message::new(
+            [1; 6].into(),
+            [255; 6].into(),
+            PacketType::Control(Control::Heartbeat(
+                Heartbeat::new(Duration::default(), 0, [1; 6].into()).with_position(position),
+            )),
+        );
+
+        routing
+            .handle_heartbeat(&msg, [2; 6].into())
+            .expect("handled heartbeat");
+
+        let route = routing
+            .get_route_to(Some([1; 6].into()))
+            .expect("has route");
+        assert_eq!(route.mac, [1; 6].into());
+        assert_eq!(routing.position_of([1; 6].into()), Some(position));
+    }
+
+    #[test]
+    fn geographic_metric_prefers_nearest_rsu() {
+        let mut args = obu_args();
+        args.node_params.position = Some(Position { lat: 0.0, lon: 0.0 });
+        args.node_params.route_metric = RouteMetric::Geographic;
+        let mut routing = Routing::new(&args, &Instant::now()).expect("routing");
+
+        let near = heartbeat_from(
+            [1; 6],
+            Position {
+                lat: 0.0,
+                lon: 0.01,
+            },
+        );
+        let far = heartbeat_from([3; 6], Position { lat: 0.0, lon: 1.0 });
+        routing
+            .handle_heartbeat(&near, [2; 6].into())
+            .expect("handled heartbeat");
+        routing
+            .handle_heartbeat(&far, [2; 6].into())
+            .expect("handled heartbeat");
+
+        let upstream = routing.get_route_to(None).expect("has upstream");
+        assert_eq!(upstream.mac, [1; 6].into());
+    }
+
+    #[test]
+    fn blacklisted_upstream_is_skipped_for_next_best() {
+        let mut args = obu_args();
+        args.node_params.position = Some(Position { lat: 0.0, lon: 0.0 });
+        args.node_params.route_metric = RouteMetric::Geographic;
+        args.node_params.upstream_blacklist = vec![[1; 6].into()];
+        let mut routing = Routing::new(&args, &Instant::now()).expect("routing");
+
+        let near = heartbeat_from(
+            [1; 6],
+            Position {
+                lat: 0.0,
+                lon: 0.01,
+            },
+        );
+        let far = heartbeat_from([3; 6], Position { lat: 0.0, lon: 1.0 });
+        routing
+            .handle_heartbeat(&near, [2; 6].into())
+            .expect("handled heartbeat");
+        routing
+            .handle_heartbeat(&far, [2; 6].into())
+            .expect("handled heartbeat");
+
+        let upstream = routing.get_route_to(None).expect("has upstream");
+        assert_eq!(upstream.mac, [3; 6].into());
+        assert!(routing.get_route_to(Some([1; 6].into())).is_none());
+
+        routing.set_upstream_blacklist(Vec::default());
+        let upstream = routing.get_route_to(None).expect("has upstream");
+        assert_eq!(upstream.mac, [1; 6].into());
+
+        routing.set_upstream_blacklist(vec![[1; 6].into()]);
+        let upstream = routing.get_route_to(None).expect("has upstream");
+        assert_eq!(upstream.mac, [3; 6].into());
+    }
+
+    #[test]
+    fn blacklisted_destination_is_reached_through_other_next_hops() {
+        let rsu = MacAddress::new([1; 6]);
+        let mut args = obu_args();
+        args.node_params.upstream_blacklist = vec![rsu];
+        let mut routing = Routing::new(&args, &Instant::now()).expect("routing");
+        let frame = relayed_heartbeat(rsu, [5; 6], 0, 2);
+        let msg = Message::try_from(&frame[..]).expect("is message");
+        routing
+            .handle_heartbeat(&msg, [2; 6].into())
+            .expect("handled heartbeat");
+
+        let route = routing
+            .get_route_to(Some(rsu))
+            .expect("route through the relay");
+        assert_eq!(route.mac, [5; 6].into());
+    }
+
+    #[test]
+    fn geographic_metric_falls_back_without_own_position() {
+        let mut args = obu_args();
+        args.node_params.route_metric = RouteMetric::Geographic;
+        let mut routing = Routing::new(&args, &Instant::now()).expect("routing");
+
+        let near = heartbeat_from(
+            [1; 6],
+            Position {
+                lat: 0.0,
+                lon: 0.01,
+            },
+        );
+        let far = heartbeat_from([3; 6], Position { lat: 0.0, lon: 1.0 });
+        routing
+            .handle_heartbeat(&near, [2; 6].into())
+            .expect("handled heartbeat");
+        routing
+            .handle_heartbeat(&far, [2; 6].into())
+            .expect("handled heartbeat");
+
+        let upstream = routing.get_route_to(None).expect("has upstream");
+        assert_eq!(upstream.mac, [3; 6].into());
+    }
+
+    #[test]
+    fn warm_routes_are_used_until_heartbeats_arrive() {
+        let path = std::env::temp_dir().join(format!("warm_routes_{}", std::process::id()));
+        std::fs::write(&path, "01:01:01:01:01:01 03:03:03:03:03:03 2\n").expect("write routes");
+        let mut args = obu_args();
+        args.warm_routes = Some(path.to_string_lossy().to_string());
+        let mut routing = Routing::new(&args, &Instant::now()).expect("routing");
+        let _ = std::fs::remove_file(&path);
+
+        let route = routing
+            .get_route_to(Some([1; 6].into()))
+            .expect("has warm route");
+        assert_eq!(route.mac, [3; 6].into());
+        let upstream = routing.get_route_to(None).expect("has warm upstream");
+        assert_eq!(upstream.mac, [3; 6].into());
+
+        let msg = heartbeat_from([1; 6], Position { lat: 0.0, lon: 0.0 });
+        routing
+            .handle_heartbeat(&msg, [2; 6].into())
+            .expect("handled heartbeat");
+
+        let route = routing
+            .get_route_to(Some([1; 6].into()))
+            .expect("has learned route");
+        assert_eq!(route.mac, [1; 6].into());
+        assert!(routing.warm.is_empty());
+    }
+
+    #[test]
+    fn upstream_changes_are_recorded() {
+        let mut args = obu_args();
+        args.node_params.upstream_change_log_interval = Some(1000);
+        let mut routing = Routing::new(&args, &Instant::now()).expect("routing");
+
+        let first = heartbeat_from([1; 6], Position { lat: 0.0, lon: 0.0 });
+        let second = heartbeat_from([3; 6], Position { lat: 0.0, lon: 0.0 });
+        routing
+            .handle_heartbeat(&first, [2; 6].into())
+            .expect("handled heartbeat");
+        routing
+            .handle_heartbeat(&second, [2; 6].into())
+            .expect("handled heartbeat");
+
+        let changes = routing.upstream_changes();
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].old, None);
+        assert_eq!(changes[0].new, [1; 6].into());
+        assert_eq!(changes[0].reason, "no cached upstream");
+        assert_eq!(changes[1].old, Some([1; 6].into()));
+        assert_eq!(changes[1].new, [3; 6].into());
+        assert_eq!(changes[1].old_hops, None);
+        assert_eq!(changes[1].new_hops, 1);
+    }
+
+    #[test]
+    fn subscribers_are_notified_of_upstream_changes() {
+        let mut routing = Routing::new(&obu_args(), &Instant::now()).expect("routing");
+        let first = heartbeat_from([1; 6], Position { lat: 0.0, lon: 0.0 });
+        routing
+            .handle_heartbeat(&first, [2; 6].into())
+            .expect("handled heartbeat");
+
+        let mut changes = routing.subscribe_upstream_changes();
+        let second = heartbeat_from([3; 6], Position { lat: 0.0, lon: 0.0 });
+        routing
+            .handle_heartbeat(&second, [2; 6].into())
+            .expect("handled heartbeat");
+
+        let change = changes.try_recv().expect("change notified");
+        assert_eq!(change.old, Some([1; 6].into()));
+        assert_eq!(change.new, [3; 6].into());
+        assert_eq!(change.reason, "cached upstream not a candidate");
+        assert!(changes.try_recv().is_err());
+    }
+
+    #[test]
+    fn upstream_path_walks_the_relays_to_the_rsu() {
+        let rsu = MacAddress::new([1; 6]);
+        let mut routing = Routing::new(&obu_args(), &Instant::now()).expect("routing");
+        assert!(routing.upstream_path().is_empty());
+
+        // The RSU, two relays and then this node
+        let frame = forwarded_heartbeat(rsu, &[[3; 6], [2; 6]], 0, 3);
+        let msg = Message::try_from(&frame[..]).expect("is message");
+        routing
+            .handle_heartbeat(&msg, [9; 6].into())
+            .expect("handled heartbeat");
+
+        assert_eq!(
+            routing.upstream_path(),
+            vec![[2; 6].into(), [3; 6].into(), rsu]
+        );
+    }
+
+    #[test]
+    fn upstream_path_stops_where_the_relay_chain_breaks() {
+        let rsu = MacAddress::new([1; 6]);
+        let mut routing = Routing::new(&obu_args(), &Instant::now()).expect("routing");
+        let mut heartbeat = |relays: &[[u8; 6]], id, hops| {
+            let frame = forwarded_heartbeat(rsu, relays, id, hops);
+            let msg = Message::try_from(&frame[..]).expect("is message");
+            routing
+                .handle_heartbeat(&msg, [9; 6].into())
+                .expect("handled heartbeat");
+            routing.upstream_path()
+        };
+
+        // The relay before the next hop did not record itself
+        assert_eq!(heartbeat(&[[2; 6]], 0, 3), vec![[2; 6].into()]);
+        // The relays nearest the RSU are not known
+        assert_eq!(
+            heartbeat(&[[4; 6], [3; 6], [2; 6]], 1, 5),
+            vec![[2; 6].into(), [3; 6].into(), [4; 6].into()]
+        );
+    }
+
+    #[test]
+    fn reducing_history_keeps_most_recent_samples() {
+        let mut args = obu_args();
+        args.node_params.hello_history = 4;
+        let mut routing = Routing::new(&args, &Instant::now()).expect("routing");
+
+        for id in 0..3 {
+            let msg = Message::new(
+                [1; 6].into(),
+                [255; 6].into(),
+                PacketType::Control(Control::Heartbeat(Heartbeat::new(
+                    Duration::default(),
+                    id,
+                    [1; 6].into(),
+                ))),
+            );
+            routing
+                .handle_heartbeat(&msg, [2; 6].into())
+                .expect("handled heartbeat");
+        }
+        assert_eq!(routing.routes[&MacAddress::new([1; 6])].len(), 3);
+
+        let mut params = args.node_params.clone();
+        params.hello_history = 1;
+        routing.update_params(params).expect("updated params");
+
+        let history = &routing.routes[&MacAddress::new([1; 6])];
+        assert_eq!(history.len(), 1);
+        assert_eq!(history.first().map(|(id, _)| *id), Some(2));
+        assert!(routing.get_route_to(Some([1; 6].into())).is_some());
+
+        let mut params = args.node_params.clone();
+        params.hello_history = 0;
+        assert!(routing.update_params(params).is_err());
+    }
+
+    #[test]
+    fn fuzzed_frames_are_handled_without_panics() {
+        let mut routing = Routing::new(&obu_args(), &Instant::now()).expect("routing");
+        let mac = MacAddress::new([9; 6]);
+        let frames = test_helpers::fuzzed_frames(0x0b0, 5000);
+        test_helpers::assert_no_panics(&frames, |frame| {
+            let Ok(msg) = Message::try_from(frame) else {
+                return;
+            };
+            let _ = routing.handle_heartbeat(&msg, mac);
+            let _ = routing.handle_heartbeat_reply(&msg, mac);
+            let _ = routing.get_route_to(None);
+        });
+    }
+
+    #[test]
+    fn path_mtu_is_clamped_to_smallest_advertised() {
+        let mut routing = Routing::new(&obu_args(), &Instant::now()).expect("routing");
+        let rsu = MacAddress::new([1; 6]);
+        let msg = Message::new(
+            rsu,
+            [255; 6].into(),
+            PacketType::Control(Control::Heartbeat(
+                Heartbeat::new(Duration::default(), 0, rsu).with_mtu(9000),
+            )),
+        );
+
+        let replies = routing
+            .handle_heartbeat(&msg, [9; 6].into())
+            .expect("handled heartbeat")
+            .expect("has replies");
+        assert_eq!(routing.path_mtu(Some(rsu)), Some(1500));
+        assert_eq!(routing.path_mtu(None), Some(1500));
+
+        let wire: Vec<Vec<u8>> = replies
+            .into_iter()
+            .map(|reply| match reply {
+                ReplyType::Wire(pkt) => pkt.concat(),
+                ReplyType::Tap(_) => panic!("heartbeats are not written to tap"),
+            })
+            .collect();
+        let rebroadcast = Message::try_from(&wire[0][..]).expect("heartbeat");
+        let PacketType::Control(Control::Heartbeat(hb)) = rebroadcast.get_packet_type() else {
+            panic!("did not rebroadcast a heartbeat");
+        };
+        assert_eq!(hb.mtu(), Some(1500));
+
+        let reply = Message::try_from(&wire[1][..]).expect("heartbeat reply");
+        let PacketType::Control(Control::HeartbeatReply(hbr)) = reply.get_packet_type() else {
+            panic!("did not reply to the heartbeat");
+        };
+        assert_eq!(hbr.mtu(), Some(1500));
+    }
+
+    #[test]
+    fn duplicate_heartbeat_replies_are_forwarded_once() {
+        let rsu = MacAddress::new([1; 6]);
+        let own = MacAddress::new([2; 6]);
+        let sender = MacAddress::new([5; 6]);
+        let heartbeat = Heartbeat::new(Duration::default(), 0, rsu);
+        let hb = Message::new(
+            rsu,
+            [255; 6].into(),
+            PacketType::Control(Control::Heartbeat(heartbeat.clone())),
+        );
+        let reply_via = |relay: [u8; 6]| {
+            Message::new(
+                relay.into(),
+                own,
+                PacketType::Control(Control::HeartbeatReply(HeartbeatReply::from_sender(
+                    &heartbeat, sender,
+                ))),
+            )
+        };
+
+        for (window, forwarded) in [(Some(1000), 1), (None, 2)] {
+            let mut args = obu_args();
+            args.node_params.reply_dedup_window = window;
+            let mut routing = Routing::new(&args, &Instant::now()).expect("routing");
+            routing
+                .handle_heartbeat(&hb, own)
+                .expect("handled heartbeat");
+
+            let count = [[3; 6], [4; 6]]
+                .into_iter()
+                .filter_map(|relay| {
+                    routing
+                        .handle_heartbeat_reply(&reply_via(relay), own)
+                        .expect("handled reply")
+                })
+                .count();
+            assert_eq!(count, forwarded);
+            assert!(routing.get_route_to(Some(sender)).is_some());
+        }
+    }
+
+    #[test]
+    fn routes_are_tentative_until_a_reply_arrives_over_them() {
+        let rsu = MacAddress::new([1; 6]);
+        let own = MacAddress::new([2; 6]);
+        let neighbour = MacAddress::new([3; 6]);
+        let heartbeat = |id| Heartbeat::new(Duration::default(), id, rsu);
+        let mut routing = Routing::new(&obu_args(), &Instant::now()).expect("routing");
+
+        routing
+            .handle_heartbeat(
+                &Message::new(
+                    neighbour,
+                    [255; 6].into(),
+                    PacketType::Control(Control::Heartbeat(heartbeat(0))),
+                ),
+                own,
+            )
+            .expect("handled heartbeat");
+        let route = routing.get_route_to(Some(neighbour)).expect("has route");
+        assert_eq!(route.mac, neighbour);
+        assert_eq!(route.status, RouteStatus::Tentative);
+
+        // The neighbour answers a heartbeat we forwarded, so it hears us too
+        routing
+            .handle_heartbeat(
+                &Message::new(
+                    rsu,
+                    [255; 6].into(),
+                    PacketType::Control(Control::Heartbeat(heartbeat(1))),
+                ),
+                own,
+            )
+            .expect("handled heartbeat");
+        routing
+            .handle_heartbeat_reply(
+                &Message::new(
+                    neighbour,
+                    own,
+                    PacketType::Control(Control::HeartbeatReply(HeartbeatReply::from_sender(
+                        &heartbeat(1),
+                        neighbour,
+                    ))),
+                ),
+                own,
+            )
+            .expect("handled reply");
+
+        let route = routing.get_route_to(Some(neighbour)).expect("has route");
+        assert_eq!(route.mac, neighbour);
+        assert_eq!(route.status, RouteStatus::Confirmed);
+        assert_eq!(
+            routing.get_route_to(Some(rsu)).map(|route| route.status),
+            Some(RouteStatus::Tentative)
+        );
+    }
+
+    #[test]
+    fn cached_upstream_reports_its_hops_and_status() {
+        let rsu = MacAddress::new([1; 6]);
+        let relay = MacAddress::new([3; 6]);
+        let mut routing = Routing::new(&obu_args(), &Instant::now()).expect("routing");
+        let frame = relayed_heartbeat(rsu, relay.bytes(), 0, 2);
+        let msg = Message::try_from(&frame[..]).expect("is message");
+        routing
+            .handle_heartbeat(&msg, [2; 6].into())
+            .expect("handled heartbeat");
+        let route = routing.get_route_to(Some(rsu)).expect("route to rsu");
+        assert_eq!(route.mac, relay);
+
+        let cached = routing.get_route_to(None).expect("cached upstream");
+        assert_eq!(cached.mac, relay);
+        assert_eq!(cached.hops, route.hops);
+        assert_ne!(cached.hops, 1);
+        assert_eq!(cached.status, RouteStatus::Tentative);
+
+        routing.confirm_upstream(rsu, relay);
+        let cached = routing.get_route_to(None).expect("cached upstream");
+        assert_eq!(cached.status, RouteStatus::Confirmed);
+    }
+
+    #[test]
+    fn stale_heartbeats_are_rejected() {
+        let mut args = obu_args();
+        args.node_params.heartbeat_max_age = Some(5000);
+        let mut routing = Routing::new(&args, &Instant::now()).expect("routing");
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("clock after epoch");
+        let heartbeat = |id, timestamp: Option<Duration>| {
+            let mut hb = Heartbeat::new(Duration::default(), id, [1; 6].into());
+            if let Some(timestamp) = timestamp {
+                hb = hb.with_timestamp(timestamp);
+            }
+            Message::new(
+                [1; 6].into(),
+                [255; 6].into(),
+                PacketType::Control(Control::Heartbeat(hb)),
+            )
+        };
+
+        let replayed = heartbeat(100, Some(now - Duration::from_secs(60)));
+        let unstamped = heartbeat(101, None);
+        let future = heartbeat(102, Some(now + Duration::from_secs(60)));
+        for stale in [replayed, unstamped, future] {
+            let replies = routing
+                .handle_heartbeat(&stale, [2; 6].into())
+                .expect("handled heartbeat");
+            assert!(replies.is_none());
+            assert!(routing.get_route_to(None).is_none());
+        }
+
+        // Within the window either way of the local clock, for skew
+        let fresh = heartbeat(0, Some(now + Duration::from_secs(1)));
+        let replies = routing
+            .handle_heartbeat(&fresh, [2; 6].into())
+            .expect("handled heartbeat");
+        assert!(replies.is_some());
+        assert_eq!(
+            routing.get_route_to(None).map(|route| route.mac),
+            Some([1; 6].into())
+        );
+    }
+
+    #[test]
+    fn recently_flapping_next_hop_is_penalized() {
+        let rsu = MacAddress::new([1; 6]);
+        let (flapping, stable, other) = (
+            MacAddress::new([3; 6]),
+            MacAddress::new([4; 6]),
+            MacAddress::new([5; 6]),
+        );
+
+        for (penalty, expected) in [(0, flapping), (1, stable)] {
+            let mut args = obu_args();
+            args.node_params.hello_history = 4;
+            args.node_params.flap_penalty = penalty;
+            let mut routing = Routing::new(&args, &Instant::now()).expect("routing");
+            for (id, relay) in [(0, flapping), (1, stable)] {
+                let msg = Message::new(
+                    relay,
+                    [255; 6].into(),
+                    PacketType::Control(Control::Heartbeat(Heartbeat::new(
+                        Duration::default(),
+                        id,
+                        rsu,
+                    ))),
+                );
+                routing
+                    .handle_heartbeat(&msg, [2; 6].into())
+                    .expect("handled heartbeat");
+            }
+
+            // Same hop count through both relays; the first one has since
+            // lost the upstream role twice
+            {
+                let mut cached = lock::lock(&routing.cached_upstream);
+                for next in [other, flapping, other] {
+                    routing.set_cached_upstream(&mut cached, &[], next, 2, "better candidate");
+                }
+                *cached = None;
+            }
+
+            let route = routing.get_route_to(Some(rsu)).expect("route to rsu");
+            assert_eq!(route.mac, expected);
+        }
+    }
+
+    #[test]
+    fn missing_heartbeat_ids_count_as_loss() {
+        let rsu = MacAddress::new([1; 6]);
+        let mut args = obu_args();
+        args.node_params.hello_history = 5;
+        let mut routing = Routing::new(&args, &Instant::now()).expect("routing");
+        assert_eq!(routing.heartbeat_loss_ratio(rsu), None);
+
+        for id in [0, 1, 3, 4] {
+            let msg = Message::new(
+                rsu,
+                [255; 6].into(),
+                PacketType::Control(Control::Heartbeat(Heartbeat::new(
+                    Duration::default(),
+                    id,
+                    rsu,
+                ))),
+            );
+            routing
+                .handle_heartbeat(&msg, [2; 6].into())
+                .expect("handled heartbeat");
+        }
+
+        let loss = routing.heartbeat_loss_ratio(rsu).expect("heard from rsu");
+        assert!((loss - 0.2).abs() < f64::EPSILON);
+        assert_eq!(routing.heartbeat_loss().len(), 1);
+    }
+
+    /// Heartbeat from `rsu` relayed by `from` after travelling `hops`
+    fn relayed_heartbeat(rsu: MacAddress, from: [u8; 6], id: u32, hops: u32) -> Vec<u8> {
+        let msg = Message::new(
+            from.into(),
+            [255; 6].into(),
+            PacketType::Control(Control::Heartbeat(Heartbeat::new(
+                Duration::default(),
+                id,
+                rsu,
+            ))),
+        );
+        let mut frame = Vec::<Vec<u8>>::from(&msg).concat();
+        frame[36..40].copy_from_slice(&hops.to_be_bytes());
+        frame
+    }
+
+    /// Heartbeat from `rsu` that travelled `hops` and was forwarded by
+    /// `relays` in turn, the last of which sent it to this node
+    fn forwarded_heartbeat(rsu: MacAddress, relays: &[[u8; 6]], id: u32, hops: u32) -> Vec<u8> {
+        let heartbeat = relays
+            .iter()
+            .fold(Heartbeat::new(Duration::default(), id, rsu), |hb, relay| {
+                hb.with_relay((*relay).into())
+            });
+        let from = relays.last().copied().unwrap_or(rsu.bytes());
+        let msg = Message::new(
+            from.into(),
+            [255; 6].into(),
+            PacketType::Control(Control::Heartbeat(heartbeat)),
+        );
+        let mut frame = Vec::<Vec<u8>>::from(&msg).concat();
+        frame[36..40].copy_from_slice(&hops.to_be_bytes());
+        frame
+    }
+
+    #[test]
+    fn handover_streak_counts_heartbeats_not_lookups() {
+        let rsu = MacAddress::new([1; 6]);
+        let (current, closer) = ([3; 6], [5; 6]);
+        let mut args = obu_args();
+        args.node_params.hello_history = 16;
+        args.node_params.handover_margin = Some(1);
+        args.node_params.handover_observations = 2;
+        let mut routing = Routing::new(&args, &Instant::now()).expect("routing");
+        let heartbeat = |routing: &mut Routing, from, id, hops| {
+            let frame = relayed_heartbeat(rsu, from, id, hops);
+            let msg = Message::try_from(&frame[..]).expect("is message");
+            routing
+                .handle_heartbeat(&msg, [2; 6].into())
+                .expect("handled heartbeat");
+        };
+        let upstream = |routing: &Routing| routing.get_route_to(Some(rsu)).map(|route| route.mac);
+
+        heartbeat(&mut routing, current, 0, 3);
+        heartbeat(&mut routing, closer, 1, 1);
+        for _ in 0..5 {
+            assert_eq!(upstream(&routing), Some(current.into()));
+        }
+
+        heartbeat(&mut routing, current, 2, 3);
+        assert_eq!(upstream(&routing), Some(closer.into()));
+    }
+
+    #[test]
+    fn upstream_is_kept_until_the_handover_margin_is_exceeded() {
+        let rsu = MacAddress::new([1; 6]);
+        let (current, marginal, closer) = ([3; 6], [4; 6], [5; 6]);
+        let mut args = obu_args();
+        args.node_params.hello_history = 16;
+        args.node_params.handover_margin = Some(1);
+        args.node_params.handover_observations = 2;
+        let mut routing = Routing::new(&args, &Instant::now()).expect("routing");
+
+        let mut heartbeat = |from: [u8; 6], id, hops: u32| {
+            let frame = relayed_heartbeat(rsu, from, id, hops);
+            let msg = Message::try_from(&frame[..]).expect("is message");
+            routing
+                .handle_heartbeat(&msg, [2; 6].into())
+                .expect("handled heartbeat");
+            (
+                routing.get_route_to(Some(rsu)).map(|route| route.mac),
+                routing.get_route_to(None).map(|route| route.mac),
+            )
+        };
+        let through = |mac: [u8; 6]| (Some(mac.into()), Some(mac.into()));
+
+        assert_eq!(heartbeat(current, 0, 3), through(current));
+        for id in 1..5 {
+            let (from, hops) = if id % 2 == 0 {
+                (current, 3)
+            } else {
+                (marginal, 2)
+            };
+            assert_eq!(heartbeat(from, id, hops), through(current));
+        }
+
+        assert_eq!(heartbeat(closer, 5, 1), through(current));
+        assert_eq!(heartbeat(current, 6, 3), through(closer));
+
+        let change = routing.upstream_changes().pop().expect("upstream changed");
+        assert_eq!(change.old, Some(current.into()));
+        assert_eq!(change.new, closer.into());
+        assert_eq!(change.old_hops, Some(3));
+        assert_eq!(change.new_hops, 1);
+        assert_eq!(change.reason, "handover margin exceeded");
+    }
+
+    #[test]
+    fn replies_are_suppressed_for_non_candidate_rsus() {
+        let mut args = obu_args();
+        args.node_params.hello_history = 16;
+        args.node_params.reply_suppression_interval = Some(60_000);
+        let mut routing = Routing::new(&args, &Instant::now()).expect("routing");
+
+        // Heartbeat from `source` as received after travelling `hops`
+        let heartbeat = |source: [u8; 6], from: [u8; 6], id, hops: u32| {
+            let msg = Message::new(
+                from.into(),
+                [255; 6].into(),
+                PacketType::Control(Control::Heartbeat(Heartbeat::new(
+                    Duration::default(),
+                    id,
+                    source.into(),
+                ))),
+            );
+            let mut frame = Vec::<Vec<u8>>::from(&msg).concat();
+            frame[36..40].copy_from_slice(&hops.to_be_bytes());
+            frame
+        };
+        let replied = |routing: &mut Routing, frame: Vec<u8>| {
+            let msg = Message::try_from(&frame[..]).expect("is message");
+            routing
+                .handle_heartbeat(&msg, [2; 6].into())
+                .expect("handled heartbeat")
+                .expect("rebroadcast")
+                .iter()
+                .any(|reply| {
+                    let ReplyType::Wire(reply) = reply else {
+                        return false;
+                    };
+                    let reply = reply.concat();
+                    let msg = Message::try_from(&reply[..]).expect("is message");
+                    matches!(
+                        msg.get_packet_type(),
+                        PacketType::Control(Control::HeartbeatReply(_))
+                    )
+                })
+        };
+        let (near, other_near, far, relay) = ([1; 6], [3; 6], [4; 6], [5; 6]);
+
+        assert!(replied(&mut routing, heartbeat(near, near, 0, 1)));
+        // The first reply to a further RSU keeps its route alive
+        assert!(replied(&mut routing, heartbeat(far, relay, 0, 2)));
+        assert!(!replied(&mut routing, heartbeat(far, relay, 1, 2)));
+        assert!(!replied(&mut routing, heartbeat(far, relay, 2, 2)));
+        assert!(replied(&mut routing, heartbeat(near, near, 1, 1)));
+        assert!(replied(
+            &mut routing,
+            heartbeat(other_near, other_near, 0, 1)
+        ));
+        assert_eq!(routing.suppressed_replies(), 2);
+
+        routing.boot -= Duration::from_secs(30);
+        assert!(replied(&mut routing, heartbeat(near, near, 2, 1)));
+        assert!(!replied(&mut routing, heartbeat(far, relay, 3, 2)));
+        routing.boot -= Duration::from_secs(31);
+        assert!(replied(&mut routing, heartbeat(near, near, 3, 1)));
+        assert!(replied(&mut routing, heartbeat(far, relay, 4, 2)));
+        assert_eq!(routing.suppressed_replies(), 3);
+    }
+
+    #[test]
+    fn heartbeat_floods_are_throttled_per_source() {
+        let mut args = obu_args();
+        args.node_params.hello_history = 16;
+        args.node_params.min_heartbeat_interval = Some(60_000);
+        let mut routing = Routing::new(&args, &Instant::now()).expect("routing");
+        let heartbeat = |source: [u8; 6], id| {
+            Message::new(
+                source.into(),
+                [255; 6].into(),
+                PacketType::Control(Control::Heartbeat(Heartbeat::new(
+                    Duration::default(),
+                    id,
+                    source.into(),
+                ))),
+            )
+        };
+
+        let processed = (0..10)
+            .filter_map(|id| {
+                routing
+                    .handle_heartbeat(&heartbeat([1; 6], id), [2; 6].into())
+                    .expect("handled heartbeat")
             })
+            .count();
+        assert_eq!(processed, 1);
+        assert_eq!(routing.throttled_heartbeats(), 9);
+        assert_eq!(routing.routes[&MacAddress::new([1; 6])].len(), 1);
+
+        let other = routing
+            .handle_heartbeat(&heartbeat([3; 6], 0), [2; 6].into())
+            .expect("handled heartbeat");
+        assert!(other.is_some());
+        assert_eq!(routing.throttled_heartbeats(), 9);
+    }
+
+    #[test]
+    fn routes_expire_after_their_ttl() {
+        let mut args = obu_args();
+        args.node_params.route_ttl = Some(100);
+        let boot = Instant::now();
+        let mut routing = Routing::new(&args, &boot).expect("routing");
+        let heartbeat = heartbeat_from([1; 6], Position { lat: 0.0, lon: 0.0 });
+        routing
+            .handle_heartbeat(&heartbeat, [2; 6].into())
+            .expect("handled heartbeat");
+        assert_eq!(
+            routing.get_route_to(None).map(|route| route.mac),
+            Some([1; 6].into())
+        );
+
+        routing.expire_routes_at(Instant::now().duration_since(boot));
+        assert!(routing.get_route_to(None).is_some());
+
+        routing.expire_routes_at(Instant::now().duration_since(boot) + Duration::from_millis(101));
+        assert!(routing.get_route_to(None).is_none());
+        assert!(routing.get_route_to(Some([1; 6].into())).is_none());
+        assert!(routing.routing_snapshot().is_empty());
+        assert_eq!(routing.position_of([1; 6].into()), None);
+    }
+
+    #[test]
+    fn routes_past_their_ttl_are_skipped_before_they_expire() {
+        let rsu = MacAddress::new([1; 6]);
+        let mut args = obu_args();
+        args.node_params.route_ttl = Some(5000);
+        args.node_params.min_heartbeat_interval = Some(1);
+        let boot = Instant::now() - Duration::from_secs(10);
+        let mut routing = Routing::new(&args, &boot).expect("routing");
+        let heartbeat = heartbeat_from([1; 6], Position { lat: 0.0, lon: 0.0 });
+        routing
+            .handle_heartbeat(&heartbeat, [2; 6].into())
+            .expect("handled heartbeat");
+        assert!(routing.get_route_to(Some(rsu)).is_some());
+        assert!(routing.get_route_to(None).is_some());
+
+        let stale = routing
+            .routes
+            .get_mut(&rsu)
+            .and_then(|seqs| seqs.get_mut(&0));
+        stale.expect("heard from the rsu").0 = Duration::ZERO;
+        assert!(routing.get_route_to(Some(rsu)).is_none());
+        assert!(routing.get_route_to(None).is_none());
+
+        // Expiry forgets the rest of what was kept about the rsu
+        assert!(!routing.path_mtus.is_empty());
+        assert!(!routing.last_heartbeat.is_empty());
+        assert!(!routing.upstream_changes().is_empty());
+        routing.expire_routes_at(Duration::from_secs(60));
+        assert!(routing.routes.is_empty());
+        assert!(routing.path_mtus.is_empty());
+        assert!(routing.last_heartbeat.is_empty());
+        assert!(routing.upstream_changes().is_empty());
+    }
+
+    #[test]
+    fn snapshot_lists_every_route_with_its_measurements() {
+        fn over_the_wire(msg: &Message) -> Vec<u8> {
+            let msg: Vec<Vec<u8>> = msg.into();
+            msg.concat()
+        }
+
+        let mut routing = Routing::new(&obu_args(), &Instant::now()).expect("routing");
+        let near = over_the_wire(&Message::new(
+            [1; 6].into(),
+            [255; 6].into(),
+            PacketType::Control(Control::Heartbeat(Heartbeat::new(
+                Duration::default(),
+                0,
+                [1; 6].into(),
+            ))),
+        ));
+        let far = over_the_wire(&Message::new(
+            [3; 6].into(),
+            [255; 6].into(),
+            PacketType::Control(Control::Heartbeat(Heartbeat::new(
+                Duration::default(),
+                0,
+                [3; 6].into(),
+            ))),
+        ));
+        let far = Message::try_from(&far[..]).expect("heartbeat");
+        let PacketType::Control(Control::Heartbeat(far)) = far.get_packet_type() else {
+            panic!("not a heartbeat");
+        };
+        let relayed = over_the_wire(&Message::new(
+            [2; 6].into(),
+            [255; 6].into(),
+            PacketType::Control(Control::Heartbeat(far.clone())),
+        ));
+
+        for frame in [near, relayed] {
+            let msg = Message::try_from(&frame[..]).expect("heartbeat");
+            routing
+                .handle_heartbeat(&msg, [9; 6].into())
+                .expect("handled heartbeat");
+        }
+
+        let snapshot = routing.routing_snapshot();
+        let route_to = |destination: [u8; 6]| {
+            snapshot
+                .iter()
+                .find(|route| route.destination == destination.into())
+                .expect("route in snapshot")
+        };
+        assert_eq!(snapshot.len(), 3);
+        assert_eq!(route_to([1; 6]).next_hop, [1; 6].into());
+        assert_eq!(route_to([1; 6]).hops, 1);
+        assert_eq!(route_to([3; 6]).next_hop, [2; 6].into());
+        assert_eq!(route_to([3; 6]).hops, 2);
+        assert!(snapshot.iter().all(|route| route.latency.is_none()));
+        assert!(snapshot
+            .iter()
+            .all(|route| route.age.is_some_and(|age| age < Duration::from_secs(1))));
     }
 }
@@ -1,9 +1,9 @@
-use crate::control::node::ReplyType;
+use crate::control::node::{self, ReplyType};
 use anyhow::Result;
+use common::device::Device;
 use futures::Future;
 use std::sync::Arc;
 use tokio_tun::Tun;
-use uninit::uninit_array;
 
 pub struct SessionParams {}
 struct InnerSession {
@@ -21,16 +21,17 @@ impl Session {
 
     pub async fn process<Fut>(
         &self,
-        callable: impl FnOnce([u8; 1500], usize) -> Fut,
+        buffer_size: usize,
+        device: &Device,
+        callable: impl FnOnce(Vec<u8>, usize) -> Fut,
     ) -> Result<Option<Vec<ReplyType>>>
     where
         Fut: Future<Output = Result<Option<Vec<ReplyType>>>>,
     {
         match self {
             Self::NoSession(tun) => {
-                let buf = uninit_array![u8; 1500];
-                let mut buf = unsafe { std::mem::transmute::<_, [u8; 1500]>(buf) };
-                let n = tun.recv(&mut buf).await?;
+                let mut buf = vec![0u8; buffer_size];
+                let n = node::tap_recv(tun, device, &mut buf).await?;
                 callable(buf, n).await
             }
             Self::ValidSession(session) => {
@@ -0,0 +1,86 @@
+use crate::control::snapshot::RouteEntry;
+use mac_address::MacAddress;
+use std::{collections::HashSet, time::Duration};
+
+/// Heartbeat periodicity that doubles, up to `max`, every heartbeat the next
+/// hops towards downstream nodes stay the same and halves, down to `min`,
+/// whenever they change.
+#[derive(Debug)]
+pub struct AdaptivePeriodicity {
+    min: Duration,
+    max: Duration,
+    current: Duration,
+    next_hops: Option<HashSet<(MacAddress, MacAddress)>>,
+}
+
+impl AdaptivePeriodicity {
+    pub fn new(initial: Duration, min: Duration, max: Duration) -> Self {
+        let max = max.max(min);
+        Self {
+            min,
+            max,
+            current: initial.clamp(min, max),
+            next_hops: None,
+        }
+    }
+
+    /// Periodicity to wait before the next heartbeat, given the routes
+    /// learned so far
+    pub fn next(&mut self, routes: &[RouteEntry]) -> Duration {
+        let next_hops: HashSet<_> = routes
+            .iter()
+            .map(|route| (route.destination, route.next_hop))
+            .collect();
+
+        match self.next_hops.replace(next_hops) {
+            Some(previous) if Some(&previous) == self.next_hops.as_ref() => {
+                self.current = self.current.saturating_mul(2).min(self.max);
+            }
+            Some(_) => {
+                tracing::debug!(periodicity = ?self.current / 2, "next hops changed");
+                self.current = (self.current / 2).max(self.min);
+            }
+            None => {}
+        }
+
+        self.current
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AdaptivePeriodicity;
+    use crate::control::{route::RouteStatus, snapshot::RouteEntry};
+    use mac_address::MacAddress;
+    use std::time::Duration;
+
+    fn route(destination: u8, next_hop: u8) -> RouteEntry {
+        RouteEntry {
+            destination: MacAddress::new([destination; 6]),
+            next_hop: MacAddress::new([next_hop; 6]),
+            hops: 1,
+            status: RouteStatus::Confirmed,
+            latency: None,
+            age: None,
+        }
+    }
+
+    #[test]
+    fn stable_topology_slows_heartbeats_and_churn_speeds_them_up() {
+        let (min, max) = (Duration::from_millis(100), Duration::from_millis(1600));
+        let mut periodicity = AdaptivePeriodicity::new(Duration::from_millis(400), min, max);
+        let stable = [route(1, 1), route(2, 1)];
+
+        assert_eq!(periodicity.next(&stable), Duration::from_millis(400));
+        assert_eq!(periodicity.next(&stable), Duration::from_millis(800));
+        assert_eq!(periodicity.next(&stable), max);
+        assert_eq!(periodicity.next(&stable), max);
+
+        let churned = [route(1, 1), route(2, 3)];
+        assert_eq!(periodicity.next(&churned), Duration::from_millis(800));
+        assert_eq!(periodicity.next(&stable), Duration::from_millis(400));
+        assert_eq!(periodicity.next(&[]), Duration::from_millis(200));
+        assert_eq!(periodicity.next(&[route(4, 4)]), min);
+        assert_eq!(periodicity.next(&[]), min);
+    }
+}
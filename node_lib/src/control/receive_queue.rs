@@ -0,0 +1,126 @@
+use serde::Serialize;
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Minimum time between two high-watermark warnings of the same queue
+const WARNING_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Depth of a receive queue as exposed in metrics
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct QueueDepth {
+    pub depth: u64,
+    pub peak: u64,
+    pub warnings: u64,
+}
+
+/// Gauge of frames read from the wire but not yet processed. Once the depth
+/// exceeds `high_watermark` a warning is logged, at most once per
+/// [`WARNING_INTERVAL`].
+#[derive(Debug)]
+pub struct ReceiveQueue {
+    high_watermark: Option<u64>,
+    depth: AtomicU64,
+    peak: AtomicU64,
+    warnings: AtomicU64,
+    last_warning: Mutex<Option<Instant>>,
+}
+
+impl ReceiveQueue {
+    pub fn new(high_watermark: Option<u32>) -> Self {
+        Self {
+            high_watermark: high_watermark.map(u64::from),
+            depth: AtomicU64::new(0),
+            peak: AtomicU64::new(0),
+            warnings: AtomicU64::new(0),
+            last_warning: Mutex::new(None),
+        }
+    }
+
+    /// Records a frame entering the queue at `now`
+    pub fn push(&self, now: Instant) {
+        let depth = self.depth.fetch_add(1, Ordering::Relaxed) + 1;
+        self.peak.fetch_max(depth, Ordering::Relaxed);
+
+        let Some(high_watermark) = self.high_watermark.filter(|x| depth > *x) else {
+            return;
+        };
+
+        let mut last = self.last_warning.lock().unwrap();
+        if last.is_some_and(|last| now.saturating_duration_since(last) < WARNING_INTERVAL) {
+            return;
+        }
+
+        *last = Some(now);
+        self.warnings.fetch_add(1, Ordering::Relaxed);
+        tracing::warn!(depth, high_watermark, "receive queue above high watermark");
+    }
+
+    /// Records a frame leaving the queue to be processed
+    pub fn pop(&self) {
+        let _ = self
+            .depth
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |x| x.checked_sub(1));
+    }
+
+    pub fn depth(&self) -> QueueDepth {
+        QueueDepth {
+            depth: self.depth.load(Ordering::Relaxed),
+            peak: self.peak.load(Ordering::Relaxed),
+            warnings: self.warnings.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{QueueDepth, ReceiveQueue};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn burst_raises_depth_and_warns_once_per_interval() {
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let queue = ReceiveQueue::new(Some(4));
+
+        // A burst arriving faster than frames are processed
+        for ms in 0..10 {
+            queue.push(at(ms));
+            if ms % 3 == 0 {
+                queue.pop();
+            }
+        }
+        assert_eq!(
+            queue.depth(),
+            QueueDepth {
+                depth: 6,
+                peak: 7,
+                warnings: 1,
+            }
+        );
+
+        queue.push(at(500));
+        assert_eq!(queue.depth().warnings, 1);
+        queue.push(at(1010));
+        assert_eq!(queue.depth().warnings, 2);
+
+        (0..10).for_each(|_| queue.pop());
+        assert_eq!(queue.depth().depth, 0);
+        assert_eq!(queue.depth().peak, 8);
+
+        queue.push(at(3000));
+        assert_eq!(queue.depth().warnings, 2);
+    }
+
+    #[test]
+    fn no_warnings_without_high_watermark() {
+        let queue = ReceiveQueue::new(None);
+        (0..1000).for_each(|_| queue.push(Instant::now()));
+        assert_eq!(queue.depth().depth, 1000);
+        assert_eq!(queue.depth().warnings, 0);
+    }
+}
@@ -1,23 +1,320 @@
 use std::{
+    collections::{HashMap, VecDeque},
     fmt::{Display, Formatter, Result},
+    str::FromStr,
     time::Duration,
 };
 
+use crate::{args::RouteMetric, control::snapshot::RouteEntry};
+use anyhow::Context;
+use indexmap::IndexMap;
 use mac_address::MacAddress;
 
-#[derive(Debug)]
+/// Whether a route was validated in both directions by a heartbeat reply
+/// arriving over it. Routes only learned from heartbeats or loaded as warm
+/// routes are tentative.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouteStatus {
+    Tentative,
+    Confirmed,
+}
+
+impl Display for RouteStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        f.write_str(match self {
+            RouteStatus::Tentative => "tentative",
+            RouteStatus::Confirmed => "confirmed",
+        })
+    }
+}
+
+impl FromStr for RouteStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "tentative" => Ok(RouteStatus::Tentative),
+            "confirmed" => Ok(RouteStatus::Confirmed),
+            _ => anyhow::bail!("invalid route status: {s}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Route {
     pub hops: u32,
     pub mac: MacAddress,
     pub latency: Option<Duration>,
+    pub status: RouteStatus,
 }
 
 impl Display for Route {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
         write!(
             f,
-            "Route {{ mac: {}, hops: {}, latency: {:?} }}",
-            self.mac, self.hops, self.latency
+            "Route {{ mac: {}, hops: {}, latency: {:?}, status: {} }}",
+            self.mac, self.hops, self.latency, self.status
+        )
+    }
+}
+
+/// Latency samples, in microseconds, of the routes through one next hop
+#[derive(Debug, Clone, Copy)]
+pub struct LatencySamples {
+    min: f64,
+    sum: f64,
+    sum_squares: f64,
+    count: f64,
+}
+
+impl Default for LatencySamples {
+    fn default() -> Self {
+        Self {
+            min: f64::MAX,
+            sum: 0.0,
+            sum_squares: 0.0,
+            count: 0.0,
+        }
+    }
+}
+
+impl LatencySamples {
+    pub fn push(&mut self, latency: f64) {
+        self.min = self.min.min(latency);
+        self.sum += latency;
+        self.sum_squares += latency * latency;
+        self.count += 1.0;
+    }
+
+    pub fn mean(&self) -> f64 {
+        if self.count == 0.0 {
+            return 0.0;
+        }
+        self.sum / self.count
+    }
+
+    pub fn variance(&self) -> f64 {
+        if self.count == 0.0 {
+            return 0.0;
+        }
+        (self.sum_squares / self.count - self.mean().powi(2)).max(0.0)
+    }
+
+    /// Lower is better. Next hops without samples score best so they get
+    /// probed.
+    pub fn score(&self, metric: RouteMetric) -> f64 {
+        if self.count == 0.0 {
+            return 0.0;
+        }
+
+        match metric {
+            RouteMetric::LowJitter => self.mean() + self.variance().sqrt(),
+            RouteMetric::Latency | RouteMetric::Geographic => (self.min + self.mean()) / 2.0,
+        }
+    }
+}
+
+/// Rebuilds a sequence history with a new capacity, keeping the most recent
+/// entries.
+pub fn resize_history<V>(history: &mut IndexMap<u32, V>, capacity: usize) {
+    let skip = history.len().saturating_sub(capacity);
+    let mut resized = IndexMap::with_capacity(capacity);
+    resized.extend(history.drain(..).skip(skip));
+    *history = resized;
+}
+
+/// Parses warm routes, one `<destination> <next hop> <hops>` entry per line.
+/// Empty lines and lines starting with `#` are ignored.
+pub fn parse_warm_routes(contents: &str) -> anyhow::Result<HashMap<MacAddress, Route>> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| -> anyhow::Result<(MacAddress, Route)> {
+            let mut fields = line.split_whitespace();
+            let (Some(destination), Some(next_hop), Some(hops), None) =
+                (fields.next(), fields.next(), fields.next(), fields.next())
+            else {
+                anyhow::bail!("malformed warm route: {line}");
+            };
+
+            Ok((
+                destination.parse()?,
+                Route {
+                    hops: hops.parse()?,
+                    mac: next_hop.parse()?,
+                    latency: None,
+                    status: RouteStatus::Tentative,
+                },
+            ))
+        })
+        .collect()
+}
+
+pub fn load_warm_routes(path: &str) -> anyhow::Result<HashMap<MacAddress, Route>> {
+    let contents =
+        std::fs::read_to_string(path).with_context(|| format!("reading warm routes {path}"))?;
+    parse_warm_routes(&contents)
+}
+
+/// Path through the mesh, from its source to its destination included
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MeshPath {
+    pub nodes: Vec<MacAddress>,
+    pub hops: u32,
+}
+
+/// Shortest path from `source` to `destination` over the links implied by
+/// routing snapshots, each node being able to reach every next hop it has a
+/// route through. Among paths with as few hops, the one whose nodes compare
+/// lowest is returned. `None` when `destination` cannot be reached.
+pub fn shortest_path(
+    snapshots: &HashMap<MacAddress, Vec<RouteEntry>>,
+    source: MacAddress,
+    destination: MacAddress,
+) -> Option<MeshPath> {
+    let neighbours = |node: &MacAddress| {
+        let mut next_hops: Vec<MacAddress> = snapshots
+            .get(node)
+            .into_iter()
+            .flatten()
+            .map(|route| route.next_hop)
+            .collect();
+        next_hops.sort_by_key(|mac| mac.bytes());
+        next_hops.dedup();
+        next_hops
+    };
+
+    // Breadth first, visiting neighbours in order, so the first time a node
+    // is reached it is through the lowest of its shortest paths
+    let mut previous: HashMap<MacAddress, MacAddress> = HashMap::default();
+    let mut queue = VecDeque::from([source]);
+    while let Some(node) = queue.pop_front() {
+        if node == destination {
+            let mut nodes = vec![destination];
+            while let Some(prev) = previous.get(nodes.last()?) {
+                nodes.push(*prev);
+            }
+            nodes.reverse();
+            let hops = u32::try_from(nodes.len() - 1).ok()?;
+            return Some(MeshPath { nodes, hops });
+        }
+
+        for next in neighbours(&node) {
+            if next != source && !previous.contains_key(&next) {
+                previous.insert(next, node);
+                queue.push_back(next);
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_warm_routes, shortest_path, LatencySamples, MeshPath, RouteStatus};
+    use crate::{args::RouteMetric, control::snapshot::RouteEntry};
+    use mac_address::MacAddress;
+    use std::collections::HashMap;
+
+    fn samples(latencies: &[f64]) -> LatencySamples {
+        latencies
+            .iter()
+            .fold(LatencySamples::default(), |mut samples, latency| {
+                samples.push(*latency);
+                samples
+            })
+    }
+
+    #[test]
+    fn jitter_aware_score_penalizes_variance() {
+        let steady = samples(&[500.0, 500.0, 500.0, 500.0]);
+        let jittery = samples(&[100.0, 900.0, 100.0, 900.0]);
+
+        assert_eq!(steady.mean(), jittery.mean());
+        assert_eq!(steady.variance(), 0.0);
+        assert_eq!(jittery.variance(), 160000.0);
+        assert!(steady.score(RouteMetric::LowJitter) < jittery.score(RouteMetric::LowJitter));
+        assert!(steady.score(RouteMetric::Latency) > jittery.score(RouteMetric::Latency));
+    }
+
+    #[test]
+    fn warm_routes_are_parsed() {
+        let routes = parse_warm_routes(
+            "# destination next_hop hops\n01:01:01:01:01:01 02:02:02:02:02:02 2\n\n",
         )
+        .expect("valid routes");
+
+        let route = routes.get(&[1; 6].into()).expect("has route");
+        assert_eq!(route.mac, [2; 6].into());
+        assert_eq!(route.hops, 2);
+        assert!(parse_warm_routes("01:01:01:01:01:01 2").is_err());
+    }
+
+    /// Snapshots where every node has a route through each of its links
+    fn snapshots(links: &[(u8, u8)]) -> HashMap<MacAddress, Vec<RouteEntry>> {
+        links
+            .iter()
+            .fold(HashMap::default(), |mut snapshots, (from, to)| {
+                let to = MacAddress::new([*to; 6]);
+                snapshots
+                    .entry(MacAddress::new([*from; 6]))
+                    .or_default()
+                    .push(RouteEntry {
+                        destination: to,
+                        next_hop: to,
+                        hops: 1,
+                        status: RouteStatus::Confirmed,
+                        latency: None,
+                        age: None,
+                    });
+                snapshots
+            })
+    }
+
+    fn mac(byte: u8) -> MacAddress {
+        MacAddress::new([byte; 6])
+    }
+
+    #[test]
+    fn shortest_path_follows_the_fewest_hops() {
+        // OBU 4 reaches RSU 1 through relay 3 or through 5 and 6
+        let topology = snapshots(&[(4, 3), (3, 1), (4, 5), (5, 6), (6, 1), (1, 3)]);
+
+        assert_eq!(
+            shortest_path(&topology, mac(4), mac(1)),
+            Some(MeshPath {
+                nodes: vec![mac(4), mac(3), mac(1)],
+                hops: 2,
+            })
+        );
+        assert_eq!(
+            shortest_path(&topology, mac(4), mac(4)),
+            Some(MeshPath {
+                nodes: vec![mac(4)],
+                hops: 0,
+            })
+        );
+    }
+
+    #[test]
+    fn unreachable_destination_has_no_path() {
+        // Links are directed, 1 has no route back to 4
+        let topology = snapshots(&[(4, 3), (3, 1), (7, 8)]);
+
+        assert_eq!(shortest_path(&topology, mac(4), mac(8)), None);
+        assert_eq!(shortest_path(&topology, mac(1), mac(4)), None);
+        assert_eq!(shortest_path(&topology, mac(9), mac(1)), None);
+    }
+
+    #[test]
+    fn equally_short_paths_are_broken_by_lowest_node() {
+        let topology = snapshots(&[(4, 5), (4, 3), (5, 1), (3, 1)]);
+
+        for _ in 0..10 {
+            let path = shortest_path(&topology, mac(4), mac(1)).expect("reachable");
+            assert_eq!(path.nodes, vec![mac(4), mac(3), mac(1)]);
+            assert_eq!(path.hops, 2);
+        }
     }
 }
@@ -1,11 +1,26 @@
 mod routing;
 
-use super::{client_cache::ClientCache, node::ReplyType};
+use super::{client_cache::ClientCache, ingress::IngressLoss, node::ReplyType};
 use crate::{
-    control::node,
+    args::NodeParameters,
+    control::{
+        capture::Capture,
+        compression::{self, Compressor},
+        counters::{NodeCounters, PacketCounters},
+        drops::{self, DropLog, DropReason, DropRecord},
+        fragment::{Fragmenter, Reassembly},
+        lock,
+        node::{self, FrameDestination, HeaderOnlyFilter},
+        periodicity::AdaptivePeriodicity,
+        receive_queue::{QueueDepth, ReceiveQueue},
+        sequence::{SequenceGaps, SequenceNumbers},
+        snapshot::RouteEntry,
+        tasks::Tasks,
+        traffic::{self, ClassCounters, ClassCounts},
+    },
     messages::{
         control::Control,
-        data::{Data, ToDownstream},
+        data::{Data, ToDownstream, ToUpstream},
         message::Message,
         packet_type::PacketType,
     },
@@ -15,10 +30,13 @@ use anyhow::{anyhow, bail, Result};
 use common::{device::Device, network_interface::NetworkInterface};
 use itertools::Itertools;
 use mac_address::MacAddress;
-use routing::Routing;
+pub(crate) use routing::Routing;
 use std::{
     io::IoSlice,
-    sync::{Arc, RwLock},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, RwLock,
+    },
     time::Duration,
 };
 use tokio_tun::Tun;
@@ -29,16 +47,62 @@ pub struct Rsu {
     tun: Arc<Tun>,
     device: Arc<Device>,
     cache: Arc<ClientCache>,
+    ingress: Arc<IngressLoss>,
+    receive_queue: Arc<ReceiveQueue>,
+    sequence: Arc<SequenceNumbers>,
+    gaps: Arc<SequenceGaps>,
+    classes: Arc<ClassCounters>,
+    counters: Arc<PacketCounters>,
+    header_only: HeaderOnlyFilter,
+    malformed_frames: AtomicU64,
+    drops: Arc<DropLog>,
+    fragmenter: Arc<Fragmenter>,
+    compressor: Arc<Compressor>,
+    reassembly: Reassembly,
+    capture: Option<Arc<Capture>>,
+    tasks: Arc<Tasks>,
 }
 
 impl Rsu {
     pub fn new(args: Args, tun: Arc<Tun>, device: Arc<Device>) -> Result<Arc<Self>> {
         let rsu = Arc::new(Self {
             routing: Arc::new(RwLock::new(Routing::new(&args)?)),
+            ingress: IngressLoss::new(
+                args.node_params.ingress_loss,
+                args.node_params.ingress_loss_seed,
+            )
+            .into(),
+            receive_queue: ReceiveQueue::new(args.node_params.receive_queue_high_watermark).into(),
+            sequence: SequenceNumbers::default().into(),
+            gaps: SequenceGaps::default().into(),
+            classes: ClassCounters::default().into(),
+            counters: PacketCounters::default().into(),
+            header_only: HeaderOnlyFilter::new(args.node_params.drop_header_only_frames),
+            malformed_frames: AtomicU64::new(0),
+            drops: DropLog::default().into(),
+            fragmenter: Fragmenter::new(device.mac_address(), args.node_params.fragment_size)
+                .into(),
+            compressor: Compressor::new(args.node_params.compress).into(),
+            reassembly: Reassembly::new(Duration::from_millis(
+                args.node_params.fragment_timeout.into(),
+            )),
+            capture: args
+                .capture_path
+                .as_deref()
+                .map(Capture::create)
+                .transpose()?
+                .map(Arc::new),
+            tasks: Tasks::default().into(),
+            cache: ClientCache::new(
+                usize::try_from(args.node_params.client_cache_capacity).unwrap_or(usize::MAX),
+                args.node_params
+                    .client_ttl
+                    .map(|ttl| Duration::from_millis(ttl.into())),
+            )
+            .into(),
             args,
             tun,
             device,
-            cache: ClientCache::default().into(),
         });
 
         tracing::info!(?rsu.args, "Setup Rsu");
@@ -48,15 +112,93 @@ impl Rsu {
         Ok(rsu)
     }
 
+    pub fn routing_snapshot(&self) -> Vec<RouteEntry> {
+        lock::read(&self.routing).routing_snapshot()
+    }
+
+    /// Frames received from the wire and waiting to be processed
+    pub fn receive_queue(&self) -> QueueDepth {
+        self.receive_queue.depth()
+    }
+
+    /// Data frames addressed to this node missing from their flow sequence
+    pub fn lost_frames(&self) -> u64 {
+        self.gaps.lost_frames()
+    }
+
+    /// Data frames handled per traffic class
+    pub fn traffic_classes(&self) -> ClassCounts {
+        self.classes.counts()
+    }
+
+    /// Frames received and sent on the wire and on the TAP
+    pub fn counters(&self) -> NodeCounters {
+        self.counters.counts()
+    }
+
+    /// Data frames dropped for carrying no payload past the ethernet header
+    pub fn header_only_frames(&self) -> u64 {
+        self.header_only.dropped()
+    }
+
+    /// Upstream frames dropped for malformed ethernet addresses
+    pub fn malformed_frames(&self) -> u64 {
+        self.malformed_frames.load(Ordering::Relaxed)
+    }
+
+    /// Clients currently remembered behind their node
+    pub fn cached_clients(&self) -> usize {
+        self.cache.len()
+    }
+
+    /// Clients remembered at most before the least recently used is evicted
+    pub fn client_cache_capacity(&self) -> usize {
+        self.cache.capacity()
+    }
+
+    /// Up to `limit` data frames dropped most recently, newest first
+    pub fn recent_drops(&self, limit: usize) -> Vec<DropRecord> {
+        self.drops.recent(limit)
+    }
+
+    pub fn update_routing_params(&self, params: NodeParameters) -> Result<()> {
+        lock::write(&self.routing).update_params(params)
+    }
+
+    /// Stops every task of the node, returning once they finished
+    pub async fn shutdown(&self) {
+        self.tasks.shutdown().await;
+        tracing::info!("Rsu shut down");
+    }
+
     fn wire_traffic_task(rsu: Arc<Self>) -> Result<()> {
         let device = rsu.device.clone();
         let tun = rsu.tun.clone();
+        let retries = rsu.args.send_retries;
+        let control_priority = rsu.args.node_params.control_priority;
 
-        tokio::task::spawn(async move {
+        let queue = rsu.receive_queue.clone();
+        let counters = rsu.counters.clone();
+        let capture = rsu.capture.clone();
+        let tasks = rsu.tasks.clone();
+        let mut frames = node::receive_frames(
+            &tasks,
+            device.clone(),
+            node::wire_frame_size(&rsu.args),
+            queue.clone(),
+            capture.clone(),
+        );
+        tasks.spawn(async move {
             loop {
                 let rsu = rsu.clone();
-                let messages = node::wire_traffic(&device, |pkt, size| {
+                let messages = node::wire_traffic(&mut frames, &queue, |pkt, size| {
                     async move {
+                        if rsu.ingress.should_drop() {
+                            tracing::trace!(dropped = rsu.ingress.dropped(), "ingress loss");
+                            return Ok(None);
+                        }
+
+                        rsu.counters.wire_received();
                         let Ok(msg) = Message::try_from(&pkt[..size]) else {
                             return Ok(None);
                         };
@@ -67,7 +209,16 @@ impl Rsu {
                     }
                 }).await;
                 if let Ok(Some(messages)) = messages {
-                    let _ = node::handle_messages(messages, &tun, &device).await;
+                    counters.record_replies(&messages);
+                    let _ = node::handle_messages(
+                        messages,
+                        &tun,
+                        &device,
+                        retries,
+                        control_priority,
+                        capture.as_deref(),
+                    )
+                    .await;
                 }
             }
         });
@@ -76,19 +227,68 @@ impl Rsu {
 
     async fn handle_msg(&self, msg: &Message<'_>) -> Result<Option<Vec<ReplyType>>> {
         match msg.get_packet_type() {
+            PacketType::Data(Data::Upstream(buf)) if buf.data().is_empty() => {
+                tracing::trace!(source = ?buf.source(), "keepalive");
+                Ok(None)
+            }
+            PacketType::Data(Data::Upstream(buf))
+                if !buf.is_fragment()
+                    && !buf.is_compressed()
+                    && self.header_only.should_drop(buf.data()) =>
+            {
+                self.drops
+                    .record(DropReason::HeaderOnly, drops::mac_of(buf.source()), None);
+                Ok(None)
+            }
             PacketType::Data(Data::Upstream(buf)) => {
-                let to: [u8; 6] = buf
-                    .data()
-                    .get(0..6)
-                    .ok_or_else(|| anyhow!("error"))?
-                    .try_into()?;
-                let to: MacAddress = to.into();
-                let from: [u8; 6] = buf
-                    .data()
-                    .get(6..12)
-                    .ok_or_else(|| anyhow!("error"))?
-                    .try_into()?;
-                let from: MacAddress = from.into();
+                let (frame, whole);
+                let buf = if buf.is_fragment() || buf.is_compressed() {
+                    let Some(source) = drops::mac_of(buf.source()) else {
+                        bail!("fragment without a source");
+                    };
+                    let reassembled = if buf.is_fragment() {
+                        let Some(frame) = self.reassembly.push(buf.data()) else {
+                            return Ok(None);
+                        };
+                        frame
+                    } else {
+                        buf.data().to_vec()
+                    };
+                    frame = if buf.is_compressed() {
+                        match compression::decompress(&reassembled) {
+                            Ok(frame) => frame,
+                            Err(e) => {
+                                tracing::debug!(?e, "undecodable compressed frame dropped");
+                                self.drops.record(DropReason::Malformed, Some(source), None);
+                                return Ok(None);
+                            }
+                        }
+                    } else {
+                        reassembled
+                    };
+                    whole = ToUpstream::new(source, &frame)
+                        .with_priority(buf.priority())
+                        .with_class(buf.class())
+                        .with_sequence(buf.sequence());
+                    &whole
+                } else {
+                    buf
+                };
+                let destination = match node::frame_destination(
+                    buf.data(),
+                    self.args.node_params.drop_malformed_frames,
+                ) {
+                    Ok(destination) => destination,
+                    Err(e) => {
+                        let dropped = self.malformed_frames.fetch_add(1, Ordering::Relaxed) + 1;
+                        tracing::debug!(?e, dropped, "malformed upstream frame dropped");
+                        self.drops
+                            .record(DropReason::Malformed, drops::mac_of(buf.source()), None);
+                        return Ok(None);
+                    }
+                };
+                self.classes.record(buf.class());
+                let (_, from) = node::ethernet_addresses(buf.data())?;
                 let source: [u8; 6] = buf
                     .source()
                     .get(0..6)
@@ -96,55 +296,77 @@ impl Rsu {
                     .try_into()?;
                 let source: MacAddress = source.into();
                 self.cache.store_mac(from, source);
-                let bcast_or_mcast = to == [255; 6].into() || to.bytes()[0] & 0x1 != 0;
-                let mut target = self.cache.get(to);
+                let (bcast_or_mcast, mut target) = match destination {
+                    FrameDestination::Broadcast => (true, None),
+                    FrameDestination::Unicast(to) => {
+                        let target = self.cache.get(to);
+                        if target.is_none() {
+                            self.drops
+                                .record(DropReason::NoRoute, Some(source), Some(to));
+                        }
+                        (false, target)
+                    }
+                };
                 let mut messages = Vec::with_capacity(1);
                 if bcast_or_mcast || target.is_some_and(|x| x == self.device.mac_address()) {
+                    self.gaps.record(source, buf.data(), buf.sequence());
                     messages.push(ReplyType::Tap(vec![buf.data().to_vec()]));
                     target = None;
                 }
 
-                let routing = self.routing.read().unwrap();
+                let clamp = self.args.node_params.clamp_path_mtu;
+                let routing = lock::read(&self.routing);
                 messages.extend(if bcast_or_mcast {
                     routing
                         .iter_next_hops()
                         .filter(|x| x != &&source)
                         .filter_map(|x| {
-                            let route = routing.get_route_to(Some(*x))?;
+                            let route = routing.get_route_for(Some(*x), buf.class())?;
                             Some((*x, route.mac))
                         })
-                        .map(|(target, next_hop)| {
-                            ReplyType::Wire(
-                                (&Message::new(
-                                    self.device.mac_address(),
-                                    next_hop,
-                                    PacketType::Data(Data::Downstream(ToDownstream::new(
-                                        buf.source(),
-                                        target,
-                                        buf.data(),
-                                    ))),
-                                ))
-                                    .into(),
-                            )
+                        .filter_map(|(target, next_hop)| {
+                            let wire = downstream_messages(
+                                &self.fragmenter,
+                                &self.compressor,
+                                self.device.mac_address(),
+                                next_hop,
+                                &ToDownstream::new(buf.source(), target, buf.data())
+                                    .with_priority(buf.priority())
+                                    .with_class(buf.class())
+                                    .with_sequence(buf.sequence()),
+                                node::payload_budget(clamp, routing.path_mtu(target)),
+                            );
+                            node::within_path_mtu(clamp, wire, routing.path_mtu(target))
                         })
+                        .flatten()
                         .collect_vec()
                 } else if let Some(target) = target {
-                    let Some(next_hop) = routing.get_route_to(Some(target)) else {
+                    let Some(next_hop) = routing.get_route_for(Some(target), buf.class()) else {
+                        self.drops
+                            .record(DropReason::NoRoute, Some(source), Some(target));
+                        return Ok(None);
+                    };
+
+                    let wire = downstream_messages(
+                        &self.fragmenter,
+                        &self.compressor,
+                        self.device.mac_address(),
+                        next_hop.mac,
+                        &ToDownstream::new(buf.source(), target, buf.data())
+                            .with_priority(buf.priority())
+                            .with_class(buf.class())
+                            .with_sequence(buf.sequence()),
+                        node::payload_budget(clamp, routing.path_mtu(target)),
+                    );
+                    let Some(outgoing) =
+                        node::within_path_mtu(clamp, wire, routing.path_mtu(target))
+                    else {
+                        self.drops
+                            .record(DropReason::PathMtu, Some(source), Some(target));
                         return Ok(None);
                     };
 
-                    vec![ReplyType::Wire(
-                        (&Message::new(
-                            self.device.mac_address(),
-                            next_hop.mac,
-                            PacketType::Data(Data::Downstream(ToDownstream::new(
-                                buf.source(),
-                                target,
-                                buf.data(),
-                            ))),
-                        ))
-                            .into(),
-                    )]
+                    outgoing
                 } else {
                     vec![]
                 });
@@ -153,9 +375,7 @@ impl Rsu {
             }
             PacketType::Control(Control::HeartbeatReply(hbr)) => {
                 if hbr.source() == self.device.mac_address() {
-                    self.routing
-                        .write()
-                        .unwrap()
+                    lock::write(&self.routing)
                         .handle_heartbeat_reply(msg, self.device.mac_address())
                 } else {
                     Ok(None)
@@ -173,22 +393,49 @@ impl Rsu {
         };
 
         let periodicity = Duration::from_millis(periodicity.into());
+        let mut adaptive = match (
+            self.args.node_params.adaptive_hello_min,
+            self.args.node_params.adaptive_hello_max,
+        ) {
+            (Some(min), Some(max)) => Some(AdaptivePeriodicity::new(
+                periodicity,
+                Duration::from_millis(min.into()),
+                Duration::from_millis(max.into()),
+            )),
+            _ => None,
+        };
+        let startup_delay = self.args.node_params.startup_delay;
         let routing = self.routing.clone();
         let device = self.device.clone();
+        let control_priority = self.args.node_params.control_priority;
+        let capture = self.capture.clone();
+
+        self.tasks.spawn(async move {
+            if let Some(delay) = startup_delay {
+                let _ = tokio_timerfd::sleep(Duration::from_millis(delay.into())).await;
+            }
 
-        tokio::task::spawn(async move {
             loop {
                 let msg: Vec<Vec<u8>> = {
-                    let mut routing = routing.write().unwrap();
+                    let mut routing = lock::write(&routing);
                     let msg = routing.send_heartbeat(device.mac_address());
                     tracing::trace!(?msg, "generated hello");
                     (&msg).into()
                 };
+                if let Some(capture) = &capture {
+                    capture.record(&msg.concat());
+                }
+
                 let vec: Vec<IoSlice> = msg.iter().map(|x| IoSlice::new(x)).collect();
-                let _ = device
-                    .send_vectored(&vec)
+                let _ = node::send_wire(&device, &vec, control_priority)
                     .await
                     .inspect_err(|e| tracing::error!(?e, "error sending hello"));
+                let periodicity = match adaptive {
+                    Some(ref mut adaptive) => {
+                        adaptive.next(&lock::read(&routing).routing_snapshot())
+                    }
+                    None => periodicity,
+                };
                 let _ = tokio_timerfd::sleep(periodicity).await;
             }
         });
@@ -196,76 +443,308 @@ impl Rsu {
     }
 
     fn process_tap_traffic(&self) -> Result<()> {
+        let buffer_size = node::tap_buffer_size(&self.args)?;
         let tun = self.tun.clone();
         let device = self.device.clone();
         let cache = self.cache.clone();
         let routing = self.routing.clone();
-        tokio::task::spawn(async move {
+        let sequence = self.sequence.clone();
+        let classes = self.classes.clone();
+        let counters = self.counters.clone();
+        let capture = self.capture.clone();
+        let drops = self.drops.clone();
+        let fragmenter = self.fragmenter.clone();
+        let compressor = self.compressor.clone();
+        let retries = self.args.send_retries;
+        let control_priority = self.args.node_params.control_priority;
+        let clamp = self.args.node_params.clamp_path_mtu;
+        let data_priority = self.args.node_params.data_priority;
+        let tag_management = self.args.node_params.tag_management_traffic;
+        self.tasks.spawn(async move {
             loop {
                 let devicec = device.clone();
                 let cache = cache.clone();
                 let routing = routing.clone();
-                let messages = node::tap_traffic(&tun, |pkt, size| async move {
-                    let data: &[u8] = &pkt[..size];
-                    let to: [u8; 6] = data[0..6].try_into()?;
-                    let to: MacAddress = to.into();
-                    let target = cache.get(to);
-                    let from: [u8; 6] = data[6..12].try_into()?;
-                    let from: MacAddress = from.into();
-                    let source_mac = devicec.mac_address().bytes();
-                    cache.store_mac(from, devicec.mac_address());
-                    let routing = routing.read().unwrap();
-                    let outgoing = if let Some(target) = target {
-                        let Some(hop) = routing.get_route_to(Some(target)) else {
-                            bail!("no route");
-                        };
+                let sequence = sequence.clone();
+                let classes = classes.clone();
+                let tap_counters = counters.clone();
+                let drops = drops.clone();
+                let fragmenter = fragmenter.clone();
+                let compressor = compressor.clone();
+                let messages =
+                    node::tap_traffic(&tun, &device, buffer_size, |pkt, size| async move {
+                        tap_counters.tap_received();
+                        let data: &[u8] = &pkt[..size];
+                        let (to, from) = node::ethernet_addresses(data)?;
+                        let target = cache.get(to);
+                        let source_mac = devicec.mac_address().bytes();
+                        cache.store_mac(from, devicec.mac_address());
+                        let sequence = sequence.next(devicec.mac_address(), data);
+                        let class = traffic::classify(data, tag_management);
+                        classes.record(class);
+                        let routing = lock::read(&routing);
+                        let outgoing = if let Some(target) = target {
+                            let Some(hop) = routing.get_route_for(Some(target), class) else {
+                                drops.record(DropReason::NoRoute, Some(from), Some(target));
+                                bail!("no route");
+                            };
 
-                        vec![ReplyType::Wire(
-                            (&Message::new(
+                            let wire = downstream_messages(
+                                &fragmenter,
+                                &compressor,
                                 devicec.mac_address(),
                                 hop.mac,
-                                PacketType::Data(Data::Downstream(ToDownstream::new(
-                                    &source_mac,
-                                    target,
-                                    data,
-                                ))),
-                            ))
-                                .into(),
-                        )]
-                    } else {
-                        routing
-                            .iter_next_hops()
-                            .filter(|x| x != &&devicec.mac_address())
-                            .filter_map(|x| {
-                                let dest = routing.get_route_to(Some(*x))?;
-                                Some((x, dest))
-                            })
-                            .map(|(x, y)| (x, y.mac))
-                            .unique_by(|(x, _)| *x)
-                            .map(|(x, next_hop)| {
-                                let msg = Message::new(
-                                    devicec.mac_address(),
-                                    next_hop,
-                                    PacketType::Data(Data::Downstream(ToDownstream::new(
-                                        &source_mac,
-                                        *x,
-                                        data,
-                                    ))),
-                                );
-                                ReplyType::Wire((&msg).into())
-                            })
-                            .collect_vec()
-                    };
-                    tracing::trace!(?outgoing, "outgoing from tap");
-                    Ok(Some(outgoing))
-                })
-                .await;
+                                &ToDownstream::new(&source_mac, target, data)
+                                    .with_priority(data_priority)
+                                    .with_class(class)
+                                    .with_sequence(sequence),
+                                node::payload_budget(clamp, routing.path_mtu(target)),
+                            );
+                            let Some(outgoing) =
+                                node::within_path_mtu(clamp, wire, routing.path_mtu(target))
+                            else {
+                                drops.record(DropReason::PathMtu, Some(from), Some(target));
+                                return Ok(None);
+                            };
+
+                            outgoing
+                        } else {
+                            routing
+                                .iter_next_hops()
+                                .filter(|x| x != &&devicec.mac_address())
+                                .filter_map(|x| {
+                                    let dest = routing.get_route_for(Some(*x), class)?;
+                                    Some((x, dest))
+                                })
+                                .map(|(x, y)| (x, y.mac))
+                                .unique_by(|(x, _)| *x)
+                                .filter_map(|(x, next_hop)| {
+                                    let wire = downstream_messages(
+                                        &fragmenter,
+                                        &compressor,
+                                        devicec.mac_address(),
+                                        next_hop,
+                                        &ToDownstream::new(&source_mac, *x, data)
+                                            .with_priority(data_priority)
+                                            .with_class(class)
+                                            .with_sequence(sequence),
+                                        node::payload_budget(clamp, routing.path_mtu(*x)),
+                                    );
+                                    node::within_path_mtu(clamp, wire, routing.path_mtu(*x))
+                                })
+                                .flatten()
+                                .collect_vec()
+                        };
+                        tracing::trace!(?outgoing, "outgoing from tap");
+                        Ok(Some(outgoing))
+                    })
+                    .await;
 
                 if let Ok(Some(messages)) = messages {
-                    let _ = node::handle_messages(messages, &tun, &device).await;
+                    counters.record_replies(&messages);
+                    let _ = node::handle_messages(
+                        messages,
+                        &tun,
+                        &device,
+                        retries,
+                        control_priority,
+                        capture.as_deref(),
+                    )
+                    .await;
                 }
             }
         });
         Ok(())
     }
 }
+
+/// Wire messages carrying `frame` through `next_hop`, compressed when
+/// enabled and split in fragments when it is too large to be sent whole,
+/// either by the fragment size or by the payload `budget` of the path
+fn downstream_messages(
+    fragmenter: &Fragmenter,
+    compressor: &Compressor,
+    from: MacAddress,
+    next_hop: MacAddress,
+    frame: &ToDownstream<'_>,
+    budget: Option<usize>,
+) -> Vec<Vec<Vec<u8>>> {
+    let compressed = compressor.compress(frame.data());
+    fragmenter
+        .payloads(compressed.as_deref().unwrap_or(frame.data()), budget)
+        .iter()
+        .map(|(fragment, payload)| {
+            (&Message::new(
+                from,
+                next_hop,
+                PacketType::Data(Data::Downstream(frame.carrying(
+                    payload,
+                    *fragment,
+                    compressed.is_some(),
+                ))),
+            ))
+                .into()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Rsu;
+    use crate::{
+        args::NodeType,
+        control::{fragment::Reassembly, lock, node::ReplyType},
+        messages::{
+            control::{heartbeat::HeartbeatReply, Control},
+            data::{Data, ToUpstream},
+            message::Message,
+            packet_type::PacketType,
+        },
+        test_helpers, Args,
+    };
+    use common::network_interface::NetworkInterface;
+    use mac_address::MacAddress;
+    use std::time::Duration;
+
+    fn rsu_args() -> Args {
+        let mut args = test_helpers::args(NodeType::Rsu);
+        args.node_params.hello_periodicity = Some(1000);
+        args
+    }
+
+    /// Makes `obu` a next hop of `rsu`, replying to a heartbeat with `mtu`
+    async fn reply_from(rsu: &Rsu, obu: MacAddress, mtu: u16) {
+        let heartbeat = lock::write(&rsu.routing).send_heartbeat(rsu.device.mac_address());
+        let PacketType::Control(Control::Heartbeat(hb)) = heartbeat.get_packet_type() else {
+            panic!("did not generate a heartbeat");
+        };
+        let reply = Message::new(
+            obu,
+            rsu.device.mac_address(),
+            PacketType::Control(Control::HeartbeatReply(
+                HeartbeatReply::from_sender(hb, obu).with_mtu(mtu),
+            )),
+        );
+        rsu.handle_msg(&reply).await.expect("handled reply");
+    }
+
+    #[tokio::test]
+    #[ignore = "needs CAP_NET_ADMIN to create TAPs"]
+    async fn shutdown_stops_the_node_tasks() {
+        let interfaces = test_helpers::node_interfaces();
+        let mut args = test_helpers::args(NodeType::Rsu);
+        args.node_params.hello_periodicity = Some(10);
+        let rsu = Rsu::new(args, interfaces.tun.clone(), interfaces.device.clone()).expect("rsu");
+        tokio::time::sleep(Duration::from_millis(30)).await;
+
+        tokio::time::timeout(Duration::from_secs(1), rsu.shutdown())
+            .await
+            .expect("tasks stopped in time");
+    }
+
+    #[tokio::test]
+    #[ignore = "needs CAP_NET_ADMIN to create TAPs"]
+    async fn frames_over_the_path_mtu_are_fragmented_instead_of_dropped() {
+        let interfaces = test_helpers::node_interfaces();
+        let mut args = rsu_args();
+        args.wire_mtu = 9000;
+        // Fragments are held to the 1500 byte path, not the fragment size
+        args.node_params.fragment_size = Some(8000);
+        let rsu = Rsu::new(args, interfaces.tun.clone(), interfaces.device.clone()).expect("rsu");
+        let (sender, receiver) = (MacAddress::new([7; 6]), MacAddress::new([2; 6]));
+        reply_from(&rsu, receiver, 1500).await;
+
+        let frame: Vec<u8> = [[0xff; 6], [2, 0, 0, 0, 0, 7]]
+            .concat()
+            .into_iter()
+            .chain((0..4000).map(|x| (x % 251) as u8))
+            .collect();
+        let upstream: Vec<Vec<u8>> = (&Message::new(
+            sender,
+            rsu.device.mac_address(),
+            PacketType::Data(Data::Upstream(ToUpstream::new(sender, &frame))),
+        ))
+            .into();
+        let upstream = upstream.concat();
+        let msg = Message::try_from(&upstream[..]).expect("valid frame");
+        let replies = rsu
+            .handle_msg(&msg)
+            .await
+            .expect("handled frame")
+            .expect("forwarded");
+
+        let reassembly = Reassembly::new(Duration::from_secs(5));
+        let mut reassembled = None;
+        for reply in &replies {
+            let ReplyType::Wire(wire) = reply else {
+                continue;
+            };
+            let wire = wire.concat();
+            assert!(wire.len() <= 1514, "{} byte message sent", wire.len());
+            let msg = Message::try_from(&wire[..]).expect("valid frame");
+            let PacketType::Data(Data::Downstream(down)) = msg.get_packet_type() else {
+                panic!("not a downstream frame");
+            };
+            assert!(down.is_fragment());
+            reassembled = reassembled.or(reassembly.push(down.data()));
+        }
+        assert_eq!(reassembled, Some(frame));
+        assert!(rsu.recent_drops(1).is_empty());
+
+        rsu.shutdown().await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs CAP_NET_ADMIN to create TAPs"]
+    async fn compressed_frames_are_held_to_the_path_mtu_by_their_compressed_size() {
+        let interfaces = test_helpers::node_interfaces();
+        let mut args = rsu_args();
+        args.wire_mtu = 9000;
+        args.node_params.compress = true;
+        let rsu = Rsu::new(args, interfaces.tun.clone(), interfaces.device.clone()).expect("rsu");
+        let (sender, receiver) = (MacAddress::new([7; 6]), MacAddress::new([2; 6]));
+        reply_from(&rsu, receiver, 1500).await;
+
+        let frame = |byte: fn(usize) -> u8| -> Vec<u8> {
+            [[0xff; 6], [2, 0, 0, 0, 0, 7]]
+                .concat()
+                .into_iter()
+                .chain((0..3000).map(byte))
+                .collect()
+        };
+        let forwarded = |frame: Vec<u8>| {
+            let rsu = &rsu;
+            async move {
+                let upstream: Vec<Vec<u8>> = (&Message::new(
+                    sender,
+                    rsu.device.mac_address(),
+                    PacketType::Data(Data::Upstream(ToUpstream::new(sender, &frame))),
+                ))
+                    .into();
+                let upstream = upstream.concat();
+                let msg = Message::try_from(&upstream[..]).expect("valid frame");
+                let replies = rsu.handle_msg(&msg).await.expect("handled frame");
+                replies
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter_map(|reply| match reply {
+                        ReplyType::Wire(wire) => Some(wire.concat()),
+                        ReplyType::Tap(_) => None,
+                    })
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        let compressible = forwarded(frame(|x| (x % 16) as u8)).await;
+        let [wire] = &compressible[..] else {
+            panic!("compressible frame not forwarded whole");
+        };
+        assert!(wire.len() <= 1514);
+
+        // Random bytes do not compress, so the frame is too large to send
+        let incompressible = forwarded(frame(|x| (x.wrapping_mul(2654435761) >> 7) as u8)).await;
+        assert!(incompressible.is_empty());
+
+        rsu.shutdown().await;
+    }
+}
@@ -1,10 +1,17 @@
 use crate::{
-    control::{node::ReplyType, route::Route},
+    args::{NodeParameters, RouteMetric},
+    control::{
+        node::{self, ReplyType},
+        route::{load_warm_routes, resize_history, LatencySamples, Route, RouteStatus},
+        snapshot::RouteEntry,
+    },
     messages::{
         control::{heartbeat::Heartbeat, Control},
+        data::TrafficClass,
         message::Message,
         packet_type::PacketType,
     },
+    position::Position,
     Args,
 };
 use anyhow::{bail, Result};
@@ -14,7 +21,7 @@ use mac_address::MacAddress;
 use std::{
     collections::{hash_map::Entry, HashMap},
     fmt::Debug,
-    time::{Duration, Instant},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 use tracing::Level;
 
@@ -30,6 +37,13 @@ pub struct Routing {
     hb_seq: u32,
     boot: Instant,
     sent: IndexMap<u32, (Duration, HashMap<MacAddress, Vec<Target>>)>,
+    position: Option<Position>,
+    route_metric: RouteMetric,
+    management_route_metric: Option<RouteMetric>,
+    mtu: u16,
+    path_mtus: HashMap<MacAddress, u16>,
+    warm: HashMap<MacAddress, Route>,
+    route_ttl: Option<Duration>,
 }
 
 impl Routing {
@@ -41,16 +55,56 @@ impl Routing {
             hb_seq: 0,
             boot: Instant::now(),
             sent: IndexMap::with_capacity(usize::try_from(args.node_params.hello_history)?),
+            position: args.node_params.position,
+            route_metric: args.node_params.route_metric,
+            management_route_metric: args.node_params.management_route_metric,
+            mtu: node::advertised_mtu(args),
+            path_mtus: HashMap::default(),
+            warm: match args.warm_routes {
+                Some(ref path) => load_warm_routes(path)?,
+                None => HashMap::default(),
+            },
+            route_ttl: args
+                .node_params
+                .route_ttl
+                .map(|ttl| Duration::from_millis(ttl.into())),
         })
     }
 
-    pub fn send_heartbeat(&mut self, address: MacAddress) -> Message {
-        let message = Heartbeat::new(
+    /// Applies a new parameter set. The sent heartbeat history is resized
+    /// keeping the most recent heartbeats.
+    pub fn update_params(&mut self, params: NodeParameters) -> Result<()> {
+        if params.hello_history == 0 {
+            bail!("we need to be able to store at least 1 hello");
+        }
+
+        resize_history(&mut self.sent, usize::try_from(params.hello_history)?);
+        self.position = params.position;
+        self.route_metric = params.route_metric;
+        self.management_route_metric = params.management_route_metric;
+        self.route_ttl = params
+            .route_ttl
+            .map(|ttl| Duration::from_millis(ttl.into()));
+        Ok(())
+    }
+
+    pub fn send_heartbeat(&mut self, address: MacAddress) -> Message<'static> {
+        let mut message = Heartbeat::new(
             Instant::now().duration_since(self.boot),
             self.hb_seq,
             address,
+        )
+        .with_mtu(self.mtu)
+        .with_timestamp(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default(),
         );
+        if let Some(position) = self.position {
+            message = message.with_position(position);
+        }
 
+        self.expire_routes_at(message.duration());
         if self.sent.first().is_some_and(|(x, _)| x > &message.id()) {
             self.sent.clear();
         }
@@ -74,6 +128,17 @@ impl Routing {
         msg
     }
 
+    /// Forgets heartbeats sent longer than the route TTL ago, along with the
+    /// routes learned from their replies.
+    fn expire_routes_at(&mut self, now: Duration) {
+        let Some(ttl) = self.route_ttl else {
+            return;
+        };
+
+        self.sent
+            .retain(|_, (sent_at, _)| now.saturating_sub(*sent_at) <= ttl);
+    }
+
     pub fn handle_heartbeat_reply(
         &mut self,
         msg: &Message,
@@ -89,7 +154,24 @@ impl Routing {
             return Ok(None);
         };
 
-        let latency = Instant::now().duration_since(self.boot) - hbr.duration();
+        for confirmed in [hbr.sender(), msg.from()?] {
+            if let Some(warm) = self.warm.remove(&confirmed) {
+                tracing::debug!(
+                    to = %confirmed,
+                    was_through = %warm,
+                    "warm route replaced on heartbeat reply",
+                );
+            }
+        }
+
+        self.path_mtus.insert(
+            hbr.sender(),
+            hbr.mtu().map_or(self.mtu, |mtu| mtu.min(self.mtu)),
+        );
+
+        let latency = Instant::now()
+            .duration_since(self.boot)
+            .saturating_sub(hbr.duration());
         match map.entry(hbr.sender()) {
             Entry::Occupied(mut entry) => {
                 let value = entry.get_mut();
@@ -142,7 +224,25 @@ impl Routing {
     }
 
     pub fn get_route_to(&self, mac: Option<MacAddress>) -> Option<Route> {
+        self.route_with_metric(mac, self.route_metric)
+    }
+
+    /// Route for frames of `class`, management traffic being selected by its
+    /// own metric when one is configured
+    pub fn get_route_for(&self, mac: Option<MacAddress>, class: TrafficClass) -> Option<Route> {
+        let metric = match class {
+            TrafficClass::User => self.route_metric,
+            TrafficClass::Management => self.management_route_metric.unwrap_or(self.route_metric),
+        };
+        self.route_with_metric(mac, metric)
+    }
+
+    fn route_with_metric(&self, mac: Option<MacAddress>, metric: RouteMetric) -> Option<Route> {
         let mac = mac?;
+        if !self.sent.values().any(|(_, m)| m.contains_key(&mac)) {
+            return self.warm.get(&mac).cloned();
+        }
+
         let route_options = self
             .sent
             .iter()
@@ -169,75 +269,110 @@ impl Routing {
 
         let (min_hops, _) = route_options.first()?;
 
-        let route_options: IndexMap<_, _> = route_options
+        route_options
             .iter()
             .filter(|(h, _)| h == &min_hops)
             .flat_map(|(hops, (_count, _min_seq, next, latency))| {
                 latency.iter().zip(next).fold(
                     HashMap::default(),
-                    |mut hm: HashMap<MacAddress, (f32, f32, f32, f32, u32)>, (val, mac)| {
-                        let entry = hm
-                            .entry(*mac)
-                            .or_insert((f32::MAX, 0.0, f32::MIN, 0.0, *hops));
-                        let val = *val as f32;
-
-                        if entry.0 > val {
-                            entry.0 = val;
-                        }
-
-                        if entry.2 < val {
-                            entry.2 = val;
-                        }
-
-                        entry.1 += val;
-                        entry.3 += 1.0;
+                    |mut hm: HashMap<MacAddress, (LatencySamples, u32)>, (val, mac)| {
+                        hm.entry(*mac)
+                            .or_insert((LatencySamples::default(), *hops))
+                            .0
+                            .push(*val as f64);
                         hm
                     },
                 )
             })
-            .map(|(mac, (min, sum, _, n, hops))| {
-                (((min + (sum / n)) / 2.0) as usize, (mac, hops, sum / n))
+            .min_by(|(ma, (a, _)), (mb, (b, _))| {
+                a.score(metric)
+                    .total_cmp(&b.score(metric))
+                    .then_with(|| ma.bytes().cmp(&mb.bytes()))
             })
-            .collect();
+            .map(|(mac, (samples, hops))| Route {
+                hops,
+                mac,
+                latency: Some(Duration::from_micros(samples.mean() as u64)),
+                status: RouteStatus::Confirmed,
+            })
+    }
 
-        route_options
-            .first()
-            .map(|(_, (mac, hops, latency))| Route {
-                hops: *hops,
-                mac: *mac,
-                latency: Some(Duration::from_micros(*latency as u64)),
+    /// Smallest MTU advertised on the path to `mac`
+    pub fn path_mtu(&self, mac: MacAddress) -> Option<u16> {
+        self.path_mtus.get(&mac).copied()
+    }
+
+    /// Current route to every known downstream node, with its latency and
+    /// age
+    pub fn routing_snapshot(&self) -> Vec<RouteEntry> {
+        let now = Instant::now().duration_since(self.boot);
+        self.current_routes()
+            .into_iter()
+            .map(|(destination, route)| RouteEntry {
+                destination,
+                next_hop: route.mac,
+                hops: route.hops,
+                status: route.status,
+                latency: route.latency,
+                age: self
+                    .last_answered(destination)
+                    .map(|sent_at| now.saturating_sub(sent_at)),
             })
+            .collect()
+    }
+
+    fn current_routes(&self) -> Vec<(MacAddress, Route)> {
+        self.iter_next_hops()
+            .filter_map(|destination| Some((*destination, self.get_route_to(Some(*destination))?)))
+            .collect()
+    }
+
+    /// When the newest heartbeat `destination` replied to was sent
+    fn last_answered(&self, destination: MacAddress) -> Option<Duration> {
+        self.sent
+            .values()
+            .filter(|(_, targets)| targets.contains_key(&destination))
+            .map(|(sent_at, _)| *sent_at)
+            .max()
     }
 
     pub fn iter_next_hops(&self) -> impl Iterator<Item = &MacAddress> {
-        self.sent.iter().flat_map(|(_, (_, m))| m.keys()).unique()
+        self.sent
+            .iter()
+            .flat_map(|(_, (_, m))| m.keys())
+            .chain(self.warm.keys())
+            .unique()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::{
-        args::{NodeParameters, NodeType},
-        control::rsu::Routing,
-        messages::{control::Control, message::Message, packet_type::PacketType},
-        Args,
+        args::{NodeType, RouteMetric},
+        control::{node, rsu::Routing},
+        messages::{
+            control::{
+                heartbeat::{Heartbeat, HeartbeatReply},
+                Control,
+            },
+            data::TrafficClass,
+            message::Message,
+            packet_type::PacketType,
+        },
+        test_helpers, Args,
     };
+    use mac_address::MacAddress;
+    use std::time::Duration;
+
+    fn rsu_args() -> Args {
+        let mut args = test_helpers::args(NodeType::Rsu);
+        args.node_params.hello_history = 1;
+        args
+    }
 
     #[test]
     fn can_generate_heartbeat() {
-        let args = Args {
-            bind: String::default(),
-            tap_name: None,
-            ip: None,
-            mtu: 1500,
-            node_params: NodeParameters {
-                node_type: NodeType::Rsu,
-                hello_history: 1,
-                hello_periodicity: None,
-            },
-        };
-
-        let Ok(mut routing) = Routing::new(&args) else {
+        let Ok(mut routing) = Routing::new(&rsu_args()) else {
             panic!("did not build a routing object");
         };
         let message = routing.send_heartbeat([1; 6].into());
@@ -266,4 +401,173 @@ mod tests {
         assert_eq!(hb.hops(), 1);
         assert_eq!(hb.id(), 0);
     }
+
+    #[test]
+    fn fuzzed_frames_are_handled_without_panics() {
+        let mut args = rsu_args();
+        args.node_params.hello_history = 8;
+        let mut routing = Routing::new(&args).expect("routing");
+        for _ in 0..8 {
+            let _ = routing.send_heartbeat([1; 6].into());
+        }
+
+        let frames = test_helpers::fuzzed_frames(0x125, 5000);
+        test_helpers::assert_no_panics(&frames, |frame| {
+            let Ok(msg) = Message::try_from(frame) else {
+                return;
+            };
+            let _ = routing.handle_heartbeat_reply(&msg, [1; 6].into());
+            let _ = routing.iter_next_hops().count();
+        });
+    }
+
+    #[test]
+    fn downstream_frames_are_clamped_to_path_mtu() {
+        let mut args = rsu_args();
+        args.wire_mtu = 9000;
+        let mut routing = Routing::new(&args).expect("routing");
+        let message = routing.send_heartbeat([1; 6].into());
+        let PacketType::Control(Control::Heartbeat(hb)) = message.get_packet_type() else {
+            panic!("did not generate a heartbeat");
+        };
+        assert_eq!(hb.mtu(), Some(9000));
+
+        let obu = MacAddress::new([2; 6]);
+        let reply = Message::new(
+            obu,
+            [1; 6].into(),
+            PacketType::Control(Control::HeartbeatReply(
+                HeartbeatReply::from_sender(hb, obu).with_mtu(1500),
+            )),
+        );
+        routing
+            .handle_heartbeat_reply(&reply, [1; 6].into())
+            .expect("handled reply");
+        assert_eq!(routing.path_mtu(obu), Some(1500));
+
+        let jumbo = vec![vec![0u8; 9014]];
+        let regular = vec![vec![0u8; 14], vec![0u8; 1500]];
+        assert!(!node::fits_path_mtu(true, &jumbo, routing.path_mtu(obu)));
+        assert!(node::fits_path_mtu(true, &regular, routing.path_mtu(obu)));
+        assert!(node::fits_path_mtu(false, &jumbo, routing.path_mtu(obu)));
+        assert!(node::fits_path_mtu(
+            true,
+            &jumbo,
+            routing.path_mtu([3; 6].into())
+        ));
+    }
+
+    /// Routing to `obu` through two next hops averaging 5ms, only `steady`
+    /// of them steadily
+    fn steady_and_jittery_routes(
+        args: &Args,
+        rsu: MacAddress,
+        (steady, jittery, obu): (MacAddress, MacAddress, MacAddress),
+    ) -> Routing {
+        let mut routing = Routing::new(args).expect("routing");
+        routing.boot -= Duration::from_secs(10);
+
+        for (id, jitter) in [(0, 1), (1, 9), (2, 1), (3, 9)] {
+            let _ = routing.send_heartbeat(rsu);
+            for (next_hop, latency) in [(steady, 5), (jittery, jitter)] {
+                let sent = Duration::from_secs(10) - Duration::from_millis(latency);
+                let heartbeat = Heartbeat::new(sent, id, rsu);
+                let reply = Message::new(
+                    next_hop,
+                    rsu,
+                    PacketType::Control(Control::HeartbeatReply(HeartbeatReply::from_sender(
+                        &heartbeat, obu,
+                    ))),
+                );
+                routing
+                    .handle_heartbeat_reply(&reply, rsu)
+                    .expect("handled reply");
+            }
+        }
+        routing
+    }
+
+    #[test]
+    fn low_jitter_metric_prefers_steady_next_hop() {
+        let rsu = MacAddress::new([1; 6]);
+        let (steady, jittery, obu) = (
+            MacAddress::new([2; 6]),
+            MacAddress::new([3; 6]),
+            MacAddress::new([4; 6]),
+        );
+
+        for (metric, expected) in [
+            (RouteMetric::LowJitter, steady),
+            (RouteMetric::Latency, jittery),
+        ] {
+            let mut args = rsu_args();
+            args.node_params.hello_history = 4;
+            args.node_params.route_metric = metric;
+            let routing = steady_and_jittery_routes(&args, rsu, (steady, jittery, obu));
+
+            let route = routing.get_route_to(Some(obu)).expect("has route");
+            assert_eq!(route.mac, expected, "{metric:?}");
+        }
+    }
+
+    #[test]
+    fn management_traffic_is_routed_by_its_own_metric() {
+        let rsu = MacAddress::new([1; 6]);
+        let (steady, jittery, obu) = (
+            MacAddress::new([2; 6]),
+            MacAddress::new([3; 6]),
+            MacAddress::new([4; 6]),
+        );
+        let mut args = rsu_args();
+        args.node_params.hello_history = 4;
+        args.node_params.route_metric = RouteMetric::Latency;
+        args.node_params.management_route_metric = Some(RouteMetric::LowJitter);
+        let mut routing = steady_and_jittery_routes(&args, rsu, (steady, jittery, obu));
+
+        let next_hop = |routing: &Routing, class| {
+            routing
+                .get_route_for(Some(obu), class)
+                .expect("has route")
+                .mac
+        };
+        assert_eq!(next_hop(&routing, TrafficClass::User), jittery);
+        assert_eq!(next_hop(&routing, TrafficClass::Management), steady);
+
+        args.node_params.management_route_metric = None;
+        routing
+            .update_params(args.node_params.clone())
+            .expect("updated");
+        assert_eq!(next_hop(&routing, TrafficClass::Management), jittery);
+    }
+
+    #[test]
+    fn routes_expire_after_their_ttl() {
+        let mut args = rsu_args();
+        args.node_params.route_ttl = Some(100);
+        let mut routing = Routing::new(&args).expect("routing");
+        let message = routing.send_heartbeat([1; 6].into());
+        let PacketType::Control(Control::Heartbeat(hb)) = message.get_packet_type() else {
+            panic!("did not generate a heartbeat");
+        };
+
+        let obu = MacAddress::new([2; 6]);
+        let reply = Message::new(
+            obu,
+            [1; 6].into(),
+            PacketType::Control(Control::HeartbeatReply(HeartbeatReply::from_sender(
+                hb, obu,
+            ))),
+        );
+        routing
+            .handle_heartbeat_reply(&reply, [1; 6].into())
+            .expect("handled reply");
+        assert!(routing.get_route_to(Some(obu)).is_some());
+
+        routing.expire_routes_at(hb.duration() + Duration::from_millis(100));
+        assert_eq!(routing.iter_next_hops().collect::<Vec<_>>(), vec![&obu]);
+
+        routing.expire_routes_at(hb.duration() + Duration::from_millis(101));
+        assert_eq!(routing.iter_next_hops().count(), 0);
+        assert!(routing.get_route_to(Some(obu)).is_none());
+    }
 }
@@ -0,0 +1,226 @@
+use mac_address::MacAddress;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+/// A flow is identified by the node a data frame entered the mesh at and the
+/// Ethernet destination of the frame it carries.
+type Flow = (MacAddress, MacAddress);
+
+fn flow(source: MacAddress, frame: &[u8]) -> Flow {
+    let destination: [u8; 6] = frame
+        .get(0..6)
+        .and_then(|x| x.try_into().ok())
+        .unwrap_or_default();
+    (source, destination.into())
+}
+
+/// Flows tracked at once by default
+const MAX_FLOWS: usize = 4096;
+
+/// Sequence number of each flow. Holds at most `capacity` flows, forgetting
+/// the least recently used one to make room for a new flow, so frames of
+/// ever new sources and destinations cannot grow it without bound.
+#[derive(Debug)]
+struct FlowTable {
+    capacity: usize,
+    clock: u64,
+    flows: HashMap<Flow, (u32, u64)>,
+}
+
+impl FlowTable {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            clock: 0,
+            flows: HashMap::default(),
+        }
+    }
+
+    fn get(&mut self, flow: &Flow) -> Option<u32> {
+        self.clock += 1;
+        let (sequence, used) = self.flows.get_mut(flow)?;
+        *used = self.clock;
+        Some(*sequence)
+    }
+
+    fn insert(&mut self, flow: Flow, sequence: u32) {
+        self.clock += 1;
+        if !self.flows.contains_key(&flow) && self.flows.len() >= self.capacity {
+            let evicted = self
+                .flows
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(flow, _)| *flow);
+            if let Some(evicted) = evicted {
+                self.flows.remove(&evicted);
+                tracing::trace!(source = %evicted.0, destination = %evicted.1, "flow forgotten");
+            }
+        }
+        self.flows.insert(flow, (sequence, self.clock));
+    }
+}
+
+/// Assigns per-flow sequence numbers to data frames entering the mesh. A
+/// flow forgotten to make room for others starts over from 0.
+#[derive(Debug)]
+pub struct SequenceNumbers {
+    next: Mutex<FlowTable>,
+}
+
+impl Default for SequenceNumbers {
+    fn default() -> Self {
+        Self::new(MAX_FLOWS)
+    }
+}
+
+impl SequenceNumbers {
+    /// Sequence numbers of at most `capacity` flows at once
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            next: Mutex::new(FlowTable::new(capacity)),
+        }
+    }
+
+    /// Sequence number of the next `frame` sent by `source`
+    pub fn next(&self, source: MacAddress, frame: &[u8]) -> u32 {
+        let mut next = self.next.lock().unwrap();
+        let flow = flow(source, frame);
+        let current = next.get(&flow).unwrap_or_default();
+        next.insert(flow, current.wrapping_add(1));
+        current
+    }
+}
+
+/// Checks sequence numbers of data frames leaving the mesh, counting the
+/// ones skipped in a flow as lost. Late or repeated frames are ignored.
+#[derive(Debug)]
+pub struct SequenceGaps {
+    last: Mutex<FlowTable>,
+    lost: AtomicU64,
+}
+
+impl Default for SequenceGaps {
+    fn default() -> Self {
+        Self::new(MAX_FLOWS)
+    }
+}
+
+impl SequenceGaps {
+    /// Checks the sequence numbers of at most `capacity` flows at once
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            last: Mutex::new(FlowTable::new(capacity)),
+            lost: AtomicU64::default(),
+        }
+    }
+
+    /// Records `frame` from `source` and returns how many frames before it
+    /// were lost
+    pub fn record(&self, source: MacAddress, frame: &[u8], sequence: u32) -> u32 {
+        let mut last = self.last.lock().unwrap();
+        let flow = flow(source, frame);
+        let lost = match last.get(&flow) {
+            None => 0,
+            Some(last) => {
+                let ahead = sequence.wrapping_sub(last);
+                if ahead == 0 || ahead > u32::MAX / 2 {
+                    return 0;
+                }
+                ahead - 1
+            }
+        };
+
+        last.insert(flow, sequence);
+        if lost > 0 {
+            self.lost.fetch_add(lost.into(), Ordering::Relaxed);
+            tracing::debug!(%source, sequence, lost, "gap in data frame sequence");
+        }
+        lost
+    }
+
+    pub fn lost_frames(&self) -> u64 {
+        self.lost.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SequenceGaps, SequenceNumbers};
+    use mac_address::MacAddress;
+
+    fn frame(destination: u8) -> Vec<u8> {
+        let mut frame = vec![destination; 6];
+        frame.extend([9; 6]);
+        frame
+    }
+
+    #[test]
+    fn sequence_numbers_are_assigned_per_flow() {
+        let numbers = SequenceNumbers::default();
+        let (a, b) = (MacAddress::new([1; 6]), MacAddress::new([2; 6]));
+
+        assert_eq!(numbers.next(a, &frame(3)), 0);
+        assert_eq!(numbers.next(a, &frame(3)), 1);
+        assert_eq!(numbers.next(a, &frame(4)), 0);
+        assert_eq!(numbers.next(b, &frame(3)), 0);
+        assert_eq!(numbers.next(a, &frame(3)), 2);
+    }
+
+    #[test]
+    fn gaps_are_counted_as_lost_frames() {
+        let numbers = SequenceNumbers::default();
+        let gaps = SequenceGaps::default();
+        let source = MacAddress::new([1; 6]);
+        let sent: Vec<u32> = (0..8).map(|_| numbers.next(source, &frame(3))).collect();
+
+        // Frames 2 and 3 never arrive, 5 arrives too late to count and 6 twice
+        let lost: Vec<u32> = [0, 1, 4, 6, 5, 6, 7]
+            .iter()
+            .map(|i| gaps.record(source, &frame(3), sent[*i]))
+            .collect();
+        assert_eq!(lost, vec![0, 0, 2, 1, 0, 0, 0]);
+        assert_eq!(gaps.lost_frames(), 3);
+
+        assert_eq!(gaps.record(source, &frame(4), 10), 0);
+        assert_eq!(gaps.record(source, &frame(4), 11), 0);
+        assert_eq!(gaps.lost_frames(), 3);
+    }
+
+    #[test]
+    fn gaps_are_detected_across_wraparound() {
+        let gaps = SequenceGaps::default();
+        let source = MacAddress::new([1; 6]);
+
+        assert_eq!(gaps.record(source, &frame(3), u32::MAX - 1), 0);
+        assert_eq!(gaps.record(source, &frame(3), 1), 2);
+        assert_eq!(gaps.lost_frames(), 2);
+    }
+
+    #[test]
+    fn least_recently_used_flows_are_forgotten() {
+        let numbers = SequenceNumbers::new(2);
+        let source = MacAddress::new([1; 6]);
+        for destination in [3, 3, 4, 3] {
+            numbers.next(source, &frame(destination));
+        }
+
+        numbers.next(source, &frame(5));
+        assert_eq!(numbers.next.lock().unwrap().flows.len(), 2);
+        assert_eq!(numbers.next(source, &frame(3)), 3);
+        assert_eq!(numbers.next(source, &frame(4)), 0);
+
+        let gaps = SequenceGaps::new(2);
+        for (destination, sequence) in [(3, 0), (4, 0), (3, 1), (5, 0)] {
+            gaps.record(source, &frame(destination), sequence);
+        }
+        assert_eq!(gaps.record(source, &frame(3), 3), 1);
+        // The flow to 4 was forgotten, so its next frame starts it over
+        assert_eq!(gaps.record(source, &frame(4), 5), 0);
+        assert_eq!(gaps.last.lock().unwrap().flows.len(), 2);
+    }
+}
@@ -0,0 +1,316 @@
+pub use crate::control::route::{shortest_path, MeshPath, RouteStatus};
+use anyhow::Context;
+use mac_address::MacAddress;
+use std::{
+    fmt::{Display, Formatter, Result},
+    str::FromStr,
+    time::Duration,
+};
+
+/// Route to a destination as seen by one node, with the measurements behind
+/// it. [`TopologySnapshot`] leaves the measurements out since they differ
+/// between otherwise identical runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RouteEntry {
+    pub destination: MacAddress,
+    pub next_hop: MacAddress,
+    pub hops: u32,
+    pub status: RouteStatus,
+    /// Mean latency measured over the route, if replies came back through it
+    pub latency: Option<Duration>,
+    /// Time since the newest heartbeat the route was learned from
+    pub age: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Line {
+    node: MacAddress,
+    route: RouteEntry,
+}
+
+impl Line {
+    fn key(&self) -> ([u8; 6], [u8; 6]) {
+        (self.node.bytes(), self.route.destination.bytes())
+    }
+}
+
+impl Display for Line {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(
+            f,
+            "{} {} {} {} {}",
+            self.node,
+            self.route.destination,
+            self.route.next_hop,
+            self.route.hops,
+            self.route.status
+        )
+    }
+}
+
+/// Routing state of a set of nodes in canonical order, so two snapshots of
+/// the same converged topology compare equal.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TopologySnapshot {
+    lines: Vec<Line>,
+}
+
+impl TopologySnapshot {
+    pub fn new(nodes: impl IntoIterator<Item = (MacAddress, Vec<RouteEntry>)>) -> Self {
+        let mut lines: Vec<Line> = nodes
+            .into_iter()
+            .flat_map(|(node, routes)| {
+                routes.into_iter().map(move |route| Line {
+                    node,
+                    route: RouteEntry {
+                        latency: None,
+                        age: None,
+                        ..route
+                    },
+                })
+            })
+            .collect();
+        lines.sort_by_key(Line::key);
+        lines.dedup_by_key(|line| line.key());
+        Self { lines }
+    }
+
+    /// Lines missing from or differing with `expected` are prefixed with `-`,
+    /// unexpected ones with `+`. Returns `None` when both match.
+    pub fn diff(&self, expected: &Self) -> Option<String> {
+        let mut out = String::new();
+        let (mut observed, mut expected) = (
+            self.lines.iter().peekable(),
+            expected.lines.iter().peekable(),
+        );
+        loop {
+            match (observed.peek(), expected.peek()) {
+                (None, None) => break,
+                (Some(o), Some(e)) if o.key() == e.key() => {
+                    if o != e {
+                        out.push_str(&format!("- {e}\n+ {o}\n"));
+                    }
+                    observed.next();
+                    expected.next();
+                }
+                (Some(o), Some(e)) if o.key() < e.key() => {
+                    out.push_str(&format!("+ {o}\n"));
+                    observed.next();
+                }
+                (Some(o), None) => {
+                    out.push_str(&format!("+ {o}\n"));
+                    observed.next();
+                }
+                (_, Some(e)) => {
+                    out.push_str(&format!("- {e}\n"));
+                    expected.next();
+                }
+            }
+        }
+
+        (!out.is_empty()).then_some(out)
+    }
+}
+
+impl Display for TopologySnapshot {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        self.lines.iter().try_for_each(|line| writeln!(f, "{line}"))
+    }
+}
+
+/// Parses one `<node> <destination> <next hop> <hops> <status>` entry per
+/// line.
+/// Empty lines and lines starting with `#` are ignored.
+impl FromStr for TopologySnapshot {
+    type Err = anyhow::Error;
+
+    fn from_str(contents: &str) -> anyhow::Result<Self> {
+        let lines =
+            contents
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(|line| -> anyhow::Result<(MacAddress, Vec<RouteEntry>)> {
+                    let mut fields = line.split_whitespace();
+                    let (
+                        Some(node),
+                        Some(destination),
+                        Some(next_hop),
+                        Some(hops),
+                        Some(status),
+                        None,
+                    ) = (
+                        fields.next(),
+                        fields.next(),
+                        fields.next(),
+                        fields.next(),
+                        fields.next(),
+                        fields.next(),
+                    )
+                    else {
+                        anyhow::bail!("malformed snapshot line: {line}");
+                    };
+
+                    Ok((
+                        node.parse()?,
+                        vec![RouteEntry {
+                            destination: destination.parse()?,
+                            next_hop: next_hop.parse()?,
+                            hops: hops.parse().context("invalid hops")?,
+                            status: status.parse()?,
+                            latency: None,
+                            age: None,
+                        }],
+                    ))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+        Ok(Self::new(lines))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RouteEntry, TopologySnapshot};
+    use crate::{
+        args::NodeType,
+        control::{node::ReplyType, obu::Routing as ObuRouting, rsu::Routing as RsuRouting},
+        messages::{control::Control, message::Message, packet_type::PacketType},
+        test_helpers,
+    };
+    use mac_address::MacAddress;
+    use std::{collections::VecDeque, time::Instant};
+
+    enum Node {
+        Rsu(RsuRouting),
+        Obu(ObuRouting),
+    }
+
+    impl Node {
+        fn handle(&mut self, msg: &Message, mac: MacAddress) -> Vec<Vec<u8>> {
+            let replies = match (self, msg.get_packet_type()) {
+                (Node::Obu(routing), PacketType::Control(Control::Heartbeat(_))) => {
+                    routing.handle_heartbeat(msg, mac)
+                }
+                (Node::Obu(routing), PacketType::Control(Control::HeartbeatReply(_))) => {
+                    routing.handle_heartbeat_reply(msg, mac)
+                }
+                (Node::Rsu(routing), PacketType::Control(Control::HeartbeatReply(hbr)))
+                    if hbr.source() == mac =>
+                {
+                    routing.handle_heartbeat_reply(msg, mac)
+                }
+                _ => Ok(None),
+            };
+
+            replies
+                .ok()
+                .flatten()
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(|reply| match reply {
+                    ReplyType::Wire(pkt) => Some(pkt.concat()),
+                    ReplyType::Tap(_) => None,
+                })
+                .collect()
+        }
+
+        fn routing_snapshot(&self) -> Vec<RouteEntry> {
+            match self {
+                Node::Rsu(routing) => routing.routing_snapshot(),
+                Node::Obu(routing) => routing.routing_snapshot(),
+            }
+        }
+    }
+
+    /// Delivers every frame to the neighbours of its sender until no more
+    /// frames are generated.
+    fn converge(nodes: &mut [(MacAddress, Node)], links: &[(usize, usize)], rsu: usize) {
+        let mut pending = VecDeque::new();
+        let (mac, Node::Rsu(routing)) = &mut nodes[rsu] else {
+            panic!("not an rsu");
+        };
+        let hello: Vec<Vec<u8>> = (&routing.send_heartbeat(*mac)).into();
+        pending.push_back((rsu, hello.concat()));
+
+        while let Some((sender, frame)) = pending.pop_front() {
+            let msg = Message::try_from(&frame[..]).expect("valid frame");
+            let to = msg.to().expect("has destination");
+            for &(a, b) in links {
+                let receiver = match (a == sender, b == sender) {
+                    (true, _) => b,
+                    (_, true) => a,
+                    _ => continue,
+                };
+                let (mac, node) = &mut nodes[receiver];
+                if to != *mac && to != [255; 6].into() {
+                    continue;
+                }
+
+                pending.extend(node.handle(&msg, *mac).into_iter().map(|f| (receiver, f)));
+            }
+        }
+    }
+
+    #[test]
+    fn line_topology_matches_golden_snapshot() {
+        let mut rsu_args = test_helpers::args(NodeType::Rsu);
+        rsu_args.node_params.hello_history = 2;
+        let obu_args = test_helpers::args(NodeType::Obu);
+        let boot = Instant::now();
+
+        let mut nodes = vec![
+            (
+                MacAddress::new([1; 6]),
+                Node::Rsu(RsuRouting::new(&rsu_args).expect("routing")),
+            ),
+            (
+                MacAddress::new([2; 6]),
+                Node::Obu(ObuRouting::new(&obu_args, &boot).expect("routing")),
+            ),
+            (
+                MacAddress::new([3; 6]),
+                Node::Obu(ObuRouting::new(&obu_args, &boot).expect("routing")),
+            ),
+        ];
+        converge(&mut nodes, &[(0, 1), (1, 2)], 0);
+
+        let observed = TopologySnapshot::new(
+            nodes
+                .iter()
+                .map(|(mac, node)| (*mac, node.routing_snapshot())),
+        );
+        let expected: TopologySnapshot = include_str!("testdata/line_topology.golden")
+            .parse()
+            .expect("valid golden snapshot");
+
+        if let Some(diff) = observed.diff(&expected) {
+            panic!("topology differs from golden snapshot:\n{diff}");
+        }
+    }
+
+    #[test]
+    fn diff_reports_missing_changed_and_unexpected_routes() {
+        let expected: TopologySnapshot =
+            "01:01:01:01:01:01 02:02:02:02:02:02 02:02:02:02:02:02 1 confirmed\n\
+             01:01:01:01:01:01 03:03:03:03:03:03 02:02:02:02:02:02 2 confirmed\n"
+                .parse()
+                .expect("valid snapshot");
+        let observed: TopologySnapshot =
+            "01:01:01:01:01:01 03:03:03:03:03:03 03:03:03:03:03:03 1 confirmed\n\
+             01:01:01:01:01:01 04:04:04:04:04:04 02:02:02:02:02:02 2 tentative\n"
+                .parse()
+                .expect("valid snapshot");
+
+        assert_eq!(expected.diff(&expected), None);
+        let diff = observed.diff(&expected).expect("snapshots differ");
+        assert_eq!(
+            diff.lines().collect::<Vec<_>>(),
+            vec![
+                "- 01:01:01:01:01:01 02:02:02:02:02:02 02:02:02:02:02:02 1 confirmed",
+                "- 01:01:01:01:01:01 03:03:03:03:03:03 02:02:02:02:02:02 2 confirmed",
+                "+ 01:01:01:01:01:01 03:03:03:03:03:03 03:03:03:03:03:03 1 confirmed",
+                "+ 01:01:01:01:01:01 04:04:04:04:04:04 02:02:02:02:02:02 2 tentative",
+            ]
+        );
+    }
+}
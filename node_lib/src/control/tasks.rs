@@ -0,0 +1,85 @@
+use std::{future::Future, sync::Mutex};
+use tokio::{sync::watch, task::JoinHandle};
+
+/// Background tasks of a node. Each runs until the node is shut down, when
+/// it is stopped at its next await point.
+#[derive(Debug)]
+pub struct Tasks {
+    stop: watch::Sender<bool>,
+    handles: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl Default for Tasks {
+    fn default() -> Self {
+        Self {
+            stop: watch::channel(false).0,
+            handles: Mutex::default(),
+        }
+    }
+}
+
+impl Tasks {
+    pub fn spawn(&self, task: impl Future<Output = ()> + Send + 'static) {
+        let mut stop = self.stop.subscribe();
+        let handle = tokio::task::spawn(async move {
+            tokio::select! {
+                _ = task => {}
+                _ = stop.wait_for(|stop| *stop) => {}
+            }
+        });
+        self.handles.lock().unwrap().push(handle);
+    }
+
+    /// Signals every task to stop and waits for them to finish
+    pub async fn shutdown(&self) {
+        self.stop.send_replace(true);
+        let handles = std::mem::take(&mut *self.handles.lock().unwrap());
+        for handle in handles {
+            let _ = handle
+                .await
+                .inspect_err(|e| tracing::warn!(?e, "task failed before shutdown"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Tasks;
+    use std::{
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    #[tokio::test]
+    async fn shutdown_stops_every_task() {
+        let tasks = Tasks::default();
+        let ticks = Arc::new(AtomicU64::new(0));
+        for _ in 0..3 {
+            let ticks = ticks.clone();
+            tasks.spawn(async move {
+                loop {
+                    ticks.fetch_add(1, Ordering::Relaxed);
+                    tokio::time::sleep(Duration::from_millis(1)).await;
+                }
+            });
+        }
+        tasks.spawn(std::future::pending());
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        tokio::time::timeout(Duration::from_secs(1), tasks.shutdown())
+            .await
+            .expect("tasks stopped in time");
+        assert!(tasks.handles.lock().unwrap().is_empty());
+
+        let stopped_at = ticks.load(Ordering::Relaxed);
+        assert!(stopped_at > 0);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(ticks.load(Ordering::Relaxed), stopped_at);
+
+        // Shutting down again is harmless
+        tasks.shutdown().await;
+    }
+}
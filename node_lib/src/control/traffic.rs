@@ -0,0 +1,92 @@
+use crate::messages::data::TrafficClass;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Data frames handled per traffic class, as exposed in metrics
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ClassCounts {
+    pub user: u64,
+    pub management: u64,
+}
+
+/// Counts data frames a node sends, forwards or delivers by traffic class.
+#[derive(Debug, Default)]
+pub struct ClassCounters {
+    user: AtomicU64,
+    management: AtomicU64,
+}
+
+impl ClassCounters {
+    pub fn record(&self, class: TrafficClass) {
+        let counter = match class {
+            TrafficClass::User => &self.user,
+            TrafficClass::Management => &self.management,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn counts(&self) -> ClassCounts {
+        ClassCounts {
+            user: self.user.load(Ordering::Relaxed),
+            management: self.management.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// DSCP of an IPv4 or IPv6 packet carried in an ethernet frame
+fn dscp(frame: &[u8]) -> Option<u8> {
+    match (frame.get(12..14)?, frame.get(14..16)?) {
+        ([0x08, 0x00], [_, tos]) => Some(tos >> 2),
+        ([0x86, 0xdd], [version, class]) => Some(((version << 4) | (class >> 4)) >> 2),
+        _ => None,
+    }
+}
+
+/// Class of a frame read from the TAP. With `tag_management`, packets marked
+/// with a network control DSCP (CS6 or CS7) are management traffic.
+pub fn classify(frame: &[u8], tag_management: bool) -> TrafficClass {
+    match dscp(frame) {
+        Some(dscp) if tag_management && dscp >= 48 => TrafficClass::Management,
+        _ => TrafficClass::User,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{classify, ClassCounters, ClassCounts};
+    use crate::messages::data::TrafficClass;
+
+    fn frame(ethertype: [u8; 2], header: [u8; 2]) -> Vec<u8> {
+        [&[1; 6][..], &[2; 6], &ethertype, &header, &[0; 20]].concat()
+    }
+
+    #[test]
+    fn network_control_packets_are_management_traffic() {
+        // CS6 on IPv4, CS7 on IPv6 and AF41 on IPv4
+        let cs6 = frame([0x08, 0x00], [0x45, 0xc0]);
+        let cs7 = frame([0x86, 0xdd], [0x6e, 0x00]);
+        let af41 = frame([0x08, 0x00], [0x45, 0x88]);
+
+        assert_eq!(classify(&cs6, true), TrafficClass::Management);
+        assert_eq!(classify(&cs7, true), TrafficClass::Management);
+        assert_eq!(classify(&af41, true), TrafficClass::User);
+        assert_eq!(classify(&cs6, false), TrafficClass::User);
+        assert_eq!(classify(&[0; 13], true), TrafficClass::User);
+    }
+
+    #[test]
+    fn frames_are_counted_per_class() {
+        let counters = ClassCounters::default();
+        counters.record(TrafficClass::Management);
+        counters.record(TrafficClass::User);
+        counters.record(TrafficClass::User);
+
+        assert_eq!(
+            counters.counts(),
+            ClassCounts {
+                user: 2,
+                management: 1,
+            }
+        );
+    }
+}
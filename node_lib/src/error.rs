@@ -0,0 +1,101 @@
+use std::fmt;
+
+/// Errors callers may want to tell apart, usually carried inside an
+/// [`anyhow::Error`] and recovered with `downcast_ref`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeError {
+    /// Frame of this protocol stamped with a version this node cannot parse
+    UnsupportedVersion(u8),
+}
+
+impl fmt::Display for NodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsupportedVersion(version) => {
+                write!(f, "unsupported protocol version {version}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for NodeError {}
+
+/// Node configuration the node cannot be created from, as reported by
+/// [`crate::args::Args::validate`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    /// No interface to bind to
+    EmptyBind,
+    /// MTU not positive or too large to advertise in heartbeats
+    InvalidMtu(i32),
+    /// TAP read buffer too small to hold a frame of the MTU
+    TapBufferTooSmall { size: usize, frame: usize },
+    /// Routing tables must store at least one hello
+    NoHelloHistory,
+    /// RSUs need a periodicity to send hellos at
+    MissingHelloPeriodicity,
+    /// Adaptive hello periodicity with a minimum above its maximum
+    AdaptiveHelloRange { min: u32, max: u32 },
+    /// Ingress loss outside of [0, 1]
+    InvalidIngressLoss(f64),
+    /// Fragments with no room for data after their header
+    FragmentSizeTooSmall(u32),
+    /// Fragments too large for the wire MTU once wrapped in a data message
+    FragmentSizeAboveMtu { size: u32, max: usize },
+    /// Frames of the MTU too large for the wire once wrapped in a data
+    /// message, with no fragmentation to split them
+    FrameAboveWireMtu { frame: usize, wire_mtu: u16 },
+    /// Handover needing no heartbeats at all to happen
+    NoHandoverObservations,
+    /// Client cache unable to hold a single client
+    NoClientCacheCapacity,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::EmptyBind => write!(f, "no interface to bind to"),
+            Self::InvalidMtu(mtu) => write!(f, "invalid mtu {mtu}"),
+            Self::TapBufferTooSmall { size, frame } => {
+                write!(
+                    f,
+                    "tap buffer of {size} bytes cannot hold a {frame} byte frame"
+                )
+            }
+            Self::NoHelloHistory => write!(f, "hello history must store at least 1 hello"),
+            Self::MissingHelloPeriodicity => write!(f, "rsu needs a hello periodicity"),
+            Self::AdaptiveHelloRange { min, max } => {
+                write!(f, "adaptive hello minimum {min} above maximum {max}")
+            }
+            Self::InvalidIngressLoss(loss) => {
+                write!(f, "ingress loss must be within [0, 1], got {loss}")
+            }
+            Self::FragmentSizeTooSmall(size) => {
+                write!(
+                    f,
+                    "fragment size {size} leaves no room past the fragment header"
+                )
+            }
+            Self::FragmentSizeAboveMtu { size, max } => {
+                write!(
+                    f,
+                    "fragment size {size} above {max}, the most a data message carries within the wire mtu"
+                )
+            }
+            Self::FrameAboveWireMtu { frame, wire_mtu } => {
+                write!(
+                    f,
+                    "{frame} byte frames exceed wire mtu {wire_mtu} once wrapped, lower the mtu or set a fragment size"
+                )
+            }
+            Self::NoHandoverObservations => {
+                write!(f, "handover needs at least 1 heartbeat observation")
+            }
+            Self::NoClientCacheCapacity => {
+                write!(f, "client cache must hold at least 1 client")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
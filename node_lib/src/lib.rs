@@ -1,25 +1,132 @@
 pub mod args;
 pub mod control;
 mod data;
+pub mod error;
 mod messages;
+pub mod position;
+#[cfg(test)]
+mod test_helpers;
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use args::{Args, NodeType};
 use common::device::Device;
-use control::node::ReplyType;
+use control::{
+    counters::NodeCounters, drops::DropRecord, node::ReplyType, receive_queue::QueueDepth,
+    snapshot::RouteEntry, traffic::ClassCounts,
+};
+use itertools::Itertools;
+use mac_address::MacAddress;
 use std::sync::Arc;
 use tokio_tun::Tun;
 
-pub trait Node {}
+pub trait Node: Send + Sync {
+    /// Whether the node is an RSU or an OBU
+    fn node_type(&self) -> NodeType;
 
-impl Node for control::rsu::Rsu {}
-impl Node for control::obu::Obu {}
+    /// Current route to every destination known by the node, with its
+    /// latency and age
+    fn routing_snapshot(&self) -> Vec<RouteEntry>;
+
+    /// Frames received from the wire and waiting to be processed
+    fn receive_queue(&self) -> QueueDepth;
+
+    /// Data frames addressed to the node missing from their flow sequence
+    fn lost_frames(&self) -> u64;
+
+    /// Data frames handled per traffic class
+    fn traffic_classes(&self) -> ClassCounts;
+
+    /// Up to `limit` data frames dropped most recently, newest first
+    fn recent_drops(&self, limit: usize) -> Vec<DropRecord>;
+
+    /// Frames received and sent on the wire and on the TAP
+    fn counters(&self) -> NodeCounters {
+        NodeCounters::default()
+    }
+
+    /// Share of heartbeats missed from every source heard from
+    fn heartbeat_loss(&self) -> Vec<(MacAddress, f64)> {
+        Vec::new()
+    }
+}
+
+impl Node for control::rsu::Rsu {
+    fn node_type(&self) -> NodeType {
+        NodeType::Rsu
+    }
+
+    fn routing_snapshot(&self) -> Vec<RouteEntry> {
+        control::rsu::Rsu::routing_snapshot(self)
+    }
+
+    fn receive_queue(&self) -> QueueDepth {
+        control::rsu::Rsu::receive_queue(self)
+    }
+
+    fn lost_frames(&self) -> u64 {
+        control::rsu::Rsu::lost_frames(self)
+    }
+
+    fn traffic_classes(&self) -> ClassCounts {
+        control::rsu::Rsu::traffic_classes(self)
+    }
+
+    fn recent_drops(&self, limit: usize) -> Vec<DropRecord> {
+        control::rsu::Rsu::recent_drops(self, limit)
+    }
+
+    fn counters(&self) -> NodeCounters {
+        control::rsu::Rsu::counters(self)
+    }
+}
+
+impl Node for control::obu::Obu {
+    fn node_type(&self) -> NodeType {
+        NodeType::Obu
+    }
+
+    fn routing_snapshot(&self) -> Vec<RouteEntry> {
+        control::obu::Obu::routing_snapshot(self)
+    }
+
+    fn receive_queue(&self) -> QueueDepth {
+        control::obu::Obu::receive_queue(self)
+    }
+
+    fn lost_frames(&self) -> u64 {
+        control::obu::Obu::lost_frames(self)
+    }
+
+    fn traffic_classes(&self) -> ClassCounts {
+        control::obu::Obu::traffic_classes(self)
+    }
+
+    fn recent_drops(&self, limit: usize) -> Vec<DropRecord> {
+        control::obu::Obu::recent_drops(self, limit)
+    }
+
+    fn counters(&self) -> NodeCounters {
+        control::obu::Obu::counters(self)
+    }
+
+    fn heartbeat_loss(&self) -> Vec<(MacAddress, f64)> {
+        control::obu::Obu::heartbeat_loss(self)
+    }
+}
+
+/// Checks `args` before anything is created from them
+fn validate(args: &Args) -> Result<()> {
+    args.validate()
+        .map_err(|errors| anyhow!("invalid configuration: {}", errors.iter().join(", ")))
+}
 
 pub fn create_with_vdev(
     args: Args,
     tun: Arc<Tun>,
     node_device: Arc<Device>,
 ) -> Result<Arc<dyn Node>> {
+    validate(&args)?;
+    node_device.set_destination_filter(args.filter_destination);
     match args.node_params.node_type {
         NodeType::Rsu => Ok(control::rsu::Rsu::new(args, tun, node_device)?),
         NodeType::Obu => Ok(control::obu::Obu::new(args, tun, node_device)?),
@@ -27,6 +134,7 @@ pub fn create_with_vdev(
 }
 
 pub fn create(args: Args) -> Result<Arc<dyn Node>> {
+    validate(&args)?;
     let tun = Arc::new(if args.ip.is_some() {
         Tun::builder()
             .name(args.tap_name.as_ref().unwrap_or(&String::default()))
@@ -1,13 +1,52 @@
+use crate::position::Position;
 use anyhow::bail;
 use mac_address::MacAddress;
 use std::{borrow::Cow, time::Duration};
 
+const TLV_POSITION: u8 = 0x01;
+const TLV_MTU: u8 = 0x02;
+const TLV_TIMESTAMP: u8 = 0x03;
+const TLV_RELAYS: u8 = 0x04;
+
+/// Relays a single TLV value has room for
+const MAX_RELAYS: usize = u8::MAX as usize / 6;
+
+fn find_tlv(mut remaining: &[u8], kind: u8) -> Option<&[u8]> {
+    while let (Some(tkind), Some(len)) = (remaining.first(), remaining.get(1)) {
+        let value = remaining.get(2..2 + usize::from(*len))?;
+        if *tkind == kind {
+            return Some(value);
+        }
+        remaining = &remaining[2 + usize::from(*len)..];
+    }
+    None
+}
+
+fn set_tlv(tlvs: &mut Vec<u8>, kind: u8, value: &[u8]) {
+    let mut kept = Vec::with_capacity(tlvs.len() + value.len() + 2);
+    let mut remaining: &[u8] = tlvs;
+    while let (Some(tkind), Some(len)) = (remaining.first(), remaining.get(1)) {
+        let Some(entry) = remaining.get(..2 + usize::from(*len)) else {
+            break;
+        };
+        if *tkind != kind {
+            kept.extend_from_slice(entry);
+        }
+        remaining = &remaining[entry.len()..];
+    }
+    kept.push(kind);
+    kept.push(u8::try_from(value.len()).expect("tlv value too long"));
+    kept.extend_from_slice(value);
+    *tlvs = kept;
+}
+
 #[derive(Debug, Clone)]
 pub struct Heartbeat<'a> {
     duration: Cow<'a, [u8]>,
     id: Cow<'a, [u8]>,
     hops: Cow<'a, [u8]>,
     source: Cow<'a, [u8]>,
+    tlvs: Cow<'a, [u8]>,
 }
 
 impl<'a> Heartbeat<'a> {
@@ -17,9 +56,68 @@ impl<'a> Heartbeat<'a> {
             id: Cow::Owned(id.to_be_bytes().to_vec()),
             hops: Cow::Owned(0u32.to_be_bytes().to_vec()),
             source: Cow::Owned(source.bytes().to_vec()),
+            tlvs: Cow::Owned(Vec::new()),
         }
     }
 
+    /// Sets a TLV, replacing any previous value of the same kind
+    pub fn with_tlv(mut self, kind: u8, value: &[u8]) -> Self {
+        set_tlv(self.tlvs.to_mut(), kind, value);
+        self
+    }
+
+    pub fn tlv(&self, kind: u8) -> Option<&[u8]> {
+        find_tlv(&self.tlvs, kind)
+    }
+
+    pub fn with_position(self, position: Position) -> Self {
+        self.with_tlv(TLV_POSITION, &position.to_bytes())
+    }
+
+    pub fn position(&self) -> Option<Position> {
+        Position::from_bytes(self.tlv(TLV_POSITION)?)
+    }
+
+    pub fn with_mtu(self, mtu: u16) -> Self {
+        self.with_tlv(TLV_MTU, &mtu.to_be_bytes())
+    }
+
+    /// Smallest MTU advertised along the path the heartbeat travelled
+    pub fn mtu(&self) -> Option<u16> {
+        Some(u16::from_be_bytes(self.tlv(TLV_MTU)?.try_into().ok()?))
+    }
+
+    /// Sets the wall clock time the source generated the heartbeat at, as
+    /// time since the Unix epoch with millisecond resolution
+    pub fn with_timestamp(self, timestamp: Duration) -> Self {
+        let millis = u64::try_from(timestamp.as_millis()).unwrap_or(u64::MAX);
+        self.with_tlv(TLV_TIMESTAMP, &millis.to_be_bytes())
+    }
+
+    pub fn timestamp(&self) -> Option<Duration> {
+        Some(Duration::from_millis(u64::from_be_bytes(
+            self.tlv(TLV_TIMESTAMP)?.try_into().ok()?,
+        )))
+    }
+
+    /// Appends `mac` to the relays that forwarded the heartbeat. Once the
+    /// TLV is full the relays nearest the source are dropped.
+    pub fn with_relay(self, mac: MacAddress) -> Self {
+        let mut relays = self.tlv(TLV_RELAYS).unwrap_or_default().to_vec();
+        relays.extend_from_slice(&mac.bytes());
+        let excess = relays.len().saturating_sub(MAX_RELAYS * 6);
+        self.with_tlv(TLV_RELAYS, &relays[excess..])
+    }
+
+    /// Relays that forwarded the heartbeat, nearest the source first
+    pub fn relays(&self) -> Vec<MacAddress> {
+        self.tlv(TLV_RELAYS)
+            .unwrap_or_default()
+            .chunks_exact(6)
+            .map(|mac| MacAddress::new(mac.try_into().unwrap()))
+            .collect()
+    }
+
     pub fn duration(&self) -> Duration {
         Duration::from_millis(
             u64::try_from(u128::from_be_bytes(
@@ -27,7 +125,7 @@ impl<'a> Heartbeat<'a> {
                     .try_into()
                     .unwrap(),
             ))
-            .unwrap(),
+            .unwrap_or(u64::MAX),
         )
     }
 
@@ -64,12 +162,14 @@ impl<'a> TryFrom<&'a [u8]> for Heartbeat<'a> {
         let id = Cow::Borrowed(id);
         let hops = Cow::Borrowed(hops);
         let source = Cow::Borrowed(source);
+        let tlvs = Cow::Borrowed(value.get(30..).unwrap_or_default());
 
         Ok(Self {
             duration,
             id,
             hops,
             source,
+            tlvs,
         })
     }
 }
@@ -80,13 +180,17 @@ impl<'a> From<&Heartbeat<'a>> for Vec<Vec<u8>> {
             panic!("did not have hops")
         };
         let hops: [u8; 4] = hops.try_into().expect("convert");
-        let hops = u32::from_be_bytes(hops) + 1;
-        vec![
+        let hops = u32::from_be_bytes(hops).saturating_add(1);
+        let mut result = vec![
             value.duration.clone().into_owned(),
             value.id.clone().into_owned(),
             hops.to_be_bytes().to_vec(),
             value.source.clone().into_owned(),
-        ]
+        ];
+        if !value.tlvs.is_empty() {
+            result.push(value.tlvs.clone().into_owned());
+        }
+        result
     }
 }
 
@@ -97,6 +201,7 @@ pub struct HeartbeatReply<'a> {
     hops: Cow<'a, [u8]>,
     source: Cow<'a, [u8]>,
     sender: Cow<'a, [u8]>,
+    tlvs: Cow<'a, [u8]>,
 }
 
 impl<'a> HeartbeatReply<'a> {
@@ -107,9 +212,29 @@ impl<'a> HeartbeatReply<'a> {
             hops: value.hops.clone(),
             source: value.source.clone(),
             sender: Cow::Owned(sender.bytes().to_vec()),
+            tlvs: Cow::Owned(Vec::new()),
         }
     }
 
+    /// Sets a TLV, replacing any previous value of the same kind
+    pub fn with_tlv(mut self, kind: u8, value: &[u8]) -> Self {
+        set_tlv(self.tlvs.to_mut(), kind, value);
+        self
+    }
+
+    pub fn tlv(&self, kind: u8) -> Option<&[u8]> {
+        find_tlv(&self.tlvs, kind)
+    }
+
+    pub fn with_mtu(self, mtu: u16) -> Self {
+        self.with_tlv(TLV_MTU, &mtu.to_be_bytes())
+    }
+
+    /// Smallest MTU advertised along the path back from the sender
+    pub fn mtu(&self) -> Option<u16> {
+        Some(u16::from_be_bytes(self.tlv(TLV_MTU)?.try_into().ok()?))
+    }
+
     pub fn duration(&self) -> Duration {
         Duration::from_millis(
             u64::try_from(u128::from_be_bytes(
@@ -117,7 +242,7 @@ impl<'a> HeartbeatReply<'a> {
                     .try_into()
                     .unwrap(),
             ))
-            .unwrap(),
+            .unwrap_or(u64::MAX),
         )
     }
 
@@ -164,6 +289,7 @@ impl<'a> TryFrom<&'a [u8]> for HeartbeatReply<'a> {
         let hops = Cow::Borrowed(hops);
         let source = Cow::Borrowed(source);
         let sender = Cow::Borrowed(sender);
+        let tlvs = Cow::Borrowed(value.get(36..).unwrap_or_default());
 
         Ok(Self {
             duration,
@@ -171,26 +297,34 @@ impl<'a> TryFrom<&'a [u8]> for HeartbeatReply<'a> {
             hops,
             source,
             sender,
+            tlvs,
         })
     }
 }
 
 impl<'a> From<&HeartbeatReply<'a>> for Vec<Vec<u8>> {
     fn from(value: &HeartbeatReply<'a>) -> Self {
-        vec![
+        let mut result = vec![
             value.duration.clone().into_owned(),
             value.id.clone().into_owned(),
             value.hops.clone().into_owned(),
             value.source.clone().into_owned(),
             value.sender.clone().into_owned(),
-        ]
+        ];
+        if !value.tlvs.is_empty() {
+            result.push(value.tlvs.clone().into_owned());
+        }
+        result
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Heartbeat;
-    use crate::messages::{control::Control, message::Message, packet_type::PacketType};
+    use super::{Heartbeat, HeartbeatReply};
+    use crate::{
+        messages::{control::Control, message::Message, packet_type::PacketType},
+        position::Position,
+    };
     use mac_address::MacAddress;
     use std::time::Duration;
 
@@ -199,7 +333,7 @@ mod tests {
         let pkt = vec![
             vec![1u8; 6],
             vec![2u8; 6],
-            vec![0x30, 0x30],
+            vec![0x30, 0x31],
             vec![0],
             vec![0],
             vec![4; 16],
@@ -216,7 +350,7 @@ mod tests {
         let pkt = vec![
             vec![1u8; 6],
             vec![2u8; 6],
-            vec![0x30, 0x30],
+            vec![0x30, 0x31],
             vec![0],
             vec![0],
             vec![4; 16],
@@ -242,7 +376,7 @@ mod tests {
         let pkt = vec![
             vec![255u8; 6],
             vec![0u8; 6],
-            vec![0x30, 0x30],
+            vec![0x30, 0x31],
             vec![0],
             vec![0],
             vec![0; 16],
@@ -260,7 +394,7 @@ mod tests {
         let pkt = vec![
             vec![1u8; 6],
             vec![2u8; 6],
-            vec![0x30, 0x30],
+            vec![0x30, 0x31],
             vec![0],
             vec![1],
             vec![4; 16],
@@ -277,4 +411,125 @@ mod tests {
         let rpkt: Vec<Vec<u8>> = (&msg).into();
         assert_eq!(pkt, rpkt);
     }
+
+    #[test]
+    fn heartbeat_position_survives_serialization() {
+        let position = Position {
+            lat: 40.2033,
+            lon: -8.4103,
+        };
+        let msg = Message::new(
+            [0; 6].into(),
+            [255; 6].into(),
+            PacketType::Control(Control::Heartbeat(
+                Heartbeat::new(Duration::default(), 0, [4; 6].into()).with_position(position),
+            )),
+        );
+
+        let pkt: Vec<Vec<u8>> = (&msg).into();
+        let pkt: Vec<u8> = pkt.iter().flat_map(|x| x.iter()).cloned().collect();
+        let msg = Message::try_from(&pkt[..]).expect("is message");
+        let PacketType::Control(Control::Heartbeat(hb)) = msg.get_packet_type() else {
+            panic!("did not parse a heartbeat");
+        };
+
+        assert_eq!(hb.position(), Some(position));
+        assert_eq!(hb.source(), [4; 6].into());
+        assert_eq!(hb.hops(), 1);
+    }
+
+    #[test]
+    fn heartbeat_without_position_has_none() {
+        let hb = Heartbeat::new(Duration::default(), 0, [4; 6].into());
+        assert_eq!(hb.position(), None);
+    }
+
+    #[test]
+    fn timestamp_tlv_survives_serialization() {
+        let timestamp = Duration::from_millis(1_700_000_000_123);
+        let msg = Message::new(
+            [0; 6].into(),
+            [255; 6].into(),
+            PacketType::Control(Control::Heartbeat(
+                Heartbeat::new(Duration::default(), 0, [4; 6].into())
+                    .with_mtu(1500)
+                    .with_timestamp(timestamp),
+            )),
+        );
+
+        let pkt: Vec<Vec<u8>> = (&msg).into();
+        let pkt = pkt.concat();
+        let msg = Message::try_from(&pkt[..]).expect("is message");
+        let PacketType::Control(Control::Heartbeat(hb)) = msg.get_packet_type() else {
+            panic!("did not parse a heartbeat");
+        };
+
+        assert_eq!(hb.timestamp(), Some(timestamp));
+        assert_eq!(hb.mtu(), Some(1500));
+        assert_eq!(
+            Heartbeat::new(Duration::default(), 0, [4; 6].into()).timestamp(),
+            None
+        );
+    }
+
+    #[test]
+    fn relays_keep_forwarding_order_and_drop_the_oldest_when_full() {
+        let hb = Heartbeat::new(Duration::default(), 0, [4; 6].into())
+            .with_relay([5; 6].into())
+            .with_mtu(1500)
+            .with_relay([6; 6].into());
+        let msg = Message::new(
+            [6; 6].into(),
+            [255; 6].into(),
+            PacketType::Control(Control::Heartbeat(hb)),
+        );
+        let pkt: Vec<Vec<u8>> = (&msg).into();
+        let pkt = pkt.concat();
+        let msg = Message::try_from(&pkt[..]).expect("is message");
+        let PacketType::Control(Control::Heartbeat(hb)) = msg.get_packet_type() else {
+            panic!("did not parse a heartbeat");
+        };
+        assert_eq!(hb.relays(), vec![[5; 6].into(), [6; 6].into()]);
+        assert_eq!(hb.mtu(), Some(1500));
+
+        let hb = (0..=super::MAX_RELAYS).fold(
+            Heartbeat::new(Duration::default(), 0, [4; 6].into()),
+            |hb, relay| hb.with_relay([u8::try_from(relay).unwrap(); 6].into()),
+        );
+        let relays = hb.relays();
+        assert_eq!(relays.len(), super::MAX_RELAYS);
+        assert_eq!(relays.first(), Some(&[1; 6].into()));
+        assert_eq!(relays.last(), Some(&[42; 6].into()));
+    }
+
+    #[test]
+    fn mtu_tlv_is_replaced_and_survives_serialization() {
+        let position = Position {
+            lat: 40.2033,
+            lon: -8.4103,
+        };
+        let hb = Heartbeat::new(Duration::default(), 0, [4; 6].into())
+            .with_mtu(9000)
+            .with_position(position)
+            .with_mtu(1500);
+        assert_eq!(hb.mtu(), Some(1500));
+        assert_eq!(hb.position(), Some(position));
+
+        let msg = Message::new(
+            [0; 6].into(),
+            [255; 6].into(),
+            PacketType::Control(Control::HeartbeatReply(
+                HeartbeatReply::from_sender(&hb, [5; 6].into()).with_mtu(1400),
+            )),
+        );
+        let pkt: Vec<Vec<u8>> = (&msg).into();
+        let pkt = pkt.concat();
+        let msg = Message::try_from(&pkt[..]).expect("is message");
+        let PacketType::Control(Control::HeartbeatReply(hbr)) = msg.get_packet_type() else {
+            panic!("did not parse a heartbeat reply");
+        };
+
+        assert_eq!(hbr.mtu(), Some(1400));
+        assert_eq!(hbr.sender(), [5; 6].into());
+    }
 }
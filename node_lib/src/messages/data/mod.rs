@@ -2,14 +2,76 @@ use anyhow::bail;
 use mac_address::MacAddress;
 use std::borrow::Cow;
 
+/// Kind of traffic a data frame carries, so management frames (config,
+/// telemetry) can be routed and accounted apart from user data
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum TrafficClass {
+    #[default]
+    User,
+    Management,
+}
+
+impl TryFrom<u8> for TrafficClass {
+    type Error = anyhow::Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::User),
+            1 => Ok(Self::Management),
+            _ => bail!("is not a valid traffic class"),
+        }
+    }
+}
+
+impl From<TrafficClass> for u8 {
+    fn from(value: TrafficClass) -> Self {
+        match value {
+            TrafficClass::User => 0,
+            TrafficClass::Management => 1,
+        }
+    }
+}
+
+/// Set in the traffic class byte of data frames carrying a fragment of a
+/// frame rather than a whole one
+const FRAGMENT_FLAG: u8 = 0x80;
+
+/// Set in the traffic class byte of data frames carrying their frame, or
+/// the fragment of it, compressed
+const COMPRESSED_FLAG: u8 = 0x40;
+
+fn class_byte(class: TrafficClass, fragment: bool, compressed: bool) -> u8 {
+    u8::from(class)
+        | if fragment { FRAGMENT_FLAG } else { 0 }
+        | if compressed { COMPRESSED_FLAG } else { 0 }
+}
+
+fn parse_class_byte(value: u8) -> anyhow::Result<(TrafficClass, bool, bool)> {
+    Ok((
+        (value & !(FRAGMENT_FLAG | COMPRESSED_FLAG)).try_into()?,
+        value & FRAGMENT_FLAG != 0,
+        value & COMPRESSED_FLAG != 0,
+    ))
+}
+
 #[derive(Debug, Clone)]
 pub struct ToUpstream<'a> {
+    priority: u8,
+    class: TrafficClass,
+    fragment: bool,
+    compressed: bool,
+    sequence: u32,
     origin: Cow<'a, [u8]>,
     data: Cow<'a, [u8]>,
 }
 
 #[derive(Debug, Clone)]
 pub struct ToDownstream<'a> {
+    priority: u8,
+    class: TrafficClass,
+    fragment: bool,
+    compressed: bool,
+    sequence: u32,
     origin: Cow<'a, [u8]>,
     destination: Cow<'a, [u8]>,
     data: Cow<'a, [u8]>,
@@ -24,11 +86,65 @@ pub enum Data<'a> {
 impl<'a> ToUpstream<'a> {
     pub fn new(node: MacAddress, data: &'a [u8]) -> Self {
         Self {
+            priority: 0,
+            class: TrafficClass::User,
+            fragment: false,
+            compressed: false,
+            sequence: 0,
             origin: Cow::Owned(node.bytes().to_vec()),
             data: Cow::Borrowed(data),
         }
     }
 
+    pub fn with_priority(mut self, priority: u8) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    pub fn with_class(mut self, class: TrafficClass) -> Self {
+        self.class = class;
+        self
+    }
+
+    pub fn with_sequence(mut self, sequence: u32) -> Self {
+        self.sequence = sequence;
+        self
+    }
+
+    /// Marks the data as one fragment of a larger frame
+    pub fn with_fragment(mut self, fragment: bool) -> Self {
+        self.fragment = fragment;
+        self
+    }
+
+    /// Marks the frame, or the fragment of it, as compressed
+    pub fn with_compressed(mut self, compressed: bool) -> Self {
+        self.compressed = compressed;
+        self
+    }
+
+    /// Forwarding priority, higher values are sent first
+    pub fn priority(&self) -> u8 {
+        self.priority
+    }
+
+    pub fn class(&self) -> TrafficClass {
+        self.class
+    }
+
+    pub fn is_fragment(&self) -> bool {
+        self.fragment
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        self.compressed
+    }
+
+    /// Sequence number within the flow from the source to the frame destination
+    pub fn sequence(&self) -> u32 {
+        self.sequence
+    }
+
     pub fn data(&self) -> &Cow<'_, [u8]> {
         &self.data
     }
@@ -41,12 +157,66 @@ impl<'a> ToUpstream<'a> {
 impl<'a> ToDownstream<'a> {
     pub fn new(origin: &'a [u8], destination: MacAddress, data: &'a [u8]) -> Self {
         Self {
+            priority: 0,
+            class: TrafficClass::User,
+            fragment: false,
+            compressed: false,
+            sequence: 0,
             origin: Cow::Borrowed(origin),
             destination: Cow::Owned(destination.bytes().to_vec()),
             data: Cow::Borrowed(data),
         }
     }
 
+    pub fn with_priority(mut self, priority: u8) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    pub fn with_class(mut self, class: TrafficClass) -> Self {
+        self.class = class;
+        self
+    }
+
+    pub fn with_sequence(mut self, sequence: u32) -> Self {
+        self.sequence = sequence;
+        self
+    }
+
+    /// Marks the data as one fragment of a larger frame
+    pub fn with_fragment(mut self, fragment: bool) -> Self {
+        self.fragment = fragment;
+        self
+    }
+
+    /// Marks the frame, or the fragment of it, as compressed
+    pub fn with_compressed(mut self, compressed: bool) -> Self {
+        self.compressed = compressed;
+        self
+    }
+
+    /// Forwarding priority, higher values are sent first
+    pub fn priority(&self) -> u8 {
+        self.priority
+    }
+
+    pub fn class(&self) -> TrafficClass {
+        self.class
+    }
+
+    pub fn is_fragment(&self) -> bool {
+        self.fragment
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        self.compressed
+    }
+
+    /// Sequence number within the flow from the source to the frame destination
+    pub fn sequence(&self) -> u32 {
+        self.sequence
+    }
+
     pub fn data(&self) -> &Cow<'_, [u8]> {
         &self.data
     }
@@ -57,24 +227,67 @@ impl<'a> ToDownstream<'a> {
     pub fn destination(&self) -> &Cow<'_, [u8]> {
         &self.destination
     }
+
+    /// This frame carrying `data` instead, as a fragment of it when
+    /// `fragment` is set and compressed when `compressed` is
+    pub fn carrying<'b>(
+        &'b self,
+        data: &'b [u8],
+        fragment: bool,
+        compressed: bool,
+    ) -> ToDownstream<'b> {
+        ToDownstream {
+            priority: self.priority,
+            class: self.class,
+            fragment,
+            compressed,
+            sequence: self.sequence,
+            origin: Cow::Borrowed(&self.origin),
+            destination: Cow::Borrowed(&self.destination),
+            data: Cow::Borrowed(data),
+        }
+    }
 }
 
 impl<'a> TryFrom<&'a [u8]> for ToUpstream<'a> {
     type Error = anyhow::Error;
 
     fn try_from(value: &'a [u8]) -> Result<Self, Self::Error> {
-        let (Some(data), Some(origin)) = (value.get(6..), value.get(..6)) else {
+        let (Some(data), Some(origin), Some(sequence), Some(class), Some(priority)) = (
+            value.get(12..),
+            value.get(6..12),
+            value.get(2..6),
+            value.get(1),
+            value.first(),
+        ) else {
             bail!("cannot get members");
         };
         let origin = Cow::Borrowed(origin);
         let data = Cow::Borrowed(data);
-        Ok(Self { origin, data })
+        let (class, fragment, compressed) = parse_class_byte(*class)?;
+        Ok(Self {
+            priority: *priority,
+            class,
+            fragment,
+            compressed,
+            sequence: u32::from_be_bytes(sequence.try_into()?),
+            origin,
+            data,
+        })
     }
 }
 
 impl<'a> From<&ToUpstream<'a>> for Vec<Vec<u8>> {
     fn from(value: &ToUpstream<'a>) -> Self {
-        vec![value.origin.to_vec(), value.data.to_vec()]
+        vec![
+            vec![
+                value.priority,
+                class_byte(value.class, value.fragment, value.compressed),
+            ],
+            value.sequence.to_be_bytes().to_vec(),
+            value.origin.to_vec(),
+            value.data.to_vec(),
+        ]
     }
 }
 
@@ -82,15 +295,34 @@ impl<'a> TryFrom<&'a [u8]> for ToDownstream<'a> {
     type Error = anyhow::Error;
 
     fn try_from(value: &'a [u8]) -> Result<Self, Self::Error> {
-        let (Some(data), Some(destination), Some(origin)) =
-            (value.get(12..), value.get(6..12), value.get(..6))
+        let (
+            Some(data),
+            Some(destination),
+            Some(origin),
+            Some(sequence),
+            Some(class),
+            Some(priority),
+        ) = (
+            value.get(18..),
+            value.get(12..18),
+            value.get(6..12),
+            value.get(2..6),
+            value.get(1),
+            value.first(),
+        )
         else {
             bail!("cannot get members");
         };
         let destination = Cow::Borrowed(destination);
         let origin = Cow::Borrowed(origin);
         let data = Cow::Borrowed(data);
+        let (class, fragment, compressed) = parse_class_byte(*class)?;
         Ok(Self {
+            priority: *priority,
+            class,
+            fragment,
+            compressed,
+            sequence: u32::from_be_bytes(sequence.try_into()?),
             origin,
             destination,
             data,
@@ -101,6 +333,11 @@ impl<'a> TryFrom<&'a [u8]> for ToDownstream<'a> {
 impl<'a> From<&ToDownstream<'a>> for Vec<Vec<u8>> {
     fn from(value: &ToDownstream<'a>) -> Self {
         vec![
+            vec![
+                value.priority,
+                class_byte(value.class, value.fragment, value.compressed),
+            ],
+            value.sequence.to_be_bytes().to_vec(),
             value.origin.to_vec(),
             value.destination.to_vec(),
             value.data.to_vec(),
@@ -124,6 +361,22 @@ impl<'a> TryFrom<&'a [u8]> for Data<'a> {
     }
 }
 
+impl Data<'_> {
+    pub fn priority(&self) -> u8 {
+        match self {
+            Data::Upstream(c) => c.priority(),
+            Data::Downstream(c) => c.priority(),
+        }
+    }
+
+    pub fn class(&self) -> TrafficClass {
+        match self {
+            Data::Upstream(c) => c.class(),
+            Data::Downstream(c) => c.class(),
+        }
+    }
+}
+
 impl<'a> From<&Data<'a>> for Vec<Vec<u8>> {
     fn from(value: &Data<'a>) -> Self {
         match value {
@@ -1,8 +1,37 @@
 use super::packet_type::PacketType;
+use crate::error::NodeError;
 use anyhow::{bail, Result};
 use mac_address::MacAddress;
 use std::borrow::Cow;
 
+/// First byte of the ethertype of every frame of this protocol
+const PROTOCOL_MAGIC: u8 = 0x30;
+
+/// Protocol version this node speaks, added to the magic in the second
+/// ethertype byte, so version 0 kept the original `0x30 0x30` marker.
+/// Data frames carry `[priority][class][sequence][origin]` (plus the
+/// destination downstream) ahead of their payload. Version 1 flags
+/// compressed data in the class byte, which version 0 nodes would take for
+/// an unknown class. Changing that header means a new version.
+pub const PROTOCOL_VERSION: u8 = 1;
+
+/// Versions fit in the low nibble of the second ethertype byte
+const MAX_PROTOCOL_VERSION: u8 = 0x0f;
+
+/// Ethertype marking frames of `version` of this protocol
+fn protocol_marker(version: u8) -> [u8; 2] {
+    [PROTOCOL_MAGIC, PROTOCOL_MAGIC + version]
+}
+
+/// Protocol version a raw frame is stamped with, if it is one of ours
+fn protocol_version(value: &[u8]) -> Option<u8> {
+    let [magic, marker] = value.get(12..14)? else {
+        return None;
+    };
+    let version = marker.checked_sub(PROTOCOL_MAGIC)?;
+    (*magic == PROTOCOL_MAGIC && version <= MAX_PROTOCOL_VERSION).then_some(version)
+}
+
 #[derive(Debug)]
 pub struct Message<'a> {
     from: Cow<'a, [u8]>,
@@ -39,14 +68,52 @@ impl<'a> Message<'a> {
     pub fn get_packet_type(&'a self) -> &PacketType<'a> {
         &self.next
     }
+
+    pub fn is_control(&self) -> bool {
+        matches!(self.next, PacketType::Control(_))
+    }
+
+    pub fn is_data(&self) -> bool {
+        matches!(self.next, PacketType::Data(_))
+    }
+
+    /// Checks if a raw frame is a control frame reading only the type byte
+    pub fn is_control_frame(value: &[u8]) -> bool {
+        Self::packet_type_byte(value) == Some(0)
+    }
+
+    /// Checks if a raw frame is a data frame reading only the type byte
+    pub fn is_data_frame(value: &[u8]) -> bool {
+        Self::packet_type_byte(value) == Some(1)
+    }
+
+    /// Forwarding priority of a raw data frame, reading only its priority
+    /// byte. None for control or foreign frames.
+    pub fn data_priority(value: &[u8]) -> Option<u8> {
+        if !Self::is_data_frame(value) {
+            return None;
+        }
+
+        value.get(16).copied()
+    }
+
+    fn packet_type_byte(value: &[u8]) -> Option<u8> {
+        if protocol_version(value) != Some(PROTOCOL_VERSION) {
+            return None;
+        }
+
+        value.get(14).copied()
+    }
 }
 
 impl<'a> TryFrom<&'a [u8]> for Message<'a> {
     type Error = anyhow::Error;
 
     fn try_from(value: &'a [u8]) -> Result<Self, Self::Error> {
-        if value.get(12..14) != Some(&[0x30, 0x30]) {
-            bail!("not from this protocol");
+        match protocol_version(value) {
+            Some(PROTOCOL_VERSION) => (),
+            Some(version) => bail!(NodeError::UnsupportedVersion(version)),
+            None => bail!("not from this protocol"),
         }
 
         let Some(from) = value.get(6..12) else {
@@ -74,7 +141,7 @@ impl<'a> From<&Message<'a>> for Vec<Vec<u8>> {
         let mut this = vec![
             value.to.clone().into_owned(),
             value.from.clone().into_owned(),
-            vec![0x30, 0x30],
+            protocol_marker(PROTOCOL_VERSION).to_vec(),
         ];
         let more: Vec<Vec<u8>> = (&value.next).into();
         this.extend(more);
@@ -84,7 +151,25 @@ impl<'a> From<&Message<'a>> for Vec<Vec<u8>> {
 
 #[cfg(test)]
 mod tests {
-    use crate::messages::message::Message;
+    use crate::{
+        error::NodeError,
+        messages::{
+            control::{
+                heartbeat::{Heartbeat, HeartbeatReply},
+                Control,
+            },
+            data::{Data, ToDownstream, ToUpstream, TrafficClass},
+            message::Message,
+            packet_type::PacketType,
+        },
+        test_helpers,
+    };
+    use std::time::Duration;
+
+    fn wire(msg: &Message) -> Vec<u8> {
+        let pkt: Vec<Vec<u8>> = msg.into();
+        pkt.iter().flat_map(|x| x.iter()).cloned().collect()
+    }
 
     #[test]
     fn message_not_from_this_protocol_cannot_be_built() {
@@ -92,4 +177,227 @@ mod tests {
         let msg = Message::try_from(&pkt[..]);
         assert!(msg.is_err());
     }
+
+    #[test]
+    fn only_the_current_protocol_version_is_parsed() {
+        let msg = Message::new(
+            [1; 6].into(),
+            [2; 6].into(),
+            PacketType::Control(Control::Heartbeat(Heartbeat::new(
+                Duration::default(),
+                0,
+                [1; 6].into(),
+            ))),
+        );
+        let mut pkt = wire(&msg);
+        assert_eq!(pkt[12..14], [0x30, 0x31]);
+        assert!(Message::try_from(&pkt[..]).is_ok());
+        assert!(Message::is_control_frame(&pkt));
+
+        for version in [0, 2] {
+            pkt[13] = 0x30 + version;
+            let error = Message::try_from(&pkt[..]).expect_err("other version");
+            assert_eq!(
+                error.downcast_ref::<NodeError>(),
+                Some(&NodeError::UnsupportedVersion(version))
+            );
+            assert!(!Message::is_control_frame(&pkt));
+        }
+
+        pkt[13] = 0x40;
+        let error = Message::try_from(&pkt[..]).expect_err("not this protocol");
+        assert!(error.downcast_ref::<NodeError>().is_none());
+    }
+
+    #[test]
+    fn frames_are_classified_from_the_type_byte() {
+        let heartbeat = Heartbeat::new(Duration::default(), 0, [1; 6].into());
+        let reply = HeartbeatReply::from_sender(&heartbeat, [2; 6].into());
+        let payload = [0u8; 14];
+        let messages = [
+            (
+                PacketType::Control(Control::Heartbeat(heartbeat.clone())),
+                true,
+            ),
+            (PacketType::Control(Control::HeartbeatReply(reply)), true),
+            (
+                PacketType::Data(Data::Upstream(ToUpstream::new([1; 6].into(), &payload))),
+                false,
+            ),
+            (
+                PacketType::Data(Data::Downstream(ToDownstream::new(
+                    &[1; 6],
+                    [2; 6].into(),
+                    &payload,
+                ))),
+                false,
+            ),
+        ];
+
+        for (packet_type, control) in messages {
+            let msg = Message::new([1; 6].into(), [2; 6].into(), packet_type);
+            let pkt = wire(&msg);
+            assert_eq!(Message::is_control_frame(&pkt), control);
+            assert_eq!(Message::is_data_frame(&pkt), !control);
+
+            let msg = Message::try_from(&pkt[..]).expect("is message");
+            assert_eq!(msg.is_control(), control);
+            assert_eq!(msg.is_data(), !control);
+        }
+    }
+
+    #[test]
+    fn data_priority_survives_the_wire() {
+        let payload = [0u8; 14];
+        let msg = Message::new(
+            [1; 6].into(),
+            [2; 6].into(),
+            PacketType::Data(Data::Upstream(
+                ToUpstream::new([1; 6].into(), &payload).with_priority(5),
+            )),
+        );
+        let pkt = wire(&msg);
+        let msg = Message::try_from(&pkt[..]).expect("is message");
+        let PacketType::Data(Data::Upstream(up)) = msg.get_packet_type() else {
+            panic!("not an upstream frame");
+        };
+        assert_eq!(up.priority(), 5);
+        assert_eq!(up.source()[..], [1; 6]);
+        assert_eq!(up.data()[..], payload);
+    }
+
+    #[test]
+    fn data_sequence_survives_the_wire() {
+        let payload = [0u8; 14];
+        let msg = Message::new(
+            [1; 6].into(),
+            [2; 6].into(),
+            PacketType::Data(Data::Downstream(
+                ToDownstream::new(&[3; 6], [4; 6].into(), &payload)
+                    .with_priority(2)
+                    .with_sequence(0x01020304),
+            )),
+        );
+        let pkt = wire(&msg);
+        let msg = Message::try_from(&pkt[..]).expect("is message");
+        let PacketType::Data(Data::Downstream(down)) = msg.get_packet_type() else {
+            panic!("not a downstream frame");
+        };
+        assert_eq!(down.sequence(), 0x01020304);
+        assert_eq!(down.priority(), 2);
+        assert_eq!(down.source()[..], [3; 6]);
+        assert_eq!(down.destination()[..], [4; 6]);
+        assert_eq!(down.data()[..], payload);
+    }
+
+    #[test]
+    fn data_class_survives_the_wire() {
+        let payload = [0u8; 14];
+        for class in [TrafficClass::User, TrafficClass::Management] {
+            let msg = Message::new(
+                [1; 6].into(),
+                [2; 6].into(),
+                PacketType::Data(Data::Upstream(
+                    ToUpstream::new([1; 6].into(), &payload)
+                        .with_class(class)
+                        .with_sequence(7),
+                )),
+            );
+            let pkt = wire(&msg);
+            let msg = Message::try_from(&pkt[..]).expect("is message");
+            let PacketType::Data(data) = msg.get_packet_type() else {
+                panic!("not a data frame");
+            };
+            assert_eq!(data.class(), class);
+        }
+
+        let mut pkt = wire(&Message::new(
+            [1; 6].into(),
+            [2; 6].into(),
+            PacketType::Data(Data::Upstream(ToUpstream::new([1; 6].into(), &payload))),
+        ));
+        pkt[17] = 9;
+        assert!(Message::try_from(&pkt[..]).is_err());
+    }
+
+    #[test]
+    fn fragment_flag_survives_the_wire() {
+        let payload = [0u8; 14];
+        for fragment in [false, true] {
+            let msg = Message::new(
+                [1; 6].into(),
+                [2; 6].into(),
+                PacketType::Data(Data::Downstream(
+                    ToDownstream::new(&[1u8; 6], [3; 6].into(), &payload)
+                        .with_class(TrafficClass::Management)
+                        .with_fragment(fragment),
+                )),
+            );
+            let pkt = wire(&msg);
+            let msg = Message::try_from(&pkt[..]).expect("is message");
+            let PacketType::Data(Data::Downstream(data)) = msg.get_packet_type() else {
+                panic!("not a downstream frame");
+            };
+            assert_eq!(data.is_fragment(), fragment);
+            assert_eq!(data.class(), TrafficClass::Management);
+        }
+    }
+
+    #[test]
+    fn compressed_flag_survives_the_wire() {
+        let payload = [0u8; 14];
+        for (fragment, compressed) in [(false, true), (true, true), (true, false)] {
+            let msg = Message::new(
+                [1; 6].into(),
+                [2; 6].into(),
+                PacketType::Data(Data::Upstream(
+                    ToUpstream::new([1; 6].into(), &payload)
+                        .with_class(TrafficClass::Management)
+                        .with_fragment(fragment)
+                        .with_compressed(compressed),
+                )),
+            );
+            let mut pkt = wire(&msg);
+            let parsed = Message::try_from(&pkt[..]).expect("is message");
+            let PacketType::Data(Data::Upstream(data)) = parsed.get_packet_type() else {
+                panic!("not an upstream frame");
+            };
+            assert_eq!(data.is_fragment(), fragment);
+            assert_eq!(data.is_compressed(), compressed);
+            assert_eq!(data.class(), TrafficClass::Management);
+
+            // Version 0 has no compressed flag to read the frame with
+            pkt[13] = 0x30;
+            let error = Message::try_from(&pkt[..]).expect_err("version 0");
+            assert_eq!(
+                error.downcast_ref::<NodeError>(),
+                Some(&NodeError::UnsupportedVersion(0))
+            );
+        }
+    }
+
+    #[test]
+    fn foreign_or_unknown_frames_are_neither() {
+        let mut pkt = [0u8; 15];
+        assert!(!Message::is_control_frame(&pkt));
+        assert!(!Message::is_data_frame(&pkt));
+
+        pkt[12..14].copy_from_slice(&[0x30, 0x31]);
+        pkt[14] = 2;
+        assert!(!Message::is_control_frame(&pkt));
+        assert!(!Message::is_data_frame(&pkt));
+        assert!(!Message::is_control_frame(&pkt[..14]));
+    }
+
+    #[test]
+    fn fuzzed_frames_parse_without_panics() {
+        let frames = test_helpers::fuzzed_frames(0x5eed, 5000);
+        test_helpers::assert_no_panics(&frames, |frame| {
+            let _ = Message::is_control_frame(frame);
+            let _ = Message::is_data_frame(frame);
+            if let Ok(msg) = Message::try_from(frame) {
+                test_helpers::exercise_message(&msg);
+            }
+        });
+    }
 }
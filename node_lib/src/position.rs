@@ -0,0 +1,49 @@
+use anyhow::{Context, Result};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    pub lat: f64,
+    pub lon: f64,
+}
+
+const EARTH_RADIUS_M: f64 = 6_371_000.0;
+
+impl Position {
+    /// Great-circle distance in meters
+    pub fn distance_to(&self, other: &Position) -> f64 {
+        let (lat1, lat2) = (self.lat.to_radians(), other.lat.to_radians());
+        let dlat = lat2 - lat1;
+        let dlon = (other.lon - self.lon).to_radians();
+        let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS_M * a.sqrt().asin()
+    }
+
+    pub fn to_bytes(&self) -> [u8; 16] {
+        let mut bytes = [0u8; 16];
+        bytes[..8].copy_from_slice(&self.lat.to_be_bytes());
+        bytes[8..].copy_from_slice(&self.lon.to_be_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let lat: [u8; 8] = bytes.get(0..8)?.try_into().ok()?;
+        let lon: [u8; 8] = bytes.get(8..16)?.try_into().ok()?;
+        Some(Self {
+            lat: f64::from_be_bytes(lat),
+            lon: f64::from_be_bytes(lon),
+        })
+    }
+}
+
+impl FromStr for Position {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (lat, lon) = s.split_once(',').context("expected lat,lon")?;
+        Ok(Self {
+            lat: lat.trim().parse()?,
+            lon: lon.trim().parse()?,
+        })
+    }
+}
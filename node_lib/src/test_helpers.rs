@@ -0,0 +1,277 @@
+use crate::{
+    args::{NodeParameters, NodeType, RouteMetric, UpstreamMode},
+    messages::{
+        control::{
+            heartbeat::{Heartbeat, HeartbeatReply},
+            Control,
+        },
+        data::{Data, ToDownstream, ToUpstream},
+        message::Message,
+        packet_type::PacketType,
+    },
+    position::Position,
+    Args,
+};
+use common::device::Device;
+use mac_address::MacAddress;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::Arc,
+    time::Duration,
+};
+use tokio_tun::Tun;
+
+/// Node arguments with every option at its default
+pub fn args(node_type: NodeType) -> Args {
+    Args {
+        bind: String::default(),
+        tap_name: None,
+        ip: None,
+        mtu: 1459,
+        wire_mtu: 1500,
+        tap_buffer_size: None,
+        send_retries: 3,
+        node_params: NodeParameters {
+            node_type,
+            hello_history: 2,
+            hello_periodicity: None,
+            startup_delay: None,
+            adaptive_hello_min: None,
+            adaptive_hello_max: None,
+            ingress_loss: 0.0,
+            ingress_loss_seed: None,
+            position: None,
+            route_metric: RouteMetric::Latency,
+            management_route_metric: None,
+            tag_management_traffic: false,
+            upstream_mode: UpstreamMode::Single,
+            upstream_change_log_interval: None,
+            rewrite_source_mac: true,
+            clamp_path_mtu: true,
+            upstream_blacklist: Vec::default(),
+            only_rsu: None,
+            control_priority: None,
+            data_priority: 0,
+            reply_dedup_window: None,
+            heartbeat_max_age: None,
+            keepalive_interval: None,
+            min_heartbeat_interval: None,
+            reply_suppression_interval: None,
+            flap_penalty: 0,
+            flap_decay: 30000,
+            client_cache_capacity: 4096,
+            client_ttl: None,
+            handover_margin: None,
+            handover_observations: 1,
+            passive: false,
+            passive_replies: false,
+            drop_malformed_frames: true,
+            drop_header_only_frames: true,
+            fragment_size: None,
+            fragment_timeout: 5000,
+            compress: false,
+            route_ttl: None,
+            receive_queue_high_watermark: None,
+        },
+        filter_destination: false,
+        warm_routes: None,
+        capture_path: None,
+    }
+}
+
+/// Interfaces to create a node with: a TAP for its own traffic and a device
+/// on a second TAP standing in for its wire, kept up while this lives.
+/// Frames sent to `wire` reach the node as if heard from a neighbour.
+pub struct NodeInterfaces {
+    pub tun: Arc<Tun>,
+    pub device: Arc<Device>,
+    pub wire: Tun,
+}
+
+/// Creating TAPs and reading raw frames needs CAP_NET_ADMIN, so tests
+/// driving a whole node are ignored unless run with `--ignored` by a user
+/// holding it.
+pub fn node_interfaces() -> NodeInterfaces {
+    let tap = || {
+        Tun::builder()
+            .tap(true)
+            .packet_info(false)
+            .up()
+            .try_build()
+            .expect("creating a TAP needs CAP_NET_ADMIN")
+    };
+    let (wire, tun) = (tap(), tap());
+    let device = Device::new(wire.name()).expect("device on the wire TAP");
+    NodeInterfaces {
+        tun: Arc::new(tun),
+        device: Arc::new(device),
+        wire,
+    }
+}
+
+/// Records of the pcap file at `path` once they are written, waiting for
+/// the capture thread to write at least `count` of them
+pub fn capture_records(path: &str, count: usize) -> Vec<Vec<u8>> {
+    let records = || {
+        let pcap = std::fs::read(path).unwrap_or_default();
+        let mut records = Vec::new();
+        let mut rest = pcap.get(24..).unwrap_or_default();
+        while let Some(header) = rest.get(..16) {
+            let len = u32::from_le_bytes(header[8..12].try_into().unwrap()) as usize;
+            let Some(record) = rest.get(..16 + len) else {
+                break;
+            };
+            records.push(record.to_vec());
+            rest = &rest[16 + len..];
+        }
+        records
+    };
+    for _ in 0..100 {
+        let records = records();
+        if records.len() >= count {
+            return records;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    records()
+}
+
+/// Valid frames of every message type, used as the base for mutation
+pub fn seed_frames() -> Vec<Vec<u8>> {
+    let rsu: MacAddress = [1; 6].into();
+    let obu: MacAddress = [2; 6].into();
+    let heartbeat = Heartbeat::new(Duration::from_millis(10), 0, rsu).with_position(Position {
+        lat: 40.2033,
+        lon: -8.4103,
+    });
+    let messages = [
+        Message::new(
+            rsu,
+            [255; 6].into(),
+            PacketType::Control(Control::Heartbeat(heartbeat.clone())),
+        ),
+        Message::new(
+            obu,
+            rsu,
+            PacketType::Control(Control::HeartbeatReply(HeartbeatReply::from_sender(
+                &heartbeat, obu,
+            ))),
+        ),
+        Message::new(
+            obu,
+            rsu,
+            PacketType::Data(Data::Upstream(ToUpstream::new(obu, &[0xaa; 64]))),
+        ),
+        Message::new(
+            rsu,
+            obu,
+            PacketType::Data(Data::Downstream(ToDownstream::new(
+                &[3; 6],
+                obu,
+                &[0xbb; 64],
+            ))),
+        ),
+    ];
+
+    messages
+        .iter()
+        .map(|msg| {
+            let pkt: Vec<Vec<u8>> = msg.into();
+            pkt.iter().flat_map(|x| x.iter()).cloned().collect()
+        })
+        .collect()
+}
+
+/// Generates `count` frames derived from [`seed_frames`] by flipping,
+/// truncating, extending or replacing bytes. The same seed always yields
+/// the same corpus so a failure can be reproduced.
+pub fn fuzzed_frames(seed: u64, count: usize) -> Vec<Vec<u8>> {
+    let seeds = seed_frames();
+    let mut rng = StdRng::seed_from_u64(seed);
+    (0..count)
+        .map(|_| {
+            let mut frame = seeds[rng.gen_range(0..seeds.len())].clone();
+            match rng.gen_range(0..4) {
+                0 => {
+                    // Keep the protocol marker so mutations reach the inner parsers
+                    for _ in 0..rng.gen_range(1..8) {
+                        let idx = rng.gen_range(14..frame.len());
+                        frame[idx] = rng.gen();
+                    }
+                }
+                1 => frame.truncate(rng.gen_range(0..frame.len())),
+                2 => frame.extend((0..rng.gen_range(1..32)).map(|_| rng.gen::<u8>())),
+                _ => {
+                    frame = (0..rng.gen_range(0..128)).map(|_| rng.gen()).collect();
+                    if frame.len() >= 15 && rng.gen_bool(0.5) {
+                        frame[12..14].copy_from_slice(&[0x30, 0x31]);
+                        frame[14] = rng.gen_range(0..2);
+                    }
+                }
+            }
+            frame
+        })
+        .collect()
+}
+
+/// Runs `handler` over every frame, failing with the offending frame if
+/// it panics. Handlers are expected to surface bad input as `Err` or
+/// `Ok(None)`.
+pub fn assert_no_panics(frames: &[Vec<u8>], mut handler: impl FnMut(&[u8])) {
+    for frame in frames {
+        if catch_unwind(AssertUnwindSafe(|| handler(frame))).is_err() {
+            panic!("handler panicked on frame {frame:02x?}");
+        }
+    }
+}
+
+/// Touches every accessor of a parsed message so lazy field decoding is
+/// exercised too.
+pub fn exercise_message(msg: &Message) {
+    let _ = msg.from();
+    let _ = msg.to();
+    let _ = msg.is_control();
+    let _ = msg.is_data();
+    let _: Vec<Vec<u8>> = msg.into();
+    match msg.get_packet_type() {
+        PacketType::Control(Control::Heartbeat(hb)) => {
+            let _ = (
+                hb.duration(),
+                hb.id(),
+                hb.hops(),
+                hb.source(),
+                hb.position(),
+                hb.timestamp(),
+            );
+        }
+        PacketType::Control(Control::HeartbeatReply(hbr)) => {
+            let _ = (
+                hbr.duration(),
+                hbr.id(),
+                hbr.hops(),
+                hbr.source(),
+                hbr.sender(),
+            );
+        }
+        PacketType::Data(Data::Upstream(up)) => {
+            let _ = (
+                up.source(),
+                up.data(),
+                up.priority(),
+                up.class(),
+                up.sequence(),
+            );
+        }
+        PacketType::Data(Data::Downstream(down)) => {
+            let _ = (
+                down.source(),
+                down.destination(),
+                down.data(),
+                down.priority(),
+                down.class(),
+                down.sequence(),
+            );
+        }
+    }
+}
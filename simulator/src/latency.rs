@@ -0,0 +1,164 @@
+#[cfg(feature = "webview")]
+use serde::Serialize;
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// Buckets per power of two, bounding the error of a percentile to 1/16th
+/// of its value
+const SUB_BUCKETS: u64 = 16;
+
+/// Latencies of up to 2^32 µs (over an hour) are told apart, longer ones
+/// fall in the last bucket
+const MAX_MICROS: u64 = (1 << 32) - 1;
+
+const BUCKETS: usize = (SUB_BUCKETS + 28 * SUB_BUCKETS) as usize;
+
+/// Delivery latency percentiles of a channel, in microseconds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "webview", derive(Serialize))]
+pub struct LatencyPercentiles {
+    pub mean_us: u64,
+    pub p50_us: u64,
+    pub p95_us: u64,
+    pub p99_us: u64,
+}
+
+/// Histogram of latencies in log-linear buckets, as in HDR histograms, so it
+/// takes the same memory however many latencies it records.
+#[derive(Debug)]
+pub struct LatencyHistogram {
+    buckets: Vec<AtomicU64>,
+    count: AtomicU64,
+    sum_us: AtomicU64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self {
+            buckets: (0..BUCKETS).map(|_| AtomicU64::new(0)).collect(),
+            count: AtomicU64::new(0),
+            sum_us: AtomicU64::new(0),
+        }
+    }
+}
+
+fn bucket_of(micros: u64) -> usize {
+    let micros = micros.min(MAX_MICROS);
+    if micros < SUB_BUCKETS {
+        return micros as usize;
+    }
+
+    let exponent = u64::from(63 - micros.leading_zeros());
+    let sub = (micros >> (exponent - 4)) & (SUB_BUCKETS - 1);
+    (SUB_BUCKETS + (exponent - 4) * SUB_BUCKETS + sub) as usize
+}
+
+/// Largest latency, in microseconds, falling in `bucket`
+fn highest_in(bucket: usize) -> u64 {
+    let bucket = bucket as u64;
+    if bucket < SUB_BUCKETS {
+        return bucket;
+    }
+
+    let shift = (bucket - SUB_BUCKETS) / SUB_BUCKETS;
+    let sub = (bucket - SUB_BUCKETS) % SUB_BUCKETS;
+    ((SUB_BUCKETS + sub + 1) << shift) - 1
+}
+
+impl LatencyHistogram {
+    pub fn record(&self, latency: Duration) {
+        let micros = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+        self.buckets[bucket_of(micros)].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_us.fetch_add(micros, Ordering::Relaxed);
+    }
+
+    /// Latency at or below which `p` percent of the latencies recorded fall,
+    /// rounded up to the end of its bucket. None before any is recorded.
+    pub fn latency_percentile(&self, p: f64) -> Option<Duration> {
+        let count = self.count.load(Ordering::Relaxed);
+        if count == 0 {
+            return None;
+        }
+
+        let rank = ((p.clamp(0.0, 100.0) / 100.0 * count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        let bucket = self
+            .buckets
+            .iter()
+            .position(|bucket| {
+                seen += bucket.load(Ordering::Relaxed);
+                seen >= rank
+            })
+            .unwrap_or(BUCKETS - 1);
+        Some(Duration::from_micros(highest_in(bucket)))
+    }
+
+    pub fn percentiles(&self) -> Option<LatencyPercentiles> {
+        let count = self.count.load(Ordering::Relaxed);
+        let micros = |p| {
+            self.latency_percentile(p)
+                .map_or(0, |latency| latency.as_micros() as u64)
+        };
+        (count > 0).then(|| LatencyPercentiles {
+            mean_us: self.sum_us.load(Ordering::Relaxed) / count,
+            p50_us: micros(50.0),
+            p95_us: micros(95.0),
+            p99_us: micros(99.0),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bucket_of, highest_in, LatencyHistogram, LatencyPercentiles, BUCKETS, MAX_MICROS};
+    use std::time::Duration;
+
+    #[test]
+    fn buckets_cover_every_latency_in_order() {
+        assert_eq!(bucket_of(MAX_MICROS), BUCKETS - 1);
+        assert_eq!(bucket_of(u64::MAX), BUCKETS - 1);
+        assert_eq!(highest_in(BUCKETS - 1), MAX_MICROS);
+        for bucket in 1..BUCKETS {
+            let lowest = highest_in(bucket - 1) + 1;
+            assert_eq!(bucket_of(lowest), bucket);
+            assert_eq!(bucket_of(highest_in(bucket)), bucket);
+        }
+    }
+
+    #[test]
+    fn percentiles_fall_in_the_expected_buckets() {
+        let histogram = LatencyHistogram::default();
+        assert_eq!(histogram.latency_percentile(50.0), None);
+        assert_eq!(histogram.percentiles(), None);
+
+        // 1ms to 1s, once each, plus a few 5s stragglers
+        for ms in 1..=1000 {
+            histogram.record(Duration::from_millis(ms));
+        }
+        for _ in 0..5 {
+            histogram.record(Duration::from_secs(5));
+        }
+
+        let within = |p, expected: u64| {
+            let latency = histogram.latency_percentile(p).expect("recorded");
+            let expected = Duration::from_millis(expected);
+            assert!(
+                latency >= expected && latency <= expected + expected / 16,
+                "p{p} is {latency:?}, expected {expected:?}"
+            );
+        };
+        within(50.0, 503);
+        within(95.0, 955);
+        within(99.0, 995);
+        within(100.0, 5000);
+
+        let percentiles = histogram.percentiles().expect("recorded");
+        assert_eq!(percentiles.mean_us, (500_500_000 + 5 * 5_000_000) / 1005);
+        assert!(percentiles.p50_us <= percentiles.p95_us);
+        assert!(percentiles.p95_us <= percentiles.p99_us);
+        assert_ne!(percentiles, LatencyPercentiles::default());
+    }
+}
@@ -1,15 +1,20 @@
 use anyhow::{bail, Context, Result};
 use clap::{Parser, ValueEnum};
-use common::device::Device;
+use common::{device::Device, network_interface::NetworkInterface};
 use config::Config;
 #[cfg(feature = "webview")]
 use itertools::Itertools;
-use node_lib::args::{Args, NodeParameters, NodeType};
+use mac_address::MacAddress;
+use node_lib::{
+    args::{Args, NodeParameters, NodeType, RouteMetric, UpstreamMode},
+    position::Position,
+};
 use std::{
     collections::HashMap,
     net::Ipv4Addr,
     str::FromStr,
     sync::{Arc, Mutex},
+    time::Duration,
 };
 use tokio::signal;
 use tokio_tun::Tun;
@@ -17,37 +22,174 @@ use tracing_subscriber::{fmt, prelude::*, EnvFilter};
 #[cfg(feature = "webview")]
 use warp::Filter;
 
+mod latency;
+
+mod medium;
+
+mod routing_dump;
+
 mod sim_args;
 use sim_args::SimArgs;
 
 mod simulator;
-use simulator::{Channel, Simulator};
+use simulator::{Channel, LinkHandle, Simulator};
+
+mod topology;
+
+#[cfg(feature = "webview")]
+mod metrics_sink;
+
+#[cfg(feature = "unix-socket")]
+mod unix_socket;
 
+/// Drops listed per node at `/drops/<node>`
+#[cfg(feature = "webview")]
+const RECENT_DROPS: usize = 32;
+
+#[cfg(feature = "webview")]
+fn nodes_route<T: Send + 'static>(
+    devices: Arc<Mutex<HashMap<String, T>>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::get()
+        .and(warp::path("nodes"))
+        .and(warp::path::end())
+        .map(move || warp::reply::json(&devices.lock().unwrap().keys().cloned().collect_vec()))
+}
+
+#[cfg(feature = "webview")]
+#[derive(serde::Serialize)]
+struct ErrorMessage {
+    code: u16,
+    message: String,
+}
+
+#[cfg(feature = "webview")]
+fn error_reply(code: warp::http::StatusCode, message: String) -> warp::reply::Response {
+    use warp::reply::Reply;
+
+    let error = ErrorMessage {
+        code: code.as_u16(),
+        message,
+    };
+    warp::reply::with_status(warp::reply::json(&error), code).into_response()
+}
+
+/// Applies channel parameters posted as JSON, given either as numbers or as
+/// strings
 #[cfg(feature = "webview")]
 async fn channel_post_fn(
     src: String,
     dst: String,
-    post: HashMap<String, String>,
+    post: HashMap<String, serde_json::Value>,
     channels: HashMap<String, HashMap<String, Arc<Channel>>>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    use warp::reply::Reply;
+    use warp::{http::StatusCode, reply::Reply};
 
-    let Some(src1) = channels.get(&src) else {
-        return Err(warp::reject::not_found());
+    let Some(channel) = channels.get(&src).and_then(|src| src.get(&dst)) else {
+        return Ok(error_reply(
+            StatusCode::NOT_FOUND,
+            format!("no channel from {src} to {dst}"),
+        ));
     };
 
-    let Some(channel) = src1.get(&dst) else {
-        return Err(warp::reject::not_found());
+    let params = post
+        .into_iter()
+        .map(|(key, value)| match value {
+            serde_json::Value::String(value) => (key, value),
+            value => (key, value.to_string()),
+        })
+        .collect();
+    match channel.set_params(params) {
+        Ok(()) => Ok(warp::reply().into_response()),
+        Err(e) => Ok(error_reply(StatusCode::BAD_REQUEST, e.to_string())),
+    }
+}
+
+#[cfg(feature = "webview")]
+fn channel_post_route(
+    channels: HashMap<String, HashMap<String, Arc<Channel>>>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::post()
+        .and(warp::path!("channel" / String / String))
+        .and(warp::path::end())
+        .and(warp::body::json())
+        .and_then(move |src, dst, post| channel_post_fn(src, dst, post, channels.clone()))
+}
+
+/// Handles to every link, by the node sending on it then the one receiving
+#[cfg(feature = "webview")]
+type LinkHandles = HashMap<String, HashMap<String, LinkHandle>>;
+
+/// Changes to a single link posted as JSON, all optional
+#[cfg(feature = "webview")]
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LinkPost {
+    up: Option<bool>,
+    loss: Option<f64>,
+    latency_ms: Option<u64>,
+}
+
+#[cfg(feature = "webview")]
+#[derive(serde::Serialize)]
+struct LinkState {
+    params: common::channel_parameters::ChannelParameters,
+    stats: simulator::LinkStats,
+}
+
+/// Brings a link up or down or changes its loss or latency, replying with
+/// the state the link is left in
+#[cfg(feature = "webview")]
+async fn link_post_fn(
+    src: String,
+    dst: String,
+    post: LinkPost,
+    links: Arc<LinkHandles>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    use warp::{http::StatusCode, reply::Reply};
+
+    let Some(link) = links.get(&src).and_then(|src| src.get(&dst)) else {
+        return Ok(error_reply(
+            StatusCode::NOT_FOUND,
+            format!("no link from {src} to {dst}"),
+        ));
     };
 
-    if channel.set_params(post).is_ok() {
-        Ok(warp::reply().into_response())
-    } else {
-        Ok(
-            warp::reply::with_status(warp::reply(), warp::http::StatusCode::BAD_REQUEST)
-                .into_response(),
-        )
+    if post.loss.is_some_and(|loss| !(0.0..=1.0).contains(&loss)) {
+        return Ok(error_reply(
+            StatusCode::BAD_REQUEST,
+            "loss must be between 0 and 1".to_string(),
+        ));
     }
+
+    if let Some(loss) = post.loss {
+        link.set_loss(loss);
+    }
+    if let Some(latency) = post.latency_ms {
+        link.set_latency(Duration::from_millis(latency));
+    }
+    match post.up {
+        Some(true) => link.bring_up(),
+        Some(false) => link.bring_down(),
+        None => {}
+    }
+
+    let state = LinkState {
+        params: link.params(),
+        stats: link.stats(),
+    };
+    Ok(warp::reply::json(&state).into_response())
+}
+
+#[cfg(feature = "webview")]
+fn link_post_route(
+    links: Arc<LinkHandles>,
+) -> impl Filter<Extract = (impl warp::Reply,), Error = warp::Rejection> + Clone {
+    warp::post()
+        .and(warp::path!("link" / String / String))
+        .and(warp::path::end())
+        .and(warp::body::json())
+        .and_then(move |src, dst, post| link_post_fn(src, dst, post, links.clone()))
 }
 
 #[tokio::main]
@@ -67,6 +209,10 @@ async fn main() -> Result<()> {
     }
 
     let devices = Arc::new(Mutex::new(HashMap::new()));
+    #[cfg(feature = "webview")]
+    let frame_size_buckets = args.frame_size_buckets.clone();
+    let nodes = Arc::new(Mutex::new(HashMap::new()));
+    let stagger = args.stagger.map(Duration::from_millis);
     let simulator = Simulator::new(&args, |name, config| {
         let Some(config) = config.get("config_path") else {
             bail!("no config for node");
@@ -84,6 +230,7 @@ async fn main() -> Result<()> {
                 .name("real")
                 .tap(true)
                 .packet_info(false)
+                .mtu(i32::from(simulator::WIRE_MTU))
                 .up()
                 .try_build()?,
         );
@@ -93,6 +240,21 @@ async fn main() -> Result<()> {
             tap_name: Some("virtual".to_string()),
             ip: Some(Ipv4Addr::from_str(&settings.get_string("ip")?)?),
             mtu: 1459,
+            wire_mtu: simulator::WIRE_MTU,
+            tap_buffer_size: settings
+                .get_int("tap_buffer_size")
+                .map(|x| usize::try_from(x).ok())
+                .ok()
+                .flatten(),
+            send_retries: settings
+                .get_int("send_retries")
+                .map(|x| u32::try_from(x).ok())
+                .ok()
+                .flatten()
+                .unwrap_or(3),
+            filter_destination: settings.get_bool("filter_destination").unwrap_or(false),
+            warm_routes: settings.get_string("warm_routes").ok(),
+            capture_path: settings.get_string("capture_path").ok(),
             node_params: NodeParameters {
                 node_type: NodeType::from_str(&settings.get_string("node_type")?, true)
                     .or_else(|_| bail!("invalid node type"))?,
@@ -102,6 +264,177 @@ async fn main() -> Result<()> {
                     .map(|x| u32::try_from(x).ok())
                     .ok()
                     .flatten(),
+                startup_delay: settings
+                    .get_int("startup_delay")
+                    .map(|x| u32::try_from(x).ok())
+                    .ok()
+                    .flatten()
+                    .or_else(|| {
+                        let delay = simulator::stagger_delay(stagger?, &mut rand::thread_rng());
+                        u32::try_from(delay.as_millis()).ok()
+                    }),
+                adaptive_hello_min: settings
+                    .get_int("adaptive_hello_min")
+                    .map(|x| u32::try_from(x).ok())
+                    .ok()
+                    .flatten(),
+                adaptive_hello_max: settings
+                    .get_int("adaptive_hello_max")
+                    .map(|x| u32::try_from(x).ok())
+                    .ok()
+                    .flatten(),
+                ingress_loss: settings.get_float("ingress_loss").unwrap_or(0.0),
+                ingress_loss_seed: settings
+                    .get_int("ingress_loss_seed")
+                    .map(|x| u64::try_from(x).ok())
+                    .ok()
+                    .flatten(),
+                position: settings
+                    .get_string("position")
+                    .ok()
+                    .map(|x| Position::from_str(&x))
+                    .transpose()?,
+                route_metric: settings
+                    .get_string("route_metric")
+                    .ok()
+                    .map(|x| RouteMetric::from_str(&x, true))
+                    .transpose()
+                    .or_else(|_| bail!("invalid route metric"))?
+                    .unwrap_or(RouteMetric::Latency),
+                management_route_metric: settings
+                    .get_string("management_route_metric")
+                    .ok()
+                    .map(|x| RouteMetric::from_str(&x, true))
+                    .transpose()
+                    .or_else(|_| bail!("invalid management route metric"))?,
+                tag_management_traffic: settings
+                    .get_bool("tag_management_traffic")
+                    .unwrap_or(false),
+                upstream_mode: settings
+                    .get_string("upstream_mode")
+                    .ok()
+                    .map(|x| UpstreamMode::from_str(&x, true))
+                    .transpose()
+                    .or_else(|_| bail!("invalid upstream mode"))?
+                    .unwrap_or(UpstreamMode::Single),
+                upstream_change_log_interval: settings
+                    .get_int("upstream_change_log_interval")
+                    .map(|x| u32::try_from(x).ok())
+                    .ok()
+                    .flatten(),
+                rewrite_source_mac: settings.get_bool("rewrite_source_mac").unwrap_or(true),
+                clamp_path_mtu: settings.get_bool("clamp_path_mtu").unwrap_or(true),
+                upstream_blacklist: settings
+                    .get_array("upstream_blacklist")
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|x| Ok(MacAddress::from_str(&x.into_string()?)?))
+                    .collect::<Result<_>>()?,
+                only_rsu: settings
+                    .get_string("only_rsu")
+                    .ok()
+                    .map(|x| MacAddress::from_str(&x))
+                    .transpose()?,
+                control_priority: settings
+                    .get_int("control_priority")
+                    .map(|x| u32::try_from(x).ok())
+                    .ok()
+                    .flatten(),
+                data_priority: settings
+                    .get_int("data_priority")
+                    .map(|x| u8::try_from(x).ok())
+                    .ok()
+                    .flatten()
+                    .unwrap_or(0),
+                reply_dedup_window: settings
+                    .get_int("reply_dedup_window")
+                    .map(|x| u32::try_from(x).ok())
+                    .ok()
+                    .flatten(),
+                heartbeat_max_age: settings
+                    .get_int("heartbeat_max_age")
+                    .map(|x| u32::try_from(x).ok())
+                    .ok()
+                    .flatten(),
+                keepalive_interval: settings
+                    .get_int("keepalive_interval")
+                    .map(|x| u32::try_from(x).ok())
+                    .ok()
+                    .flatten(),
+                min_heartbeat_interval: settings
+                    .get_int("min_heartbeat_interval")
+                    .map(|x| u32::try_from(x).ok())
+                    .ok()
+                    .flatten(),
+                reply_suppression_interval: settings
+                    .get_int("reply_suppression_interval")
+                    .map(|x| u32::try_from(x).ok())
+                    .ok()
+                    .flatten(),
+                flap_penalty: settings
+                    .get_int("flap_penalty")
+                    .map(|x| u32::try_from(x).ok())
+                    .ok()
+                    .flatten()
+                    .unwrap_or(0),
+                flap_decay: settings
+                    .get_int("flap_decay")
+                    .map(|x| u32::try_from(x).ok())
+                    .ok()
+                    .flatten()
+                    .unwrap_or(30000),
+                client_cache_capacity: settings
+                    .get_int("client_cache_capacity")
+                    .map(|x| u32::try_from(x).ok())
+                    .ok()
+                    .flatten()
+                    .unwrap_or(4096),
+                client_ttl: settings
+                    .get_int("client_ttl")
+                    .map(|x| u32::try_from(x).ok())
+                    .ok()
+                    .flatten(),
+                handover_margin: settings
+                    .get_int("handover_margin")
+                    .ok()
+                    .map(u32::try_from)
+                    .transpose()
+                    .or_else(|_| bail!("handover_margin must not be negative"))?,
+                handover_observations: settings
+                    .get_int("handover_observations")
+                    .ok()
+                    .map(u32::try_from)
+                    .transpose()
+                    .or_else(|_| bail!("handover_observations must not be negative"))?
+                    .unwrap_or(1),
+                passive: settings.get_bool("passive").unwrap_or(false),
+                passive_replies: settings.get_bool("passive_replies").unwrap_or(false),
+                drop_malformed_frames: settings.get_bool("drop_malformed_frames").unwrap_or(true),
+                drop_header_only_frames: settings
+                    .get_bool("drop_header_only_frames")
+                    .unwrap_or(true),
+                fragment_size: settings
+                    .get_int("fragment_size")
+                    .map(|x| u32::try_from(x).ok())
+                    .ok()
+                    .flatten(),
+                fragment_timeout: settings
+                    .get_int("fragment_timeout")
+                    .map(|x| u32::try_from(x).ok())
+                    .ok()
+                    .flatten()
+                    .unwrap_or(5000),
+                compress: settings.get_bool("compress").unwrap_or(false),
+                route_ttl: settings
+                    .get_int("route_ttl")
+                    .map(|x| u32::try_from(x).ok())
+                    .ok()
+                    .flatten(),
+                receive_queue_high_watermark: settings
+                    .get_int("receive_queue_high_watermark")
+                    .map(|x| u32::try_from(x).ok())
+                    .ok()
+                    .flatten(),
             },
         };
 
@@ -129,27 +462,50 @@ async fn main() -> Result<()> {
         };
 
         let dev = Arc::new(Device::new(tun.name())?);
+        #[cfg(feature = "webview")]
+        if let Some(ref buckets) = frame_size_buckets {
+            dev.set_frame_size_buckets(buckets.clone());
+        }
         let node = node_lib::create_with_vdev(args, virtual_tun, dev.clone())?;
         devices
             .lock()
             .unwrap()
             .insert(name.to_string(), dev.clone());
+        nodes
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), (dev.mac_address(), node.clone()));
         Ok((dev, tun, node))
     })?;
 
+    #[cfg(feature = "webview")]
+    if let Some(sink) = args.metrics_sink {
+        tokio::spawn(metrics_sink::run(sink, devices.clone(), nodes.clone()));
+    }
+
     #[cfg(feature = "webview")]
     {
+        let nodesc = nodes.clone();
+        let nodes = nodes_route(devices.clone());
+
         let devicesc = devices.clone();
-        let nodes = warp::get()
-            .and(warp::path("nodes"))
+        let stats = warp::get()
+            .and(warp::path("stats"))
             .and(warp::path::end())
             .map(move || {
-                warp::reply::json(&devicesc.lock().unwrap().keys().cloned().collect_vec())
+                warp::reply::json(
+                    &devicesc
+                        .lock()
+                        .unwrap()
+                        .iter()
+                        .map(|(node, device)| (node, device.stats()))
+                        .collect::<HashMap<_, _>>(),
+                )
             });
 
         let devicesc = devices.clone();
-        let stats = warp::get()
-            .and(warp::path("stats"))
+        let frame_sizes = warp::get()
+            .and(warp::path("frame_sizes"))
             .and(warp::path::end())
             .map(move || {
                 warp::reply::json(
@@ -157,11 +513,143 @@ async fn main() -> Result<()> {
                         .lock()
                         .unwrap()
                         .iter()
-                        .map(|(node, device)| (node, device.stats()))
+                        .map(|(node, device)| (node, device.frame_sizes()))
                         .collect::<HashMap<_, _>>(),
                 )
             });
 
+        let nodesd = nodesc.clone();
+        let drops = warp::get()
+            .and(warp::path!("drops" / String))
+            .and(warp::path::end())
+            .map(move |name: String| {
+                let records = nodesd
+                    .lock()
+                    .unwrap()
+                    .get(&name)
+                    .map(|(_, node)| node.recent_drops(RECENT_DROPS))
+                    .unwrap_or_default();
+                warp::reply::json(
+                    &records
+                        .iter()
+                        .map(|record| {
+                            serde_json::json!({
+                                "reason": record.reason.to_string(),
+                                "source": record.source.map(|mac| mac.to_string()),
+                                "destination": record.destination.map(|mac| mac.to_string()),
+                                "at_ms": record.at.as_millis() as u64,
+                            })
+                        })
+                        .collect::<Vec<_>>(),
+                )
+            });
+
+        let nodesp = nodesc.clone();
+        let node_counters = warp::get()
+            .and(warp::path!("counters" / String))
+            .and(warp::path::end())
+            .map(move |name: String| {
+                warp::reply::json(
+                    &nodesp
+                        .lock()
+                        .unwrap()
+                        .get(&name)
+                        .map(|(_, node)| node.counters()),
+                )
+            });
+
+        let nodesl = nodesc.clone();
+        let heartbeat_loss = warp::get()
+            .and(warp::path!("heartbeat_loss" / String))
+            .and(warp::path::end())
+            .map(move |name: String| {
+                let loss = nodesl
+                    .lock()
+                    .unwrap()
+                    .get(&name)
+                    .map(|(_, node)| node.heartbeat_loss())
+                    .unwrap_or_default();
+                warp::reply::json(
+                    &loss
+                        .iter()
+                        .map(|(source, ratio)| (source.to_string(), *ratio))
+                        .collect::<HashMap<_, _>>(),
+                )
+            });
+
+        let nodesr = nodesc.clone();
+        let node_routes = warp::get()
+            .and(warp::path!("routes" / String))
+            .and(warp::path::end())
+            .map(move |name: String| {
+                let routes = nodesr
+                    .lock()
+                    .unwrap()
+                    .get(&name)
+                    .map(|(_, node)| node.routing_snapshot())
+                    .unwrap_or_default();
+                warp::reply::json(
+                    &routes
+                        .iter()
+                        .map(|route| {
+                            serde_json::json!({
+                                "destination": route.destination.to_string(),
+                                "next_hop": route.next_hop.to_string(),
+                                "hops": route.hops,
+                                "latency_us": route.latency.map(|latency| latency.as_micros() as u64),
+                                "age_ms": route.age.map(|age| age.as_millis() as u64),
+                                "status": route.status.to_string(),
+                            })
+                        })
+                        .collect::<Vec<_>>(),
+                )
+            });
+
+        let receive_queue = warp::get()
+            .and(warp::path("receive_queue"))
+            .and(warp::path::end())
+            .map(move || {
+                warp::reply::json(
+                    &nodesc
+                        .lock()
+                        .unwrap()
+                        .iter()
+                        .map(|(name, (_, node))| (name.clone(), node.receive_queue()))
+                        .collect::<HashMap<_, _>>(),
+                )
+            });
+
+        let devicesc = devices.clone();
+        let channelsc = simulator.get_channels();
+        let metrics = warp::get()
+            .and(warp::path("metrics"))
+            .and(warp::path::end())
+            .map(move || {
+                let stats = devicesc
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .map(|(node, device)| (node.clone(), device.stats()))
+                    .collect::<HashMap<_, _>>();
+                let links = channelsc
+                    .iter()
+                    .map(|(node, onode)| {
+                        (
+                            node.clone(),
+                            onode
+                                .iter()
+                                .map(|(onode, channel)| (onode.clone(), channel.stats()))
+                                .collect::<HashMap<_, _>>(),
+                        )
+                    })
+                    .collect::<HashMap<_, _>>();
+                warp::reply::with_header(
+                    metrics_sink::prometheus(&stats, &links),
+                    "content-type",
+                    "text/plain; version=0.0.4",
+                )
+            });
+
         let node_stats = warp::get()
             .and(warp::path!("node" / String))
             .and(warp::path::end())
@@ -200,24 +688,83 @@ async fn main() -> Result<()> {
             });
 
         let channelsc = channels.clone();
-        let channel_post = warp::post()
-            .and(warp::path!("channel" / String / String))
+        let link_stats = warp::get()
+            .and(warp::path("link_stats"))
             .and(warp::path::end())
-            .and(warp::body::json())
-            .and_then(move |src, dst, post| channel_post_fn(src, dst, post, channelsc.clone()));
+            .map(move || {
+                warp::reply::json(
+                    &channelsc
+                        .iter()
+                        .map(|(node, onode)| {
+                            (
+                                node,
+                                onode
+                                    .iter()
+                                    .map(|(onode, channel)| (onode, channel.stats()))
+                                    .collect::<HashMap<_, _>>(),
+                            )
+                        })
+                        .collect::<HashMap<_, _>>(),
+                )
+            });
+
+        let node_types = simulator.get_node_types();
+        let channelsc = channels.clone();
+        let topology_dot = warp::get()
+            .and(warp::path("topology.dot"))
+            .and(warp::path::end())
+            .map(move || {
+                warp::reply::with_header(
+                    topology::render(
+                        &node_types,
+                        &simulator::link_parameters(&channelsc),
+                        topology::TopologyFormat::Dot,
+                    ),
+                    "content-type",
+                    "text/vnd.graphviz",
+                )
+            });
+
+        let channel_post = channel_post_route(channels.clone());
+        let links = channels
+            .iter()
+            .map(|(src, dsts)| {
+                let links = dsts
+                    .keys()
+                    .filter_map(|dst| Some((dst.clone(), simulator.link(src, dst)?)))
+                    .collect();
+                (src.clone(), links)
+            })
+            .collect();
+        let link_post = link_post_route(Arc::new(links));
 
         let cors = warp::cors().allow_any_origin();
 
         let routes = nodes
             .or(node_stats)
             .or(stats)
+            .or(frame_sizes)
+            .or(receive_queue)
+            .or(drops)
+            .or(node_routes)
+            .or(node_counters)
+            .or(heartbeat_loss)
             .or(channels_get)
+            .or(link_stats)
+            .or(metrics)
+            .or(topology_dot)
             .or(channel_post)
+            .or(link_post)
             .with(cors);
+        #[cfg(feature = "unix-socket")]
+        if let Some(ref path) = args.unix_socket {
+            tokio::spawn(warp::serve(routes.clone()).run_incoming(unix_socket::incoming(path)?));
+        }
         tokio::select! {
             _ = warp::serve(routes).run(([127, 0, 0, 1], 3030)) => {}
             _ = simulator.run() => {}
             _ = signal::ctrl_c() => {}
+            _ = max_runtime(args.max_runtime) => {}
         }
     }
     #[cfg(not(feature = "webview"))]
@@ -225,8 +772,176 @@ async fn main() -> Result<()> {
         tokio::select! {
             _ = simulator.run() => {}
             _ = signal::ctrl_c() => {}
+            _ = max_runtime(args.max_runtime) => {}
         }
     }
 
+    if let Some(ref path) = args.dump_routing {
+        let snapshots = nodes
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, (mac, node))| (name.clone(), (*mac, node.routing_snapshot())))
+            .collect();
+        routing_dump::write(path, &snapshots)?;
+    }
+
+    if let Some(format) = args.export_topology {
+        topology::write(&simulator.export_topology(format), format)?;
+    }
+
     Ok(())
 }
+
+async fn max_runtime(seconds: Option<u64>) {
+    match seconds {
+        Some(seconds) => tokio::time::sleep(Duration::from_secs(seconds)).await,
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(all(test, feature = "webview"))]
+mod tests {
+    use super::{channel_post_route, link_post_route};
+    use crate::simulator::{Channel, FakeTap, Simulator};
+    use common::channel_parameters::ChannelParameters;
+    use std::{
+        collections::HashMap,
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    #[tokio::test]
+    async fn posted_parameters_are_applied_to_the_channel() {
+        let (tap, _, _) = FakeTap::open();
+        let channel = Channel::new(
+            ChannelParameters {
+                latency: Duration::ZERO,
+                loss: 0.0,
+                jitter: Duration::ZERO,
+                reorder: 0.0,
+                bandwidth_bps: None,
+            },
+            [2; 6].into(),
+            tap,
+            None,
+            Instant::now(),
+            &"n1".to_string(),
+            &"n2".to_string(),
+        );
+        let channels = HashMap::from([(
+            "n1".to_string(),
+            HashMap::from([("n2".to_string(), channel.clone())]),
+        )]);
+        let route = channel_post_route(channels);
+
+        let reply = warp::test::request()
+            .method("POST")
+            .path("/channel/n1/n2")
+            .json(&serde_json::json!({"latency": 40, "loss": "0.25"}))
+            .reply(&route)
+            .await;
+        assert_eq!(reply.status(), 200);
+        assert_eq!(channel.params().latency, Duration::from_millis(40));
+        assert_eq!(channel.params().loss, 0.25);
+
+        let reply = warp::test::request()
+            .method("POST")
+            .path("/channel/n1/n3")
+            .json(&serde_json::json!({"latency": 40, "loss": 0.25}))
+            .reply(&route)
+            .await;
+        assert_eq!(reply.status(), 404);
+        let error: serde_json::Value = serde_json::from_slice(reply.body()).expect("json");
+        assert_eq!(error["code"], 404);
+
+        let reply = warp::test::request()
+            .method("POST")
+            .path("/channel/n1/n2")
+            .json(&serde_json::json!({"latency": -1, "loss": 0.5}))
+            .reply(&route)
+            .await;
+        assert_eq!(reply.status(), 400);
+        assert_eq!(channel.params().loss, 0.25);
+
+        for loss in [-0.1, 1.5] {
+            let reply = warp::test::request()
+                .method("POST")
+                .path("/channel/n1/n2")
+                .json(&serde_json::json!({"latency": 40, "loss": loss}))
+                .reply(&route)
+                .await;
+            assert_eq!(reply.status(), 400);
+            let error: serde_json::Value = serde_json::from_slice(reply.body()).expect("json");
+            assert_eq!(error["message"], "loss must be between 0 and 1");
+        }
+        assert_eq!(channel.params().loss, 0.25);
+    }
+
+    #[tokio::test]
+    async fn posted_link_changes_are_applied_to_the_link() {
+        let (tap, _, _) = FakeTap::open();
+        let channel = Channel::new(
+            ChannelParameters {
+                latency: Duration::ZERO,
+                loss: 0.0,
+                jitter: Duration::ZERO,
+                reorder: 0.0,
+                bandwidth_bps: None,
+            },
+            [2; 6].into(),
+            tap,
+            None,
+            Instant::now(),
+            &"n1".to_string(),
+            &"n2".to_string(),
+        );
+        let simulator = Simulator::with_channels(HashMap::from([(
+            "n1".to_string(),
+            HashMap::from([("n2".to_string(), channel.clone())]),
+        )]));
+        let link = simulator.link("n1", "n2").expect("link from n1 to n2");
+        let links = HashMap::from([("n1".to_string(), HashMap::from([("n2".to_string(), link)]))]);
+        let route = link_post_route(Arc::new(links));
+
+        let reply = warp::test::request()
+            .method("POST")
+            .path("/link/n1/n2")
+            .json(&serde_json::json!({"up": false, "loss": 0.5, "latency_ms": 30}))
+            .reply(&route)
+            .await;
+        assert_eq!(reply.status(), 200);
+        let state: serde_json::Value = serde_json::from_slice(reply.body()).expect("json");
+        assert_eq!(state["params"]["loss"], 0.5);
+        assert_eq!(state["params"]["latency_us"], 30_000);
+        let mut frame = vec![0u8; 64];
+        frame[..6].copy_from_slice(&[255; 6]);
+        assert!(channel.send(frame.clone(), Duration::ZERO).await.is_err());
+
+        let reply = warp::test::request()
+            .method("POST")
+            .path("/link/n1/n2")
+            .json(&serde_json::json!({"up": true, "loss": 0.0}))
+            .reply(&route)
+            .await;
+        assert_eq!(reply.status(), 200);
+        assert!(channel.send(frame, Duration::ZERO).await.is_ok());
+
+        let reply = warp::test::request()
+            .method("POST")
+            .path("/link/n1/n2")
+            .json(&serde_json::json!({"loss": 1.5}))
+            .reply(&route)
+            .await;
+        assert_eq!(reply.status(), 400);
+        assert_eq!(channel.params().loss, 0.0);
+
+        let reply = warp::test::request()
+            .method("POST")
+            .path("/link/n2/n1")
+            .json(&serde_json::json!({"up": false}))
+            .reply(&route)
+            .await;
+        assert_eq!(reply.status(), 404);
+    }
+}
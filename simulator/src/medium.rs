@@ -0,0 +1,147 @@
+use config::Value;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MediumParameters {
+    /// Bits per second, used to compute how long a frame occupies the medium
+    pub bitrate: u64,
+    /// Duration of a backoff slot
+    pub slot: Duration,
+    /// Upper bound of the random backoff, in slots
+    pub max_backoff: u32,
+    pub seed: Option<u64>,
+}
+
+impl From<HashMap<String, Value>> for MediumParameters {
+    fn from(param: HashMap<String, Value>) -> Self {
+        let bitrate = match param.get("bitrate") {
+            Some(val) => val.clone().into_uint().unwrap_or(6_000_000),
+            None => 6_000_000,
+        };
+        let slot = match param.get("slot") {
+            Some(val) => val.clone().into_uint().unwrap_or(9),
+            None => 9,
+        };
+        let max_backoff = match param.get("max_backoff") {
+            Some(val) => val.clone().into_uint().unwrap_or(16),
+            None => 16,
+        };
+        let seed = param
+            .get("seed")
+            .and_then(|val| val.clone().into_uint().ok());
+
+        Self {
+            bitrate: bitrate.max(1),
+            slot: Duration::from_micros(slot),
+            max_backoff: u32::try_from(max_backoff).unwrap_or(u32::MAX).max(1),
+            seed,
+        }
+    }
+}
+
+/// Shared wireless medium. Channels tagged with the same medium contend for
+/// it: a transmission starting while another is on air is deferred until
+/// the medium is idle plus a random backoff.
+#[derive(Debug)]
+pub struct Medium {
+    name: String,
+    parameters: MediumParameters,
+    busy_until: Mutex<Option<Instant>>,
+    rng: Mutex<StdRng>,
+}
+
+impl Medium {
+    pub fn new(name: &str, parameters: MediumParameters) -> Self {
+        tracing::info!(name, ?parameters, "Created medium");
+        Self {
+            name: name.to_string(),
+            parameters,
+            busy_until: Mutex::new(None),
+            rng: Mutex::new(match parameters.seed {
+                Some(seed) => StdRng::seed_from_u64(seed),
+                None => StdRng::from_entropy(),
+            }),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn airtime(&self, size: usize) -> Duration {
+        let bits = u128::try_from(size).unwrap_or(u128::MAX).saturating_mul(8);
+        let nanos = bits.saturating_mul(1_000_000_000) / u128::from(self.parameters.bitrate);
+        Duration::from_nanos(u64::try_from(nanos).unwrap_or(u64::MAX))
+    }
+
+    /// Reserves the medium for a frame of `size` bytes sent at `now` and
+    /// returns how long the transmission has to be deferred.
+    pub fn reserve(&self, now: Instant, size: usize) -> Duration {
+        let mut busy_until = self.busy_until.lock().unwrap();
+        let start = match *busy_until {
+            Some(busy) if busy > now => {
+                let slots = self
+                    .rng
+                    .lock()
+                    .unwrap()
+                    .gen_range(1..=self.parameters.max_backoff);
+                busy + self.parameters.slot * slots
+            }
+            _ => now,
+        };
+
+        *busy_until = Some(start + self.airtime(size));
+        start - now
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Medium, MediumParameters};
+    use std::time::{Duration, Instant};
+
+    fn medium() -> Medium {
+        Medium::new(
+            "m1",
+            MediumParameters {
+                bitrate: 8_000_000,
+                slot: Duration::from_micros(10),
+                max_backoff: 4,
+                seed: Some(7),
+            },
+        )
+    }
+
+    #[test]
+    fn concurrent_senders_are_serialized_with_backoff() {
+        let medium = medium();
+        let now = Instant::now();
+        let airtime = Duration::from_micros(1000);
+
+        assert_eq!(medium.reserve(now, 1000), Duration::ZERO);
+
+        let second = medium.reserve(now, 1000);
+        assert!(second >= airtime + Duration::from_micros(10));
+        assert!(second <= airtime + Duration::from_micros(40));
+
+        let third = medium.reserve(now, 1000);
+        assert!(third >= second + airtime + Duration::from_micros(10));
+    }
+
+    #[test]
+    fn idle_medium_does_not_defer() {
+        let medium = medium();
+        let now = Instant::now();
+
+        assert_eq!(medium.reserve(now, 1000), Duration::ZERO);
+        assert_eq!(
+            medium.reserve(now + Duration::from_millis(2), 1000),
+            Duration::ZERO
+        );
+    }
+}
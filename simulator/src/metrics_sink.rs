@@ -0,0 +1,541 @@
+use crate::simulator::LinkStats;
+use anyhow::Result;
+use common::{
+    device::Device,
+    stats::{FrameSizeHistogram, Stats},
+};
+use mac_address::MacAddress;
+use node_lib::{
+    control::{receive_queue::QueueDepth, traffic::ClassCounts},
+    Node,
+};
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tokio::net::UdpSocket;
+
+pub const METRICS_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Largest datagram sent to the sink, so every node fits however many there
+/// are without exceeding a common path MTU
+const MAX_DATAGRAM: usize = 1400;
+
+fn escape_tag(value: &str) -> String {
+    value
+        .replace(',', "\\,")
+        .replace('=', "\\=")
+        .replace(' ', "\\ ")
+}
+
+fn integer(value: u128) -> i64 {
+    i64::try_from(value).unwrap_or(i64::MAX)
+}
+
+/// Renders one `measurement` line per node in InfluxDB line protocol, with
+/// the fields `fields` renders from the value of the node.
+fn node_protocol<T>(
+    measurement: &str,
+    values: &HashMap<String, T>,
+    fields: impl Fn(&T) -> String,
+    timestamp: Duration,
+) -> String {
+    let mut nodes: Vec<_> = values.iter().collect();
+    nodes.sort_by_key(|(a, _)| *a);
+    nodes.iter().fold(String::new(), |mut out, (node, value)| {
+        let _ = writeln!(
+            out,
+            "{measurement},node={} {} {}",
+            escape_tag(node),
+            fields(value),
+            timestamp.as_nanos(),
+        );
+        out
+    })
+}
+
+/// Renders node stats in InfluxDB line protocol, one line per node.
+pub fn line_protocol(stats: &HashMap<String, Stats>, timestamp: Duration) -> String {
+    node_protocol(
+        "node_stats",
+        stats,
+        |stats| {
+            format!(
+                "received_packets={}i,received_bytes={}i,transmitted_packets={}i,transmitted_bytes={}i,tun_read_errors={}i,tun_write_errors={}i",
+                integer(stats.received_packets),
+                integer(stats.received_bytes),
+                integer(stats.transmitted_packets),
+                integer(stats.transmitted_bytes),
+                integer(stats.tun_read_errors),
+                integer(stats.tun_write_errors),
+            )
+        },
+        timestamp,
+    )
+}
+
+/// Renders frame size histograms in InfluxDB line protocol, one line per
+/// node and bucket. `le` is the bucket upper bound, `+Inf` for the last one.
+pub fn frame_size_protocol(
+    histograms: &HashMap<String, FrameSizeHistogram>,
+    timestamp: Duration,
+) -> String {
+    let mut nodes: Vec<_> = histograms.iter().collect();
+    nodes.sort_by_key(|(a, _)| *a);
+    nodes
+        .iter()
+        .fold(String::new(), |mut out, (node, histogram)| {
+            let bounds = histogram
+                .bounds()
+                .iter()
+                .map(ToString::to_string)
+                .chain(std::iter::once("+Inf".to_string()));
+            for (le, count) in bounds.zip(histogram.counts()) {
+                let _ = writeln!(
+                    out,
+                    "frame_sizes,node={},le={} count={}i {}",
+                    escape_tag(node),
+                    le,
+                    integer(*count),
+                    timestamp.as_nanos(),
+                );
+            }
+            out
+        })
+}
+
+/// Fields of the wire receive queue of a node
+fn receive_queue_fields(queue: &QueueDepth) -> String {
+    format!(
+        "depth={}i,peak={}i,warnings={}i",
+        integer(queue.depth.into()),
+        integer(queue.peak.into()),
+        integer(queue.warnings.into()),
+    )
+}
+
+/// Fields of the data frames a node found missing from their flow sequence
+fn lost_frames_fields(lost: &u64) -> String {
+    format!("count={}i", integer(u128::from(*lost)))
+}
+
+/// Fields of the data frames a node handled per traffic class
+fn traffic_class_fields(counts: &ClassCounts) -> String {
+    format!(
+        "user={}i,management={}i",
+        integer(counts.user.into()),
+        integer(counts.management.into()),
+    )
+}
+
+type NodeSeries = (&'static str, fn(&dyn Node) -> String);
+
+/// Per node series sent to the sink, by measurement name
+const NODE_SERIES: [NodeSeries; 3] = [
+    ("receive_queue", |node| {
+        receive_queue_fields(&node.receive_queue())
+    }),
+    ("lost_frames", |node| {
+        lost_frames_fields(&node.lost_frames())
+    }),
+    ("traffic_class", |node| {
+        traffic_class_fields(&node.traffic_classes())
+    }),
+];
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+type NodeCounter = (&'static str, &'static str, fn(&Stats) -> u128);
+
+type LinkSeries = (
+    &'static str,
+    &'static str,
+    &'static str,
+    fn(&LinkStats) -> String,
+);
+
+type Nodes = Arc<Mutex<HashMap<String, (MacAddress, Arc<dyn Node>)>>>;
+
+const NODE_COUNTERS: [NodeCounter; 6] = [
+    (
+        "node_received_packets_total",
+        "Frames received by the node",
+        |stats| stats.received_packets,
+    ),
+    (
+        "node_received_bytes_total",
+        "Bytes received by the node",
+        |stats| stats.received_bytes,
+    ),
+    (
+        "node_transmitted_packets_total",
+        "Frames sent by the node",
+        |stats| stats.transmitted_packets,
+    ),
+    (
+        "node_transmitted_bytes_total",
+        "Bytes sent by the node",
+        |stats| stats.transmitted_bytes,
+    ),
+    (
+        "node_tun_read_errors_total",
+        "Errors reading from the node TAP",
+        |stats| stats.tun_read_errors,
+    ),
+    (
+        "node_tun_write_errors_total",
+        "Errors writing to the node TAP",
+        |stats| stats.tun_write_errors,
+    ),
+];
+
+/// Renders node and channel stats in the Prometheus text exposition format.
+/// Channel series are labelled with the nodes at both ends.
+pub fn prometheus(
+    stats: &HashMap<String, Stats>,
+    links: &HashMap<String, HashMap<String, LinkStats>>,
+) -> String {
+    let mut out = String::new();
+    let mut nodes: Vec<_> = stats.iter().collect();
+    nodes.sort_by_key(|(a, _)| *a);
+    for (name, help, value) in NODE_COUNTERS {
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} counter");
+        for (node, stats) in &nodes {
+            let _ = writeln!(
+                out,
+                "{name}{{node=\"{}\"}} {}",
+                escape_label(node),
+                value(stats)
+            );
+        }
+    }
+
+    let mut links: Vec<_> = links
+        .iter()
+        .flat_map(|(from, to)| to.iter().map(move |(to, stats)| (from, to, stats)))
+        .collect();
+    links.sort_by(|(fa, ta, _), (fb, tb, _)| (fa, ta).cmp(&(fb, tb)));
+    let link_series: [LinkSeries; 3] = [
+        (
+            "link_forwarded_frames_total",
+            "counter",
+            "Frames a channel delivered, warmup included",
+            |stats| (stats.forwarded + stats.warmup_forwarded).to_string(),
+        ),
+        (
+            "link_dropped_frames_total",
+            "counter",
+            "Frames a channel lost or dropped while down, warmup included",
+            |stats| (stats.dropped + stats.warmup_dropped).to_string(),
+        ),
+        (
+            "link_drop_rate",
+            "gauge",
+            "Share of frames a channel dropped after the warmup",
+            |stats| stats.drop_rate().to_string(),
+        ),
+    ];
+    for (name, kind, help, value) in link_series {
+        let _ = writeln!(out, "# HELP {name} {help}");
+        let _ = writeln!(out, "# TYPE {name} {kind}");
+        for (from, to, stats) in &links {
+            let _ = writeln!(
+                out,
+                "{name}{{from=\"{}\",to=\"{}\"}} {}",
+                escape_label(from),
+                escape_label(to),
+                value(stats)
+            );
+        }
+    }
+    out
+}
+
+/// Splits line protocol `payload` in datagrams of at most [`MAX_DATAGRAM`]
+/// bytes on line boundaries. A longer line is sent in a datagram of its own.
+fn datagrams(payload: &str) -> Vec<&str> {
+    let mut datagrams = Vec::new();
+    let (mut start, mut end) = (0, 0);
+    for line in payload.split_inclusive('\n') {
+        if end > start && end + line.len() - start > MAX_DATAGRAM {
+            datagrams.push(&payload[start..end]);
+            start = end;
+        }
+        end += line.len();
+    }
+    if end > start {
+        datagrams.push(&payload[start..end]);
+    }
+    datagrams
+}
+
+/// Sends line protocol `payload` to `sink`, split in as many datagrams as
+/// needed
+async fn send_lines(socket: &UdpSocket, sink: SocketAddr, payload: &str) -> Result<()> {
+    for datagram in datagrams(payload) {
+        socket.send_to(datagram.as_bytes(), sink).await?;
+    }
+    Ok(())
+}
+
+pub async fn send_metrics(
+    socket: &UdpSocket,
+    sink: SocketAddr,
+    stats: &HashMap<String, Stats>,
+) -> Result<()> {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?;
+    send_lines(socket, sink, &line_protocol(stats, timestamp)).await
+}
+
+pub async fn run(
+    sink: SocketAddr,
+    devices: Arc<Mutex<HashMap<String, Arc<Device>>>>,
+    nodes: Nodes,
+) -> Result<()> {
+    let bind: SocketAddr = if sink.is_ipv4() {
+        ([0, 0, 0, 0], 0).into()
+    } else {
+        ([0u16; 8], 0).into()
+    };
+    let socket = UdpSocket::bind(bind).await?;
+    loop {
+        let stats = devices
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(node, device)| (node.clone(), device.stats()))
+            .collect::<HashMap<_, _>>();
+        let _ = send_metrics(&socket, sink, &stats)
+            .await
+            .inspect_err(|e| tracing::error!(?e, "error sending metrics"));
+        let histograms = devices
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(node, device)| (node.clone(), device.frame_sizes()))
+            .collect::<HashMap<_, _>>();
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?;
+        let _ = send_lines(&socket, sink, &frame_size_protocol(&histograms, timestamp))
+            .await
+            .inspect_err(|e| tracing::error!(?e, "error sending frame sizes"));
+        // Every node metric is read under one lock and sent together
+        let mut metrics = String::new();
+        {
+            let nodes = nodes.lock().unwrap();
+            for (measurement, fields) in NODE_SERIES {
+                let values: HashMap<_, _> = nodes
+                    .iter()
+                    .map(|(name, (_, node))| (name.clone(), fields(node.as_ref())))
+                    .collect();
+                metrics.push_str(&node_protocol(
+                    measurement,
+                    &values,
+                    String::clone,
+                    timestamp,
+                ));
+            }
+        }
+        let _ = send_lines(&socket, sink, &metrics)
+            .await
+            .inspect_err(|e| tracing::error!(?e, "error sending node metrics"));
+        let _ = tokio_timerfd::sleep(METRICS_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        datagrams, frame_size_protocol, line_protocol, lost_frames_fields, node_protocol,
+        prometheus, receive_queue_fields, send_metrics, traffic_class_fields, MAX_DATAGRAM,
+    };
+    use crate::simulator::LinkStats;
+    use common::stats::{FrameSizeHistogram, Stats};
+    use node_lib::control::{receive_queue::QueueDepth, traffic::ClassCounts};
+    use std::{collections::HashMap, time::Duration};
+    use tokio::net::UdpSocket;
+
+    #[test]
+    fn stats_are_rendered_as_line_protocol() {
+        let stats = HashMap::from([(
+            "n 1".to_string(),
+            Stats {
+                received_packets: 44,
+                received_bytes: 3074,
+                transmitted_packets: 33,
+                transmitted_bytes: 3210,
+                tun_read_errors: 0,
+                tun_write_errors: 1,
+            },
+        )]);
+
+        assert_eq!(
+            line_protocol(&stats, Duration::from_secs(1)),
+            "node_stats,node=n\\ 1 received_packets=44i,received_bytes=3074i,transmitted_packets=33i,transmitted_bytes=3210i,tun_read_errors=0i,tun_write_errors=1i 1000000000\n"
+        );
+    }
+
+    #[test]
+    fn frame_sizes_are_rendered_per_bucket() {
+        let mut histogram = FrameSizeHistogram::new(vec![64, 1500]);
+        for size in [60, 1000, 1400, 9000] {
+            histogram.record(size);
+        }
+        let histograms = HashMap::from([("n1".to_string(), histogram)]);
+
+        assert_eq!(
+            frame_size_protocol(&histograms, Duration::from_secs(1)),
+            "frame_sizes,node=n1,le=64 count=1i 1000000000\n\
+             frame_sizes,node=n1,le=1500 count=2i 1000000000\n\
+             frame_sizes,node=n1,le=+Inf count=1i 1000000000\n"
+        );
+    }
+
+    #[test]
+    fn node_series_are_rendered_as_line_protocol() {
+        let timestamp = Duration::from_secs(1);
+        let queues = HashMap::from([(
+            "n1".to_string(),
+            QueueDepth {
+                depth: 3,
+                peak: 40,
+                warnings: 2,
+            },
+        )]);
+        let lost = HashMap::from([("n2".to_string(), 3), ("n1".to_string(), 0)]);
+        let classes = HashMap::from([(
+            "n1".to_string(),
+            ClassCounts {
+                user: 12,
+                management: 3,
+            },
+        )]);
+
+        for (rendered, expected) in [
+            (
+                node_protocol("receive_queue", &queues, receive_queue_fields, timestamp),
+                "receive_queue,node=n1 depth=3i,peak=40i,warnings=2i 1000000000\n",
+            ),
+            (
+                node_protocol("lost_frames", &lost, lost_frames_fields, timestamp),
+                "lost_frames,node=n1 count=0i 1000000000\n\
+                 lost_frames,node=n2 count=3i 1000000000\n",
+            ),
+            (
+                node_protocol("traffic_class", &classes, traffic_class_fields, timestamp),
+                "traffic_class,node=n1 user=12i,management=3i 1000000000\n",
+            ),
+        ] {
+            assert_eq!(rendered, expected);
+        }
+    }
+
+    #[test]
+    fn stats_are_rendered_for_prometheus() {
+        let stats = HashMap::from([(
+            "n1".to_string(),
+            Stats {
+                received_packets: 44,
+                ..Default::default()
+            },
+        )]);
+        let links = HashMap::from([(
+            "n1".to_string(),
+            HashMap::from([(
+                "n\"2".to_string(),
+                LinkStats {
+                    forwarded: 3,
+                    dropped: 1,
+                    warmup_forwarded: 2,
+                    ..Default::default()
+                },
+            )]),
+        )]);
+
+        let rendered = prometheus(&stats, &links);
+        let types: Vec<_> = rendered
+            .lines()
+            .filter(|line| line.starts_with("# TYPE "))
+            .collect();
+        assert_eq!(types.len(), 9);
+        assert!(types.iter().all(|line| {
+            let fields: Vec<_> = line.split(' ').collect();
+            fields.len() == 4 && ["counter", "gauge"].contains(&fields[3])
+        }));
+        assert!(rendered
+            .lines()
+            .any(|line| line == "node_received_packets_total{node=\"n1\"} 44"));
+        assert!(rendered
+            .lines()
+            .any(|line| line == "link_forwarded_frames_total{from=\"n1\",to=\"n\\\"2\"} 5"));
+        assert!(rendered
+            .lines()
+            .any(|line| line == "link_drop_rate{from=\"n1\",to=\"n\\\"2\"} 0.25"));
+    }
+
+    #[tokio::test]
+    async fn metrics_are_sent_to_the_sink() {
+        let sink = UdpSocket::bind("127.0.0.1:0").await.expect("bind sink");
+        let socket = UdpSocket::bind("127.0.0.1:0").await.expect("bind socket");
+        let stats = HashMap::from([
+            ("n1".to_string(), Stats::default()),
+            ("n2".to_string(), Stats::default()),
+        ]);
+
+        send_metrics(&socket, sink.local_addr().expect("address"), &stats)
+            .await
+            .expect("sent metrics");
+
+        let mut buf = [0u8; 1500];
+        let n = sink.recv(&mut buf).await.expect("received metrics");
+        let received = std::str::from_utf8(&buf[..n]).expect("utf8");
+        let lines: Vec<_> = received.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("node_stats,node=n1 received_packets=0i,"));
+        assert!(lines[1].starts_with("node_stats,node=n2 received_packets=0i,"));
+        assert!(lines.iter().all(|line| line
+            .rsplit(' ')
+            .next()
+            .is_some_and(|ts| ts.parse::<u128>().is_ok())));
+    }
+
+    #[tokio::test]
+    async fn metrics_of_many_nodes_are_split_in_datagrams() {
+        let sink = UdpSocket::bind("127.0.0.1:0").await.expect("bind sink");
+        let socket = UdpSocket::bind("127.0.0.1:0").await.expect("bind socket");
+        let stats: HashMap<_, _> = (0..200)
+            .map(|node| (format!("n{node}"), Stats::default()))
+            .collect();
+
+        send_metrics(&socket, sink.local_addr().expect("address"), &stats)
+            .await
+            .expect("sent metrics");
+
+        let mut lines = 0;
+        let mut buf = [0u8; 1500];
+        while lines < stats.len() {
+            let n = sink.recv(&mut buf).await.expect("received metrics");
+            assert!(n <= MAX_DATAGRAM, "{n} byte datagram");
+            let received = std::str::from_utf8(&buf[..n]).expect("utf8");
+            assert!(received.ends_with('\n'));
+            lines += received.lines().count();
+        }
+        assert_eq!(lines, stats.len());
+    }
+
+    #[test]
+    fn long_lines_are_sent_alone() {
+        let long = format!("{}\n", "x".repeat(MAX_DATAGRAM + 1));
+        let payload = format!("a\n{long}b\n");
+        assert_eq!(datagrams(&payload), vec!["a\n", &long[..], "b\n"]);
+        assert!(datagrams("").is_empty());
+    }
+}
@@ -0,0 +1,78 @@
+use anyhow::Result;
+use mac_address::MacAddress;
+use node_lib::control::snapshot::RouteEntry;
+use serde_json::{json, Map, Value};
+use std::{collections::HashMap, path::Path};
+
+/// Renders the routing table of every node, keyed by node name.
+pub fn to_json(nodes: &HashMap<String, (MacAddress, Vec<RouteEntry>)>) -> Value {
+    Value::Object(
+        nodes
+            .iter()
+            .map(|(name, (mac, routes))| {
+                let routes = routes
+                    .iter()
+                    .map(|route| {
+                        json!({
+                            "destination": route.destination.to_string(),
+                            "next_hop": route.next_hop.to_string(),
+                            "hops": route.hops,
+                            "status": route.status.to_string(),
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                (
+                    name.clone(),
+                    json!({ "mac": mac.to_string(), "routes": routes }),
+                )
+            })
+            .collect::<Map<_, _>>(),
+    )
+}
+
+pub fn write(path: &Path, nodes: &HashMap<String, (MacAddress, Vec<RouteEntry>)>) -> Result<()> {
+    std::fs::write(path, serde_json::to_string_pretty(&to_json(nodes))?)?;
+    tracing::info!(path = %path.display(), nodes = nodes.len(), "dumped routing state");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::to_json;
+    use mac_address::MacAddress;
+    use node_lib::control::snapshot::{RouteEntry, RouteStatus};
+    use std::collections::HashMap;
+
+    #[test]
+    fn every_node_has_an_entry() {
+        let rsu = MacAddress::new([1; 6]);
+        let obu = MacAddress::new([2; 6]);
+        let nodes = HashMap::from([
+            (
+                "n1".to_string(),
+                (
+                    rsu,
+                    vec![RouteEntry {
+                        destination: obu,
+                        next_hop: obu,
+                        hops: 1,
+                        status: RouteStatus::Confirmed,
+                        latency: None,
+                        age: None,
+                    }],
+                ),
+            ),
+            ("n2".to_string(), (obu, vec![])),
+        ]);
+
+        let dump = to_json(&nodes);
+        let dump = dump.as_object().expect("object");
+        assert_eq!(dump.len(), 2);
+        assert_eq!(dump["n1"]["mac"], rsu.to_string());
+        assert_eq!(dump["n1"]["routes"][0]["next_hop"], obu.to_string());
+        assert_eq!(dump["n1"]["routes"][0]["hops"], 1);
+        assert_eq!(dump["n1"]["routes"][0]["status"], "confirmed");
+        assert_eq!(dump["n2"]["mac"], obu.to_string());
+        assert_eq!(dump["n2"]["routes"].as_array().map(Vec::len), Some(0));
+    }
+}
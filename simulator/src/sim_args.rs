@@ -1,4 +1,8 @@
+use crate::topology::TopologyFormat;
 use clap::Parser;
+#[cfg(feature = "webview")]
+use std::net::SocketAddr;
+use std::path::PathBuf;
 
 #[derive(Parser, Debug, Clone)]
 #[command(author, version, about, long_about = None)]
@@ -9,4 +13,39 @@ pub struct SimArgs {
 
     #[arg(short, long, default_value_t = false)]
     pub pretty: bool,
+
+    /// Stop the simulation after this many seconds
+    #[arg(long)]
+    pub max_runtime: Option<u64>,
+
+    /// Spread node startup at random over this many milliseconds
+    #[arg(long)]
+    pub stagger: Option<u64>,
+
+    /// Seconds after start whose frames are left out of the link stats
+    #[arg(long)]
+    pub warmup: Option<u64>,
+
+    /// Write the routing state of every node to this JSON file on exit
+    #[arg(long)]
+    pub dump_routing: Option<PathBuf>,
+
+    /// Export the topology in this format to a timestamped file on exit
+    #[arg(long)]
+    pub export_topology: Option<TopologyFormat>,
+
+    /// UDP address receiving node stats in InfluxDB line protocol
+    #[cfg(feature = "webview")]
+    #[arg(long)]
+    pub metrics_sink: Option<SocketAddr>,
+
+    /// Upper bounds in bytes of the frame size histogram buckets (comma separated)
+    #[cfg(feature = "webview")]
+    #[arg(long, value_delimiter = ',')]
+    pub frame_size_buckets: Option<Vec<usize>>,
+
+    /// Also serve the webview endpoints on this Unix domain socket
+    #[cfg(feature = "unix-socket")]
+    #[arg(long)]
+    pub unix_socket: Option<PathBuf>,
 }
@@ -1,4 +1,7 @@
+use crate::latency::{LatencyHistogram, LatencyPercentiles};
+use crate::medium::{Medium, MediumParameters};
 use crate::sim_args::SimArgs;
+use crate::topology::{self, Links, TopologyFormat};
 use anyhow::Context;
 use anyhow::{bail, Error, Result};
 use common::channel_parameters::ChannelParameters;
@@ -11,9 +14,14 @@ use futures::StreamExt;
 use itertools::Itertools;
 use mac_address::MacAddress;
 use netns_rs::NetNs;
-use node_lib::Node;
+use node_lib::{args::NodeType, Node};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+#[cfg(feature = "webview")]
+use serde::Serialize;
 use std::collections::VecDeque;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Mutex;
 use std::time::Instant;
 use std::{
@@ -23,7 +31,23 @@ use std::{
 };
 use tokio::sync::mpsc::UnboundedSender;
 use tokio_tun::Tun;
-use uninit::uninit_array;
+
+/// MTU of the TAP every node binds to, which frames are carried between
+pub const WIRE_MTU: u16 = 1500;
+
+/// Largest frame read from a node TAP, a frame of the wire MTU with its
+/// ethernet header
+const FRAME_LEN: usize = WIRE_MTU as usize + 14;
+
+/// Random delay, within `window`, before a node starts sending heartbeats.
+/// Nodes created together would otherwise all send their first heartbeat at
+/// once.
+pub fn stagger_delay(window: Duration, rng: &mut impl Rng) -> Duration {
+    if window.is_zero() {
+        return Duration::ZERO;
+    }
+    window.mul_f64(rng.gen::<f64>())
+}
 
 pub struct NamespaceWrapper(Option<NetNs>);
 
@@ -43,17 +67,254 @@ impl Drop for NamespaceWrapper {
 }
 
 struct Packet {
-    packet: [u8; 1500],
-    size: usize,
+    packet: Vec<u8>,
+    /// When the packet was handed to the channel
+    queued: Instant,
     instant: Instant,
+    /// Where within the jitter window the packet latency falls, in [-1, 1]
+    jitter: f64,
+}
+
+impl Packet {
+    /// When the packet is due for delivery over a channel with `params`
+    fn due(&self, params: &ChannelParameters) -> Instant {
+        self.instant + jittered(params.latency, params.jitter, self.jitter)
+    }
+}
+
+/// `latency` moved by `position` times `jitter`, never below zero
+fn jittered(latency: Duration, jitter: Duration, position: f64) -> Duration {
+    if position >= 0.0 {
+        latency + jitter.mul_f64(position)
+    } else {
+        latency.saturating_sub(jitter.mul_f64(-position))
+    }
+}
+
+/// Token bucket pacing frames to a channel bandwidth. The bucket holds up to
+/// a tenth of a second of traffic, at least a full frame, so short bursts go
+/// through unthrottled.
+#[derive(Debug)]
+struct TokenBucket {
+    /// Bits that can be sent right away, negative when in debt
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    fn new(now: Instant) -> Self {
+        Self {
+            tokens: f64::INFINITY,
+            last: now,
+        }
+    }
+
+    fn capacity(rate: u64) -> f64 {
+        (rate as f64 / 10.0).max(1500.0 * 8.0)
+    }
+
+    /// Tokens in the bucket at `now`, refilled at `rate` bits per second
+    fn refilled(&self, rate: u64, now: Instant) -> f64 {
+        let refill = now.saturating_duration_since(self.last).as_secs_f64() * rate as f64;
+        (self.tokens + refill).min(Self::capacity(rate))
+    }
+
+    /// Takes `bits` out of the bucket refilled at `rate` bits per second,
+    /// returning how long to wait before sending them
+    fn reserve(&mut self, bits: u64, rate: u64, now: Instant) -> Duration {
+        let rate = rate.max(1);
+        self.tokens = self.refilled(rate, now) - bits as f64;
+        self.last = now;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / rate as f64)
+        }
+    }
+
+    /// Bits that can be sent right away at `now`
+    fn level(&self, rate: u64, now: Instant) -> u64 {
+        self.refilled(rate.max(1), now).max(0.0) as u64
+    }
+}
+
+/// Queues `packet` in the order packets are due, letting it overtake the
+/// packet due right before it with probability `params.reorder`. Returns
+/// whether the packet is now the next one delivered.
+fn enqueue(
+    queue: &mut VecDeque<Packet>,
+    packet: Packet,
+    params: &ChannelParameters,
+    rng: &mut impl Rng,
+) -> bool {
+    let due = packet.due(params);
+    let mut at = queue.partition_point(|queued| queued.due(params) <= due);
+    if params.reorder > 0.0 && at > 0 && rng.gen::<f64>() < params.reorder {
+        at -= 1;
+    }
+    queue.insert(at, packet);
+    at == 0
+}
+
+/// Interface of the node a channel delivers frames to, also read for the
+/// frames that node sends
+#[derive(Clone)]
+pub enum Tap {
+    Device(Arc<Tun>),
+    #[cfg(test)]
+    Fake(Arc<FakeTap>),
+}
+
+impl Tap {
+    async fn send_all(&self, buf: &[u8]) -> std::io::Result<()> {
+        match self {
+            Self::Device(tun) => tun.send_all(buf).await,
+            #[cfg(test)]
+            Self::Fake(tap) => {
+                let _ = tap.delivered.send(buf.to_vec());
+                Ok(())
+            }
+        }
+    }
+
+    async fn recv(&self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Device(tun) => tun.recv(buf).await,
+            #[cfg(test)]
+            Self::Fake(tap) => {
+                let frame = tap
+                    .sent
+                    .lock()
+                    .await
+                    .recv()
+                    .await
+                    .ok_or(std::io::ErrorKind::BrokenPipe)?;
+                let size = frame.len().min(buf.len());
+                buf[..size].copy_from_slice(&frame[..size]);
+                Ok(size)
+            }
+        }
+    }
+}
+
+/// In-memory TAP standing in for a node, so channels can be tested without
+/// CAP_NET_ADMIN
+#[cfg(test)]
+pub struct FakeTap {
+    sent: tokio::sync::Mutex<tokio::sync::mpsc::UnboundedReceiver<Vec<u8>>>,
+    delivered: UnboundedSender<Vec<u8>>,
+}
+
+#[cfg(test)]
+impl FakeTap {
+    /// The TAP, with a sender of frames as if its node wrote them and a
+    /// receiver of the frames delivered to its node
+    pub fn open() -> (
+        Tap,
+        UnboundedSender<Vec<u8>>,
+        tokio::sync::mpsc::UnboundedReceiver<Vec<u8>>,
+    ) {
+        let (send, sent) = tokio::sync::mpsc::unbounded_channel();
+        let (delivered, deliveries) = tokio::sync::mpsc::unbounded_channel();
+        let tap = Self {
+            sent: sent.into(),
+            delivered,
+        };
+        (Tap::Fake(Arc::new(tap)), send, deliveries)
+    }
 }
 
 pub struct Channel {
     tx: UnboundedSender<()>,
     parameters: RwLock<ChannelParameters>,
     mac: MacAddress,
-    tun: Arc<Tun>,
+    tun: Tap,
     queue: Mutex<VecDeque<Packet>>,
+    medium: Option<Arc<Medium>>,
+    up: AtomicBool,
+    counters: LinkCounters,
+    bucket: Mutex<TokenBucket>,
+    latency: LatencyHistogram,
+    rng: Mutex<StdRng>,
+}
+
+/// Frames accepted and dropped (lost or link down) by a channel since it was
+/// created. Frames not addressed to the channel's node are not counted.
+/// Frames seen before the warmup ended are counted apart, in `warmup_*`, so
+/// route discovery does not skew the steady state figures.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "webview", derive(Serialize))]
+pub struct LinkStats {
+    pub forwarded: u64,
+    pub dropped: u64,
+    pub warmup_forwarded: u64,
+    pub warmup_dropped: u64,
+    /// Bits the bandwidth limit lets through right away, zero when the link
+    /// is saturated. Not set without a limit.
+    pub bucket_bits: Option<u64>,
+    /// Time frames took from being sent to being delivered after the
+    /// warmup. Not set before any was delivered.
+    pub latency: Option<LatencyPercentiles>,
+}
+
+impl LinkStats {
+    /// Share of frames dropped after the warmup
+    pub fn drop_rate(&self) -> f64 {
+        match self.forwarded + self.dropped {
+            0 => 0.0,
+            total => self.dropped as f64 / total as f64,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct LinkCounters {
+    warmup_until: Instant,
+    forwarded: AtomicU64,
+    dropped: AtomicU64,
+    warmup_forwarded: AtomicU64,
+    warmup_dropped: AtomicU64,
+}
+
+impl LinkCounters {
+    fn new(warmup_until: Instant) -> Self {
+        Self {
+            warmup_until,
+            forwarded: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            warmup_forwarded: AtomicU64::new(0),
+            warmup_dropped: AtomicU64::new(0),
+        }
+    }
+
+    fn forwarded(&self, now: Instant) {
+        let counter = if now < self.warmup_until {
+            &self.warmup_forwarded
+        } else {
+            &self.forwarded
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn dropped(&self, now: Instant) {
+        let counter = if now < self.warmup_until {
+            &self.warmup_dropped
+        } else {
+            &self.dropped
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn stats(&self) -> LinkStats {
+        LinkStats {
+            forwarded: self.forwarded.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+            warmup_forwarded: self.warmup_forwarded.load(Ordering::Relaxed),
+            warmup_dropped: self.warmup_dropped.load(Ordering::Relaxed),
+            bucket_bits: None,
+            latency: None,
+        }
+    }
 }
 
 impl Channel {
@@ -61,76 +322,170 @@ impl Channel {
         *self.parameters.read().unwrap()
     }
 
+    pub fn medium(&self) -> Option<&Arc<Medium>> {
+        self.medium.as_ref()
+    }
+
+    pub fn stats(&self) -> LinkStats {
+        let bucket_bits = self
+            .params()
+            .bandwidth_bps
+            .map(|rate| self.bucket.lock().unwrap().level(rate, Instant::now()));
+        LinkStats {
+            bucket_bits,
+            latency: self.latency.percentiles(),
+            ..self.counters.stats()
+        }
+    }
+
+    /// Applies the parameters posted for the channel. Latency and loss are
+    /// required, the others keep their current value when left out. A null
+    /// `bandwidth_bps` lifts the cap.
     pub fn set_params(&self, params: HashMap<String, String>) -> Result<()> {
+        let current = self.params();
         let result = ChannelParameters {
             latency: Duration::from_millis(u64::from_str_radix(
                 params.get("latency").context("could not get latency")?,
                 10,
             )?),
             loss: f64::from_str(params.get("loss").context("could not get loss")?)?,
+            jitter: params
+                .get("jitter")
+                .map(|jitter| jitter.parse::<u64>())
+                .transpose()?
+                .map_or(current.jitter, Duration::from_millis),
+            reorder: params
+                .get("reorder")
+                .map(|reorder| reorder.parse::<f64>())
+                .transpose()?
+                .unwrap_or(current.reorder),
+            bandwidth_bps: match params.get("bandwidth_bps").map(String::as_str) {
+                None => current.bandwidth_bps,
+                Some("null") => None,
+                Some(bandwidth) => Some(bandwidth.parse::<u64>()?),
+            },
         };
+        if !(0.0..=1.0).contains(&result.loss) {
+            bail!("loss must be between 0 and 1");
+        }
+        if !(0.0..=1.0).contains(&result.reorder) {
+            bail!("reorder must be between 0 and 1");
+        }
+        if result.bandwidth_bps == Some(0) {
+            bail!("bandwidth_bps must be above 0, or null to lift the cap");
+        }
 
+        self.set_parameters(result);
+        Ok(())
+    }
+
+    pub fn set_parameters(&self, params: ChannelParameters) {
         let mut inner_params = self.parameters.write().unwrap();
-        *inner_params = result;
+        *inner_params = params;
         let _ = self.tx.send(());
-        Ok(())
+    }
+
+    /// Draws loss, jitter and reordering from a generator seeded with
+    /// `seed`, so the same frames are affected on every run.
+    pub fn seed(&self, seed: u64) {
+        *self.rng.lock().unwrap() = StdRng::seed_from_u64(seed);
     }
 
     pub fn new(
         parameters: ChannelParameters,
         mac: MacAddress,
-        tun: Arc<Tun>,
+        tun: Tap,
+        medium: Option<Arc<Medium>>,
+        warmup_until: Instant,
         from: &String,
         to: &String,
     ) -> Arc<Self> {
         let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
-        tracing::info!(from, to, ?parameters, "Created channel");
+        tracing::info!(
+            from,
+            to,
+            ?parameters,
+            medium = medium.as_ref().map(|m| m.name()),
+            "Created channel"
+        );
         let this = Arc::new(Self {
             tx,
             parameters: parameters.into(),
             mac,
             tun,
             queue: VecDeque::with_capacity(1024).into(),
+            medium,
+            up: AtomicBool::new(true),
+            counters: LinkCounters::new(warmup_until),
+            bucket: TokenBucket::new(Instant::now()).into(),
+            latency: LatencyHistogram::default(),
+            rng: StdRng::from_entropy().into(),
         });
         let thisc = this.clone();
         tokio::spawn(async move {
             loop {
-                let Some(packet) = thisc.queue.lock().unwrap().pop_front() else {
+                let params = thisc.params();
+                let due = thisc.queue.lock().unwrap().front().map(|p| p.due(&params));
+                let Some(due) = due else {
                     let _ = rx.recv().await;
                     continue;
                 };
-                loop {
-                    let latency = thisc.parameters.read().unwrap().latency;
-                    let duration = (packet.instant + latency).duration_since(Instant::now());
-                    if duration.is_zero() {
-                        let _ = thisc.tun.send_all(&packet.packet[..packet.size]).await;
-                        break;
-                    } else {
-                        tokio::select! {
-                            _ = tokio_timerfd::sleep(duration) => {
-                                let _ = thisc.tun.send_all(&packet.packet[..packet.size]).await;
-                                break;
-                            },
-                            _ = rx.recv() => {},
-                        }
+                // Woken early when a packet due sooner is queued or the
+                // parameters change
+                let duration = due.saturating_duration_since(Instant::now());
+                if !duration.is_zero() {
+                    tokio::select! {
+                        _ = tokio_timerfd::sleep(duration) => {},
+                        _ = rx.recv() => {},
                     }
+                    continue;
+                }
+                let Some(packet) = thisc.queue.lock().unwrap().pop_front() else {
+                    continue;
+                };
+
+                if let Some(rate) = thisc.params().bandwidth_bps {
+                    let bits = packet.packet.len() as u64 * 8;
+                    let wait = thisc
+                        .bucket
+                        .lock()
+                        .unwrap()
+                        .reserve(bits, rate, Instant::now());
+                    if !wait.is_zero() {
+                        let _ = tokio_timerfd::sleep(wait).await;
+                    }
+                }
+                let _ = thisc.tun.send_all(&packet.packet).await;
+                if packet.queued >= thisc.counters.warmup_until {
+                    thisc.latency.record(packet.queued.elapsed());
                 }
             }
         });
         this
     }
 
-    pub async fn send(&self, packet: [u8; 1500], size: usize) -> Result<()> {
-        self.should_send(&packet[..size])?;
-        let mut queue = self.queue.lock().unwrap();
-        if queue.is_empty() {
+    /// Sends a packet whose transmission starts `defer` from now, as decided
+    /// by medium contention.
+    pub async fn send(&self, packet: Vec<u8>, defer: Duration) -> Result<()> {
+        self.should_send(&packet)?;
+        self.counters.forwarded(Instant::now());
+        let params = self.params();
+        let mut rng = self.rng.lock().unwrap();
+        let jitter = if params.jitter.is_zero() {
+            0.0
+        } else {
+            rng.gen_range(-1.0..=1.0)
+        };
+        let now = Instant::now();
+        let packet = Packet {
+            packet,
+            queued: now,
+            instant: now + defer,
+            jitter,
+        };
+        if enqueue(&mut self.queue.lock().unwrap(), packet, &params, &mut *rng) {
             let _ = self.tx.send(());
         }
-        queue.push_back(Packet {
-            packet,
-            size,
-            instant: Instant::now(),
-        });
         Ok(())
     }
 
@@ -141,12 +496,15 @@ impl Channel {
             bail!("not the right mac address")
         }
 
+        if !self.up.load(Ordering::Relaxed) {
+            self.counters.dropped(Instant::now());
+            bail!("link down")
+        }
+
         let loss = self.parameters.read().unwrap().loss;
-        if loss > 0.0 {
-            let mut rng = rand::thread_rng();
-            if rand::Rng::gen::<f64>(&mut rng) < loss {
-                bail!("packet lost")
-            }
+        if loss > 0.0 && self.rng.lock().unwrap().gen::<f64>() < loss {
+            self.counters.dropped(Instant::now());
+            bail!("packet lost")
         }
 
         Ok(())
@@ -161,6 +519,7 @@ impl Channel {
 pub struct Simulator {
     _namespaces: Vec<NamespaceWrapper>,
     channels: HashMap<String, HashMap<String, Arc<Channel>>>,
+    node_types: HashMap<String, NodeType>,
 }
 
 type CallbackReturn = Result<(Arc<Device>, Arc<Tun>, Arc<dyn Node>)>;
@@ -168,10 +527,12 @@ type CallbackReturn = Result<(Arc<Device>, Arc<Tun>, Arc<dyn Node>)>;
 impl Simulator {
     fn parse_topology(
         config_file: &str,
+        warmup_until: Instant,
         callback: impl Fn(&str, &HashMap<String, Value>) -> CallbackReturn + Clone,
     ) -> Result<(
         HashMap<String, HashMap<String, Arc<Channel>>>,
         Vec<NamespaceWrapper>,
+        HashMap<String, NodeType>,
     )> {
         let settings = Config::builder()
             .add_source(config::File::with_name(config_file))
@@ -188,38 +549,63 @@ impl Simulator {
             })
             .collect::<HashMap<_, _>>();
 
-        let topology = settings.get_table("topology")?;
-        let topology: HashMap<String, HashMap<String, ChannelParameters>> = topology
+        let mediums: HashMap<String, Arc<Medium>> = settings
+            .get_table("mediums")
+            .unwrap_or_default()
             .iter()
-            .map(|(key, val)| {
-                let val = val.clone().into_table().unwrap_or_default();
+            .map(|(name, val)| {
+                let param = val.clone().into_table().unwrap_or_default();
                 (
-                    key.clone(),
-                    val.iter()
-                        .map(|(onode, param)| {
-                            let param = param.clone().into_table().unwrap_or_default();
-                            let param = ChannelParameters::from(param);
-                            (onode.clone(), param)
-                        })
-                        .collect(),
+                    name.clone(),
+                    Arc::new(Medium::new(name, MediumParameters::from(param))),
                 )
             })
             .collect();
 
+        let topology = settings
+            .get_table("topology")?
+            .iter()
+            .map(|(key, val)| {
+                let val = val.clone().into_table().unwrap_or_default();
+                let links = val
+                    .iter()
+                    .map(|(onode, param)| {
+                        let param = param.clone().into_table().unwrap_or_default();
+                        let medium = match param.get("medium") {
+                            Some(name) => {
+                                let name = name.clone().into_string()?;
+                                let medium = mediums.get(&name).with_context(|| {
+                                    format!("link {key} -> {onode} uses unknown medium {name}")
+                                })?;
+                                Some(medium.clone())
+                            }
+                            None => None,
+                        };
+                        let params = ChannelParameters::try_from(param).map_err(|e| {
+                            Error::msg(format!("link {key} -> {onode} has invalid parameters: {e}"))
+                        })?;
+                        Ok((onode.clone(), (params, medium)))
+                    })
+                    .collect::<Result<HashMap<_, _>>>()?;
+                Ok((key.clone(), links))
+            })
+            .collect::<Result<HashMap<_, _>>>()?;
+
         Ok(nodes.iter().fold(
-            (HashMap::default(), Vec::default()),
-            |(channels, mut namespaces), (node, node_params)| {
+            (HashMap::default(), Vec::default(), HashMap::default()),
+            |(channels, mut namespaces, mut node_types), (node, node_params)| {
                 let Ok(device) =
                     Self::create_namespaces(&mut namespaces, node, node_params, callback.clone())
                 else {
-                    return (channels, namespaces);
+                    return (channels, namespaces, node_types);
                 };
+                node_types.insert(node.to_string(), device.2.node_type());
 
                 (
                     topology
                         .iter()
                         .fold(channels, |mut channels, (tnode, connections)| {
-                            let Some(parameters) = connections.get(node) else {
+                            let Some((parameters, medium)) = connections.get(node) else {
                                 return channels;
                             };
 
@@ -228,7 +614,9 @@ impl Simulator {
                                 Channel::new(
                                     *parameters,
                                     device.0.mac_address(),
-                                    device.1.clone(),
+                                    Tap::Device(device.1.clone()),
+                                    medium.clone(),
+                                    warmup_until,
                                     tnode,
                                     node,
                                 ),
@@ -236,6 +624,7 @@ impl Simulator {
                             channels
                         }),
                     namespaces,
+                    node_types,
                 )
             },
         ))
@@ -263,10 +652,13 @@ impl Simulator {
     where
         F: Fn(&str, &HashMap<String, Value>) -> CallbackReturn + Clone,
     {
-        let (channels, namespaces) = Self::parse_topology(&args.config_file, callback)?;
+        let warmup_until = Instant::now() + Duration::from_secs(args.warmup.unwrap_or_default());
+        let (channels, namespaces, node_types) =
+            Self::parse_topology(&args.config_file, warmup_until, callback)?;
         Ok(Self {
             _namespaces: namespaces,
             channels,
+            node_types,
         })
     }
 
@@ -282,8 +674,17 @@ impl Simulator {
         loop {
             if let Some(Ok((buf, size, node, channel))) = future_set.next().await {
                 if let Some(connections) = self.channels.get(&node) {
+                    // One transmission per medium, shared by every receiver on it
+                    let now = Instant::now();
+                    let mut deferrals: HashMap<&str, Duration> = HashMap::default();
                     for channel in connections.values() {
-                        let _ = channel.send(buf, size).await;
+                        let defer = match channel.medium() {
+                            Some(medium) => *deferrals
+                                .entry(medium.name())
+                                .or_insert_with(|| medium.reserve(now, size)),
+                            None => Duration::ZERO,
+                        };
+                        let _ = channel.send(buf[..size].to_vec(), defer).await;
                     }
                 }
 
@@ -295,9 +696,8 @@ impl Simulator {
     async fn generate_channel_reads(
         node: String,
         channel: Arc<Channel>,
-    ) -> Result<([u8; 1500], usize, String, Arc<Channel>), Error> {
-        let buf = uninit_array![u8; 1500];
-        let mut buf = unsafe { std::mem::transmute::<_, [u8; 1500]>(buf) };
+    ) -> Result<(Vec<u8>, usize, String, Arc<Channel>), Error> {
+        let mut buf = vec![0u8; FRAME_LEN];
         let n = channel.recv(&mut buf).await?;
         Ok((buf, n, node, channel))
     }
@@ -305,4 +705,469 @@ impl Simulator {
     pub fn get_channels(&self) -> HashMap<String, HashMap<String, Arc<Channel>>> {
         self.channels.clone()
     }
+
+    pub fn get_node_types(&self) -> HashMap<String, NodeType> {
+        self.node_types.clone()
+    }
+
+    /// Current topology, with the parameters every channel has now
+    pub fn export_topology(&self, format: TopologyFormat) -> String {
+        topology::render(&self.node_types, &link_parameters(&self.channels), format)
+    }
+
+    /// Simulator over `channels` alone, without namespaces or nodes
+    #[cfg(test)]
+    pub fn with_channels(channels: HashMap<String, HashMap<String, Arc<Channel>>>) -> Self {
+        Self {
+            _namespaces: Vec::default(),
+            channels,
+            node_types: HashMap::default(),
+        }
+    }
+
+    /// Handle to the channel carrying frames from `from` to `to`
+    pub fn link(&self, from: &str, to: &str) -> Option<LinkHandle> {
+        let channel = self.channels.get(from)?.get(to)?;
+        Some(LinkHandle(channel.clone()))
+    }
+}
+
+/// Parameters every channel in `channels` has now
+pub fn link_parameters(channels: &HashMap<String, HashMap<String, Arc<Channel>>>) -> Links {
+    channels
+        .iter()
+        .map(|(node, onode)| {
+            (
+                node.clone(),
+                onode
+                    .iter()
+                    .map(|(onode, channel)| (onode.clone(), channel.params()))
+                    .collect(),
+            )
+        })
+        .collect()
+}
+
+/// Direct control over a single channel, for tests and scripted scenarios
+pub struct LinkHandle(Arc<Channel>);
+
+impl LinkHandle {
+    pub fn set_loss(&self, loss: f64) {
+        let params = self.0.params();
+        self.0.set_parameters(ChannelParameters { loss, ..params });
+    }
+
+    pub fn set_latency(&self, latency: Duration) {
+        let params = self.0.params();
+        self.0
+            .set_parameters(ChannelParameters { latency, ..params });
+    }
+
+    /// A link that is down drops every frame, as if out of range
+    pub fn bring_down(&self) {
+        self.0.up.store(false, Ordering::Relaxed);
+    }
+
+    pub fn bring_up(&self) {
+        self.0.up.store(true, Ordering::Relaxed);
+    }
+
+    pub fn params(&self) -> ChannelParameters {
+        self.0.params()
+    }
+
+    pub fn stats(&self) -> LinkStats {
+        self.0.stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        jittered, stagger_delay, Channel, FakeTap, LinkCounters, LinkStats, Simulator, TokenBucket,
+        FRAME_LEN,
+    };
+    use common::channel_parameters::ChannelParameters;
+    use rand::{rngs::StdRng, SeedableRng};
+    use std::{
+        collections::HashMap,
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+    use tokio::sync::mpsc::UnboundedReceiver;
+
+    #[tokio::test]
+    async fn link_can_be_brought_down_and_up() {
+        let (channel, mut deliveries) = fake_channel(params(0, 0, 0.0));
+        let simulator = Simulator::with_channels(HashMap::from([(
+            "n1".to_string(),
+            HashMap::from([("n2".to_string(), channel.clone())]),
+        )]));
+        assert!(simulator.link("n2", "n1").is_none());
+        let link = simulator.link("n1", "n2").expect("link from n1 to n2");
+
+        let send = |id| channel.send(test_frame(id), Duration::ZERO);
+        send(0).await.expect("sent while up");
+        assert_eq!(delivered(&mut deliveries, 1).await, vec![0]);
+
+        link.bring_down();
+        assert!(send(1).await.is_err());
+        assert!(send(2).await.is_err());
+        link.bring_up();
+        send(3).await.expect("sent once back up");
+        assert_eq!(delivered(&mut deliveries, 1).await, vec![3]);
+        assert!(deliveries.try_recv().is_err());
+        let stats = link.stats();
+        assert_eq!((stats.forwarded, stats.dropped), (2, 2));
+
+        link.set_latency(Duration::from_millis(20));
+        link.set_loss(0.5);
+        assert_eq!(
+            link.params(),
+            ChannelParameters {
+                latency: Duration::from_millis(20),
+                loss: 0.5,
+                jitter: Duration::ZERO,
+                reorder: 0.0,
+                bandwidth_bps: None,
+            }
+        );
+    }
+
+    #[test]
+    fn node_startup_is_spread_across_the_window() {
+        let window = Duration::from_secs(10);
+        let mut rng = StdRng::seed_from_u64(0x57a9);
+        let mut delays: Vec<_> = (0..200).map(|_| stagger_delay(window, &mut rng)).collect();
+        delays.sort();
+
+        assert!(delays.iter().all(|delay| *delay < window));
+        assert!(delays[0] < Duration::from_secs(1));
+        assert!(delays[199] > Duration::from_secs(9));
+        // Every second of the window sees some node start
+        for second in 0..10 {
+            let start = Duration::from_secs(second);
+            assert!(delays
+                .iter()
+                .any(|delay| (start..start + Duration::from_secs(1)).contains(delay)));
+        }
+
+        assert_eq!(stagger_delay(Duration::ZERO, &mut rng), Duration::ZERO);
+    }
+
+    #[test]
+    fn warmup_frames_do_not_count_toward_the_drop_rate() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let counters = LinkCounters::new(at(10));
+
+        // Route discovery loses most frames
+        counters.forwarded(at(1));
+        (2..8).for_each(|secs| counters.dropped(at(secs)));
+        assert_eq!(counters.stats().drop_rate(), 0.0);
+
+        (10..13).for_each(|secs| counters.forwarded(at(secs)));
+        counters.dropped(at(13));
+        let stats = counters.stats();
+        assert_eq!(
+            stats,
+            LinkStats {
+                forwarded: 3,
+                dropped: 1,
+                warmup_forwarded: 1,
+                warmup_dropped: 6,
+                bucket_bits: None,
+                latency: None,
+            }
+        );
+        assert_eq!(stats.drop_rate(), 0.25);
+    }
+
+    /// Channel to a fake TAP, with a receiver of the frames it delivers
+    fn fake_channel(parameters: ChannelParameters) -> (Arc<Channel>, Deliveries) {
+        let (tap, _, deliveries) = FakeTap::open();
+        let channel = Channel::new(
+            parameters,
+            [2; 6].into(),
+            tap,
+            None,
+            Instant::now(),
+            &"n1".to_string(),
+            &"n2".to_string(),
+        );
+        (channel, deliveries)
+    }
+
+    type Deliveries = UnboundedReceiver<Vec<u8>>;
+
+    /// Broadcast frame carrying `id`
+    fn test_frame(id: u8) -> Vec<u8> {
+        let mut frame = vec![0u8; 64];
+        frame[..6].copy_from_slice(&[255; 6]);
+        frame[14] = id;
+        frame
+    }
+
+    #[tokio::test]
+    async fn frames_of_the_wire_mtu_are_carried_whole() {
+        let (tap, sent, mut deliveries) = FakeTap::open();
+        let channel = Channel::new(
+            params(0, 0, 0.0),
+            [2; 6].into(),
+            tap,
+            None,
+            Instant::now(),
+            &"n1".to_string(),
+            &"n2".to_string(),
+        );
+        let mut frame = test_frame(7);
+        frame.resize(FRAME_LEN, 0x42);
+        sent.send(frame.clone()).expect("sent by the node");
+
+        let (buf, size, _, channel) = Simulator::generate_channel_reads("n1".to_string(), channel)
+            .await
+            .expect("read from the node");
+        assert_eq!(buf[..size], frame[..]);
+        channel
+            .send(buf[..size].to_vec(), Duration::ZERO)
+            .await
+            .expect("sent");
+        let delivered = tokio::time::timeout(Duration::from_secs(5), deliveries.recv())
+            .await
+            .expect("delivered in time");
+        assert_eq!(delivered, Some(frame));
+    }
+
+    /// Ids of the next `count` frames delivered
+    async fn delivered(deliveries: &mut Deliveries, count: usize) -> Vec<u8> {
+        let mut ids = Vec::with_capacity(count);
+        while ids.len() < count {
+            let frame = tokio::time::timeout(Duration::from_secs(5), deliveries.recv())
+                .await
+                .expect("delivered in time")
+                .expect("channel open");
+            ids.push(frame[14]);
+        }
+        ids
+    }
+
+    /// Sends 50 test frames back to back, returning the order they arrive in
+    async fn delivery_order(channel: &Channel, deliveries: &mut Deliveries) -> Vec<u8> {
+        for id in 0..50 {
+            channel
+                .send(test_frame(id), Duration::ZERO)
+                .await
+                .expect("sent");
+        }
+        delivered(deliveries, 50).await
+    }
+
+    fn params(latency_ms: u64, jitter_ms: u64, reorder: f64) -> ChannelParameters {
+        ChannelParameters {
+            latency: Duration::from_millis(latency_ms),
+            loss: 0.0,
+            jitter: Duration::from_millis(jitter_ms),
+            reorder,
+            bandwidth_bps: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn reorder_probability_shuffles_delivery() {
+        let (channel, mut deliveries) = fake_channel(params(10, 0, 0.0));
+        channel.seed(0x5eed);
+        let in_order: Vec<u8> = (0..50).collect();
+        assert_eq!(delivery_order(&channel, &mut deliveries).await, in_order);
+
+        channel.set_parameters(params(10, 0, 0.5));
+        let reordered = delivery_order(&channel, &mut deliveries).await;
+        assert_ne!(reordered, in_order);
+        let mut sorted = reordered.clone();
+        sorted.sort();
+        assert_eq!(sorted, in_order);
+    }
+
+    #[tokio::test]
+    async fn jittered_frames_overtake_slower_ones() {
+        let (channel, mut deliveries) = fake_channel(params(20, 15, 0.0));
+        channel.seed(0x5eed);
+        let in_order: Vec<u8> = (0..50).collect();
+
+        let jittered = delivery_order(&channel, &mut deliveries).await;
+        assert_ne!(jittered, in_order);
+        let mut sorted = jittered.clone();
+        sorted.sort();
+        assert_eq!(sorted, in_order);
+    }
+
+    #[tokio::test]
+    async fn posted_parameters_keep_what_they_leave_out() {
+        let (channel, _deliveries) = fake_channel(params(10, 4, 0.2));
+        let post = |fields: &[(&str, &str)]| {
+            channel.set_params(
+                fields
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect(),
+            )
+        };
+
+        post(&[("latency", "30"), ("loss", "0.1")]).expect("applied");
+        assert_eq!(
+            channel.params(),
+            ChannelParameters {
+                loss: 0.1,
+                ..params(30, 4, 0.2)
+            }
+        );
+
+        for reorder in ["-0.1", "1.5", "NaN"] {
+            assert!(post(&[("latency", "30"), ("loss", "0"), ("reorder", reorder)]).is_err());
+        }
+        assert!(post(&[("latency", "30"), ("loss", "0"), ("bandwidth_bps", "0")]).is_err());
+
+        post(&[
+            ("latency", "30"),
+            ("loss", "0"),
+            ("bandwidth_bps", "1000000"),
+        ])
+        .expect("applied");
+        post(&[("latency", "30"), ("loss", "0")]).expect("applied");
+        assert_eq!(channel.params().bandwidth_bps, Some(1_000_000));
+        post(&[("latency", "30"), ("loss", "0"), ("bandwidth_bps", "null")]).expect("applied");
+        assert_eq!(channel.params().bandwidth_bps, None);
+
+        post(&[
+            ("latency", "30"),
+            ("loss", "0"),
+            ("jitter", "0"),
+            ("reorder", "1"),
+        ])
+        .expect("applied");
+        assert_eq!(channel.params(), params(30, 0, 1.0));
+    }
+
+    #[test]
+    fn jitter_spreads_latency_around_its_mean() {
+        let (latency, jitter) = (Duration::from_millis(10), Duration::from_millis(4));
+        assert_eq!(jittered(latency, jitter, 0.0), latency);
+        assert_eq!(jittered(latency, jitter, 1.0), Duration::from_millis(14));
+        assert_eq!(jittered(latency, jitter, -1.0), Duration::from_millis(6));
+        assert_eq!(
+            jittered(Duration::from_millis(2), jitter, -1.0),
+            Duration::ZERO
+        );
+    }
+
+    #[tokio::test]
+    async fn bandwidth_limit_throttles_bulk_transfers() {
+        let rate = 5_000_000;
+        let (channel, mut deliveries) = fake_channel(ChannelParameters {
+            bandwidth_bps: Some(rate),
+            ..params(0, 0, 0.0)
+        });
+        let frames = 84;
+        assert_eq!(channel.stats().bucket_bits, Some(rate / 10));
+
+        // About 1 Mbit back to back: half fits the bucket, the rest takes
+        // around 100ms at the rate. Only the lower bound holds on a busy host.
+        let start = Instant::now();
+        for id in 0..frames {
+            let mut frame = test_frame(id);
+            frame.resize(1500, 0);
+            channel.send(frame, Duration::ZERO).await.expect("sent");
+        }
+        assert_eq!(
+            delivered(&mut deliveries, frames.into()).await.len(),
+            usize::from(frames)
+        );
+        let elapsed = start.elapsed();
+        assert!(elapsed > Duration::from_millis(90), "took {elapsed:?}");
+    }
+
+    #[test]
+    fn token_bucket_paces_bursts_to_the_rate() {
+        let rate = 5_000_000;
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        let mut bucket = TokenBucket::new(start);
+        let close = |level: u64, expected: u64| level.abs_diff(expected) <= 1;
+
+        // A tenth of a second of traffic goes through right away
+        assert_eq!(bucket.level(rate, start), rate / 10);
+        let waits: Vec<_> = (0..100)
+            .map(|_| bucket.reserve(100_000, rate, start))
+            .collect();
+        assert!(waits[..5].iter().all(Duration::is_zero));
+        assert_eq!(waits[5], Duration::from_millis(20));
+        assert_eq!(waits[99], Duration::from_millis(1_900));
+        assert_eq!(bucket.level(rate, start), 0);
+
+        // The debt is paid off at the rate, then an idle link refills up to
+        // a tenth of a second of traffic
+        assert_eq!(bucket.level(rate, at(1_900)), 0);
+        assert!(close(bucket.level(rate, at(1_940)), 200_000));
+        assert_eq!(bucket.level(rate, at(2_100)), rate / 10);
+        assert_eq!(bucket.level(rate, at(60_000)), rate / 10);
+
+        // Frames sent after the refill wait again only once it runs out
+        assert!(bucket.reserve(400_000, rate, at(2_100)).is_zero());
+        assert_eq!(
+            bucket.reserve(200_000, rate, at(2_100)),
+            Duration::from_millis(20)
+        );
+    }
+
+    #[test]
+    fn links_on_unknown_mediums_are_rejected() {
+        let path = std::env::temp_dir().join(format!("medium-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            "[nodes.n1]\nconfig_path = \"n1.yaml\"\n\n\
+             [mediums.air]\n\n\
+             [topology.n1.n2]\nlatency = 0\nloss = 0\nmedium = \"water\"\n",
+        )
+        .expect("config written");
+
+        let result = Simulator::parse_topology(
+            path.to_str().expect("utf-8 path"),
+            Instant::now(),
+            |_, _| unreachable!("no node is created"),
+        );
+        std::fs::remove_file(&path).expect("config removed");
+
+        let error = result.err().expect("unknown medium rejected");
+        assert!(
+            error.to_string().contains("unknown medium water"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn links_with_out_of_range_parameters_are_rejected() {
+        for (link, expected) in [
+            ("loss = 1.5", "loss must be within [0, 1]"),
+            ("loss = 0\nreorder = -0.1", "reorder must be within [0, 1]"),
+        ] {
+            let path = std::env::temp_dir().join(format!("ranges-{}.toml", std::process::id()));
+            std::fs::write(
+                &path,
+                format!(
+                    "[nodes.n1]\nconfig_path = \"n1.yaml\"\n\n\
+                     [topology.n1.n2]\nlatency = 0\n{link}\n"
+                ),
+            )
+            .expect("config written");
+
+            let result = Simulator::parse_topology(
+                path.to_str().expect("utf-8 path"),
+                Instant::now(),
+                |_, _| unreachable!("no node is created"),
+            );
+            std::fs::remove_file(&path).expect("config removed");
+
+            let error = result.err().expect("out of range parameters rejected");
+            assert!(error.to_string().contains(expected), "{error}");
+        }
+    }
 }
@@ -0,0 +1,168 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use common::channel_parameters::ChannelParameters;
+use node_lib::args::NodeType;
+use serde_json::json;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum, Debug)]
+pub enum TopologyFormat {
+    Dot,
+    Json,
+}
+
+impl TopologyFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            TopologyFormat::Dot => "dot",
+            TopologyFormat::Json => "json",
+        }
+    }
+}
+
+/// Parameters of the channel from every node to each node it reaches
+pub type Links = HashMap<String, HashMap<String, ChannelParameters>>;
+
+fn type_name(node_type: NodeType) -> &'static str {
+    match node_type {
+        NodeType::Rsu => "rsu",
+        NodeType::Obu => "obu",
+    }
+}
+
+/// Every node with its type and every link with its latency and loss,
+/// sorted by name so exports of the same topology compare equal
+pub fn render(nodes: &HashMap<String, NodeType>, links: &Links, format: TopologyFormat) -> String {
+    let nodes: BTreeMap<_, _> = nodes.iter().collect();
+    let links: BTreeMap<_, BTreeMap<_, _>> = links
+        .iter()
+        .map(|(from, to)| (from, to.iter().collect()))
+        .collect();
+    let edges = links
+        .iter()
+        .flat_map(|(from, to)| to.iter().map(move |(to, params)| (*from, *to, *params)));
+
+    match format {
+        TopologyFormat::Dot => {
+            let mut dot = "digraph topology {\n".to_string();
+            for (name, node_type) in &nodes {
+                let _ = writeln!(dot, "    \"{name}\" [type=\"{}\"];", type_name(**node_type));
+            }
+            for (from, to, params) in edges {
+                let _ = writeln!(
+                    dot,
+                    "    \"{from}\" -> \"{to}\" [latency_us={}, loss={}];",
+                    params.latency.as_micros(),
+                    params.loss
+                );
+            }
+            dot.push_str("}\n");
+            dot
+        }
+        TopologyFormat::Json => json!({
+            "nodes": nodes
+                .iter()
+                .map(|(name, node_type)| json!({ "name": name, "type": type_name(**node_type) }))
+                .collect::<Vec<_>>(),
+            "edges": edges
+                .map(|(from, to, params)| {
+                    json!({
+                        "from": from,
+                        "to": to,
+                        "latency_us": params.latency.as_micros() as u64,
+                        "loss": params.loss,
+                    })
+                })
+                .collect::<Vec<_>>(),
+        })
+        .to_string(),
+    }
+}
+
+/// Writes the topology to `topology-<unix seconds>.<format>`, returning its
+/// path
+pub fn write(topology: &str, format: TopologyFormat) -> Result<PathBuf> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let path = PathBuf::from(format!("topology-{now}.{}", format.extension()));
+    std::fs::write(&path, topology)?;
+    tracing::info!(path = %path.display(), "exported topology");
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render, Links, TopologyFormat};
+    use common::channel_parameters::ChannelParameters;
+    use node_lib::args::NodeType;
+    use std::{collections::HashMap, time::Duration};
+
+    fn link(latency_ms: u64, loss: f64) -> ChannelParameters {
+        ChannelParameters {
+            latency: Duration::from_millis(latency_ms),
+            loss,
+            jitter: Duration::ZERO,
+            reorder: 0.0,
+            bandwidth_bps: None,
+        }
+    }
+
+    fn topology() -> (HashMap<String, NodeType>, Links) {
+        let nodes = HashMap::from([
+            ("n1".to_string(), NodeType::Rsu),
+            ("n2".to_string(), NodeType::Obu),
+            ("n3".to_string(), NodeType::Obu),
+        ]);
+        let links = HashMap::from([
+            (
+                "n1".to_string(),
+                HashMap::from([("n2".to_string(), link(10, 0.0))]),
+            ),
+            (
+                "n2".to_string(),
+                HashMap::from([
+                    ("n1".to_string(), link(10, 0.0)),
+                    ("n3".to_string(), link(5, 0.25)),
+                ]),
+            ),
+        ]);
+        (nodes, links)
+    }
+
+    #[test]
+    fn dot_declares_every_node_and_link() {
+        let (nodes, links) = topology();
+        let dot = render(&nodes, &links, TopologyFormat::Dot);
+
+        assert!(dot.starts_with("digraph topology {\n"));
+        assert!(dot.ends_with("}\n"));
+        assert!(dot.contains("    \"n1\" [type=\"rsu\"];\n"));
+        assert!(dot.contains("    \"n2\" [type=\"obu\"];\n"));
+        assert!(dot.contains("    \"n3\" [type=\"obu\"];\n"));
+        assert!(dot.contains("    \"n1\" -> \"n2\" [latency_us=10000, loss=0];\n"));
+        assert!(dot.contains("    \"n2\" -> \"n1\" [latency_us=10000, loss=0];\n"));
+        assert!(dot.contains("    \"n2\" -> \"n3\" [latency_us=5000, loss=0.25];\n"));
+        assert_eq!(dot.matches("->").count(), 3);
+        assert_eq!(dot, render(&nodes, &links, TopologyFormat::Dot));
+    }
+
+    #[test]
+    fn json_lists_nodes_and_edges() {
+        let (nodes, links) = topology();
+        let json: serde_json::Value =
+            serde_json::from_str(&render(&nodes, &links, TopologyFormat::Json)).expect("json");
+
+        assert_eq!(json["nodes"][0]["name"], "n1");
+        assert_eq!(json["nodes"][0]["type"], "rsu");
+        assert_eq!(json["nodes"].as_array().map(Vec::len), Some(3));
+        assert_eq!(json["edges"].as_array().map(Vec::len), Some(3));
+        assert_eq!(json["edges"][2]["from"], "n2");
+        assert_eq!(json["edges"][2]["to"], "n3");
+        assert_eq!(json["edges"][2]["latency_us"], 5000);
+        assert_eq!(json["edges"][2]["loss"], 0.25);
+    }
+}
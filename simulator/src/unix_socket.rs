@@ -0,0 +1,85 @@
+use anyhow::{bail, Context, Result};
+use futures::Stream;
+use std::{io, os::unix::fs::FileTypeExt, path::Path};
+use tokio::net::{UnixListener, UnixStream};
+
+/// Connections accepted on a Unix domain socket bound at `path`, to be
+/// served with `warp::serve(..).run_incoming`. A socket file left behind by
+/// a previous run is replaced, anything else at `path` is left alone.
+pub fn incoming(path: &Path) -> Result<impl Stream<Item = io::Result<UnixStream>>> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)
+            .with_context(|| format!("removing stale socket {}", path.display()))?,
+        Ok(_) => bail!("{} exists and is not a socket", path.display()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => {
+            return Err(e).with_context(|| format!("checking socket path {}", path.display()))
+        }
+    }
+
+    let listener = UnixListener::bind(path)
+        .with_context(|| format!("binding unix socket {}", path.display()))?;
+    tracing::info!(path = %path.display(), "serving webview on unix socket");
+    Ok(futures::stream::unfold(listener, |listener| async move {
+        let connection = listener.accept().await.map(|(stream, _)| stream);
+        Some((connection, listener))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::incoming;
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+    };
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::UnixStream,
+    };
+
+    #[tokio::test]
+    async fn nodes_are_served_over_the_socket() {
+        let path = std::env::temp_dir().join(format!("simulator-{}.sock", std::process::id()));
+        let devices = Arc::new(Mutex::new(HashMap::from([
+            ("n1".to_string(), ()),
+            ("n2".to_string(), ()),
+        ])));
+        let connections = incoming(&path).expect("bound socket");
+        tokio::spawn(warp::serve(crate::nodes_route(devices)).run_incoming(connections));
+
+        let mut stream = UnixStream::connect(&path).await.expect("connected");
+        stream
+            .write_all(b"GET /nodes HTTP/1.0\r\n\r\n")
+            .await
+            .expect("sent request");
+        let mut response = String::new();
+        stream
+            .read_to_string(&mut response)
+            .await
+            .expect("read response");
+        let _ = std::fs::remove_file(&path);
+
+        assert!(response.starts_with("HTTP/1.0 200"), "{response}");
+        let (_, body) = response.split_once("\r\n\r\n").expect("has body");
+        let mut nodes: Vec<String> = serde_json::from_str(body).expect("nodes json");
+        nodes.sort();
+        assert_eq!(nodes, ["n1", "n2"]);
+    }
+
+    #[tokio::test]
+    async fn only_stale_sockets_are_replaced() {
+        let path = std::env::temp_dir().join(format!("simulator-{}.file", std::process::id()));
+        std::fs::write(&path, "keep me").expect("file written");
+        let result = incoming(&path).map(|_| ());
+        let contents = std::fs::read_to_string(&path).expect("file kept");
+        std::fs::remove_file(&path).expect("file removed");
+        assert!(result.is_err());
+        assert_eq!(contents, "keep me");
+
+        let path = std::env::temp_dir().join(format!("simulator-{}.stale", std::process::id()));
+        drop(incoming(&path).expect("bound socket"));
+        assert!(incoming(&path).is_ok());
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -38,31 +38,52 @@ fn OutterForm(props: &Props) -> Html {
         ));
         fromc.set(Some(node))
     });
-    // let emit = |latency, loss| {
-    //     wasm_bindgen_futures::spawn_local(async move {
-    //         let Ok(request) = Request::post(format!("http://127.0.0.1:3030/channel/{}/{}", *from, *to))
-    //             .send()
-    //             .await
-    //         else {
-    //             gloo_timers::future::sleep(std::time::Duration::from_secs(1)).await;
-    //             continue;
-    //         };
-    //
-    //         if let Ok(mut node_stats) = request.json::<Vec<String>>().await {
-    //             node_stats.sort();
-    //             tracing::info!(?node_stats, "these are the current nodes");
-    //             if *nodes != node_stats {
-    //                 nodes.set(node_stats);
-    //             }
-    //         }
-    //     });
-    // };
+    let channels = props.channels.clone();
+    let fromc = from.clone();
+    let toc = to.clone();
+    let emit = move |key: &'static str, value: String| {
+        let (Some(from), Some(to)) = ((*fromc).clone(), (*toc).clone()) else {
+            return;
+        };
+        let Some(params) = channels.get(&from).and_then(|x| x.get(&to)) else {
+            return;
+        };
+
+        // Every parameter is posted so the ones not edited are kept
+        let mut body = HashMap::from([
+            ("latency", params.latency.as_millis().to_string()),
+            ("loss", params.loss.to_string()),
+            ("jitter", params.jitter.as_millis().to_string()),
+            ("reorder", params.reorder.to_string()),
+        ]);
+        if let Some(bandwidth) = params.bandwidth_bps {
+            body.insert("bandwidth_bps", bandwidth.to_string());
+        }
+        body.insert(key, value);
+        wasm_bindgen_futures::spawn_local(async move {
+            let Ok(request) =
+                Request::post(&format!("http://127.0.0.1:3030/channel/{from}/{to}")).json(&body)
+            else {
+                return;
+            };
+
+            match request.send().await {
+                Ok(response) if response.ok() => tracing::info!(from, to, key, "channel updated"),
+                Ok(response) => {
+                    tracing::warn!(status = response.status(), "channel update rejected")
+                }
+                Err(e) => tracing::error!(?e, "could not update channel"),
+            }
+        });
+    };
 
+    let emitc = emit.clone();
     let emit_latency = Callback::from(move |input_event: Event| {
         let input_event_target = input_event.target().unwrap();
         let current_input_text = input_event_target.unchecked_into::<HtmlInputElement>();
 
         tracing::info!(?current_input_text, "emitted latency");
+        emitc("latency", current_input_text.value());
     });
 
     let emit_loss = Callback::from(move |input_event: Event| {
@@ -70,6 +91,7 @@ fn OutterForm(props: &Props) -> Html {
         let current_input_text = input_event_target.unchecked_into::<HtmlInputElement>();
 
         tracing::info!(?current_input_text, "emitted loss");
+        emit("loss", current_input_text.value());
     });
 
     let toc = to.clone();